@@ -29,14 +29,15 @@ impl From<&Quaternion<Float>> for Matrix4<Float> {
     fn from(quat: &Quaternion<Float>) -> Self {
         let mut matrix = SquareMatrix::identity();
 
-        let q0 = quat.x;
-        let q1 = quat.y;
-        let q2 = quat.z;
-        let q3 = quat.w;
-        let q0_sq = quat.x * quat.x;
-        let q1_sq = quat.y * quat.y;
-        let q2_sq = quat.z * quat.z;
-        let q3_sq = quat.w * quat.w;
+        // q0 is the scalar part
+        let q0 = quat.w;
+        let q1 = quat.x;
+        let q2 = quat.y;
+        let q3 = quat.z;
+        let q0_sq = q0 * q0;
+        let q1_sq = q1 * q1;
+        let q2_sq = q2 * q2;
+        let q3_sq = q3 * q3;
         let q0q1_2 = 2.*q0*q1;
         let q0q2_2 = 2.*q0*q2;
         let q0q3_2 = 2.*q0*q3;
@@ -67,6 +68,61 @@ impl Matrix4<Float> {
         }
         return matrix;
     }
+
+    pub fn transpose(&self) -> Self {
+        let mut matrix = Self::zero();
+        for i in 0..4 {
+            for j in 0..4 {
+                matrix.m[i][j] = self.m[j][i];
+            }
+        }
+        matrix
+    }
+
+    // Gauss-Jordan elimination with partial pivoting, None if singular
+    pub fn inverse(&self) -> Option<Self> {
+        let mut a = self.m;
+        let mut inv = Self::identity().m;
+        for col in 0..4 {
+            let pivot = (col..4)
+                .max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))
+                .unwrap();
+            if a[pivot][col].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+
+            let scale = 1. / a[col][col];
+            for j in 0..4 {
+                a[col][j] *= scale;
+                inv[col][j] *= scale;
+            }
+            for row in 0..4 {
+                if row == col {
+                    continue;
+                }
+                let factor = a[row][col];
+                for j in 0..4 {
+                    a[row][j] -= factor * a[col][j];
+                    inv[row][j] -= factor * inv[col][j];
+                }
+            }
+        }
+        Some(Self { m: inv })
+    }
+
+    // Multiplying by a Vector3 treats it as a point, this leaves out the
+    // translation
+    pub fn transform_vector(&self, v: Vector3<Float>) -> Vector3<Float> {
+        let mut out_vector = Vector3::default();
+        for i in 0..3 {
+            for j in 0..3 {
+                out_vector[i] += self.m[i][j] * v[j];
+            }
+        }
+        out_vector
+    }
 }
 
 impl<T> Default for Matrix4<T>
@@ -111,6 +167,21 @@ impl<T> std::ops::IndexMut<[usize; 2]> for Matrix4<T>
     }
 }
 
+impl<T> ops::Add<Matrix4<T>> for Matrix4<T>
+    where T: Numeric {
+    type Output = Matrix4<T>;
+
+    fn add(self, _rhs: Matrix4<T>) -> Self::Output {
+        let mut out_matrix = self;
+        for i in 0..4 {
+            for j in 0..4 {
+                out_matrix.m[i][j] += _rhs.m[i][j];
+            }
+        }
+        out_matrix
+    }
+}
+
 impl<T> ops::Mul<T> for Matrix4<T>
     where T: Numeric {
     type Output = Matrix4<T>;
@@ -232,6 +303,20 @@ mod tests {
 
     }
 
+    #[test]
+    fn test_matrix_inverse() {
+        let m1 = temp_mat1();
+        let product = m1 * m1.inverse().unwrap();
+        for i in 0..4 {
+            for j in 0..4 {
+                let expected = if i == j { 1. } else { 0. };
+                assert!((product[[i, j]] - expected).abs() < 1e-5);
+            }
+        }
+        // The rows of temp_mat2 repeat, it has no inverse
+        assert_eq!(temp_mat2().inverse(), None);
+    }
+
     #[test]
     fn test_matrix_mul_vector() {
         let m1 = temp_mat1();
@@ -240,5 +325,20 @@ mod tests {
         let m1xv1 = Vector3 {x: 2., y: 20., z: 28. };
         assert_eq!(m1 * v1, m1xv1);
     }
+
+    #[test]
+    fn test_matrix_from_quaternion() {
+        // glTF stores [x, y, z, w], this is a quarter turn around y
+        let half = (0.5 as Float).sqrt();
+        let quat = Quaternion::from(&[0., half, 0., half]);
+        let m = Matrix4::from(&quat);
+        let v = m * Vector3 {x: 1.0, y: 0.0, z: 0.0 };
+        assert!((v.x - 0.).abs() < 1e-6 && (v.y - 0.).abs() < 1e-6 && (v.z + 1.).abs() < 1e-6);
+        let v = m * Vector3 {x: 0.0, y: 0.0, z: 1.0 };
+        assert!((v.x - 1.).abs() < 1e-6 && (v.y - 0.).abs() < 1e-6 && (v.z - 0.).abs() < 1e-6);
+
+        // The identity rotation has w = 1
+        assert_eq!(Matrix4::from(&Quaternion::from(&[0., 0., 0., 1.])), Matrix4::identity());
+    }
 }
 
@@ -1,5 +1,10 @@
+use std::ops;
+
 use funty::Numeric;
 
+use crate::{Float, Matrix4};
+
+// Stored in glTF order, w is the scalar part
 #[derive(PartialEq, Debug, Copy, Clone)]
 pub struct Quaternion<T>
     where T: Numeric {
@@ -14,4 +19,143 @@ impl<T> From<&[T; 4]> for Quaternion<T>
     fn from(v: &[T; 4]) -> Self {
         Self { x: v[0], y: v[1], z: v[2], w: v[3] }
     }
-}
\ No newline at end of file
+}
+
+// Rotation part of a matrix without shear or scale
+impl From<&Matrix4<Float>> for Quaternion<Float> {
+    fn from(m: &Matrix4<Float>) -> Self {
+        let trace = m.m[0][0] + m.m[1][1] + m.m[2][2];
+        let quat = if trace > 0. {
+            let s = 0.5 / (trace + 1.).sqrt();
+            Self {
+                x: (m.m[2][1] - m.m[1][2]) * s,
+                y: (m.m[0][2] - m.m[2][0]) * s,
+                z: (m.m[1][0] - m.m[0][1]) * s,
+                w: 0.25 / s,
+            }
+        } else if m.m[0][0] > m.m[1][1] && m.m[0][0] > m.m[2][2] {
+            let s = 2. * (1. + m.m[0][0] - m.m[1][1] - m.m[2][2]).sqrt();
+            Self {
+                x: 0.25 * s,
+                y: (m.m[0][1] + m.m[1][0]) / s,
+                z: (m.m[0][2] + m.m[2][0]) / s,
+                w: (m.m[2][1] - m.m[1][2]) / s,
+            }
+        } else if m.m[1][1] > m.m[2][2] {
+            let s = 2. * (1. + m.m[1][1] - m.m[0][0] - m.m[2][2]).sqrt();
+            Self {
+                x: (m.m[0][1] + m.m[1][0]) / s,
+                y: 0.25 * s,
+                z: (m.m[1][2] + m.m[2][1]) / s,
+                w: (m.m[0][2] - m.m[2][0]) / s,
+            }
+        } else {
+            let s = 2. * (1. + m.m[2][2] - m.m[0][0] - m.m[1][1]).sqrt();
+            Self {
+                x: (m.m[0][2] + m.m[2][0]) / s,
+                y: (m.m[1][2] + m.m[2][1]) / s,
+                z: 0.25 * s,
+                w: (m.m[1][0] - m.m[0][1]) / s,
+            }
+        };
+        quat.normalize()
+    }
+}
+
+impl Quaternion<Float> {
+    pub fn identity() -> Self {
+        Self { x: 0., y: 0., z: 0., w: 1. }
+    }
+
+    pub fn dot(q1: Self, q2: Self) -> Float {
+        q1.x * q2.x + q1.y * q2.y + q1.z * q2.z + q1.w * q2.w
+    }
+
+    pub fn normalize(&self) -> Self {
+        let length = Self::dot(*self, *self).sqrt();
+        Self {
+            x: self.x / length,
+            y: self.y / length,
+            z: self.z / length,
+            w: self.w / length,
+        }
+    }
+
+    pub fn conjugate(&self) -> Self {
+        Self { x: -self.x, y: -self.y, z: -self.z, w: self.w }
+    }
+
+    // Angle between the orientations, in radians
+    pub fn angle_between(q1: Self, q2: Self) -> Float {
+        2. * Self::dot(q1, q2).abs().min(1.).acos()
+    }
+
+    // Constant speed interpolation along the shortest arc
+    pub fn slerp(q1: Self, q2: Self, t: Float) -> Self {
+        let mut cos_theta = Self::dot(q1, q2);
+        let mut q2 = q2;
+        // q and -q are the same rotation, go the short way around
+        if cos_theta < 0. {
+            cos_theta = -cos_theta;
+            q2 = Self { x: -q2.x, y: -q2.y, z: -q2.z, w: -q2.w };
+        }
+        let (w1, w2) = if cos_theta > 0.9995 {
+            // Nearly parallel, linear interpolation is as good and stable
+            (1. - t, t)
+        } else {
+            let theta = cos_theta.acos();
+            let sin_theta = theta.sin();
+            (((1. - t) * theta).sin() / sin_theta, (t * theta).sin() / sin_theta)
+        };
+        Self {
+            x: w1 * q1.x + w2 * q2.x,
+            y: w1 * q1.y + w2 * q2.y,
+            z: w1 * q1.z + w2 * q2.z,
+            w: w1 * q1.w + w2 * q2.w,
+        }
+        .normalize()
+    }
+}
+
+// Hamilton product, the rotation `rhs` followed by `self`
+impl ops::Mul<Quaternion<Float>> for Quaternion<Float> {
+    type Output = Quaternion<Float>;
+
+    fn mul(self, rhs: Quaternion<Float>) -> Self::Output {
+        Self {
+            x: self.w * rhs.x + self.x * rhs.w + self.y * rhs.z - self.z * rhs.y,
+            y: self.w * rhs.y - self.x * rhs.z + self.y * rhs.w + self.z * rhs.x,
+            z: self.w * rhs.z + self.x * rhs.y - self.y * rhs.x + self.z * rhs.w,
+            w: self.w * rhs.w - self.x * rhs.x - self.y * rhs.y - self.z * rhs.z,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quaternion_slerp() {
+        // 90 degrees around y
+        let half = std::f32::consts::FRAC_PI_4;
+        let q = Quaternion { x: 0., y: half.sin(), z: 0., w: half.cos() };
+        let m = Matrix4::from(&q);
+        assert!((m[[0, 2]] - 1.).abs() < 1e-5);
+        assert!((m[[2, 0]] + 1.).abs() < 1e-5);
+
+        // Back and forth between matrices and quaternions
+        let back = Quaternion::from(&m);
+        assert!(Quaternion::angle_between(q, back) < 1e-3);
+
+        // Halfway is 45 degrees around y
+        let mid = Quaternion::slerp(Quaternion::identity(), q, 0.5);
+        let expected = (half / 2.).sin();
+        assert!((mid.y - expected).abs() < 1e-5);
+        assert!((Quaternion::angle_between(Quaternion::identity(), mid) - half).abs() < 1e-3);
+
+        // Two eighth turns make a quarter turn, and undo each other
+        assert!(Quaternion::angle_between(mid * mid, q) < 1e-3);
+        assert!(Quaternion::angle_between(mid * mid.conjugate(), Quaternion::identity()) < 1e-3);
+    }
+}
@@ -1,38 +1,222 @@
-use std::fmt::Error;
-use std::{fs::File};
+use std::fs::File;
 use std::io;
 use std::io::BufRead;
 use std::path::Path;
-use ply_rs::{parser, ply::{self}};
 
+use math::{Float, Vec2, Vec3};
+use ply_rs::parser;
+use ply_rs::ply::{DefaultElement, Property};
+
+// Mesh as the file stores it, faces keep all of their corners so quads can
+// be subdivided as quads. Normals and uvs are per position, or empty.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PolygonMesh {
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub uv: Vec<Vec2>,
+    pub faces: Vec<Vec<u32>>,
+}
+
+impl PolygonMesh {
+    // Indices of triangles fanned out of the first corner of every face
+    pub fn triangulate(&self) -> Vec<u32> {
+        let mut indices = Vec::new();
+        for face in &self.faces {
+            for i in 1..face.len().saturating_sub(1) {
+                indices.extend([face[0], face[i], face[i + 1]]);
+            }
+        }
+        indices
+    }
+
+    fn check(self) -> Result<Self, String> {
+        let count = self.positions.len();
+        if self.faces.iter().flatten().any(|&index| index as usize >= count) {
+            return Err("face index out of range".to_string());
+        }
+        if !self.normals.is_empty() && self.normals.len() != count {
+            return Err(format!("{} normals for {} positions", self.normals.len(), count));
+        }
+        if !self.uv.is_empty() && self.uv.len() != count {
+            return Err(format!("{} uvs for {} positions", self.uv.len(), count));
+        }
+        Ok(self)
+    }
+}
 
 fn read_lines<P>(filename: P) -> io::Result<io::Lines<io::BufReader<File>>>
-where P: AsRef<Path>, {
+where
+    P: AsRef<Path>,
+{
     let file = File::open(filename)?;
     Ok(io::BufReader::new(file).lines())
 }
 
+fn scalar(property: Option<&Property>) -> Option<Float> {
+    Some(match property? {
+        Property::Char(x) => *x as Float,
+        Property::UChar(x) => *x as Float,
+        Property::Short(x) => *x as Float,
+        Property::UShort(x) => *x as Float,
+        Property::Int(x) => *x as Float,
+        Property::UInt(x) => *x as Float,
+        Property::Float(x) => *x as Float,
+        Property::Double(x) => *x as Float,
+        _ => return None,
+    })
+}
+
+fn index_list(property: Option<&Property>) -> Option<Vec<u32>> {
+    Some(match property? {
+        Property::ListChar(list) => list.iter().map(|&i| i as u32).collect(),
+        Property::ListUChar(list) => list.iter().map(|&i| i as u32).collect(),
+        Property::ListShort(list) => list.iter().map(|&i| i as u32).collect(),
+        Property::ListUShort(list) => list.iter().map(|&i| i as u32).collect(),
+        Property::ListInt(list) => list.iter().map(|&i| i as u32).collect(),
+        Property::ListUInt(list) => list.clone(),
+        _ => return None,
+    })
+}
+
+// First of the properties the vertex has, exporters name uvs differently
+fn first_scalar(vertex: &DefaultElement, names: &[&str]) -> Option<Float> {
+    names.iter().find_map(|name| scalar(vertex.get(*name)))
+}
+
+pub fn parse_ply(path: &Path) -> Result<PolygonMesh, String> {
+    let mut file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let ply = parser::Parser::<DefaultElement>::new()
+        .read_ply(&mut file)
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
 
-pub fn parse_ply(path: &std::path::Path) -> Result<ply::Ply<ply::DefaultElement>, Error> {
-    let _ = match File::open(path) {
-        Ok(mut file) => {
-            let ply_parser = parser::Parser::<ply::DefaultElement>::new();
-            Ok(ply_parser.read_ply(&mut file))
+    let mut mesh = PolygonMesh::default();
+    for vertex in ply.payload.get("vertex").into_iter().flatten() {
+        let coordinate = |name| scalar(vertex.get(name)).ok_or(format!("vertex without {}", name));
+        mesh.positions.push(Vec3::new(coordinate("x")?, coordinate("y")?, coordinate("z")?));
+        if let (Some(nx), Some(ny), Some(nz)) = (scalar(vertex.get("nx")), scalar(vertex.get("ny")), scalar(vertex.get("nz"))) {
+            mesh.normals.push(Vec3::new(nx, ny, nz));
         }
-        Err(e) => Err(e)
-    };
-    Err(Error)
+        let u = first_scalar(vertex, &["u", "s", "texture_u", "texture_s"]);
+        let v = first_scalar(vertex, &["v", "t", "texture_v", "texture_t"]);
+        if let (Some(u), Some(v)) = (u, v) {
+            mesh.uv.push(Vec2::new(&[u, v]));
+        }
+    }
+    for face in ply.payload.get("face").into_iter().flatten() {
+        let corners = index_list(face.get("vertex_indices").or(face.get("vertex_index")))
+            .ok_or("face without vertex_indices".to_string())?;
+        mesh.faces.push(corners);
+    }
+    mesh.check()
+}
+
+// Positions, uvs, normals and polygonal faces of a Wavefront OBJ file.
+// Corners of a face may pick a uv or a normal other than their position's,
+// the last one seen for a position is kept.
+pub fn parse_obj(path: &Path) -> Result<PolygonMesh, String> {
+    let lines = read_lines(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut mesh = PolygonMesh::default();
+    let (mut uv, mut normals) = (Vec::new(), Vec::new());
+    let mut corners: Vec<(u32, Option<usize>, Option<usize>)> = Vec::new();
+
+    for (number, line) in lines.enumerate() {
+        let line = line.map_err(|e| e.to_string())?;
+        let error = |what: &str| format!("{}:{}: {}", path.display(), number + 1, what);
+        let mut tokens = line.split_whitespace();
+        let numbers = |tokens: std::str::SplitWhitespace| -> Result<Vec<Float>, String> {
+            tokens.map(|t| t.parse::<Float>().map_err(|_| error("invalid number"))).collect()
+        };
+        match tokens.next() {
+            Some("v") => match numbers(tokens)?.as_slice() {
+                [x, y, z, ..] => mesh.positions.push(Vec3::new(*x, *y, *z)),
+                _ => return Err(error("vertex with less than 3 coordinates")),
+            },
+            Some("vt") => match numbers(tokens)?.as_slice() {
+                [u, v, ..] => uv.push(Vec2::new(&[*u, *v])),
+                [u] => uv.push(Vec2::new(&[*u, 0.])),
+                _ => return Err(error("texture coordinate without values")),
+            },
+            Some("vn") => match numbers(tokens)?.as_slice() {
+                [x, y, z, ..] => normals.push(Vec3::new(*x, *y, *z)),
+                _ => return Err(error("normal with less than 3 coordinates")),
+            },
+            Some("f") => {
+                // v, v/vt, v//vn or v/vt/vn, negative indices count from
+                // the end
+                let mut face = Vec::new();
+                for corner in tokens {
+                    let mut parts = corner.split('/');
+                    let index = |part: Option<&str>, count: usize| -> Result<Option<usize>, String> {
+                        match part {
+                            None | Some("") => Ok(None),
+                            Some(part) => {
+                                let i: i64 = part.parse().map_err(|_| error("invalid face index"))?;
+                                let i = if i < 0 { count as i64 + i } else { i - 1 };
+                                if i < 0 || i as usize >= count {
+                                    return Err(error("face index out of range"));
+                                }
+                                Ok(Some(i as usize))
+                            }
+                        }
+                    };
+                    let position = index(parts.next(), mesh.positions.len())?.ok_or(error("face corner without a position"))?;
+                    let texture = index(parts.next(), uv.len())?;
+                    let normal = index(parts.next(), normals.len())?;
+                    corners.push((position as u32, texture, normal));
+                    face.push(position as u32);
+                }
+                if face.len() >= 3 {
+                    mesh.faces.push(face);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let count = mesh.positions.len();
+    if corners.iter().any(|corner| corner.1.is_some()) {
+        mesh.uv = vec![Vec2::from(0.); count];
+    }
+    if corners.iter().any(|corner| corner.2.is_some()) {
+        mesh.normals = vec![Vec3::from(0.); count];
+    }
+    for (position, texture, normal) in corners {
+        if let Some(texture) = texture {
+            mesh.uv[position as usize] = uv[texture];
+        }
+        if let Some(normal) = normal {
+            mesh.normals[position as usize] = normals[normal];
+        }
+    }
+    mesh.check()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    #[test_log::test]
+    #[test]
     fn test_open_ply() {
-        let path = std::env::current_dir().unwrap();
-        println!("The current directory is {}", path.display());
-        let filename = std::path::Path::new("../assets/pbrt4/pbrt-book/geometry/mesh_00001.ply");
-        let result = parse_ply(filename);
+        let filename = Path::new("../assets/pbrt4/pbrt-book/geometry/mesh_00001.ply");
+        let mesh = parse_ply(filename).unwrap();
+        assert_eq!((mesh.positions.len(), mesh.faces.len()), (40000, 20000));
+        assert_eq!((mesh.normals.len(), mesh.uv.len()), (40000, 40000));
+        assert!(mesh.faces.iter().all(|face| face.len() >= 3));
+    }
+
+    #[test]
+    fn test_open_obj() {
+        let path = std::env::temp_dir().join("mesh-loader-test.obj");
+        std::fs::write(
+            &path,
+            "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nv 2 0 0\nvt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\n\
+             f 1/1 2/2 3/3 4/4\nf 2 5 -3\n",
+        )
+        .unwrap();
+        let mesh = parse_obj(&path).unwrap();
+        assert_eq!(mesh.faces, vec![vec![0, 1, 2, 3], vec![1, 4, 2]]);
+        assert_eq!(mesh.uv[2], Vec2::new(&[1., 1.]));
+        assert_eq!(mesh.triangulate(), vec![0, 1, 2, 0, 2, 3, 1, 4, 2]);
+        std::fs::remove_file(&path).unwrap();
     }
 }
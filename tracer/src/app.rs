@@ -3,264 +3,44 @@ use eframe::{
     epaint::ColorImage,
 };
 
-use math::{Float, Vec3};
-use rayon::iter::{IntoParallelRefMutIterator, ParallelIterator};
+use math::{Float, Vec2, Vec3};
 
-use std::{
-    path::{Path, PathBuf},
-};
 use std::{sync::Arc, time::Instant};
 use strum::IntoEnumIterator;
 use strum_macros::{Display, EnumIter};
 
 use crate::{
+    cameras::lens::{ApertureShape, ThinLens},
     core::{
-        film::Film, primitive::Primitive, sampler, scene::Scene, shape::Shape, spectrum::Spectrum,
+        aov::AovType,
+        color_space::ColorSpace,
+        film::Film,
+        filter::{Filter, FilterType},
+        image_pipeline::{quantize, ImagePipeline, ToneMapOperator},
+        imageio::ImageFormat,
+        sampler, scene::Scene, spectrum::Spectrum,
         view::View,
     },
+    denoisers::atrous::AtrousDenoiser,
     integrators::direct_lighting::{FrameBuffer, RenderSettings, SCREEN_HEIGHT, SCREEN_WIDTH},
+    scenes::{raytracing_weekend_scene, SceneOption},
 };
 
-use crate::shapes::{mesh::Mesh, sphere::Sphere};
-
-use crate::materials::{ConstantMaterial, DieletricMaterial, LambertMaterial, MetalMaterial};
-
-use crate::cameras::perspective::PerspectiveCamera;
 use crate::integrators::direct_lighting::DirectLightingIntegrator;
 
-
-fn pbrt4_scene() -> Scene {
-    let camera_position: Vec3 = Vec3::new(0., 5.5, -30.5);
-    let camera_lookat: Vec3 = Vec3::new(0., 0., -1.);
-
-    // Create new camera
-    let cam = PerspectiveCamera::new(SCREEN_WIDTH, SCREEN_HEIGHT, camera_position, camera_lookat);
-    let mut scene = Scene::default();
-    scene.persp_camera = cam;
-
-    let pbrt_filename = "book.pbrt";
-    let pbrt_relative_path = "assets/pbrt4/pbrt-book/";
-    let pbrt_filepath = pbrt_relative_path.to_string() + pbrt_filename;
-    log::info!("Loading scene: {}", &pbrt_filepath);
-    let pbrt_scene = pbrt4::Scene::from_file(&pbrt_filepath).unwrap();
-
-    println!("Global options: {:#?}", pbrt_scene.options);
-
-    if let Some(camera) = pbrt_scene.camera {
-        println!("Camera: {:#?}", camera);
-        // TODO: Extract camera position from pbrt
-        // match camera.params {
-        //     Perspective(c) => {}
-        // }
-        // camera_position = camera.params.
-    }
-
-    if let Some(film) = pbrt_scene.film {
-        println!("Film: {:#?}", film);
-    }
-
-    if let Some(integrator) = pbrt_scene.integrator {
-        println!("Integrator: {:#?}", integrator);
-    }
-
-    if let Some(accelerator) = pbrt_scene.accelerator {
-        println!("Accelerator: {:#?}", accelerator);
-    }
-
-    if let Some(sampler) = pbrt_scene.sampler {
-        println!("Sampler: {:#?}", sampler);
-    }
-
-    println!("World begin");
-
-    for texture in pbrt_scene.textures {
-        println!("Texture: {:#?}", texture);
-    }
-
-    for material in pbrt_scene.materials {
-        println!("Material: {:#?}", material);
-    }
-
-    for light in pbrt_scene.lights {
-        println!("Light: {:#?}", light);
-    }
-
-    for medium in pbrt_scene.mediums {
-        println!("Medium: {:#?}", medium);
-    }
-
-    for shape in pbrt_scene.shapes {
-        println!("Shape: {:#?}", shape);
-        let mut ply_path = PathBuf::from("E:/CODES/rust_raytracer/");
-        ply_path.push(Path::new(pbrt_relative_path));
-        let _ = match shape.params {
-            pbrt4::types::Shape::PlyMesh { filename } => {
-                ply_path.push(Path::new(&filename[1..filename.len() - 1]));
-                let mesh = Mesh::from_ply(&ply_path);
-            }
-            _ => {}
-        };
-    }
-
-    println!("Done");
-
-    scene.environment_light = |ray| -> Spectrum {
-        let t = 0.5 * ray.direction.y + 1.0;
-        let sky_color = (1. - t) * Vec3::new(1., 1., 1.) + t * Vec3::new(0.5, 0.7, 1.);
-        let sky_environment = Spectrum::ColorRGB(sky_color);
-        return sky_environment;
-    };
-
-    return scene;
-}
-
-fn gltf_scene() -> Scene {
-    let camera_position: Vec3 = Vec3::new(15., 2.5, 0.0);
-    // let mut camera_position: Vec3 = Vec3::new(0.,25.5,10.);
-    let camera_lookat: Vec3 = Vec3::new(0., 0., -1.);
-
-    let mut scene = Scene::from("assets/glTF/CesiumMilkTruck/glTF/CesiumMilkTruck.gltf");
-
-    scene.environment_light = |ray| -> Spectrum {
-        let t = 0.5 * ray.direction.y + 1.0;
-        let sky_color = (1. - t) * Vec3::new(1., 1., 1.) + t * Vec3::new(0.5, 0.7, 1.);
-        let sky_environment = Spectrum::ColorRGB(sky_color);
-        return sky_environment;
-    };
-
-    let cam = PerspectiveCamera::new(SCREEN_WIDTH, SCREEN_HEIGHT, camera_position, camera_lookat);
-    scene.persp_camera = cam;
-
-    // Floor
-    scene.add(Primitive::new(
-        Shape::Sphere(Sphere::new(Vec3::new(0., -100.5, -1.), 100.)),
-        Option::Some(Arc::new(ConstantMaterial::new(Spectrum::ColorRGB(
-            Vec3::new(0.2, 0.2, 0.2),
-        )))),
-    ));
-
-    return scene;
-}
-
-fn raytracing_weekend_scene() -> Scene {
-    let camera_position: Vec3 = Vec3::new(0., 0.5, -5.5);
-    let camera_lookat: Vec3 = Vec3::new(0., 0., -1.);
-
-    // Create new camera
-    let cam = PerspectiveCamera::new(SCREEN_WIDTH, SCREEN_HEIGHT, camera_position, camera_lookat);
-    let mut scene = Scene::default();
-    scene.persp_camera = cam;
-
-    scene.environment_light = |ray| -> Spectrum {
-        let t = 0.5 * ray.direction.y + 1.0;
-        //let sky_color = (1. - t) * Vec3::new(1., 1., 1.) + t * Vec3::new(0.2, 0.2, 1.);
-        //let sky_color = (1. - t) * Vec3::new(1., 1., 1.) + t * Vec3::new(0.2, 0.2, 0.2);
-        let sky_color = (1. - t) * Vec3::new(1., 1., 1.) + t * Vec3::new(0.5, 0.7, 1.);
-        let sky_environment = Spectrum::ColorRGB(sky_color);
-        return sky_environment;
-    };
-
-    scene.add(Primitive::new(
-        Shape::Sphere(Sphere::new(Vec3::new(0., 0., -1.), 0.5)),
-        Option::Some(Arc::new(LambertMaterial::new(Spectrum::ColorRGB(
-            Vec3::new(0.5, 0.5, 0.5),
-        )))),
-    ));
-
-    scene.add(Primitive::new(
-        Shape::Sphere(Sphere::new(Vec3::new(1., 0., -1.), 0.5)),
-        Option::Some(Arc::new(MetalMaterial::new(Spectrum::ColorRGB(Vec3::new(
-            0.2, 0.5, 0.5,
-        ))))),
-    ));
-
-    scene.add(Primitive::new(
-        Shape::Sphere(Sphere::new(Vec3::new(-1., 0., -1.), 0.5)),
-        Option::Some(Arc::new(DieletricMaterial::new(Spectrum::ColorRGB(
-            Vec3::new(1.0, 1.0, 1.0),
-        )))),
-    ));
-
-    // Ground
-    scene.add(Primitive::new(
-        Shape::Sphere(Sphere::new(Vec3::new(0., -100.5, -1.), 100.)),
-        Option::Some(Arc::new(LambertMaterial::new(Spectrum::ColorRGB(
-            Vec3::new(0.2, 0.2, 0.2),
-        )))),
-    ));
-
-    return scene;
-}
-
-fn furnace_test() -> Scene {
-    let reveal = true;
-    let camera_position: Vec3 = Vec3::new(0., 5., -15.5);
-    let camera_lookat: Vec3 = Vec3::new(0., 0., 10.);
-
-    // Create new camera
-    let cam = PerspectiveCamera::new(SCREEN_WIDTH, SCREEN_HEIGHT, camera_position, camera_lookat);
-    let mut scene = Scene::default();
-    scene.persp_camera = cam;
-
-    scene.environment_light = |_ray| -> Spectrum {
-        Spectrum::ColorRGB(Vec3 {
-            x: 0.5,
-            y: 0.5,
-            z: 0.5,
-        })
-    };
-
-    scene.add(Primitive::new(
-        Shape::Sphere(Sphere::new(Vec3::zero(), 2.)),
-        Option::Some(Arc::new(ConstantMaterial::new(Spectrum::ColorRGB(Vec3 {
-            x: 1.0,
-            y: 1.0,
-            z: 1.0,
-        })))),
-    ));
-
-    if reveal {
-        scene.add(Primitive::new(
-            Shape::Sphere(Sphere::new(
-                Vec3 {
-                    x: 3.,
-                    y: 0.,
-                    z: -0.5,
-                },
-                1.,
-            )),
-            Option::Some(Arc::new(ConstantMaterial::new(Spectrum::ColorRGB(Vec3 {
-                x: 0.8,
-                y: 0.0,
-                z: 0.0,
-            })))),
-        ));
-
-        scene.add(Primitive::new(
-            Shape::Sphere(Sphere::new(
-                Vec3 {
-                    x: 0.,
-                    y: 3.,
-                    z: -0.5,
-                },
-                1.,
-            )),
-            Option::Some(Arc::new(ConstantMaterial::new(Spectrum::ColorRGB(Vec3 {
-                x: 0.8,
-                y: 0.1,
-                z: 0.02,
-            })))),
-        ));
-    }
-
-    return scene;
-}
-
 // ------------------------------------------------------------
 // Test sampler
 // ------------------------------------------------------------
 struct TestSampler {}
 
+// What the central panel shows
+#[derive(Debug, EnumIter, PartialEq, Clone, Copy, Display)]
+enum DisplayView {
+    Beauty,
+    // Samples taken per pixel, from blue (few) to red (the maximum)
+    SampleCount,
+}
+
 #[derive(Debug, EnumIter, PartialEq, Clone, Copy, Display)]
 enum SamplerTestOption {
     UnitDisk,
@@ -274,11 +54,13 @@ impl TestSampler {
 
         let num_samples = 1000;
         let color = Spectrum::ColorRGB(Vec3::from(1.0));
-        for _i in 0..num_samples {
+        let mut sampler = sampler::Sampler::new(0);
+        for i in 0..num_samples {
             // Return a point ranges from -1 to 1
+            sampler.start_pixel_sample(0, 0, i);
 
             // Uncomment to sample from unit disk
-            let random = sampler::Sampler::random_vec2_0_1();
+            let random = sampler.random_vec2_0_1();
             let mut point = sampler::Sampler::sample_unit_disk_concentric(random);
             //let mut point = sampler.sample_from_pixel( Vec2 {0: 10., 1: 10.}, width, height);
             point.0 = point.x() * width as Float / 4.0 + width as Float / 2.0;
@@ -292,13 +74,6 @@ impl TestSampler {
     }
 }
 
-#[derive(Debug, EnumIter, PartialEq, Clone, Copy, Display)]
-enum SceneOption {
-    Spheres,
-    Truck,
-    FurnaceTest,
-    Pbrt4,
-}
 
 pub struct RustracerApp {
     name: String,
@@ -317,10 +92,24 @@ pub struct RustracerApp {
     dropped_files: Vec<egui::DroppedFile>,
     picked_path: Option<String>,
     scene_option: SceneOption,
+    // Scene option `scene` was built from, lens edits survive until it changes
+    loaded_scene: SceneOption,
     view: View,
     scene: Scene,
     render_settings: RenderSettings,
-    framebuffer: FrameBuffer
+    filter_type: FilterType,
+    framebuffer: FrameBuffer,
+    image_pipeline: ImagePipeline,
+    // AOVs to render and write along the beauty image
+    aovs: Vec<(AovType, bool)>,
+    // Show and write the denoised film instead of the raw one
+    denoise: bool,
+    denoiser: AtrousDenoiser,
+    display_view: DisplayView,
+    // Pipeline, denoise toggle and view the on screen image was last produced with
+    displayed_pipeline: Option<(ImagePipeline, bool, DisplayView)>,
+    // Clicking the image focuses the lens on what is under the cursor
+    click_to_focus: bool,
 }
 
 impl Default for RustracerApp {
@@ -342,10 +131,19 @@ impl Default for RustracerApp {
             dropped_files: Vec::new(),
             picked_path: None,
             scene_option: SceneOption::Spheres,
+            loaded_scene: SceneOption::Spheres,
             view: View::new(SCREEN_WIDTH, SCREEN_HEIGHT),
             scene: raytracing_weekend_scene(),
             render_settings: RenderSettings::default(),
-            framebuffer: FrameBuffer::new(SCREEN_WIDTH, SCREEN_HEIGHT)
+            filter_type: FilterType::Box,
+            framebuffer: FrameBuffer::new(SCREEN_WIDTH, SCREEN_HEIGHT, Filter::default()),
+            image_pipeline: ImagePipeline::default(),
+            aovs: AovType::iter().map(|aov| (aov, false)).collect(),
+            denoise: false,
+            denoiser: AtrousDenoiser::default(),
+            display_view: DisplayView::Beauty,
+            displayed_pipeline: None,
+            click_to_focus: false,
         }
     }
 }
@@ -363,13 +161,77 @@ fn load_image_from_path(
     ))
 }
 
-fn render_frame(view: &View, scene: &Scene, framebuffer: &FrameBuffer, settings: &RenderSettings) -> FrameBuffer {
+impl RustracerApp {
+    // Start accumulating samples from scratch
+    fn start_render(&mut self) {
+        self.rendering = true;
+        self.view = View::new(self.width, self.height);
+        self.render_settings.filter = Filter::from(self.filter_type);
+        self.framebuffer = FrameBuffer::new(self.width, self.height, self.render_settings.filter);
+        self.framebuffer.film.color_space = self.render_settings.working_space;
+        let aovs: Vec<AovType> = self.aovs.iter().filter(|(_, enabled)| *enabled).map(|(aov, _)| *aov).collect();
+        self.framebuffer.film.enable_aovs(&aovs);
+        // Always keep the features so denoising can be toggled live
+        self.framebuffer.film.enable_denoiser_features();
+    }
+
+    fn lens_ui(&mut self, ui: &mut egui::Ui) {
+        let Some(lens) = self.scene.camera.lens() else {
+            return;
+        };
+        let mut edited = lens;
+        let mut pinhole = lens.f_stop.is_infinite();
+        let (mut blades, mut rotation) = match lens.aperture {
+            ApertureShape::Circular => (0, 0.),
+            ApertureShape::Polygonal { blades, rotation } => (blades, rotation),
+        };
+        ui.collapsing("Lens", |ui| {
+            ui.add(
+                egui::Slider::new(&mut edited.focal_length, 8.0..=300.0)
+                    .logarithmic(true)
+                    .text("Focal length (mm)"),
+            );
+            ui.add(egui::Checkbox::new(&mut pinhole, "Pinhole"));
+            if !pinhole {
+                if edited.f_stop.is_infinite() {
+                    edited.f_stop = ThinLens::default().f_stop;
+                }
+                ui.add(egui::Slider::new(&mut edited.f_stop, 0.95..=32.0).logarithmic(true).text("f-stop"));
+                ui.add(
+                    egui::DragValue::new(&mut edited.focus_distance)
+                        .clamp_range(0.01..=1e6)
+                        .speed(0.01)
+                        .prefix("Focus distance: "),
+                );
+                ui.add(egui::Slider::new(&mut blades, 0..=12).text("Aperture blades (0 = circular)"));
+                if blades >= 3 {
+                    ui.add(egui::Slider::new(&mut rotation, 0.0..=360.0).text("Blade rotation"));
+                }
+                ui.add(egui::Checkbox::new(&mut self.click_to_focus, "Click to focus"));
+            }
+        });
+        if pinhole {
+            edited.f_stop = Float::INFINITY;
+        }
+        edited.aperture = if blades >= 3 {
+            ApertureShape::Polygonal { blades, rotation }
+        } else {
+            ApertureShape::Circular
+        };
+        if edited != lens {
+            if let Some(camera) = self.scene.camera.with_lens(edited) {
+                self.scene.camera = camera;
+                self.start_render();
+            }
+        }
+    }
+}
+
+fn render_frame(view: &View, scene: &Scene, framebuffer: &mut FrameBuffer, settings: &RenderSettings) {
     let start = Instant::now();
-    let new_frame = DirectLightingIntegrator::render(&scene, &view, &framebuffer, &settings);
+    DirectLightingIntegrator::render(&scene, &view, framebuffer, &settings);
     //let duration = start.elapsed();
     // log::info!("Render time: {:?}", duration);
-
-    return new_frame;
 }
 
 /// This function initializes the Rustracer application with default settings.
@@ -413,7 +275,7 @@ impl eframe::App for RustracerApp {
                 // Write to film
                 let mut film = Film::new(SCREEN_WIDTH, SCREEN_HEIGHT, "test samplers");
                 film.set_pixels(&pixels);
-                let path = film.write_image();
+                let path = film.write_image(ImageFormat::Png, &self.image_pipeline);
                 log::info!("Image written to: {:?}", film.file_name);
                 self.image_test = Some(Arc::new(
                     load_image_from_path(std::path::Path::new(&path)).unwrap(),
@@ -444,6 +306,16 @@ impl eframe::App for RustracerApp {
                 egui::Slider::new(&mut self.render_settings.sample_per_pixel, 1..=100).text("Samples per pixels"),
             );
 
+            ui.add(egui::DragValue::new(&mut self.render_settings.seed).prefix("Seed: "));
+
+            egui::ComboBox::from_label("Reconstruction filter")
+                .selected_text(format!("{:?}", self.filter_type))
+                .show_ui(ui, |ui| {
+                    for option in FilterType::iter() {
+                        ui.selectable_value(&mut self.filter_type, option, option.to_string());
+                    }
+                });
+
             egui::color_picker::color_edit_button_rgb(ui, &mut self.render_settings.skycolor_tint)
                 .labelled_by(ui.label("Sky color tint").id);
 
@@ -457,54 +329,95 @@ impl eframe::App for RustracerApp {
                 "Write to file",
             ));
 
+            egui::ComboBox::from_label("Output format")
+                .selected_text(format!("{:?}", self.render_settings.output_format))
+                .show_ui(ui, |ui| {
+                    for option in ImageFormat::iter() {
+                        ui.selectable_value(&mut self.render_settings.output_format, option, option.to_string());
+                    }
+                });
+
+            egui::ComboBox::from_label("Working space")
+                .selected_text(self.render_settings.working_space.to_string())
+                .show_ui(ui, |ui| {
+                    for option in ColorSpace::iter() {
+                        ui.selectable_value(&mut self.render_settings.working_space, option, option.to_string());
+                    }
+                });
+
+            ui.collapsing("AOVs", |ui| {
+                for (aov, enabled) in self.aovs.iter_mut() {
+                    ui.checkbox(enabled, aov.to_string());
+                }
+            });
+
+            ui.collapsing("Display", |ui| {
+                ui.add(egui::Slider::new(&mut self.image_pipeline.exposure, -10.0..=10.0).text("Exposure (EV)"));
+                ui.add(
+                    egui::DragValue::new(&mut self.image_pipeline.white_balance)
+                        .clamp_range(0.0..=25000.0)
+                        .speed(10.)
+                        .prefix("White balance (K, 0 = off): "),
+                );
+                egui::ComboBox::from_label("Tone mapping")
+                    .selected_text(format!("{:?}", self.image_pipeline.tone_map))
+                    .show_ui(ui, |ui| {
+                        for option in ToneMapOperator::iter() {
+                            ui.selectable_value(&mut self.image_pipeline.tone_map, option, option.to_string());
+                        }
+                    });
+                if self.image_pipeline.tone_map == ToneMapOperator::ExtendedReinhard {
+                    ui.add(egui::Slider::new(&mut self.image_pipeline.white_point, 0.1..=20.0).text("White point"));
+                }
+                egui::ComboBox::from_label("Output space")
+                    .selected_text(self.image_pipeline.output_space.to_string())
+                    .show_ui(ui, |ui| {
+                        for option in ColorSpace::iter() {
+                            ui.selectable_value(&mut self.image_pipeline.output_space, option, option.to_string());
+                        }
+                    });
+                ui.add(egui::Checkbox::new(&mut self.image_pipeline.dither, "Dither"));
+                ui.add(egui::Checkbox::new(&mut self.denoise, "Denoise"));
+                egui::ComboBox::from_label("View")
+                    .selected_text(format!("{:?}", self.display_view))
+                    .show_ui(ui, |ui| {
+                        for option in DisplayView::iter() {
+                            ui.selectable_value(&mut self.display_view, option, option.to_string());
+                        }
+                    });
+            });
+
+            ui.collapsing("Adaptive sampling", |ui| {
+                ui.add(egui::Checkbox::new(&mut self.render_settings.adaptive_sampling, "Enabled"));
+                ui.add(
+                    egui::Slider::new(&mut self.render_settings.noise_threshold, 0.001..=0.2)
+                        .logarithmic(true)
+                        .text("Noise threshold"),
+                );
+                ui.add(egui::Slider::new(&mut self.render_settings.min_samples, 2..=64).text("Min samples"));
+            });
+
+            self.lens_ui(ui);
+
             if ui.add(egui::Button::new("Render")).clicked() {
                 // Toggle rendering
-                self.rendering = !self.rendering;
-                if self.rendering == true {
-                    // Do we need to reinitialize the view here?
-                    self.view = View::new(self.width, self.height);
-                    self.scene = match self.scene_option {
-                        SceneOption::Spheres => raytracing_weekend_scene(),
-                        SceneOption::Truck => gltf_scene(),
-                        SceneOption::FurnaceTest => furnace_test(),
-                        SceneOption::Pbrt4 => pbrt4_scene(),
-                    };
-                    self.framebuffer = FrameBuffer::new(self.width, self.height);
-                } 
+                if self.rendering {
+                    self.rendering = false;
+                } else {
+                    if self.scene_option != self.loaded_scene {
+                        self.scene = self.scene_option.create_scene();
+                        self.loaded_scene = self.scene_option;
+                    }
+                    self.start_render();
+                }
             }
 
             if self.rendering {
                 self.framebuffer.current_sample += 1;
 
                 // RENDER!
-                let new_frame = render_frame(&self.view, &self.scene, &self.framebuffer, &self.render_settings);
-                self.framebuffer = new_frame;
-                
-                // Gamma correction
-                let mut gamma_corrected_spectrum = self.framebuffer.spectrums.clone();
-                gamma_corrected_spectrum.par_iter_mut().for_each(|spectrum| {
-                    let Spectrum::ColorRGB(color) = *spectrum;
-                    *spectrum = Spectrum::ColorRGB(Vec3::sqrt(color));
-                });
-
-                // Convert Vec<Spectrum> to Vec<u8> in RGBA format
-                let mut rgba_pixels = Vec::with_capacity((SCREEN_WIDTH * SCREEN_HEIGHT * 4) as usize);
-                for spectrum in &gamma_corrected_spectrum {
-                    let rgb = spectrum.to_rgb(); // Assumes to_rgb() -> Vec3 in [0,1]
-
-                    let r = (rgb.x.clamp(0.0, 1.0) * 255.0) as u8;
-                    let g = (rgb.y.clamp(0.0, 1.0) * 255.0) as u8;
-                    let b = (rgb.z.clamp(0.0, 1.0) * 255.0) as u8;
-                    rgba_pixels.push(r);
-                    rgba_pixels.push(g);
-                    rgba_pixels.push(b);
-                    rgba_pixels.push(255); // Alpha channel
-                }
-                let color_image = eframe::epaint::ColorImage::from_rgba_unmultiplied(
-                    [SCREEN_WIDTH as usize, SCREEN_HEIGHT as usize],
-                    &rgba_pixels,
-                );
-                self.image = Some(Arc::new(color_image));
+                render_frame(&self.view, &self.scene, &mut self.framebuffer, &self.render_settings);
+                self.displayed_pipeline = None;
 
                 // Uncomment to load image from file instead of rendering
                 //self.image = Some(Arc::new(
@@ -512,27 +425,90 @@ impl eframe::App for RustracerApp {
                 //));
 
                 if self.render_settings.write_to_file {
-                    // Write to film
-                    let mut film = Film::new(SCREEN_WIDTH, SCREEN_HEIGHT, "image");
-                    film.set_pixels(&gamma_corrected_spectrum);
-                    film.file_name = format!("render_{:?}_spp{}_{}x{}.png", self.scene_option, self.render_settings.sample_per_pixel, self.width, self.height);
-                    let path = film.write_image();
+                    // Write the linear film, the writer applies the display transform for LDR formats
+                    let mut film = if self.denoise {
+                        self.framebuffer.film.denoised(&self.denoiser)
+                    } else {
+                        self.framebuffer.film.clone()
+                    };
+                    film.file_name = match &self.scene.output_filename {
+                        Some(file_name) => file_name.clone(),
+                        None => format!("render_{:?}_spp{}_{}x{}", self.scene_option, self.render_settings.sample_per_pixel, self.width, self.height),
+                    };
+                    let path = film.write_image(self.render_settings.output_format, &self.image_pipeline);
                     log::info!("Image written to: {:?}", path);
                 }
 
                 // End rendering if we have accumulated enough samples or all the rays have terminated
-                if self.framebuffer.current_sample >= self.render_settings.sample_per_pixel {
+                if self.framebuffer.current_sample >= self.render_settings.sample_per_pixel
+                    || self.framebuffer.all_converged()
+                {
                     self.rendering = false;
                 }
 
             }
 
+            // Redisplay when a new pass landed or the display settings changed,
+            // the film itself is left untouched
+            let display_state = (self.image_pipeline, self.denoise, self.display_view);
+            if self.framebuffer.current_sample > 0 && self.displayed_pipeline != Some(display_state) {
+                self.displayed_pipeline = Some(display_state);
+                let display = match self.display_view {
+                    DisplayView::Beauty => {
+                        let pixels = if self.denoise {
+                            self.framebuffer.film.denoised(&self.denoiser).get_pixels()
+                        } else {
+                            self.framebuffer.film.get_pixels()
+                        };
+                        self.image_pipeline.process(&pixels, self.width, self.framebuffer.film.color_space)
+                    }
+                    DisplayView::SampleCount => self
+                        .framebuffer
+                        .film
+                        .sample_count_heatmap(self.render_settings.sample_per_pixel),
+                };
+
+                // Convert to u8 in RGBA format
+                let mut rgba_pixels = Vec::with_capacity((self.width * self.height * 4) as usize);
+                for rgb in &display {
+                    rgba_pixels.push(quantize(rgb[0]));
+                    rgba_pixels.push(quantize(rgb[1]));
+                    rgba_pixels.push(quantize(rgb[2]));
+                    rgba_pixels.push(255); // Alpha channel
+                }
+                let color_image = eframe::epaint::ColorImage::from_rgba_unmultiplied(
+                    [self.width as usize, self.height as usize],
+                    &rgba_pixels,
+                );
+                self.image = Some(Arc::new(color_image));
+            }
+
             if let Some(image) = self.image.take() {
                 self.texture = Some(ctx.load_texture("image", image, Default::default()));
             }
 
+            let mut focus_uv = None;
             if let Some(texture) = self.texture.as_ref() {
-                ui.image((texture.id(), texture.size_vec2()));
+                let response =
+                    ui.add(egui::Image::new((texture.id(), texture.size_vec2())).sense(egui::Sense::click()));
+                if self.click_to_focus && response.clicked() {
+                    if let Some(position) = response.interact_pointer_pos() {
+                        let rect = response.rect;
+                        focus_uv = Some(Vec2::new(&[
+                            (position.x - rect.min.x) / rect.width(),
+                            1. - (position.y - rect.min.y) / rect.height(),
+                        ]));
+                    }
+                }
+            }
+            if let Some(uv) = focus_uv {
+                match self.scene.autofocus(&uv) {
+                    Some(distance) => {
+                        log::info!("Focused at {} units", distance);
+                        self.start_render();
+                    }
+                    None => log::info!("Nothing to focus on"),
+                }
             }
 
             ctx.request_repaint_after(std::time::Duration::from_millis(16));
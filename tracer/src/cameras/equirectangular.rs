@@ -0,0 +1,34 @@
+use std::f32::consts::PI;
+
+use math::{Vec2, Vec3};
+
+use crate::cameras::{Camera, CameraFrame};
+use crate::core::{ray::Ray, sampler::Sampler};
+
+// 360 degree panorama in the latitude-longitude layout used by VR viewers
+// and environment maps. u covers the longitude with the view direction in
+// the middle of the image, v the latitude from straight down to straight up.
+// The film should be twice as wide as it is high.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct EquirectangularCamera {
+    frame: CameraFrame,
+}
+
+impl EquirectangularCamera {
+    pub fn new(frame: CameraFrame) -> Self {
+        Self { frame }
+    }
+}
+
+impl Camera for EquirectangularCamera {
+    fn generate_ray(&self, uv: &Vec2, sampler: &mut Sampler) -> Option<Ray> {
+        let longitude = 2. * PI * (uv.0 - 0.5);
+        let latitude = PI * (uv.1 - 0.5);
+        let direction = Vec3::new(
+            latitude.cos() * longitude.sin(),
+            latitude.sin(),
+            latitude.cos() * longitude.cos(),
+        );
+        Some(Ray::new(self.frame.eye, self.frame.to_world(direction)).at_time(self.frame.sample_time(sampler)))
+    }
+}
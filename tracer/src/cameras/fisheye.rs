@@ -0,0 +1,47 @@
+use math::{Float, Vec2, Vec3};
+
+use crate::cameras::{Camera, CameraFrame};
+use crate::core::{ray::Ray, sampler::Sampler};
+
+// Equidistant fisheye: the angle from the view direction grows linearly with
+// the distance from the center of the image. The image circle is inscribed
+// in the shorter side of the film, the film outside of it stays black.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct FisheyeCamera {
+    frame: CameraFrame,
+    // Field of view across the image circle, in degrees, up to 360
+    fov: Float,
+    // Film extent relative to the image circle radius
+    half_width: Float,
+    half_height: Float,
+}
+
+impl FisheyeCamera {
+    pub fn new(width: u32, height: u32, frame: CameraFrame, fov: Float) -> Self {
+        let aspect = width as Float / height as Float;
+        let (half_width, half_height) = if aspect > 1. { (aspect, 1.) } else { (1., 1. / aspect) };
+        Self {
+            frame,
+            fov,
+            half_width,
+            half_height,
+        }
+    }
+}
+
+impl Camera for FisheyeCamera {
+    fn generate_ray(&self, uv: &Vec2, sampler: &mut Sampler) -> Option<Ray> {
+        let time = self.frame.sample_time(sampler);
+        let x = (2. * uv.0 - 1.) * self.half_width;
+        let y = (2. * uv.1 - 1.) * self.half_height;
+        let r = (x * x + y * y).sqrt();
+        if r > 1. {
+            return None;
+        }
+
+        let theta = r * 0.5 * self.fov.to_radians();
+        let phi = y.atan2(x);
+        let direction = Vec3::new(theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos());
+        Some(Ray::new(self.frame.eye, self.frame.to_world(direction)).at_time(time))
+    }
+}
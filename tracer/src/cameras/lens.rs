@@ -0,0 +1,128 @@
+use std::f32::consts::PI;
+
+use math::{Float, Vec2};
+
+use crate::core::sampler::Sampler;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ApertureShape {
+    Circular,
+    // Regular polygon with a corner per blade, rotated by `rotation` degrees
+    Polygonal { blades: u32, rotation: Float },
+}
+
+impl ApertureShape {
+    // Point on the aperture of radius 1 from a uniform sample of [0, 1]^2.
+    // Both mappings are continuous so stratified samples stay stratified.
+    pub fn sample(&self, u: Vec2) -> Vec2 {
+        match *self {
+            ApertureShape::Polygonal { blades, rotation } if blades >= 3 => {
+                // Pick the triangle between the center and one edge, then a
+                // uniform point in it
+                let n = blades as Float;
+                let blade = ((u.0 * n) as u32).min(blades - 1);
+                let u0 = u.0 * n - blade as Float;
+                let corner = |k: u32| {
+                    let angle = rotation.to_radians() + 2. * PI * k as Float / n;
+                    Vec2::new(&[angle.cos(), angle.sin()])
+                };
+                let s = u0.sqrt();
+                s * (1. - u.1) * corner(blade) + s * u.1 * corner(blade + 1)
+            }
+            _ => Sampler::sample_unit_disk_concentric(u),
+        }
+    }
+}
+
+// Thin lens described like a real camera. Lengths on the camera side are in
+// millimeters, the focus distance is in scene units, which are taken to be
+// meters.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ThinLens {
+    pub sensor_width: Float,
+    pub sensor_height: Float,
+    pub focal_length: Float,
+    // Infinity makes a pinhole
+    pub f_stop: Float,
+    pub focus_distance: Float,
+    pub aperture: ApertureShape,
+}
+
+impl Default for ThinLens {
+    // 28mm f/2.8 on a full frame sensor
+    fn default() -> Self {
+        Self {
+            sensor_width: 36.,
+            sensor_height: 24.,
+            focal_length: 28.,
+            f_stop: 2.8,
+            focus_distance: 10.,
+            aperture: ApertureShape::Circular,
+        }
+    }
+}
+
+impl ThinLens {
+    pub fn aperture_radius(&self) -> Float {
+        0.5 * self.focal_length / self.f_stop / 1000.
+    }
+
+    // Tangents of the half field of view across the film width and height.
+    // The sensor is cropped to the aspect ratio of the film, so its width
+    // covers a wider film and its height a taller one.
+    pub fn field_of_view(&self, width: u32, height: u32) -> (Float, Float) {
+        let film_aspect = width as Float / height as Float;
+        if film_aspect >= self.sensor_width / self.sensor_height {
+            let tan_half_width = 0.5 * self.sensor_width / self.focal_length;
+            (tan_half_width, tan_half_width / film_aspect)
+        } else {
+            let tan_half_height = 0.5 * self.sensor_height / self.focal_length;
+            (tan_half_height * film_aspect, tan_half_height)
+        }
+    }
+
+    // Set the focal length giving `fov` degrees across the shorter side of
+    // the film, the way pbrt specifies perspective cameras
+    pub fn with_field_of_view(mut self, fov: Float, width: u32, height: u32) -> Self {
+        self.focal_length = 1.;
+        let (tan_half_width, tan_half_height) = self.field_of_view(width, height);
+        self.focal_length = tan_half_width.min(tan_half_height) / (0.5 * fov.to_radians()).tan();
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_aperture_samples_stay_inside() {
+        let shapes = [
+            ApertureShape::Circular,
+            ApertureShape::Polygonal { blades: 5, rotation: 18. },
+        ];
+        for shape in shapes {
+            let mut sum = Vec2::from(0.);
+            // A multiple of the blade count, so every blade gets as many samples
+            let n = 60;
+            for i in 0..n {
+                for j in 0..n {
+                    let u = Vec2::new(&[(i as Float + 0.5) / n as Float, (j as Float + 0.5) / n as Float]);
+                    let p = shape.sample(u);
+                    assert!(p.length() <= 1. + 1e-5);
+                    sum += p;
+                }
+            }
+            // Both shapes are centered on the optical axis
+            assert!(sum.length() / ((n * n) as Float) < 1e-2);
+        }
+    }
+
+    #[test]
+    fn test_field_of_view() {
+        let lens = ThinLens::default().with_field_of_view(40., 1280, 720);
+        let (tan_half_width, tan_half_height) = lens.field_of_view(1280, 720);
+        assert!((tan_half_height - (20. as Float).to_radians().tan()).abs() < 1e-5);
+        assert!((tan_half_width / tan_half_height - 1280. / 720.).abs() < 1e-4);
+    }
+}
@@ -1 +1,175 @@
+pub mod equirectangular;
+pub mod fisheye;
+pub mod lens;
+pub mod orthographic;
 pub mod perspective;
+
+use core::fmt::Debug;
+use std::sync::Arc;
+
+use math::{Float, Mat4, Vec2, Vec3};
+
+use crate::cameras::lens::ThinLens;
+use crate::core::{ray::Ray, ray::RayDifferential, sampler::Sampler};
+
+// Generates the rays leaving the camera through film samples. `uv` is the
+// position of the sample on the film, in [0, 1]^2 with v going up. Cameras
+// that don't cover the whole film, like the image circle of a fisheye, return
+// None outside of it.
+pub trait Camera: Debug + Send + Sync {
+    fn generate_ray(&self, uv: &Vec2, sampler: &mut Sampler) -> Option<Ray>;
+
+    // Ray with the rays through the film a pixel of size `pixel` over, with
+    // the same lens and time samples. Where the next pixel is off the film
+    // the one before it gives the offset instead.
+    fn generate_ray_differential(&self, uv: &Vec2, pixel: Vec2, sampler: &mut Sampler) -> Option<Ray> {
+        let start = *sampler;
+        let ray = self.generate_ray(uv, sampler)?;
+        let offset = |du: Float, dv: Float| {
+            let shifted = |sign: Float| {
+                let mut sampler = start;
+                self.generate_ray(&Vec2::new(&[uv.0 + sign * du, uv.1 + sign * dv]), &mut sampler)
+            };
+            match shifted(1.) {
+                Some(next) => Some((next.origin, next.direction)),
+                None => shifted(-1.).map(|previous| {
+                    (2. * ray.origin - previous.origin, 2. * ray.direction - previous.direction)
+                }),
+            }
+        };
+        match (offset(pixel.0, 0.), offset(0., pixel.1)) {
+            (Some((rx_origin, rx_direction)), Some((ry_origin, ry_direction))) => Some(ray.with_differential(
+                RayDifferential { rx_origin, rx_direction, ry_origin, ry_direction },
+            )),
+            _ => Some(ray),
+        }
+    }
+
+    // Lens of the cameras that have depth of field
+    fn lens(&self) -> Option<ThinLens> {
+        None
+    }
+
+    // Same camera seen through another lens, None for cameras without one
+    fn with_lens(&self, _lens: ThinLens) -> Option<Arc<dyn Camera>> {
+        None
+    }
+}
+
+// Times the shutter opens and closes. Every ray gets a time in between, moving
+// objects are blurred over the interval.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Shutter {
+    pub open: Float,
+    pub close: Float,
+}
+
+impl Default for Shutter {
+    // pbrt's default, a whole frame of motion
+    fn default() -> Self {
+        Self { open: 0., close: 1. }
+    }
+}
+
+impl Shutter {
+    pub fn sample(&self, u: Float) -> Float {
+        self.open + u * (self.close - self.open)
+    }
+}
+
+// Position and orientation of a camera, and when it looks. It looks down
+// `forward`, with `right` and `up` spanning the film plane. Like pbrt the
+// frame is left handed, `right` is `up` x `forward`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct CameraFrame {
+    pub eye: Vec3,
+    pub forward: Vec3,
+    pub right: Vec3,
+    pub up: Vec3,
+    pub shutter: Shutter,
+}
+
+impl CameraFrame {
+    pub fn look_at(eye: Vec3, look_at: Vec3) -> Self {
+        const WORLD_UP: Vec3 = Vec3 {
+            x: 0.,
+            y: 1.,
+            z: 0.,
+        };
+        let forward = (look_at - eye).normalize();
+        let right = Vec3::cross(WORLD_UP, forward).normalize();
+        let up = Vec3::cross(forward, right);
+        Self {
+            eye,
+            forward,
+            right,
+            up,
+            shutter: Shutter::default(),
+        }
+    }
+
+    // Frame of a pbrt camera, whose camera space looks down +z with +y up
+    pub fn from_matrix(world_from_camera: &Mat4) -> Self {
+        Self {
+            eye: *world_from_camera * Vec3::zero(),
+            forward: world_from_camera.transform_vector(Vec3::new(0., 0., 1.)).normalize(),
+            right: world_from_camera.transform_vector(Vec3::new(1., 0., 0.)).normalize(),
+            up: world_from_camera.transform_vector(Vec3::new(0., 1., 0.)).normalize(),
+            shutter: Shutter::default(),
+        }
+    }
+
+    // Time of a new ray, always draws a sample
+    pub fn sample_time(&self, sampler: &mut Sampler) -> Float {
+        self.shutter.sample(sampler.random_0_1())
+    }
+
+    // Direction given in camera space (right, up, forward) to world space
+    pub fn to_world(&self, direction: Vec3) -> Vec3 {
+        self.right * direction.x + self.up * direction.y + self.forward * direction.z
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use math::Float;
+
+    use super::{
+        equirectangular::EquirectangularCamera, fisheye::FisheyeCamera,
+        orthographic::OrthographicCamera, *,
+    };
+
+    fn assert_near(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 1e-4, "{:?} != {:?}", a, b);
+    }
+
+    fn uv(u: Float, v: Float) -> Vec2 {
+        Vec2::new(&[u, v])
+    }
+
+    #[test]
+    fn test_camera_rays() {
+        let frame = CameraFrame::look_at(Vec3::new(0., 0., -5.), Vec3::zero());
+        let forward = Vec3::new(0., 0., 1.);
+        let mut sampler = Sampler::new(0);
+
+        let orthographic = OrthographicCamera::new(frame, [-2., 2., -1., 1.]);
+        let ray = orthographic.generate_ray(&uv(1., 0.5), &mut sampler).unwrap();
+        assert_near(ray.origin, Vec3::new(2., 0., -5.));
+        assert_near(ray.direction, forward);
+
+        // 180 degree fisheye on a square film, the edge of the image circle
+        // looks sideways and the corners are outside of it
+        let fisheye = FisheyeCamera::new(100, 100, frame, 180.);
+        assert_near(fisheye.generate_ray(&uv(0.5, 0.5), &mut sampler).unwrap().direction, forward);
+        assert_near(fisheye.generate_ray(&uv(1., 0.5), &mut sampler).unwrap().direction, frame.right);
+        assert_near(fisheye.generate_ray(&uv(0.5, 1.), &mut sampler).unwrap().direction, frame.up);
+        assert!(fisheye.generate_ray(&uv(1., 1.), &mut sampler).is_none());
+
+        let panorama = EquirectangularCamera::new(frame);
+        assert_near(panorama.generate_ray(&uv(0.5, 0.5), &mut sampler).unwrap().direction, forward);
+        assert_near(panorama.generate_ray(&uv(0.75, 0.5), &mut sampler).unwrap().direction, frame.right);
+        assert_near(panorama.generate_ray(&uv(0., 0.5), &mut sampler).unwrap().direction, -forward);
+        assert_near(panorama.generate_ray(&uv(0.3, 1.), &mut sampler).unwrap().direction, frame.up);
+    }
+}
@@ -0,0 +1,46 @@
+use math::{Float, Vec2};
+
+use crate::cameras::{Camera, CameraFrame};
+use crate::core::{ray::Ray, sampler::Sampler};
+
+// Parallel projection, for elevations and plans where lines that are
+// parallel in the scene must stay parallel in the image.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct OrthographicCamera {
+    frame: CameraFrame,
+    // Extent of the film along right and up, [x_min, x_max, y_min, y_max]
+    screen_window: [Float; 4],
+}
+
+impl OrthographicCamera {
+    pub fn new(frame: CameraFrame, screen_window: [Float; 4]) -> Self {
+        Self {
+            frame,
+            screen_window,
+        }
+    }
+
+    // Window spanning [-half_extent, half_extent] along the shorter side of
+    // the film, pbrt's default with a half extent of 1
+    pub fn fit_screen_window(width: u32, height: u32, half_extent: Float) -> [Float; 4] {
+        let aspect = width as Float / height as Float;
+        if aspect > 1. {
+            [-aspect * half_extent, aspect * half_extent, -half_extent, half_extent]
+        } else {
+            [-half_extent, half_extent, -half_extent / aspect, half_extent / aspect]
+        }
+    }
+}
+
+impl Camera for OrthographicCamera {
+    fn generate_ray(&self, uv: &Vec2, sampler: &mut Sampler) -> Option<Ray> {
+        let [x_min, x_max, y_min, y_max] = self.screen_window;
+        let x = x_min + uv.0 * (x_max - x_min);
+        let y = y_min + uv.1 * (y_max - y_min);
+        Some(Ray::new(
+            self.frame.eye + self.frame.right * x + self.frame.up * y,
+            self.frame.forward,
+        )
+        .at_time(self.frame.sample_time(sampler)))
+    }
+}
@@ -1,101 +1,94 @@
-use math::{Float, Vec2, Vec3};
+use std::sync::Arc;
 
+use math::{Vec2, Vec3};
+
+use crate::cameras::{lens::ThinLens, Camera, CameraFrame};
 use crate::core::{ray::Ray, sampler::Sampler};
 
+// Perspective camera with a thin lens. Points on the plane `focus_distance`
+// away are sharp, the rest is blurred by the aperture.
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub struct PerspectiveCamera {
     width: u32,
     height: u32,
-    eye: Vec3,
-    look_at: Vec3,
-    vfov: Float,
-    aspect: Float,
-    aperture: Float,
-    focus_dist: Float,
+    frame: CameraFrame,
+    lens: ThinLens,
 
+    // Film mapped on the plane in focus
     lower_left: Vec3,
     horizontal: Vec3,
     vertical: Vec3,
-    up: Vec3,
-    forward: Vec3,
-    right: Vec3,
 }
 
 impl Default for PerspectiveCamera {
     fn default() -> Self {
-        Self {
-            width: 100,
-            height: 100,
-            eye: Vec3::zero(),
-            look_at: Vec3::zero(),
-            vfov: 40.,
-            aspect: 1.,
-            aperture: 0.02,
-            focus_dist: 0.0,
-            forward: Vec3::zero(),
-            right: Vec3::zero(),
-            up: Vec3::zero(),
-            lower_left: Vec3::zero(),
-            horizontal: Vec3::zero(),
-            vertical: Vec3::zero(),
-        }
+        PerspectiveCamera::new(
+            100,
+            100,
+            CameraFrame::look_at(Vec3::zero(), Vec3::new(0., 0., 1.)),
+            ThinLens::default(),
+        )
     }
 }
 
 impl PerspectiveCamera {
-    pub fn new(width: u32, height: u32, eye: Vec3, look_at: Vec3) -> Self {
-        let eye = eye;
-        let focus_dist = (eye - look_at).length();
-        let aperture = 0.02;
-        let vfov = 40.;
-        let aspect = width as f32 / height as f32;
-
-        let theta = vfov * std::f32::consts::PI / 180.;
-        let half_height = (theta * 0.5).tan();
-        let half_width = half_height * aspect;
-
-        const WORLD_UP: Vec3 = Vec3 {
-            x: 0.,
-            y: 1.,
-            z: 0.,
-        };
-        let forward = (look_at - eye).normalize();
-        let right = Vec3::cross(WORLD_UP, forward);
-        let up = Vec3::cross(forward, right);
+    pub fn new(width: u32, height: u32, frame: CameraFrame, lens: ThinLens) -> Self {
+        let (tan_half_width, tan_half_height) = lens.field_of_view(width, height);
+        let focus_dist = lens.focus_distance;
+        let CameraFrame {
+            eye,
+            forward,
+            right,
+            up,
+            ..
+        } = frame;
 
-        let lower_left = eye - half_width * focus_dist * right - half_height * focus_dist * up
+        let lower_left = eye - tan_half_width * focus_dist * right - tan_half_height * focus_dist * up
             + focus_dist * forward;
-        let horizontal = 2. * half_width * focus_dist * right;
-        let vertical = 2. * half_height * focus_dist * up;
+        let horizontal = 2. * tan_half_width * focus_dist * right;
+        let vertical = 2. * tan_half_height * focus_dist * up;
 
         Self {
             width,
             height,
-            eye: eye,
-            look_at: look_at,
-            vfov: vfov,
-            aspect: aspect,
-            aperture: aperture,
-            focus_dist: focus_dist,
-            forward: forward,
-            right: right,
-            up: up,
-            lower_left: lower_left,
-            horizontal: horizontal,
-            vertical: vertical,
+            frame,
+            lens,
+            lower_left,
+            horizontal,
+            vertical,
         }
     }
 
-    pub fn set_position(&mut self, position: &Vec3) {
-        self.eye = position.clone();
+    // Camera at `eye` focused on `look_at`
+    pub fn look_at(width: u32, height: u32, eye: Vec3, look_at: Vec3, lens: ThinLens) -> Self {
+        let lens = ThinLens {
+            focus_distance: (look_at - eye).length(),
+            ..lens
+        };
+        PerspectiveCamera::new(width, height, CameraFrame::look_at(eye, look_at), lens)
     }
+}
 
-    pub fn get_ray(&self, uv: &Vec2) -> Ray {
-        let rp: Vec2 = self.aperture * Sampler::sample_unit_disk();
-        let offset: Vec3 = self.right * rp.0 + self.up * rp.1;
-        Ray::new(
-            self.eye + offset,
-            self.lower_left + uv.0 * self.horizontal + uv.1 * self.vertical - self.eye - offset,
+impl Camera for PerspectiveCamera {
+    fn generate_ray(&self, uv: &Vec2, sampler: &mut Sampler) -> Option<Ray> {
+        // Always draw the lens sample so the dimensions used after it don't
+        // depend on the aperture
+        let u = sampler.random_vec2_0_1();
+        let rp: Vec2 = self.lens.aperture_radius() * self.lens.aperture.sample(u);
+        let offset: Vec3 = self.frame.right * rp.0 + self.frame.up * rp.1;
+        let eye = self.frame.eye;
+        Some(Ray::new(
+            eye + offset,
+            self.lower_left + uv.0 * self.horizontal + uv.1 * self.vertical - eye - offset,
         )
+        .at_time(self.frame.sample_time(sampler)))
+    }
+
+    fn lens(&self) -> Option<ThinLens> {
+        Some(self.lens)
+    }
+
+    fn with_lens(&self, lens: ThinLens) -> Option<Arc<dyn Camera>> {
+        Some(Arc::new(PerspectiveCamera::new(self.width, self.height, self.frame, lens)))
     }
 }
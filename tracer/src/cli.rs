@@ -0,0 +1,289 @@
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Instant;
+
+use strum::IntoEnumIterator;
+
+use math::Float;
+
+use crate::core::{
+    aov::AovType, film::Film, filter::{Filter, FilterType}, image_pipeline::{ImagePipeline, ToneMapOperator},
+    scene::Scene, view::View,
+};
+use crate::denoisers::atrous::AtrousDenoiser;
+use crate::integrators::direct_lighting::{
+    DirectLightingIntegrator, FrameBuffer, RenderSettings, SCREEN_HEIGHT, SCREEN_WIDTH,
+};
+use crate::scenes::{gltf_file_scene, pbrt_file_scene, SceneOption};
+use crate::shapes::skinning::SkinningMethod;
+
+pub const USAGE: &str = "\
+Render a scene without opening the UI.
+
+Usage: tracer [options]
+
+Options:
+  --scene <name>        spheres, truck, furnacetest, pbrt4, shapes, sdf, csg,
+                        media or smoke
+                        (default: spheres)
+  --scene-file <file>   pbrt-v4 or glTF scene to render instead of a built-in one
+  --spp <n>             samples per pixel (default: 5)
+  --seed <n>            seed of the random sequences (default: 0)
+  --filter <name>       box, tent, gaussian, mitchell or lanczossinc (default: box)
+  --output <file>       output file, its extension picks the format
+  --format <name>       png, ppm, exrhalf, exrfloat, hdr or pfm, when --output has none
+  --aov <names>         comma separated AOVs to write, e.g. depth,normal,albedo
+  --exposure <ev>       exposure compensation in stops, for 8-bit output
+  --tonemap <name>      clamp, reinhard, extendedreinhard, aces, agx or hable
+  --adaptive            stop sampling pixels once they are below the noise threshold
+  --noise-threshold <e> relative error target of adaptive sampling (default: 0.02)
+  --min-spp <n>         samples taken before a pixel may stop (default: 8)
+  --denoise             write the denoised image
+  --single-thread       render on one thread
+  --spectral            trace sampled wavelengths instead of RGB
+  --working-space <name> srgb, acescg, rec2020 or p3, to compute light in (default: srgb)
+  --output-space <name> srgb, acescg, rec2020 or p3, of the written images (default: srgb)
+  --frames <a>-<b>      render frames a to b of the animation as output/frame_0001.png, ...
+  --fps <n>             frames per second of the animation (default: 24)
+  --skinning <name>     linearblend or dualquaternion, for skinned glTF meshes (default: linearblend)
+  --help                print this message
+";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CliOptions {
+    pub scene: SceneOption,
+    pub scene_file: Option<String>,
+    pub render_settings: RenderSettings,
+    pub filter_type: FilterType,
+    pub output: Option<String>,
+    pub aovs: Vec<AovType>,
+    pub image_pipeline: ImagePipeline,
+    pub denoise: bool,
+    // First and last frame of a sequence, counted from 1
+    pub frames: Option<(u32, u32)>,
+    pub fps: Float,
+    pub skinning: SkinningMethod,
+}
+
+impl Default for CliOptions {
+    fn default() -> Self {
+        Self {
+            scene: SceneOption::Spheres,
+            scene_file: None,
+            render_settings: RenderSettings::default(),
+            filter_type: FilterType::Box,
+            output: None,
+            aovs: Vec::new(),
+            image_pipeline: ImagePipeline::default(),
+            denoise: false,
+            frames: None,
+            fps: 24.,
+            skinning: SkinningMethod::default(),
+        }
+    }
+}
+
+fn parse_value<T: FromStr>(flag: &str, value: Option<&String>) -> Result<T, String> {
+    let value = value.ok_or(format!("missing value for {}", flag))?;
+    T::from_str(value).map_err(|_| format!("invalid value '{}' for {}", value, flag))
+}
+
+fn parse_frames(flag: &str, value: Option<&String>) -> Result<(u32, u32), String> {
+    let range: String = parse_value(flag, value)?;
+    let invalid = || format!("invalid frame range '{}' for {}", range, flag);
+    let (first, last) = match range.split_once('-') {
+        Some((first, last)) => (first.parse().map_err(|_| invalid())?, last.parse().map_err(|_| invalid())?),
+        None => {
+            let frame = range.parse().map_err(|_| invalid())?;
+            (frame, frame)
+        }
+    };
+    if first == 0 || first > last {
+        return Err(invalid());
+    }
+    Ok((first, last))
+}
+
+fn parse_aov(name: &str) -> Result<AovType, String> {
+    AovType::iter()
+        .find(|aov| aov.name() == name)
+        .ok_or(format!("unknown AOV '{}'", name))
+}
+
+// Parse the arguments following the program name. Returns None when the
+// usage was asked for.
+pub fn parse_args(args: &[String]) -> Result<Option<CliOptions>, String> {
+    let mut options = CliOptions::default();
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        match flag.as_str() {
+            "--scene" => options.scene = parse_value(flag, args.next())?,
+            "--scene-file" => options.scene_file = Some(parse_value(flag, args.next())?),
+            "--spp" => options.render_settings.sample_per_pixel = parse_value(flag, args.next())?,
+            "--seed" => options.render_settings.seed = parse_value(flag, args.next())?,
+            "--filter" => options.filter_type = parse_value(flag, args.next())?,
+            "--output" => options.output = Some(parse_value(flag, args.next())?),
+            "--format" => options.render_settings.output_format = parse_value(flag, args.next())?,
+            "--aov" => {
+                let names: String = parse_value(flag, args.next())?;
+                for name in names.split(',') {
+                    options.aovs.push(parse_aov(name.trim())?);
+                }
+            }
+            "--exposure" => options.image_pipeline.exposure = parse_value(flag, args.next())?,
+            "--tonemap" => {
+                options.image_pipeline.tone_map = parse_value::<ToneMapOperator>(flag, args.next())?
+            }
+            "--adaptive" => options.render_settings.adaptive_sampling = true,
+            "--noise-threshold" => options.render_settings.noise_threshold = parse_value(flag, args.next())?,
+            "--min-spp" => options.render_settings.min_samples = parse_value(flag, args.next())?,
+            "--denoise" => options.denoise = true,
+            "--single-thread" => options.render_settings.single_thread = true,
+            "--spectral" => options.render_settings.spectral = true,
+            "--working-space" => options.render_settings.working_space = parse_value(flag, args.next())?,
+            "--output-space" => options.image_pipeline.output_space = parse_value(flag, args.next())?,
+            "--frames" => options.frames = Some(parse_frames(flag, args.next())?),
+            "--fps" => {
+                let fps: Float = parse_value(flag, args.next())?;
+                if !fps.is_finite() || fps <= 0. {
+                    return Err(format!("invalid value '{}' for {}", fps, flag));
+                }
+                options.fps = fps;
+            }
+            "--skinning" => options.skinning = parse_value(flag, args.next())?,
+            "--help" | "-h" => return Ok(None),
+            _ => return Err(format!("unknown option '{}'", flag)),
+        }
+    }
+    options.render_settings.filter = Filter::from(options.filter_type);
+    Ok(Some(options))
+}
+
+fn is_gltf(path: &str) -> bool {
+    let extension = Path::new(path).extension().unwrap_or_default();
+    extension == "gltf" || extension == "glb"
+}
+
+// Render the image or the frames asked for, returns the written paths
+pub fn render(options: &CliOptions) -> Result<Vec<String>, String> {
+    if let Some(frames) = options.frames {
+        return render_sequence(options, frames);
+    }
+
+    let scene = match &options.scene_file {
+        Some(path) if is_gltf(path) => {
+            let mut animation = gltf_file_scene(path);
+            animation.set_skinning(options.skinning);
+            animation.scene_at(0.)
+        }
+        Some(path) => pbrt_file_scene(path),
+        None => options.scene.create_scene(),
+    };
+    let settings = &options.render_settings;
+    let mut film = render_film(&scene, options);
+    film.file_name = match (&options.output, &scene.output_filename) {
+        (Some(file_name), _) | (None, Some(file_name)) => file_name.clone(),
+        (None, None) => {
+            let scene_name = match &options.scene_file {
+                Some(path) => Path::new(path).file_stem().unwrap_or_default().to_string_lossy().to_string(),
+                None => format!("{:?}", options.scene),
+            };
+            format!("render_{}_spp{}_{}x{}", scene_name, settings.sample_per_pixel, film.width, film.height)
+        }
+    };
+    Ok(vec![film.write_image(settings.output_format, &options.image_pipeline)])
+}
+
+// Frame N is the animation at (N - 1) / fps seconds. The scene is loaded
+// once and posed again for every frame.
+fn render_sequence(options: &CliOptions, (first, last): (u32, u32)) -> Result<Vec<String>, String> {
+    let mut animation = match &options.scene_file {
+        Some(path) if is_gltf(path) => gltf_file_scene(path),
+        Some(path) => return Err(format!("{} has no animation, sequences need a glTF scene", path)),
+        None => options
+            .scene
+            .animated_scene()
+            .ok_or(format!("the {} scene has no animation", options.scene))?,
+    };
+    animation.set_skinning(options.skinning);
+    log::info!("Animation is {} seconds long", animation.duration());
+
+    let mut paths = Vec::new();
+    for frame in first..=last {
+        let scene = animation.scene_at((frame - 1) as Float / options.fps);
+        let mut film = render_film(&scene, options);
+        film.file_name = options.output.clone().unwrap_or("frame".to_string());
+        paths.push(film.write_frame(frame, options.render_settings.output_format, &options.image_pipeline));
+    }
+    Ok(paths)
+}
+
+// Render all the samples of `scene`, denoised if asked for
+fn render_film(scene: &Scene, options: &CliOptions) -> Film {
+    let settings = &options.render_settings;
+    let view = View::new(SCREEN_WIDTH, SCREEN_HEIGHT);
+    let mut framebuffer = FrameBuffer::new(view.width, view.height, settings.filter);
+    framebuffer.film.color_space = settings.working_space;
+    framebuffer.film.enable_aovs(&options.aovs);
+    if options.denoise {
+        framebuffer.film.enable_denoiser_features();
+    }
+
+    let start = Instant::now();
+    while framebuffer.current_sample < settings.sample_per_pixel && !framebuffer.all_converged() {
+        framebuffer.current_sample += 1;
+        DirectLightingIntegrator::render(scene, &view, &mut framebuffer, settings);
+    }
+    log::info!("Rendered {} passes in {:?}", framebuffer.current_sample, start.elapsed());
+
+    if options.denoise {
+        framebuffer.film.denoised(&AtrousDenoiser::default())
+    } else {
+        framebuffer.film
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::color_space::ColorSpace;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let options = parse_args(&args("--scene FurnaceTest --spp 16 --filter mitchell --aov depth,normal --denoise"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(options.scene, SceneOption::FurnaceTest);
+        assert_eq!(options.render_settings.sample_per_pixel, 16);
+        assert_eq!(options.render_settings.filter, Filter::from(FilterType::Mitchell));
+        assert_eq!(options.aovs, vec![AovType::Depth, AovType::Normal]);
+        assert!(options.denoise);
+        assert!(!options.render_settings.spectral);
+        assert!(parse_args(&args("--spectral")).unwrap().unwrap().render_settings.spectral);
+        let spaces = parse_args(&args("--working-space ACEScg --output-space p3")).unwrap().unwrap();
+        assert_eq!(spaces.render_settings.working_space, ColorSpace::AcesCg);
+        assert_eq!(spaces.image_pipeline.output_space, ColorSpace::DisplayP3);
+
+        assert_eq!(parse_args(&args("--help")).unwrap(), None);
+        assert!(parse_args(&args("--spp")).is_err());
+        assert!(parse_args(&args("--aov nothing")).is_err());
+
+        let sequence = parse_args(&args("--scene truck --frames 1-30 --fps 30 --skinning DualQuaternion"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(sequence.frames, Some((1, 30)));
+        assert_eq!(sequence.fps, 30.);
+        assert_eq!(sequence.skinning, SkinningMethod::DualQuaternion);
+        assert_eq!(parse_args(&args("--frames 7")).unwrap().unwrap().frames, Some((7, 7)));
+        assert!(parse_args(&args("--frames 0-3")).is_err());
+        assert!(parse_args(&args("--frames 5-2")).is_err());
+        assert!(parse_args(&args("--fps 0")).is_err());
+        assert!(parse_args(&args("--fps -24")).is_err());
+        assert!(parse_args(&args("--fps NaN")).is_err());
+        assert!(parse_args(&args("--fps inf")).is_err());
+    }
+}
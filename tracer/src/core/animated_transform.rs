@@ -0,0 +1,180 @@
+use math::{Float, Mat4, Quaternion, Vec3};
+
+use crate::core::bounds::Bounds3f;
+use crate::core::Transform;
+
+// Object to world matrix split into translation, rotation and the rest
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct Keyframe {
+    time: Float,
+    translation: Vec3,
+    rotation: Quaternion<Float>,
+    // Scale and shear, whatever the rotation leaves
+    scale: Mat4,
+}
+
+impl Keyframe {
+    fn new(time: Float, matrix: &Mat4) -> Self {
+        let translation = Vec3::new(matrix[[0, 3]], matrix[[1, 3]], matrix[[2, 3]]);
+        let mut linear = *matrix;
+        for i in 0..3 {
+            linear.m[i][3] = 0.;
+        }
+
+        // Polar decomposition, average the matrix with its inverse transpose
+        // until it settles on the closest rotation
+        let mut rotation = linear;
+        for _ in 0..100 {
+            let Some(inverse) = rotation.inverse() else {
+                break;
+            };
+            let inverse_transpose = inverse.transpose();
+            let mut next = rotation;
+            let mut change: Float = 0.;
+            for i in 0..3 {
+                for j in 0..3 {
+                    next.m[i][j] = 0.5 * (rotation.m[i][j] + inverse_transpose.m[i][j]);
+                    change = change.max((next.m[i][j] - rotation.m[i][j]).abs());
+                }
+            }
+            rotation = next;
+            if change < 1e-5 {
+                break;
+            }
+        }
+
+        Self {
+            time,
+            translation,
+            rotation: Quaternion::from(&rotation),
+            scale: rotation.transpose() * linear,
+        }
+    }
+
+    fn matrix(&self) -> Mat4 {
+        Transform::translate(self.translation).matrix * Mat4::from(&self.rotation) * self.scale
+    }
+}
+
+// Object to world transform changing over time. Between keyframes the
+// translation and scale are interpolated linearly and the rotation is
+// slerped, so a spinning object turns at constant speed. Before the first
+// keyframe and after the last one it holds still.
+#[derive(Clone, PartialEq, Debug)]
+pub struct AnimatedTransform {
+    keyframes: Vec<Keyframe>,
+}
+
+impl AnimatedTransform {
+    // Keyframes in increasing time
+    pub fn new(keyframes: &[(Float, Mat4)]) -> Self {
+        assert!(!keyframes.is_empty(), "an animated transform needs a keyframe");
+        Self {
+            keyframes: keyframes.iter().map(|(time, matrix)| Keyframe::new(*time, matrix)).collect(),
+        }
+    }
+
+    pub fn is_animated(&self) -> bool {
+        self.keyframes.windows(2).any(|pair| pair[0].matrix() != pair[1].matrix())
+    }
+
+    // Held at the first and last keyframes outside of them, a NaN time is
+    // the first one
+    pub fn interpolate(&self, time: Float) -> Mat4 {
+        let first = &self.keyframes[0];
+        let last = &self.keyframes[self.keyframes.len() - 1];
+        if time.is_nan() || time <= first.time {
+            return first.matrix();
+        }
+        if time >= last.time {
+            return last.matrix();
+        }
+
+        let i = self.keyframes.partition_point(|keyframe| keyframe.time <= time) - 1;
+        let (k0, k1) = (&self.keyframes[i], &self.keyframes[i + 1]);
+        let dt = (time - k0.time) / (k1.time - k0.time);
+        let mut scale = Mat4::zero();
+        for r in 0..4 {
+            for c in 0..4 {
+                scale.m[r][c] = (1. - dt) * k0.scale.m[r][c] + dt * k1.scale.m[r][c];
+            }
+        }
+        Keyframe {
+            time,
+            translation: (1. - dt) * k0.translation + dt * k1.translation,
+            rotation: Quaternion::slerp(k0.rotation, k1.rotation, dt),
+            scale,
+        }
+        .matrix()
+    }
+
+    // Box around an object space box over the whole motion. Each segment is
+    // sampled, translation and scale stay inside the sampled boxes and the
+    // box is padded by how far the rotation can bulge between samples.
+    pub fn motion_bounds(&self, bounds: &Bounds3f) -> Bounds3f {
+        const STEPS: usize = 64;
+        let mut motion_bounds = bounds.transform(&self.keyframes[0].matrix());
+        for pair in self.keyframes.windows(2) {
+            let (k0, k1) = (&pair[0], &pair[1]);
+            let mut radius: Float = 0.;
+            for step in 0..=STEPS {
+                let time = k0.time + (k1.time - k0.time) * step as Float / STEPS as Float;
+                let matrix = self.interpolate(time);
+                let step_bounds = bounds.transform(&matrix);
+                motion_bounds = motion_bounds.union(&step_bounds);
+                for i in 0..8 {
+                    radius = radius.max((step_bounds.corner(i) - matrix * Vec3::zero()).length());
+                }
+            }
+            let step_angle = Quaternion::angle_between(k0.rotation, k1.rotation) / STEPS as Float;
+            motion_bounds = motion_bounds.expand(radius * (1. - (0.5 * step_angle).cos()));
+        }
+        motion_bounds
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 1e-4, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn test_animated_transform() {
+        let start = Transform::translate(Vec3::new(-1., 0., 0.)).matrix;
+        let end = (Transform::translate(Vec3::new(1., 0., 0.))
+            * Transform::rotate_y(std::f32::consts::FRAC_PI_2)
+            * Transform::scale(Vec3::from(3.)))
+        .matrix;
+        let motion = AnimatedTransform::new(&[(0., start), (1., end)]);
+        assert!(motion.is_animated());
+
+        // The keyframes come back as they were given
+        let x = Vec3::new(1., 0., 0.);
+        assert_near(motion.interpolate(0.) * x, start * x);
+        assert_near(motion.interpolate(1.) * x, end * x);
+        assert_near(motion.interpolate(2.) * x, end * x);
+        assert_near(motion.interpolate(Float::NAN) * x, start * x);
+
+        // Halfway it has turned 45 degrees, not cut the corner
+        let half = std::f32::consts::FRAC_1_SQRT_2;
+        assert_near(motion.interpolate(0.5) * x, 2. * Vec3::new(half, 0., -half));
+
+        let unit = Bounds3f {
+            p_min: Vec3::from(-1.),
+            p_max: Vec3::from(1.),
+        };
+        let bounds = motion.motion_bounds(&unit);
+        for step in 0..=100 {
+            let matrix = motion.interpolate(step as Float / 100.);
+            for i in 0..8 {
+                let p = matrix * unit.corner(i);
+                for axis in 0..3 {
+                    assert!(p[axis] >= bounds.p_min[axis] && p[axis] <= bounds.p_max[axis]);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,254 @@
+use math::{Float, Mat4, Quaternion, Vec3};
+
+use crate::core::Transform;
+
+// Local transform and morph target weights of a glTF node, the parts an
+// animation channel can drive
+#[derive(Clone, PartialEq, Debug)]
+pub struct NodePose {
+    pub translation: Vec3,
+    pub rotation: Quaternion<Float>,
+    pub scale: Vec3,
+    pub weights: Vec<Float>,
+}
+
+impl Default for NodePose {
+    fn default() -> Self {
+        Self {
+            translation: Vec3::zero(),
+            rotation: Quaternion::identity(),
+            scale: Vec3::from(1.),
+            weights: Vec::new(),
+        }
+    }
+}
+
+impl NodePose {
+    pub fn matrix(&self) -> Mat4 {
+        (Transform::translate(self.translation) * Transform::from(&self.rotation) * Transform::scale(self.scale)).matrix
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Property {
+    Translation,
+    Rotation,
+    Scale,
+    MorphWeights,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Interpolation {
+    Linear,
+    Step,
+    // Hermite spline, every keyframe has an in tangent, a value and an out
+    // tangent
+    CubicSpline,
+}
+
+impl From<gltf::animation::Interpolation> for Interpolation {
+    fn from(interpolation: gltf::animation::Interpolation) -> Self {
+        match interpolation {
+            gltf::animation::Interpolation::Linear => Interpolation::Linear,
+            gltf::animation::Interpolation::Step => Interpolation::Step,
+            gltf::animation::Interpolation::CubicSpline => Interpolation::CubicSpline,
+        }
+    }
+}
+
+// Keyframes of one property of one node. The values are flattened, `width`
+// floats per value, one per morph target for weights, and three values per
+// keyframe for cubic splines.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Channel {
+    pub node: usize,
+    pub property: Property,
+    pub interpolation: Interpolation,
+    pub times: Vec<Float>,
+    pub values: Vec<Float>,
+}
+
+impl Channel {
+    fn width(&self) -> usize {
+        let per_keyframe = match self.interpolation {
+            Interpolation::CubicSpline => 3,
+            _ => 1,
+        };
+        self.values.len() / (per_keyframe * self.times.len()).max(1)
+    }
+
+    // Value of keyframe `i`, `offset` 0 is the in tangent, 1 the value and 2
+    // the out tangent of cubic splines
+    fn keyframe(&self, i: usize, offset: usize) -> &[Float] {
+        let width = self.width();
+        let start = match self.interpolation {
+            Interpolation::CubicSpline => (3 * i + offset) * width,
+            _ => i * width,
+        };
+        &self.values[start..start + width]
+    }
+
+    // Value at `time`, held at the first and last keyframes outside of them.
+    // A NaN time is the first keyframe.
+    pub fn sample(&self, time: Float) -> Vec<Float> {
+        let last = self.times.len() - 1;
+        if time.is_nan() || time <= self.times[0] {
+            return self.keyframe(0, 1).to_vec();
+        }
+        if time >= self.times[last] {
+            return self.keyframe(last, 1).to_vec();
+        }
+
+        let i = self.times.partition_point(|&keyframe_time| keyframe_time <= time) - 1;
+        let dt = self.times[i + 1] - self.times[i];
+        let s = (time - self.times[i]) / dt;
+        let value = match self.interpolation {
+            Interpolation::Step => self.keyframe(i, 1).to_vec(),
+            // Rotations turn at constant speed
+            Interpolation::Linear if self.property == Property::Rotation => {
+                let rotation = Quaternion::slerp(
+                    Quaternion::from(&to_array(self.keyframe(i, 1))),
+                    Quaternion::from(&to_array(self.keyframe(i + 1, 1))),
+                    s,
+                );
+                vec![rotation.x, rotation.y, rotation.z, rotation.w]
+            }
+            Interpolation::Linear => {
+                let (v0, v1) = (self.keyframe(i, 1), self.keyframe(i + 1, 1));
+                v0.iter().zip(v1).map(|(a, b)| (1. - s) * a + s * b).collect()
+            }
+            Interpolation::CubicSpline => {
+                let (v0, b0) = (self.keyframe(i, 1), self.keyframe(i, 2));
+                let (a1, v1) = (self.keyframe(i + 1, 0), self.keyframe(i + 1, 1));
+                let (s2, s3) = (s * s, s * s * s);
+                (0..v0.len())
+                    .map(|c| {
+                        (2. * s3 - 3. * s2 + 1.) * v0[c]
+                            + (s3 - 2. * s2 + s) * dt * b0[c]
+                            + (-2. * s3 + 3. * s2) * v1[c]
+                            + (s3 - s2) * dt * a1[c]
+                    })
+                    .collect()
+            }
+        };
+        if self.property == Property::Rotation {
+            let rotation = Quaternion::from(&to_array(&value)).normalize();
+            return vec![rotation.x, rotation.y, rotation.z, rotation.w];
+        }
+        value
+    }
+}
+
+fn to_array(value: &[Float]) -> [Float; 4] {
+    [value[0], value[1], value[2], value[3]]
+}
+
+// Channels played together, like the wheels spinning on the milk truck
+#[derive(Clone, PartialEq, Debug)]
+pub struct Animation {
+    pub name: Option<String>,
+    pub channels: Vec<Channel>,
+}
+
+impl Animation {
+    pub fn from_gltf(animation: &gltf::Animation, buffers: &[gltf::buffer::Data]) -> Self {
+        use gltf::animation::util::ReadOutputs;
+
+        let mut channels = Vec::new();
+        for channel in animation.channels() {
+            let reader = channel.reader(|buffer| Some(&buffers[buffer.index()]));
+            let (Some(inputs), Some(outputs)) = (reader.read_inputs(), reader.read_outputs()) else {
+                continue;
+            };
+            let (property, values): (Property, Vec<Float>) = match outputs {
+                ReadOutputs::Translations(iter) => (Property::Translation, iter.flatten().collect()),
+                ReadOutputs::Rotations(iter) => (Property::Rotation, iter.into_f32().flatten().collect()),
+                ReadOutputs::Scales(iter) => (Property::Scale, iter.flatten().collect()),
+                ReadOutputs::MorphTargetWeights(iter) => (Property::MorphWeights, iter.into_f32().collect()),
+            };
+            channels.push(Channel {
+                node: channel.target().node().index(),
+                property,
+                interpolation: Interpolation::from(channel.sampler().interpolation()),
+                times: inputs.collect(),
+                values,
+            });
+        }
+        Self {
+            name: animation.name().map(String::from),
+            channels,
+        }
+    }
+
+    // Time of the last keyframe, in seconds
+    pub fn duration(&self) -> Float {
+        self.channels
+            .iter()
+            .filter_map(|channel| channel.times.last())
+            .fold(0., |duration: Float, &time| duration.max(time))
+    }
+
+    // Set the animated properties of `nodes` to their values at `time`
+    pub fn apply(&self, time: Float, nodes: &mut [NodePose]) {
+        for channel in self.channels.iter().filter(|channel| !channel.times.is_empty()) {
+            let Some(node) = nodes.get_mut(channel.node) else {
+                continue;
+            };
+            let value = channel.sample(time);
+            match channel.property {
+                Property::Translation => node.translation = Vec3::new(value[0], value[1], value[2]),
+                Property::Rotation => node.rotation = Quaternion::from(&to_array(&value)),
+                Property::Scale => node.scale = Vec3::new(value[0], value[1], value[2]),
+                Property::MorphWeights => node.weights = value,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn channel(property: Property, interpolation: Interpolation, values: Vec<Float>) -> Channel {
+        Channel {
+            node: 0,
+            property,
+            interpolation,
+            times: vec![0., 1.],
+            values,
+        }
+    }
+
+    #[test]
+    fn test_channel_interpolation() {
+        let linear = channel(Property::Translation, Interpolation::Linear, vec![0., 0., 0., 2., 4., 6.]);
+        assert_eq!(linear.sample(0.25), vec![0.5, 1., 1.5]);
+        assert_eq!(linear.sample(-1.), vec![0., 0., 0.]);
+        assert_eq!(linear.sample(3.), vec![2., 4., 6.]);
+        assert_eq!(linear.sample(Float::NAN), vec![0., 0., 0.]);
+        assert_eq!(linear.sample(Float::INFINITY), vec![2., 4., 6.]);
+
+        let step = channel(Property::Scale, Interpolation::Step, vec![1., 1., 1., 2., 2., 2.]);
+        assert_eq!(step.sample(0.99), vec![1., 1., 1.]);
+
+        // Flat tangents ease in and out, halfway is halfway
+        let cubic = channel(
+            Property::Translation,
+            Interpolation::CubicSpline,
+            vec![0., 0., 0., 0., 0., 0., 0., 0., 0., 0., 0., 0., 2., 2., 2., 0., 0., 0.],
+        );
+        assert_eq!(cubic.sample(0.5), vec![1., 1., 1.]);
+        assert!(cubic.sample(0.1)[0] < 0.1 * 2.);
+
+        // A quarter turn around y, slerped
+        let half = std::f32::consts::FRAC_PI_4;
+        let rotation = channel(
+            Property::Rotation,
+            Interpolation::Linear,
+            vec![0., 0., 0., 1., 0., half.sin(), 0., half.cos()],
+        );
+        let mid = rotation.sample(0.5);
+        assert!((mid[1] - (half / 2.).sin()).abs() < 1e-5);
+        assert!((mid[3] - (half / 2.).cos()).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,150 @@
+use math::{Float, Vec2, Vec3};
+use strum_macros::{Display, EnumIter};
+
+use crate::core::spectrum::Spectrum;
+
+// Arbitrary output variables: extra per-pixel channels written next to the
+// beauty image for compositing.
+//
+// Geometric channels describe the first surface seen through the pixel and
+// are averaged over the samples that hit something. Ids are never averaged,
+// a pixel keeps the id of its first sample. Light channels split the beauty
+// image by the lobe of the first scattering event and by how many bounces
+// the light took; they go through the reconstruction filter like the beauty
+// image, so adding them all up gives it back.
+#[derive(Debug, EnumIter, PartialEq, Eq, Clone, Copy, Display)]
+pub enum AovType {
+    // Distance from the camera along the ray, `SurfaceInteraction::t`
+    Depth,
+    Position,
+    Normal,
+    Albedo,
+    Uv,
+    // Ids start at 1, 0 is the background
+    PrimitiveId,
+    MaterialId,
+    DiffuseDirect,
+    DiffuseIndirect,
+    SpecularDirect,
+    SpecularIndirect,
+    // Light seen directly by the camera
+    Emission,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum AovFiltering {
+    // Average of the samples that hit a surface
+    Average,
+    // Value of the first sample
+    First,
+    // Reconstruction filter of the film
+    Filtered,
+}
+
+impl AovType {
+    // Layer name in the output files
+    pub fn name(&self) -> &'static str {
+        match self {
+            AovType::Depth => "depth",
+            AovType::Position => "position",
+            AovType::Normal => "normal",
+            AovType::Albedo => "albedo",
+            AovType::Uv => "uv",
+            AovType::PrimitiveId => "primitive_id",
+            AovType::MaterialId => "material_id",
+            AovType::DiffuseDirect => "diffuse_direct",
+            AovType::DiffuseIndirect => "diffuse_indirect",
+            AovType::SpecularDirect => "specular_direct",
+            AovType::SpecularIndirect => "specular_indirect",
+            AovType::Emission => "emission",
+        }
+    }
+
+    pub fn channels(&self) -> &'static [&'static str] {
+        match self {
+            AovType::Depth => &["Z"],
+            AovType::Position | AovType::Normal => &["X", "Y", "Z"],
+            AovType::Uv => &["U", "V"],
+            AovType::PrimitiveId | AovType::MaterialId => &["id"],
+            _ => &["R", "G", "B"],
+        }
+    }
+
+    pub fn filtering(&self) -> AovFiltering {
+        match self {
+            AovType::Depth
+            | AovType::Position
+            | AovType::Normal
+            | AovType::Albedo
+            | AovType::Uv => AovFiltering::Average,
+            AovType::PrimitiveId | AovType::MaterialId => AovFiltering::First,
+            _ => AovFiltering::Filtered,
+        }
+    }
+
+    // Whether the channel holds radiance, and so gets tone mapped for 8-bit
+    // output
+    pub fn is_light(&self) -> bool {
+        self.filtering() == AovFiltering::Filtered
+    }
+}
+
+// Everything the integrator records about one camera sample for the AOVs
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AovSample {
+    pub hit: bool,
+    pub depth: Float,
+    pub position: Vec3,
+    pub normal: Vec3,
+    pub albedo: Spectrum,
+    pub uv: Vec2,
+    pub primitive_id: u32,
+    pub material_id: u32,
+    pub diffuse_direct: Spectrum,
+    pub diffuse_indirect: Spectrum,
+    pub specular_direct: Spectrum,
+    pub specular_indirect: Spectrum,
+    pub emission: Spectrum,
+}
+
+impl Default for AovSample {
+    fn default() -> Self {
+        let black = Spectrum::ColorRGB(Vec3::from(0.));
+        Self {
+            hit: false,
+            depth: 0.,
+            position: Vec3::from(0.),
+            normal: Vec3::from(0.),
+            albedo: black,
+            uv: Vec2::from(0.),
+            primitive_id: 0,
+            material_id: 0,
+            diffuse_direct: black,
+            diffuse_indirect: black,
+            specular_direct: black,
+            specular_indirect: black,
+            emission: black,
+        }
+    }
+}
+
+impl AovSample {
+    // Value of one AOV, padded to three channels
+    pub fn value(&self, aov: AovType) -> Spectrum {
+        let rgb = match aov {
+            AovType::Depth => Vec3::new(self.depth, 0., 0.),
+            AovType::Position => self.position,
+            AovType::Normal => self.normal,
+            AovType::Albedo => self.albedo.to_rgb(),
+            AovType::Uv => Vec3::new(self.uv.x(), self.uv.y(), 0.),
+            AovType::PrimitiveId => Vec3::new(self.primitive_id as Float, 0., 0.),
+            AovType::MaterialId => Vec3::new(self.material_id as Float, 0., 0.),
+            AovType::DiffuseDirect => self.diffuse_direct.to_rgb(),
+            AovType::DiffuseIndirect => self.diffuse_indirect.to_rgb(),
+            AovType::SpecularDirect => self.specular_direct.to_rgb(),
+            AovType::SpecularIndirect => self.specular_indirect.to_rgb(),
+            AovType::Emission => self.emission.to_rgb(),
+        };
+        Spectrum::ColorRGB(rgb)
+    }
+}
@@ -1,7 +1,95 @@
-use math::Vec3;
+use math::{Float, Mat4, Vec3};
+
+use crate::core::ray::Ray;
 
 #[derive(Clone, PartialEq, Debug)]
 pub struct Bounds3f {
     pub p_min: Vec3,
-    pub p_max: Vec3, 
-}
\ No newline at end of file
+    pub p_max: Vec3,
+}
+
+impl Default for Bounds3f {
+    // Empty, the union with anything is the other box
+    fn default() -> Self {
+        Self {
+            p_min: Vec3::from(Float::MAX),
+            p_max: Vec3::from(Float::MIN),
+        }
+    }
+}
+
+impl Bounds3f {
+    pub fn union(&self, other: &Bounds3f) -> Bounds3f {
+        Bounds3f {
+            p_min: Vec3::component_min(self.p_min, other.p_min),
+            p_max: Vec3::component_max(self.p_max, other.p_max),
+        }
+    }
+
+    pub fn union_point(&self, point: Vec3) -> Bounds3f {
+        Bounds3f {
+            p_min: Vec3::component_min(self.p_min, point),
+            p_max: Vec3::component_max(self.p_max, point),
+        }
+    }
+
+    pub fn expand(&self, delta: Float) -> Bounds3f {
+        Bounds3f {
+            p_min: self.p_min - Vec3::from(delta),
+            p_max: self.p_max + Vec3::from(delta),
+        }
+    }
+
+    pub fn centroid(&self) -> Vec3 {
+        0.5 * (self.p_min + self.p_max)
+    }
+
+    pub fn diagonal(&self) -> Vec3 {
+        self.p_max - self.p_min
+    }
+
+    pub fn corner(&self, i: usize) -> Vec3 {
+        Vec3::new(
+            if i & 1 == 0 { self.p_min.x } else { self.p_max.x },
+            if i & 2 == 0 { self.p_min.y } else { self.p_max.y },
+            if i & 4 == 0 { self.p_min.z } else { self.p_max.z },
+        )
+    }
+
+    // Box around the transformed corners
+    pub fn transform(&self, matrix: &Mat4) -> Bounds3f {
+        (0..8).fold(Bounds3f::default(), |bounds, i| {
+            bounds.union_point(*matrix * self.corner(i))
+        })
+    }
+
+    // Slab test, true if the ray enters the box before `t_max`
+    pub fn intersect_p(&self, ray: &Ray, t_max: Float) -> bool {
+        self.intersect_range(ray, t_max).is_some()
+    }
+
+    // Part of [0, `t_max`] the ray spends inside the box
+    pub fn intersect_range(&self, ray: &Ray, t_max: Float) -> Option<(Float, Float)> {
+        let mut t0: Float = 0.;
+        let mut t1 = t_max;
+        for axis in 0..3 {
+            let inv_direction = 1. / ray.direction[axis];
+            let mut t_near = (self.p_min[axis] - ray.origin[axis]) * inv_direction;
+            let mut t_far = (self.p_max[axis] - ray.origin[axis]) * inv_direction;
+            if t_near > t_far {
+                std::mem::swap(&mut t_near, &mut t_far);
+            }
+            // NaN from 0 * inf when the ray grazes a slab keeps the range
+            if t_near > t0 {
+                t0 = t_near;
+            }
+            if t_far < t1 {
+                t1 = t_far;
+            }
+            if t0 > t1 {
+                return None;
+            }
+        }
+        Some((t0, t1))
+    }
+}
@@ -0,0 +1,220 @@
+use math::{Float, Vec3};
+
+use crate::core::bounds::Bounds3f;
+use crate::core::ray::Ray;
+
+const MAX_ITEMS_IN_LEAF: usize = 4;
+const BUCKETS: usize = 12;
+
+#[derive(Clone, PartialEq, Debug)]
+struct BvhNode {
+    bounds: Bounds3f,
+    // Leaves list `count` items from `offset` in the item indices, interior
+    // nodes have their first child right after them and the second one at
+    // `offset`
+    offset: usize,
+    count: usize,
+    axis: usize,
+}
+
+// Bounding volume hierarchy over anything with a box. The boxes of moving
+// primitives cover their whole motion, so the same tree answers rays at
+// every time of the shutter interval.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Bvh {
+    nodes: Vec<BvhNode>,
+    items: Vec<usize>,
+}
+
+impl Bvh {
+    // Split with the surface area heuristic, the items are the indices in
+    // `bounds`
+    pub fn new(bounds: &[Bounds3f]) -> Self {
+        let mut bvh = Bvh {
+            nodes: Vec::with_capacity(2 * bounds.len()),
+            items: (0..bounds.len()).collect(),
+        };
+        if !bounds.is_empty() {
+            bvh.build(bounds, 0, bounds.len());
+        }
+        bvh
+    }
+
+    fn build(&mut self, bounds: &[Bounds3f], start: usize, end: usize) -> usize {
+        let node_index = self.nodes.len();
+        let node_bounds = self.items[start..end]
+            .iter()
+            .fold(Bounds3f::default(), |node_bounds, &item| node_bounds.union(&bounds[item]));
+        self.nodes.push(BvhNode {
+            bounds: node_bounds.clone(),
+            offset: start,
+            count: end - start,
+            axis: 0,
+        });
+        if end - start <= MAX_ITEMS_IN_LEAF {
+            return node_index;
+        }
+
+        let centroid_bounds = self.items[start..end]
+            .iter()
+            .fold(Bounds3f::default(), |centroids, &item| centroids.union_point(bounds[item].centroid()));
+        let extent = centroid_bounds.diagonal();
+        let axis = if extent.x > extent.y && extent.x > extent.z {
+            0
+        } else if extent.y > extent.z {
+            1
+        } else {
+            2
+        };
+        // All the centroids in one spot, nothing to split
+        if extent[axis] <= 0. {
+            return node_index;
+        }
+
+        let bucket_of = |item: usize| {
+            let offset = (bounds[item].centroid()[axis] - centroid_bounds.p_min[axis]) / extent[axis];
+            ((offset * BUCKETS as Float) as usize).min(BUCKETS - 1)
+        };
+        let mut bucket_bounds = vec![Bounds3f::default(); BUCKETS];
+        let mut bucket_counts = [0; BUCKETS];
+        for &item in &self.items[start..end] {
+            let bucket = bucket_of(item);
+            bucket_bounds[bucket] = bucket_bounds[bucket].union(&bounds[item]);
+            bucket_counts[bucket] += 1;
+        }
+
+        // Cost of splitting after each bucket, relative to the node's area
+        let mut best_split = 0;
+        let mut best_cost = Float::MAX;
+        for split in 0..BUCKETS - 1 {
+            let side = |buckets: std::ops::Range<usize>| {
+                let count: usize = bucket_counts[buckets.clone()].iter().sum();
+                let side_bounds = buckets.fold(Bounds3f::default(), |b, i| b.union(&bucket_bounds[i]));
+                count as Float * surface_area(&side_bounds)
+            };
+            let cost = side(0..split + 1) + side(split + 1..BUCKETS);
+            if cost < best_cost {
+                best_cost = cost;
+                best_split = split;
+            }
+        }
+        let leaf_cost = (end - start) as Float * surface_area(&node_bounds);
+        if end - start <= 2 * MAX_ITEMS_IN_LEAF && leaf_cost <= best_cost {
+            return node_index;
+        }
+
+        let mid = start + partition(&mut self.items[start..end], |item| bucket_of(item) <= best_split);
+        if mid == start || mid == end {
+            return node_index;
+        }
+
+        self.build(bounds, start, mid);
+        let second = self.build(bounds, mid, end);
+        self.nodes[node_index].offset = second;
+        self.nodes[node_index].count = 0;
+        self.nodes[node_index].axis = axis;
+        node_index
+    }
+
+    // Calls `hit` with the items whose boxes the ray enters before `t_max`,
+    // front to back along the ray. `hit` returns the distance of a new
+    // closest hit, which stops the search past it. True if any item was hit.
+    pub fn intersect<F>(&self, ray: &Ray, mut t_max: Float, mut hit: F) -> bool
+    where
+        F: FnMut(usize) -> Option<Float>,
+    {
+        if self.nodes.is_empty() {
+            return false;
+        }
+        let mut any_hit = false;
+        let mut stack = vec![0];
+        while let Some(node_index) = stack.pop() {
+            let node = &self.nodes[node_index];
+            if !node.bounds.intersect_p(ray, t_max) {
+                continue;
+            }
+            if node.count > 0 {
+                for &item in &self.items[node.offset..node.offset + node.count] {
+                    if let Some(t) = hit(item) {
+                        any_hit = true;
+                        t_max = t_max.min(t);
+                    }
+                }
+            } else if ray.direction[node.axis] < 0. {
+                stack.push(node_index + 1);
+                stack.push(node.offset);
+            } else {
+                stack.push(node.offset);
+                stack.push(node_index + 1);
+            }
+        }
+        any_hit
+    }
+}
+
+fn surface_area(bounds: &Bounds3f) -> Float {
+    let d: Vec3 = bounds.diagonal();
+    if d.x < 0. {
+        return 0.;
+    }
+    2. * (d.x * d.y + d.y * d.z + d.z * d.x)
+}
+
+// Move the items matching `predicate` to the front, returns how many there are
+fn partition<F>(items: &mut [usize], predicate: F) -> usize
+where
+    F: Fn(usize) -> bool,
+{
+    let mut first = 0;
+    for i in 0..items.len() {
+        if predicate(items[i]) {
+            items.swap(first, i);
+            first += 1;
+        }
+    }
+    first
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bvh_finds_the_closest_box() {
+        // A row of unit boxes along z and a few off to the side
+        let mut bounds = Vec::new();
+        for i in 0..20 {
+            let center = Vec3::new(0., 0., 3. * i as Float);
+            bounds.push(Bounds3f {
+                p_min: center - Vec3::from(0.5),
+                p_max: center + Vec3::from(0.5),
+            });
+            bounds.push(Bounds3f {
+                p_min: center + Vec3::new(4.5, -0.5, -0.5),
+                p_max: center + Vec3::new(5.5, 0.5, 0.5),
+            });
+        }
+        let bvh = Bvh::new(&bounds);
+
+        let ray = Ray::new(Vec3::new(0., 0., 100.), Vec3::new(0., 0., -1.));
+        let mut visited = Vec::new();
+        let mut closest = (Float::MAX, None);
+        let hit = bvh.intersect(&ray, Float::MAX, |item| {
+            visited.push(item);
+            // Distance to the front face of the box
+            let t = ray.origin.z - bounds[item].p_max.z;
+            if t < closest.0 {
+                closest = (t, Some(item));
+                return Some(t);
+            }
+            None
+        });
+        assert!(hit);
+        assert_eq!(closest.1, Some(38));
+        // Most of the boxes behind the closest one are culled
+        assert!(visited.len() < 10, "{:?}", visited);
+
+        let miss = Ray::new(Vec3::new(0., 10., 0.), Vec3::new(1., 0., 0.));
+        assert!(!bvh.intersect(&miss, Float::MAX, |_| Some(0.)));
+    }
+}
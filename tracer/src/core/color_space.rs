@@ -0,0 +1,236 @@
+use std::sync::OnceLock;
+
+use math::{Float, Vec3};
+use strum::IntoEnumIterator;
+use strum_macros::{Display, EnumIter, EnumString};
+
+use crate::core::spectrum::Spectrum;
+
+pub type Mat3 = [[Float; 3]; 3];
+
+// Von Kries adaptation in the cone response space of Bradford
+pub const BRADFORD: Mat3 = [
+    [0.8951, 0.2664, -0.1614],
+    [-0.7502, 1.7135, 0.0367],
+    [0.0389, -0.0685, 1.0296],
+];
+
+pub const BRADFORD_INV: Mat3 = [
+    [0.9869929, -0.1470543, 0.1599627],
+    [0.4323053, 0.5183603, 0.0492912],
+    [-0.0085287, 0.0400428, 0.9684867],
+];
+
+pub fn mul(m: &Mat3, v: [Float; 3]) -> [Float; 3] {
+    [
+        m[0][0] * v[0] + m[0][1] * v[1] + m[0][2] * v[2],
+        m[1][0] * v[0] + m[1][1] * v[1] + m[1][2] * v[2],
+        m[2][0] * v[0] + m[2][1] * v[1] + m[2][2] * v[2],
+    ]
+}
+
+pub fn mul_mat(a: &Mat3, b: &Mat3) -> Mat3 {
+    let mut m = [[0.; 3]; 3];
+    for (i, row) in m.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = a[i][0] * b[0][j] + a[i][1] * b[1][j] + a[i][2] * b[2][j];
+        }
+    }
+    m
+}
+
+fn invert(m: &Mat3) -> Mat3 {
+    let cofactor = |r0: usize, r1: usize, c0: usize, c1: usize| m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0];
+    let adjugate = [
+        [cofactor(1, 2, 1, 2), -cofactor(0, 2, 1, 2), cofactor(0, 1, 1, 2)],
+        [-cofactor(1, 2, 0, 2), cofactor(0, 2, 0, 2), -cofactor(0, 1, 0, 2)],
+        [cofactor(1, 2, 0, 1), -cofactor(0, 2, 0, 1), cofactor(0, 1, 0, 1)],
+    ];
+    let determinant = m[0][0] * adjugate[0][0] + m[0][1] * adjugate[1][0] + m[0][2] * adjugate[2][0];
+    adjugate.map(|row| row.map(|value| value / determinant))
+}
+
+fn xy_to_xyz(x: Float, y: Float) -> [Float; 3] {
+    [x / y, 1., (1. - x - y) / y]
+}
+
+// Matrix taking XYZ relative to the white `from` to XYZ relative to `to`
+pub fn chromatic_adaptation(from: [Float; 3], to: [Float; 3]) -> Mat3 {
+    let source = mul(&BRADFORD, from);
+    let target = mul(&BRADFORD, to);
+    let mut scale = [[0.; 3]; 3];
+    for c in 0..3 {
+        scale[c][c] = target[c] / source[c];
+    }
+    mul_mat(&BRADFORD_INV, &mul_mat(&scale, &BRADFORD))
+}
+
+// Linear RGB spaces, defined by their primaries and white point. Colors in
+// scene files are linear sRGB; the working space is what the integrator
+// multiplies colors in, the output space what images are written in.
+#[derive(Debug, EnumIter, EnumString, PartialEq, Eq, Clone, Copy, Display, Hash, Default)]
+#[strum(ascii_case_insensitive)]
+pub enum ColorSpace {
+    // Rec. 709 primaries with a D65 white
+    #[default]
+    #[strum(serialize = "srgb", serialize = "rec709")]
+    LinearSrgb,
+    // ACES AP1 primaries with the ACES white, close to D60
+    #[strum(serialize = "acescg")]
+    AcesCg,
+    #[strum(serialize = "rec2020")]
+    Rec2020,
+    #[strum(serialize = "p3", serialize = "displayp3")]
+    DisplayP3,
+}
+
+impl ColorSpace {
+    // xy chromaticities of red, green, blue and white
+    fn chromaticities(&self) -> [(Float, Float); 4] {
+        const D65: (Float, Float) = (0.3127, 0.3290);
+        match self {
+            ColorSpace::LinearSrgb => [(0.64, 0.33), (0.30, 0.60), (0.15, 0.06), D65],
+            ColorSpace::AcesCg => [(0.713, 0.293), (0.165, 0.830), (0.128, 0.044), (0.32168, 0.33767)],
+            ColorSpace::Rec2020 => [(0.708, 0.292), (0.170, 0.797), (0.131, 0.046), D65],
+            ColorSpace::DisplayP3 => [(0.680, 0.320), (0.265, 0.690), (0.150, 0.060), D65],
+        }
+    }
+
+    pub fn white(&self) -> [Float; 3] {
+        let (x, y) = self.chromaticities()[3];
+        xy_to_xyz(x, y)
+    }
+
+    // Columns are the XYZ of the primaries, scaled so that RGB 1 is white
+    pub fn rgb_to_xyz(&self) -> Mat3 {
+        let c = self.chromaticities();
+        let primaries = [xy_to_xyz(c[0].0, c[0].1), xy_to_xyz(c[1].0, c[1].1), xy_to_xyz(c[2].0, c[2].1)];
+        let unscaled = [0, 1, 2].map(|row| primaries.map(|primary| primary[row]));
+        let scale = mul(&invert(&unscaled), self.white());
+        unscaled.map(|row| [row[0] * scale[0], row[1] * scale[1], row[2] * scale[2]])
+    }
+
+    pub fn xyz_to_rgb(&self) -> Mat3 {
+        invert(&self.rgb_to_xyz())
+    }
+
+    // Matrix taking colors of this space to `to`, adapting the white
+    pub fn conversion(&self, to: ColorSpace) -> &'static Mat3 {
+        static MATRICES: OnceLock<Vec<Mat3>> = OnceLock::new();
+        let matrices = MATRICES.get_or_init(|| {
+            let spaces: Vec<ColorSpace> = ColorSpace::iter().collect();
+            let mut matrices = Vec::with_capacity(spaces.len() * spaces.len());
+            for from in &spaces {
+                for to in &spaces {
+                    let adaptation = chromatic_adaptation(from.white(), to.white());
+                    matrices.push(mul_mat(&to.xyz_to_rgb(), &mul_mat(&adaptation, &from.rgb_to_xyz())));
+                }
+            }
+            matrices
+        });
+        &matrices[*self as usize * ColorSpace::iter().len() + to as usize]
+    }
+
+    pub fn convert(&self, rgb: Vec3, to: ColorSpace) -> Vec3 {
+        if *self == to {
+            return rgb;
+        }
+        Vec3::from(&mul(self.conversion(to), [rgb.x, rgb.y, rgb.z]))
+    }
+
+    // Scene colors, in linear sRGB, in this space
+    pub fn from_srgb(&self, spectrum: Spectrum) -> Spectrum {
+        match spectrum {
+            Spectrum::ColorRGB(rgb) => Spectrum::ColorRGB(ColorSpace::LinearSrgb.convert(rgb, *self)),
+            Spectrum::Sampled(_) => spectrum,
+        }
+    }
+
+    // Transfer function images in this space are encoded with. ACEScg is a
+    // scene referred space and stays linear.
+    pub fn oetf(&self, linear: Float) -> Float {
+        match self {
+            ColorSpace::LinearSrgb | ColorSpace::DisplayP3 => srgb_oetf(linear),
+            ColorSpace::Rec2020 => {
+                if linear < 0.0181 {
+                    4.5 * linear
+                } else {
+                    1.0993 * linear.powf(0.45) - 0.0993
+                }
+            }
+            ColorSpace::AcesCg => linear,
+        }
+    }
+}
+
+// Exact piecewise sRGB opto-electronic transfer function
+pub fn srgb_oetf(linear: Float) -> Float {
+    if linear <= 0.0031308 {
+        12.92 * linear
+    } else {
+        1.055 * linear.powf(1. / 2.4) - 0.055
+    }
+}
+
+pub fn srgb_eotf(encoded: Float) -> Float {
+    if encoded <= 0.04045 {
+        encoded / 12.92
+    } else {
+        ((encoded + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+// How the values of an image file relate to light. Color maps are usually
+// sRGB encoded, data maps like roughness or normals are linear.
+#[derive(Debug, EnumIter, EnumString, PartialEq, Eq, Clone, Copy, Display, Hash)]
+#[strum(ascii_case_insensitive)]
+pub enum ColorEncoding {
+    Srgb,
+    Linear,
+}
+
+impl ColorEncoding {
+    // Linear value of an 8-bit one
+    pub fn decode_u8(&self, value: u8) -> Float {
+        static SRGB: OnceLock<[Float; 256]> = OnceLock::new();
+        match self {
+            ColorEncoding::Srgb => SRGB.get_or_init(|| std::array::from_fn(|i| srgb_eotf(i as Float / 255.)))[value as usize],
+            ColorEncoding::Linear => value as Float / 255.,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_color_space() {
+        // The primaries give back the standard sRGB matrix
+        let srgb = ColorSpace::LinearSrgb.rgb_to_xyz();
+        assert!((srgb[0][0] - 0.4124564).abs() < 1e-3 && (srgb[1][1] - 0.7151522).abs() < 1e-3);
+
+        // White stays white, conversions are invertible, and saturated sRGB
+        // colors are inside the wider gamuts
+        let red = Vec3::new(1., 0., 0.);
+        for space in ColorSpace::iter() {
+            let white = ColorSpace::LinearSrgb.convert(Vec3::from(1.), space);
+            assert!((white - Vec3::from(1.)).length() < 1e-3, "{} {:?}", space, white);
+            let back = space.convert(ColorSpace::LinearSrgb.convert(red, space), ColorSpace::LinearSrgb);
+            assert!((back - red).length() < 1e-4);
+            let converted = ColorSpace::LinearSrgb.convert(red, space);
+            assert!(converted.x >= 0. && converted.y >= -1e-4 && converted.z >= -1e-4);
+        }
+        let acescg = ColorSpace::LinearSrgb.convert(red, ColorSpace::AcesCg);
+        assert!((acescg - Vec3::new(0.613, 0.070, 0.021)).length() < 5e-3, "{:?}", acescg);
+
+        assert_eq!("rec709".parse::<ColorSpace>(), Ok(ColorSpace::LinearSrgb));
+        assert_eq!("ACEScg".parse::<ColorSpace>(), Ok(ColorSpace::AcesCg));
+
+        for value in [0, 10, 128, 255] {
+            let linear = ColorEncoding::Srgb.decode_u8(value);
+            assert!((srgb_oetf(linear) * 255. - value as Float).abs() < 1e-3);
+        }
+        assert_eq!(ColorEncoding::Linear.decode_u8(51), 0.2);
+    }
+}
@@ -1,99 +1,539 @@
 use chrono::{DateTime, Utc};
-use image::{ImageBuffer, RgbImage};
-use math::Vec3;
-use std::fs::File;
-use std::io::prelude::*;
+use math::{Float, Vec2, Vec3};
 use std::path::Path;
 
+use rayon::prelude::*;
+
+use crate::core::aov::{AovFiltering, AovSample, AovType};
+use crate::core::color_space::{mul, ColorSpace};
+use crate::denoisers::atrous::{AtrousDenoiser, DenoiserFeatures, DENOISER_FEATURES};
+use crate::core::filter::Filter;
+use crate::core::image_pipeline::{heatmap, ImagePipeline};
+use crate::core::imageio::{self, ImageFormat, ImageLayer};
 use crate::core::spectrum::Spectrum;
 
-#[derive(Clone)]
+// Weighted sum of all the filtered samples that fell on a pixel. Kept in
+// double precision so thousands of samples can be accumulated without losing
+// the contribution of the last ones.
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct Pixel {
+    pub rgb_sum: [f64; 3],
+    pub weight_sum: f64,
+}
+
+impl Pixel {
+    fn add(&mut self, radiance: &Spectrum, weight: Float) {
+        let rgb = radiance.to_rgb();
+        self.rgb_sum[0] += (weight * rgb.x) as f64;
+        self.rgb_sum[1] += (weight * rgb.y) as f64;
+        self.rgb_sum[2] += (weight * rgb.z) as f64;
+        self.weight_sum += weight as f64;
+    }
+
+    pub fn value(&self) -> Spectrum {
+        if self.weight_sum == 0. {
+            return Spectrum::ColorRGB(Vec3::from(0.));
+        }
+        let inv_weight = 1. / self.weight_sum;
+        Spectrum::ColorRGB(Vec3::new(
+            (self.rgb_sum[0] * inv_weight) as Float,
+            (self.rgb_sum[1] * inv_weight) as Float,
+            (self.rgb_sum[2] * inv_weight) as Float,
+        ))
+    }
+}
+
+// Running mean and variance of the luminance of the samples taken in a
+// pixel, with Welford's online algorithm.
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct Welford {
+    pub count: u32,
+    pub mean: f64,
+    pub m2: f64,
+}
+
+impl Welford {
+    pub fn add(&mut self, value: Float) {
+        let value = value as f64;
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    // Unbiased variance of the samples
+    pub fn variance(&self) -> Float {
+        if self.count < 2 {
+            return 0.;
+        }
+        (self.m2 / (self.count - 1) as f64) as Float
+    }
+
+    // Variance of the estimated mean, i.e. the squared standard error
+    pub fn variance_of_mean(&self) -> Float {
+        if self.count == 0 {
+            return 0.;
+        }
+        self.variance() / self.count as Float
+    }
+
+    // Standard error relative to the mean. Dark pixels are measured against
+    // a small floor instead, so they don't need countless samples.
+    pub fn relative_error(&self) -> Float {
+        self.variance_of_mean().sqrt() / (self.mean as Float).max(1e-2)
+    }
+}
+
+// A radiance sample at a continuous film position. Film positions are in
+// pixels, with (0, 0) at the top left corner of the image and pixel (x, y)
+// covering [x, x + 1) x [y, y + 1).
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct FilmSample {
+    pub p_film: Vec2,
+    pub radiance: Spectrum,
+}
+
+// Accumulated values of one arbitrary output variable
+#[derive(Clone, Debug)]
+pub struct AovBuffer {
+    pub aov: AovType,
+    pub pixels: Vec<Pixel>,
+    // Whether the AOV is written out, or only kept for the denoiser
+    pub output: bool,
+}
+
+#[derive(Clone, Debug)]
 pub struct Film {
     pub width: u32,
     pub height: u32,
     pub file_name: String,
-    pub pixels: Vec<Spectrum>,
+    pub filter: Filter,
+    pub pixels: Vec<Pixel>,
+    // Luminance statistics of the samples taken inside each pixel
+    pub variance: Vec<Welford>,
+    pub aovs: Vec<AovBuffer>,
+    // Working color space of the image and the light AOVs
+    pub color_space: ColorSpace,
 }
 
 impl Default for Film {
     fn default() -> Self {
-        Self {
-            width: 100,
-            height: 100,
-            file_name: "Image".to_owned(),
-            pixels: vec![Spectrum::ColorRGB(Vec3::from(0.)); 100 as usize * 100 as usize],
-        }
+        Film::new(100, 100, "Image")
     }
 }
 
 impl Film {
-    // TODO: new only from pixels. And make it so that pixels is the only struct that's needed.
     pub fn new(width: u32, height: u32, file_name: &str) -> Self {
+        Film::with_filter(width, height, file_name, Filter::default())
+    }
+
+    pub fn with_filter(width: u32, height: u32, file_name: &str, filter: Filter) -> Self {
         Self {
             width,
             height,
             file_name: String::from(file_name),
-            pixels: vec![Spectrum::ColorRGB(Vec3::from(0.)); width as usize * height as usize],
+            filter,
+            pixels: vec![Pixel::default(); width as usize * height as usize],
+            variance: vec![Welford::default(); width as usize * height as usize],
+            aovs: Vec::new(),
+            color_space: ColorSpace::default(),
         }
     }
 
     pub fn set_pixel(&mut self, x: u32, y: u32, color: Spectrum) {
         let index = (x + self.width * y) as usize;
-        self.pixels[index] = color;
+        self.pixels[index] = Pixel::default();
+        self.pixels[index].add(&color, 1.);
+    }
+
+    pub fn set_pixels(&mut self, pixels: &[Spectrum]) {
+        for (pixel, color) in self.pixels.iter_mut().zip(pixels) {
+            *pixel = Pixel::default();
+            pixel.add(color, 1.);
+        }
+    }
+
+    pub fn get_pixel(&self, x: u32, y: u32) -> Spectrum {
+        self.pixels[(x + self.width * y) as usize].value()
+    }
+
+    // Reconstructed image, row by row from the top
+    pub fn get_pixels(&self) -> Vec<Spectrum> {
+        self.pixels.iter().map(|pixel| pixel.value()).collect()
+    }
+
+    // Splat one sample to every pixel within the filter radius
+    pub fn add_sample(&mut self, sample: &FilmSample) {
+        let radius = self.filter.radius();
+        // Pixel centers are at half integer coordinates
+        let p = sample.p_film - Vec2::from(0.5);
+        let x0 = Float::max(0., (p.x() - radius.x()).ceil()) as u32;
+        let y0 = Float::max(0., (p.y() - radius.y()).ceil()) as u32;
+        let x1 = Float::min((self.width - 1) as Float, (p.x() + radius.x()).floor()) as i64;
+        let y1 = Float::min((self.height - 1) as Float, (p.y() + radius.y()).floor()) as i64;
+        for y in y0 as i64..=y1 {
+            for x in x0 as i64..=x1 {
+                let offset = Vec2::new(&[x as Float - p.x(), y as Float - p.y()]);
+                let weight = self.filter.evaluate(&offset);
+                if weight != 0. {
+                    self.pixels[(x + y * self.width as i64) as usize].add(&sample.radiance, weight);
+                }
+            }
+        }
+    }
+
+    // Add one pass of samples, at most one per pixel and in pixel order, None
+    // for pixels that weren't sampled. This gives the same result as calling
+    // `add_sample` for each of them, but every pixel gathers from its
+    // neighbours in a fixed order so the work can be spread over threads
+    // without changing a single bit of the output.
+    pub fn add_pass(&mut self, samples: &[Option<FilmSample>], parallel: bool) {
+        debug_assert_eq!(samples.len(), self.pixels.len());
+        let (width, height, filter) = (self.width, self.height, self.filter);
+        gather_pass(&mut self.pixels, width, height, filter, samples, |_, sample| sample.radiance, parallel);
+        for (variance, sample) in self.variance.iter_mut().zip(samples) {
+            if let Some(sample) = sample {
+                variance.add(sample.radiance.luminance());
+            }
+        }
+    }
+
+    // Allocate a buffer for each of `aovs`, dropping the ones not listed
+    pub fn enable_aovs(&mut self, aovs: &[AovType]) {
+        let num_pixels = self.pixels.len();
+        self.aovs = aovs
+            .iter()
+            .map(|aov| AovBuffer {
+                aov: *aov,
+                pixels: vec![Pixel::default(); num_pixels],
+                output: true,
+            })
+            .collect();
+    }
+
+    // Also record the feature buffers the denoiser is guided by
+    pub fn enable_denoiser_features(&mut self) {
+        for aov in DENOISER_FEATURES {
+            if self.aov(aov).is_none() {
+                self.aovs.push(AovBuffer {
+                    aov,
+                    pixels: vec![Pixel::default(); self.pixels.len()],
+                    output: false,
+                });
+            }
+        }
     }
 
-    pub fn set_pixels(&mut self, pixels: &Vec<Spectrum>) {
-        self.pixels = pixels.to_vec();
+    // Accumulated values of an AOV, if it's recorded
+    pub fn aov(&self, aov: AovType) -> Option<Vec<Spectrum>> {
+        let buffer = self.aovs.iter().find(|buffer| buffer.aov == aov)?;
+        Some(buffer.pixels.iter().map(|pixel| pixel.value()).collect())
     }
 
-    pub fn write_image(&self) -> String {
+    // Copy of the film with the image replaced by its denoised version.
+    // Films without feature buffers are denoised on color alone.
+    pub fn denoised(&self, denoiser: &AtrousDenoiser) -> Film {
+        let num_pixels = self.pixels.len();
+        let channel = |aov: AovType| -> Option<Vec<Vec3>> {
+            Some(self.aov(aov)?.iter().map(|value| value.to_rgb()).collect())
+        };
+        let features = DenoiserFeatures {
+            albedo: channel(AovType::Albedo).unwrap_or_else(|| vec![Vec3::from(1.); num_pixels]),
+            normal: channel(AovType::Normal).unwrap_or_else(|| vec![Vec3::from(0.); num_pixels]),
+            depth: match channel(AovType::Depth) {
+                Some(depth) => depth.iter().map(|value| value.x).collect(),
+                None => vec![0.; num_pixels],
+            },
+        };
+        let variance: Vec<Float> = self.variance.iter().map(|v| v.variance_of_mean()).collect();
+        let denoised = denoiser.denoise(&self.get_pixels(), &variance, &features, self.width, self.height);
+
+        let mut film = self.clone();
+        film.set_pixels(&denoised);
+        film
+    }
+
+    // Add the AOVs recorded for one pass of `add_pass`
+    pub fn add_aov_pass(&mut self, samples: &[Option<FilmSample>], aov_samples: &[AovSample], parallel: bool) {
+        debug_assert_eq!(aov_samples.len(), self.pixels.len());
+        let (width, height, filter) = (self.width, self.height, self.filter);
+        for buffer in self.aovs.iter_mut() {
+            let aov = buffer.aov;
+            match aov.filtering() {
+                AovFiltering::Filtered => gather_pass(
+                    &mut buffer.pixels,
+                    width,
+                    height,
+                    filter,
+                    samples,
+                    |index, _| aov_samples[index].value(aov),
+                    parallel,
+                ),
+                // Each sample lies inside its own pixel, no need to look
+                // at the neighbours
+                AovFiltering::Average => {
+                    for (pixel, sample) in buffer.pixels.iter_mut().zip(aov_samples) {
+                        if sample.hit {
+                            pixel.add(&sample.value(aov), 1.);
+                        }
+                    }
+                }
+                // Pixels skipped by adaptive sampling have no sample
+                AovFiltering::First => {
+                    for ((pixel, aov_sample), sample) in buffer.pixels.iter_mut().zip(aov_samples).zip(samples) {
+                        if sample.is_none() {
+                            continue;
+                        }
+                        if pixel.weight_sum == 0. {
+                            pixel.add(&aov_sample.value(aov), 1.);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // Enabled AOVs as linear layers, in the order they were enabled
+    pub fn aov_layers(&self) -> Vec<ImageLayer> {
+        self.aovs
+            .iter()
+            .filter(|buffer| buffer.output)
+            .map(|buffer| {
+                let channels = buffer.aov.channels();
+                let mut data = Vec::with_capacity(buffer.pixels.len() * channels.len());
+                for pixel in &buffer.pixels {
+                    let value = pixel.value().to_float_array();
+                    data.extend_from_slice(&value[..channels.len()]);
+                }
+                ImageLayer::new(buffer.aov.name(), channels, data)
+            })
+            .collect()
+    }
+
+    // Display colors showing how many samples each pixel received, relative
+    // to `max_samples`
+    pub fn sample_count_heatmap(&self, max_samples: u32) -> Vec<[Float; 3]> {
+        self.variance
+            .iter()
+            .map(|variance| heatmap(variance.count as Float / max_samples.max(1) as Float))
+            .collect()
+    }
+
+    // Main image as a linear RGB layer of `output_space`
+    pub fn beauty_layer(&self, output_space: ColorSpace) -> ImageLayer {
+        let conversion = self.color_space.conversion(output_space);
+        let mut data = Vec::with_capacity(self.pixels.len() * 3);
+        for pixel in &self.pixels {
+            data.extend_from_slice(&mul(conversion, pixel.value().to_float_array()));
+        }
+        ImageLayer::new("", &["R", "G", "B"], data)
+    }
+
+    // Main image run through the display pipeline, for 8-bit formats
+    pub fn display_layer(&self, pipeline: &ImagePipeline) -> ImageLayer {
+        let data = pipeline
+            .process(&self.get_pixels(), self.width, self.color_space)
+            .into_iter()
+            .flatten()
+            .collect();
+        ImageLayer::new("", &["R", "G", "B"], data)
+    }
+
+    // Write the film to the output folder. The format comes from the
+    // extension of `file_name` when it has a known one, `format` otherwise.
+    // High dynamic range formats get the linear radiance, `pipeline` is only
+    // applied to 8-bit ones. Enabled AOVs are written along.
+    pub fn write_image(&self, format: ImageFormat, pipeline: &ImagePipeline) -> String {
         let now: DateTime<Utc> = Utc::now();
         log::info!("UTC now is: {}", now);
-        let path_ppm_string = format!(
-            "output/{}-{}.ppm",
-            self.file_name,
-            now.format("%v-%H-%M-%S")
+
+        let (stem, format) = self.output_name(format);
+        let path_string = format!(
+            "output/{}-{}.{}",
+            stem,
+            now.format("%v-%H-%M-%S"),
+            format.extension()
         );
-        let path_ppm = Path::new(&path_ppm_string);
+        self.write_layers(path_string, format, pipeline)
+    }
 
-        let mut img_png: RgbImage = ImageBuffer::new(self.width, self.height);
-        let mut file = match File::create(&path_ppm) {
-            Ok(file) => file,
-            Err(why) => panic!("couldn't create {}: {}", path_ppm.display(), why),
+    // Write frame `frame` of a sequence as output/<file name>_0001.exr and so
+    // on. Unlike single images they have no timestamp, so they sort and load
+    // as a sequence.
+    pub fn write_frame(&self, frame: u32, format: ImageFormat, pipeline: &ImagePipeline) -> String {
+        let (stem, format) = self.output_name(format);
+        let path_string = format!("output/{}_{:04}.{}", stem, frame, format.extension());
+        self.write_layers(path_string, format, pipeline)
+    }
+
+    // File name without its extension and the format the extension picks
+    fn output_name(&self, format: ImageFormat) -> (String, ImageFormat) {
+        let file_path = Path::new(&self.file_name);
+        match ImageFormat::from_path(file_path, format) {
+            Some(file_format) => (
+                file_path.file_stem().unwrap_or_default().to_string_lossy().to_string(),
+                file_format,
+            ),
+            None => (self.file_name.clone(), format),
+        }
+    }
+
+    fn write_layers(&self, path_string: String, format: ImageFormat, pipeline: &ImagePipeline) -> String {
+        let path = Path::new(&path_string);
+        if let Err(why) = std::fs::create_dir_all("output") {
+            panic!("couldn't create output folder: {}", why);
+        }
+
+        let mut layers = vec![if format.is_hdr() {
+            self.beauty_layer(pipeline.output_space)
+        } else {
+            self.display_layer(pipeline)
+        }];
+        let written = self.aovs.iter().filter(|buffer| buffer.output);
+        for (buffer, mut layer) in written.zip(self.aov_layers()) {
+            if buffer.aov.is_light() {
+                let pixels: Vec<Spectrum> = buffer.pixels.iter().map(|pixel| pixel.value()).collect();
+                layer.data = if format.is_hdr() {
+                    let conversion = self.color_space.conversion(pipeline.output_space);
+                    pixels.iter().flat_map(|pixel| mul(conversion, pixel.to_float_array())).collect()
+                } else {
+                    pipeline.process(&pixels, self.width, self.color_space).into_iter().flatten().collect()
+                };
+            }
+            layers.push(layer);
+        }
+        match imageio::write_image(path, format, self.width, self.height, &layers) {
+            Ok(paths) => {
+                for path in paths {
+                    log::info!("successfully wrote image to {}", path.display());
+                }
+            }
+            Err(why) => panic!("couldn't write image to {}: {}", path.display(), why),
         };
 
-        let mut image: String = format!("P3\n{} {}\n255\n", self.width, self.height);
-        for y in (0..=self.height - 1).rev() {
-            for x in 0..self.width {
-                let index = (x + self.width * y) as usize;
-                let Spectrum::ColorRGB(color) = &self.pixels[index];
-                let ir = (255.99 * color.r()) as u8;
-                let ig = (255.99 * color.g()) as u8;
-                let ib = (255.99 * color.b()) as u8;
-
-                // TODO: Create an option to write either to ppm or png, but not both
-                image.push_str(&format!("{} {} {}\n", ir, ig, ib));
-                img_png.put_pixel(x, self.height - y - 1, image::Rgb([ir, ig, ib]));
+        return path_string;
+    }
+}
+
+// Filter one pass of samples, at most one per pixel and in pixel order, into
+// `pixels`. Every pixel gathers from its neighbours in a fixed order so the
+// result doesn't depend on the number of threads.
+fn gather_pass(
+    pixels: &mut [Pixel],
+    width: u32,
+    height: u32,
+    filter: Filter,
+    samples: &[Option<FilmSample>],
+    radiance: impl Fn(usize, &FilmSample) -> Spectrum + Sync,
+    parallel: bool,
+) {
+    let width = width as i64;
+    let height = height as i64;
+    let radius = filter.radius();
+    // Only samples taken at most this many pixels away can reach the
+    // center of a pixel
+    let reach_x = radius.x().ceil() as i64;
+    let reach_y = radius.y().ceil() as i64;
+
+    let gather = |index: usize, pixel: &mut Pixel| {
+        let px = index as i64 % width;
+        let py = index as i64 / width;
+        let center = Vec2::new(&[px as Float + 0.5, py as Float + 0.5]);
+        for y in (py - reach_y).max(0)..=(py + reach_y).min(height - 1) {
+            for x in (px - reach_x).max(0)..=(px + reach_x).min(width - 1) {
+                let sample_index = (x + y * width) as usize;
+                let Some(sample) = &samples[sample_index] else {
+                    continue;
+                };
+                let offset = center - sample.p_film;
+                if offset.x().abs() > radius.x() || offset.y().abs() > radius.y() {
+                    continue;
+                }
+                let weight = filter.evaluate(&offset);
+                if weight != 0. {
+                    pixel.add(&radiance(sample_index, sample), weight);
+                }
             }
         }
+    };
 
-        match file.write_all(image.as_bytes()) {
-            Ok(_) => log::info!("successfully wrote image to {}", path_ppm.display()),
-            Err(why) => panic!("couldn't write image to {}: {}", path_ppm.display(), why),
-        };
+    if parallel {
+        pixels
+            .par_iter_mut()
+            .enumerate()
+            .for_each(|(index, pixel)| gather(index, pixel));
+    } else {
+        for (index, pixel) in pixels.iter_mut().enumerate() {
+            gather(index, pixel);
+        }
+    }
+}
 
-        // Write to png
-        let path_png_string = format!(
-            "output/{}-{}.png",
-            self.file_name,
-            now.format("%v-%H-%M-%S")
-        );
-        let path_png = Path::new(&path_png_string);
-        match img_png.save(path_png) {
-            Ok(_) => log::info!("successfully wrote image to {}", path_png.display()),
-            Err(why) => panic!("couldn't write image to {}: {}", path_png.display(), why),
-        };
+#[cfg(test)]
+mod tests {
+    use strum::IntoEnumIterator;
+
+    use super::*;
+    use crate::core::filter::FilterType;
+
+    fn jittered_pass(width: u32, height: u32, radiance: impl Fn(u32, u32) -> Float) -> Vec<Option<FilmSample>> {
+        (0..width * height)
+            .map(|i| {
+                let (x, y) = (i % width, i / width);
+                // Deterministic jitter inside each pixel
+                let jitter = ((i * 7919) % 97) as Float / 97.;
+                Some(FilmSample {
+                    p_film: Vec2::new(&[x as Float + jitter, y as Float + 1. - jitter]),
+                    radiance: Spectrum::ColorRGB(Vec3::from(radiance(x, y))),
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_constant_radiance_is_preserved_by_every_filter() {
+        for filter_type in FilterType::iter() {
+            let mut film = Film::with_filter(16, 12, "test", Filter::from(filter_type));
+            film.add_pass(&jittered_pass(16, 12, |_, _| 0.25), true);
+            film.add_pass(&jittered_pass(16, 12, |_, _| 0.75), true);
+            for pixel in film.get_pixels() {
+                let rgb = pixel.to_rgb();
+                assert!((rgb.x - 0.5).abs() < 1e-4, "{} gave {}", filter_type, rgb);
+            }
+        }
+    }
+
+    #[test]
+    fn test_pass_matches_individual_splats() {
+        let samples = jittered_pass(9, 7, |x, y| (x * y) as Float);
+        let mut gathered = Film::with_filter(9, 7, "test", Filter::from(FilterType::Gaussian));
+        gathered.add_pass(&samples, false);
+        let mut splatted = Film::with_filter(9, 7, "test", Filter::from(FilterType::Gaussian));
+        for sample in samples.iter().flatten() {
+            splatted.add_sample(sample);
+        }
+        for (a, b) in gathered.pixels.iter().zip(&splatted.pixels) {
+            assert!((a.weight_sum - b.weight_sum).abs() < 1e-5);
+            assert!((a.rgb_sum[0] - b.rgb_sum[0]).abs() < 1e-4);
+        }
+    }
 
-        return path_png_string;
+    #[test]
+    fn test_first_aov_skips_unsampled_pixels() {
+        let mut film = Film::with_filter(3, 1, "test", Filter::from(FilterType::Box));
+        film.enable_aovs(&[AovType::PrimitiveId]);
+        // Adaptive sampling skipped the first pixel
+        let mut samples = jittered_pass(3, 1, |_, _| 1.);
+        samples[0] = None;
+        let aov_samples: Vec<AovSample> = (0..3)
+            .map(|i| AovSample {
+                hit: true,
+                primitive_id: i + 1,
+                ..AovSample::default()
+            })
+            .collect();
+        film.add_aov_pass(&samples, &aov_samples, false);
+        let ids: Vec<Float> = film.aovs[0].pixels.iter().map(|pixel| pixel.value().to_rgb().x).collect();
+        assert_eq!(ids, vec![0., 2., 3.]);
     }
 }
@@ -0,0 +1,74 @@
+use math::{Float, Vec2};
+use strum_macros::{Display, EnumIter, EnumString};
+
+use crate::filters::{
+    boxfilter::BoxFilter, gaussian::GaussianFilter, lanczos::LanczosSincFilter,
+    mitchell::MitchellFilter, tent::TentFilter,
+};
+
+// Pixel reconstruction filters. A sample contributes to every pixel whose
+// center lies within `radius` of it, weighted by `evaluate`.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Filter {
+    Box(BoxFilter),
+    Tent(TentFilter),
+    Gaussian(GaussianFilter),
+    Mitchell(MitchellFilter),
+    LanczosSinc(LanczosSincFilter),
+}
+
+#[derive(Debug, EnumIter, EnumString, PartialEq, Clone, Copy, Display)]
+#[strum(ascii_case_insensitive)]
+pub enum FilterType {
+    Box,
+    Tent,
+    Gaussian,
+    Mitchell,
+    LanczosSinc,
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Filter::from(FilterType::Box)
+    }
+}
+
+// Filters with their usual default parameters
+impl From<FilterType> for Filter {
+    fn from(filter_type: FilterType) -> Self {
+        match filter_type {
+            FilterType::Box => Filter::Box(BoxFilter::new(Vec2::from(0.5))),
+            FilterType::Tent => Filter::Tent(TentFilter::new(Vec2::from(1.))),
+            FilterType::Gaussian => Filter::Gaussian(GaussianFilter::new(Vec2::from(1.5), 0.5)),
+            FilterType::Mitchell => {
+                Filter::Mitchell(MitchellFilter::new(Vec2::from(2.), 1. / 3., 1. / 3.))
+            }
+            FilterType::LanczosSinc => {
+                Filter::LanczosSinc(LanczosSincFilter::new(Vec2::from(3.), 3.))
+            }
+        }
+    }
+}
+
+impl Filter {
+    pub fn radius(&self) -> Vec2 {
+        match self {
+            Filter::Box(filter) => filter.radius,
+            Filter::Tent(filter) => filter.radius,
+            Filter::Gaussian(filter) => filter.radius,
+            Filter::Mitchell(filter) => filter.radius,
+            Filter::LanczosSinc(filter) => filter.radius,
+        }
+    }
+
+    // `p` is the offset from the filter center, in pixels
+    pub fn evaluate(&self, p: &Vec2) -> Float {
+        match self {
+            Filter::Box(filter) => filter.evaluate(p),
+            Filter::Tent(filter) => filter.evaluate(p),
+            Filter::Gaussian(filter) => filter.evaluate(p),
+            Filter::Mitchell(filter) => filter.evaluate(p),
+            Filter::LanczosSinc(filter) => filter.evaluate(p),
+        }
+    }
+}
@@ -0,0 +1,305 @@
+use math::Float;
+use strum_macros::{Display, EnumIter, EnumString};
+
+use crate::core::color_space::{chromatic_adaptation, mul, mul_mat, ColorSpace, Mat3};
+use crate::core::sampler::Sampler;
+use crate::core::spectrum::Spectrum;
+
+// Turns the linear, scene referred radiance of the film into display encoded
+// values in [0, 1] of the output color space. Runs after the render, so
+// every setting here can be changed without re-rendering.
+//
+//   radiance -> output space -> exposure -> white balance -> tone map -> OETF -> dither
+
+#[derive(Debug, EnumIter, EnumString, PartialEq, Clone, Copy, Display)]
+#[strum(ascii_case_insensitive)]
+pub enum ToneMapOperator {
+    // Plain clamp to [0, 1]
+    Clamp,
+    Reinhard,
+    ExtendedReinhard,
+    Aces,
+    AgX,
+    Hable,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ImagePipeline {
+    // Exposure compensation in stops
+    pub exposure: Float,
+    // Color temperature, in kelvin, of the illuminant that should appear
+    // white. 0 disables white balancing.
+    pub white_balance: Float,
+    pub tone_map: ToneMapOperator,
+    // Smallest luminance mapped to pure white by the extended Reinhard operator
+    pub white_point: Float,
+    // Add one LSB of triangular noise before quantization to hide banding
+    pub dither: bool,
+    // Color space of the written images, high dynamic range ones included
+    pub output_space: ColorSpace,
+}
+
+impl Default for ImagePipeline {
+    fn default() -> Self {
+        Self {
+            exposure: 0.,
+            white_balance: 0.,
+            tone_map: ToneMapOperator::Clamp,
+            white_point: 4.,
+            dither: true,
+            output_space: ColorSpace::LinearSrgb,
+        }
+    }
+}
+
+// ---- Color matrices
+
+// ACES fit by Stephen Hill: sRGB -> ACES AP1 with the RRT saturation folded in
+const ACES_INPUT: Mat3 = [
+    [0.59719, 0.35458, 0.04823],
+    [0.07600, 0.90834, 0.01566],
+    [0.02840, 0.13383, 0.83777],
+];
+
+const ACES_OUTPUT: Mat3 = [
+    [1.60475, -0.53108, -0.07367],
+    [-0.10208, 1.10813, -0.00605],
+    [-0.00327, -0.07276, 1.07602],
+];
+
+// AgX inset and outset matrices, from Troy Sobotka's Blender configuration
+const AGX_INSET: Mat3 = [
+    [0.84247905, 0.0784336, 0.079223745],
+    [0.042328242, 0.87846863, 0.07916613],
+    [0.042375654, 0.0784336, 0.879143],
+];
+
+const AGX_OUTSET: Mat3 = [
+    [1.196879, -0.09802088, -0.09902974],
+    [-0.052896854, 1.1519032, -0.098961174],
+    [-0.052971635, -0.09804345, 1.1510737],
+];
+
+fn luminance(rgb: [Float; 3]) -> Float {
+    0.2126 * rgb[0] + 0.7152 * rgb[1] + 0.0722 * rgb[2]
+}
+
+// ---- White balance
+
+// xy chromaticity of a black body at `temperature` kelvin, using the cubic
+// spline fit of Kim et al. Valid from 1667K to 25000K.
+pub fn planckian_xy(temperature: Float) -> (Float, Float) {
+    let t = temperature.clamp(1667., 25000.);
+    let x = if t <= 4000. {
+        -0.2661239e9 / (t * t * t) - 0.2343589e6 / (t * t) + 0.8776956e3 / t + 0.179910
+    } else {
+        -3.025847e9 / (t * t * t) + 2.107038e6 / (t * t) + 0.2226347e3 / t + 0.240390
+    };
+    let y = if t <= 2222. {
+        -1.1063814 * x * x * x - 1.3481102 * x * x + 2.1855583 * x - 0.20219683
+    } else if t <= 4000. {
+        -0.9549476 * x * x * x - 1.3741859 * x * x + 2.09137 * x - 0.16748867
+    } else {
+        3.081758 * x * x * x - 5.873387 * x * x + 3.7511299 * x - 0.37001483
+    };
+    (x, y)
+}
+
+// Matrix of `space` that maps the white of a black body at `temperature` to
+// the white of the space, using a Bradford von Kries adaptation.
+pub fn white_balance_matrix(temperature: Float, space: ColorSpace) -> Mat3 {
+    let (x, y) = planckian_xy(temperature);
+    let adaptation = chromatic_adaptation([x / y, 1., (1. - x - y) / y], space.white());
+    mul_mat(&space.xyz_to_rgb(), &mul_mat(&adaptation, &space.rgb_to_xyz()))
+}
+
+// ---- Tone mapping operators
+
+fn reinhard(rgb: [Float; 3], white_point: Option<Float>) -> [Float; 3] {
+    let l = luminance(rgb);
+    if l <= 0. {
+        return [0.; 3];
+    }
+    let mapped = match white_point {
+        Some(white) => l * (1. + l / (white * white)) / (1. + l),
+        None => l / (1. + l),
+    };
+    let scale = mapped / l;
+    [rgb[0] * scale, rgb[1] * scale, rgb[2] * scale]
+}
+
+fn aces(rgb: [Float; 3]) -> [Float; 3] {
+    let v = mul(&ACES_INPUT, rgb).map(|v| {
+        let a = v * (v + 0.0245786) - 0.000090537;
+        let b = v * (0.983729 * v + 0.432951) + 0.238081;
+        a / b
+    });
+    mul(&ACES_OUTPUT, v)
+}
+
+fn agx(rgb: [Float; 3]) -> [Float; 3] {
+    const MIN_EV: Float = -12.47393;
+    const MAX_EV: Float = 4.026069;
+    let v = mul(&AGX_INSET, rgb).map(|v| {
+        let x = (v.max(1e-10).log2().clamp(MIN_EV, MAX_EV) - MIN_EV) / (MAX_EV - MIN_EV);
+        // Polynomial fit of the default AgX contrast curve
+        let x2 = x * x;
+        let x4 = x2 * x2;
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2
+            + 0.1191 * x
+            - 0.00232
+    });
+    // The curve outputs values meant for a 2.2 display, bring them back to
+    // linear so every operator goes through the same OETF
+    mul(&AGX_OUTSET, v).map(|v| v.max(0.).powf(2.2))
+}
+
+fn hable_curve(x: Float) -> Float {
+    const A: Float = 0.15;
+    const B: Float = 0.50;
+    const C: Float = 0.10;
+    const D: Float = 0.20;
+    const E: Float = 0.02;
+    const F: Float = 0.30;
+    ((x * (A * x + C * B) + D * E) / (x * (A * x + B) + D * F)) - E / F
+}
+
+fn hable(rgb: [Float; 3]) -> [Float; 3] {
+    const EXPOSURE_BIAS: Float = 2.;
+    const LINEAR_WHITE: Float = 11.2;
+    let white_scale = 1. / hable_curve(LINEAR_WHITE);
+    rgb.map(|v| hable_curve(v * EXPOSURE_BIAS) * white_scale)
+}
+
+impl ToneMapOperator {
+    pub fn apply(&self, rgb: [Float; 3], white_point: Float) -> [Float; 3] {
+        let mapped = match self {
+            ToneMapOperator::Clamp => rgb,
+            ToneMapOperator::Reinhard => reinhard(rgb, None),
+            ToneMapOperator::ExtendedReinhard => reinhard(rgb, Some(white_point)),
+            ToneMapOperator::Aces => aces(rgb),
+            ToneMapOperator::AgX => agx(rgb),
+            ToneMapOperator::Hable => hable(rgb),
+        };
+        mapped.map(|v| v.clamp(0., 1.))
+    }
+}
+
+// ---- Encoding
+
+const DITHER_SEED: u64 = 0x5eed_d17e;
+
+impl ImagePipeline {
+    // Matrix taking linear colors of `working_space` to the output space,
+    // white balanced
+    pub fn color_matrix(&self, working_space: ColorSpace) -> Mat3 {
+        let conversion = *working_space.conversion(self.output_space);
+        if self.white_balance > 0. {
+            mul_mat(&white_balance_matrix(self.white_balance, self.output_space), &conversion)
+        } else {
+            conversion
+        }
+    }
+
+    // Display encoded color of a single pixel, without dithering. `color`
+    // comes from `color_matrix`.
+    pub fn encode(&self, rgb: [Float; 3], color: &Mat3) -> [Float; 3] {
+        let exposed = mul(color, rgb).map(|v| v * (2. as Float).powf(self.exposure));
+        self.tone_map
+            .apply(exposed, self.white_point)
+            .map(|v| self.output_space.oetf(v))
+    }
+
+    // Display encoded pixels of a width x height image in `working_space`,
+    // rows top to bottom. When dithering, values are offset by up to one
+    // 8-bit step so they should be rounded, not truncated, when quantized.
+    pub fn process(&self, pixels: &[Spectrum], width: u32, working_space: ColorSpace) -> Vec<[Float; 3]> {
+        let color = self.color_matrix(working_space);
+        let mut sampler = Sampler::new(DITHER_SEED);
+        pixels
+            .iter()
+            .enumerate()
+            .map(|(index, pixel)| {
+                let encoded = self.encode(pixel.to_float_array(), &color);
+                if !self.dither {
+                    return encoded;
+                }
+                // Triangular noise in [-1, 1] LSB
+                let x = index as u32 % width;
+                let y = index as u32 / width;
+                sampler.start_pixel_sample(x, y, 0);
+                encoded.map(|v| {
+                    let noise = sampler.random_0_1() - sampler.random_0_1();
+                    v + noise / 255.
+                })
+            })
+            .collect()
+    }
+}
+
+// False color ramp from dark blue (0) through green to red (1), for debug
+// views
+pub fn heatmap(t: Float) -> [Float; 3] {
+    const STOPS: [[Float; 3]; 5] = [
+        [0.05, 0.05, 0.4],
+        [0., 0.6, 0.9],
+        [0.1, 0.8, 0.2],
+        [1., 0.85, 0.],
+        [0.9, 0.05, 0.05],
+    ];
+    let x = t.clamp(0., 1.) * (STOPS.len() - 1) as Float;
+    let i = (x as usize).min(STOPS.len() - 2);
+    let f = x - i as Float;
+    let (a, b) = (STOPS[i], STOPS[i + 1]);
+    [
+        a[0] + (b[0] - a[0]) * f,
+        a[1] + (b[1] - a[1]) * f,
+        a[2] + (b[2] - a[2]) * f,
+    ]
+}
+
+// Round a display encoded value to 8 bits
+pub fn quantize(value: Float) -> u8 {
+    (value.clamp(0., 1.) * 255. + 0.5) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::color_space::srgb_oetf;
+    use strum::IntoEnumIterator;
+
+    #[test]
+    fn test_srgb_oetf() {
+        assert_eq!(srgb_oetf(0.), 0.);
+        assert!((srgb_oetf(1.) - 1.).abs() < 1e-6);
+        assert!((srgb_oetf(0.18) - 0.4613561).abs() < 1e-5);
+        // Both pieces meet at the breakpoint
+        let below = srgb_oetf(0.0031308);
+        let above = 1.055 * (0.0031308 as Float).powf(1. / 2.4) - 0.055;
+        assert!((below - above).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_tone_maps_are_monotonic() {
+        for operator in ToneMapOperator::iter() {
+            let mut previous = operator.apply([0.; 3], 4.);
+            assert!(previous.iter().all(|v| *v < 0.01), "{}", operator);
+            for i in 1..100 {
+                let value = operator.apply([i as Float * 0.1; 3], 4.);
+                assert!(luminance(value) >= luminance(previous) - 1e-5, "{}", operator);
+                previous = value;
+            }
+        }
+    }
+
+    #[test]
+    fn test_white_balance_neutralizes_illuminant() {
+        // A tungsten white becomes neutral
+        let (x, y) = planckian_xy(2856.);
+        let tungsten = mul(&ColorSpace::LinearSrgb.xyz_to_rgb(), [x / y, 1., (1. - x - y) / y]);
+        let balanced = mul(&white_balance_matrix(2856., ColorSpace::LinearSrgb), tungsten);
+        assert!((balanced[0] - balanced[2]).abs() < 1e-3);
+        assert!((balanced[0] - balanced[1]).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,328 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use exr::prelude::{
+    f16, AnyChannel, AnyChannels, Encoding, FlatSamples, Image, Layer, LayerAttributes, SmallVec,
+    WritableImage,
+};
+use image::{ImageBuffer, RgbImage};
+use math::Float;
+use strum_macros::{Display, EnumIter, EnumString};
+
+use crate::core::image_pipeline::quantize;
+
+#[derive(Debug, EnumIter, EnumString, PartialEq, Clone, Copy, Display)]
+#[strum(ascii_case_insensitive)]
+pub enum ImageFormat {
+    Png,
+    Ppm,
+    ExrHalf,
+    ExrFloat,
+    Hdr,
+    Pfm,
+}
+
+impl ImageFormat {
+    // Format matching the extension of `path`, if it is one we can write.
+    // Both exr variants share the extension, `exr_default` breaks the tie.
+    pub fn from_path(path: &Path, exr_default: ImageFormat) -> Option<ImageFormat> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "png" => Some(ImageFormat::Png),
+            "ppm" => Some(ImageFormat::Ppm),
+            "exr" => match exr_default {
+                ImageFormat::ExrFloat => Some(ImageFormat::ExrFloat),
+                _ => Some(ImageFormat::ExrHalf),
+            },
+            "hdr" => Some(ImageFormat::Hdr),
+            "pfm" => Some(ImageFormat::Pfm),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Ppm => "ppm",
+            ImageFormat::ExrHalf | ImageFormat::ExrFloat => "exr",
+            ImageFormat::Hdr => "hdr",
+            ImageFormat::Pfm => "pfm",
+        }
+    }
+
+    // Whether the format stores linear, unclipped radiance
+    pub fn is_hdr(&self) -> bool {
+        !matches!(self, ImageFormat::Png | ImageFormat::Ppm)
+    }
+}
+
+// One named set of channels of an image. Data is interleaved per pixel and
+// rows go from the top of the image to the bottom. Png and ppm expect display
+// encoded values, the other formats linear ones.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImageLayer {
+    // Empty for the main (beauty) image
+    pub name: String,
+    // Channel names, e.g. ["R", "G", "B"] or ["Z"]
+    pub channels: Vec<String>,
+    pub data: Vec<Float>,
+}
+
+impl ImageLayer {
+    pub fn new(name: &str, channels: &[&str], data: Vec<Float>) -> Self {
+        Self {
+            name: name.to_owned(),
+            channels: channels.iter().map(|channel| channel.to_string()).collect(),
+            data,
+        }
+    }
+
+    pub fn num_channels(&self) -> usize {
+        self.channels.len()
+    }
+
+    // RGB value of a pixel. Single channel layers are replicated to grey,
+    // layers with two channels get a zero blue.
+    fn rgb(&self, index: usize) -> [Float; 3] {
+        let n = self.num_channels();
+        let pixel = &self.data[index * n..(index + 1) * n];
+        match n {
+            1 => [pixel[0]; 3],
+            2 => [pixel[0], pixel[1], 0.],
+            _ => [pixel[0], pixel[1], pixel[2]],
+        }
+    }
+}
+
+// Write all the layers to `path`. OpenEXR stores every layer in the one
+// file, with channels named "<layer>.<channel>". The other formats only hold
+// a single RGB image, so the extra layers go to sibling files named
+// "<stem>.<layer>.<extension>".
+pub fn write_image(
+    path: &Path,
+    format: ImageFormat,
+    width: u32,
+    height: u32,
+    layers: &[ImageLayer],
+) -> io::Result<Vec<PathBuf>> {
+    match format {
+        ImageFormat::ExrHalf | ImageFormat::ExrFloat => {
+            write_exr(path, width, height, layers, format == ImageFormat::ExrHalf)?;
+            Ok(vec![path.to_path_buf()])
+        }
+        _ => {
+            let mut paths = vec![];
+            for layer in layers {
+                let layer_path = if layer.name.is_empty() {
+                    path.to_path_buf()
+                } else {
+                    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+                    path.with_file_name(format!("{}.{}.{}", stem, layer.name, format.extension()))
+                };
+                match format {
+                    ImageFormat::Png => write_png(&layer_path, width, height, layer)?,
+                    ImageFormat::Ppm => write_ppm(&layer_path, width, height, layer)?,
+                    ImageFormat::Hdr => write_hdr(&layer_path, width, height, layer)?,
+                    ImageFormat::Pfm => write_pfm(&layer_path, width, height, layer)?,
+                    ImageFormat::ExrHalf | ImageFormat::ExrFloat => unreachable!(),
+                }
+                paths.push(layer_path);
+            }
+            Ok(paths)
+        }
+    }
+}
+
+fn to_io_error<E: std::fmt::Display>(error: E) -> io::Error {
+    io::Error::other(error.to_string())
+}
+
+
+pub fn write_png(path: &Path, width: u32, height: u32, layer: &ImageLayer) -> io::Result<()> {
+    let mut img_png: RgbImage = ImageBuffer::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            let rgb = layer.rgb((x + width * y) as usize);
+            img_png.put_pixel(x, y, image::Rgb([quantize(rgb[0]), quantize(rgb[1]), quantize(rgb[2])]));
+        }
+    }
+    img_png.save(path).map_err(to_io_error)
+}
+
+pub fn write_ppm(path: &Path, width: u32, height: u32, layer: &ImageLayer) -> io::Result<()> {
+    let mut image: String = format!("P3\n{} {}\n255\n", width, height);
+    for index in 0..(width * height) as usize {
+        let rgb = layer.rgb(index);
+        image.push_str(&format!("{} {} {}\n", quantize(rgb[0]), quantize(rgb[1]), quantize(rgb[2])));
+    }
+    File::create(path)?.write_all(image.as_bytes())
+}
+
+pub fn write_exr(
+    path: &Path,
+    width: u32,
+    height: u32,
+    layers: &[ImageLayer],
+    half: bool,
+) -> io::Result<()> {
+    let num_pixels = (width * height) as usize;
+    let mut channels = vec![];
+    for layer in layers {
+        let n = layer.num_channels();
+        for (c, channel) in layer.channels.iter().enumerate() {
+            let name = if layer.name.is_empty() {
+                channel.clone()
+            } else {
+                format!("{}.{}", layer.name, channel)
+            };
+            let values = (0..num_pixels).map(|index| layer.data[index * n + c]);
+            let samples = if half {
+                FlatSamples::F16(values.map(f16::from_f32).collect())
+            } else {
+                FlatSamples::F32(values.collect())
+            };
+            channels.push(AnyChannel::new(name.as_str(), samples));
+        }
+    }
+
+    let layer = Layer::new(
+        (width as usize, height as usize),
+        LayerAttributes::named("rustracer"),
+        Encoding::FAST_LOSSLESS,
+        AnyChannels::sort(SmallVec::from_vec(channels)),
+    );
+    Image::from_layer(layer).write().to_file(path).map_err(to_io_error)
+}
+
+// Portable float map: little endian floats, rows stored bottom to top
+pub fn write_pfm(path: &Path, width: u32, height: u32, layer: &ImageLayer) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    write!(file, "PF\n{} {}\n-1.0\n", width, height)?;
+    for y in (0..height).rev() {
+        for x in 0..width {
+            for value in layer.rgb((x + width * y) as usize) {
+                file.write_all(&value.to_le_bytes())?;
+            }
+        }
+    }
+    file.flush()
+}
+
+// Shared exponent encoding of the Radiance format
+fn to_rgbe(rgb: [Float; 3]) -> [u8; 4] {
+    let v = rgb[0].max(rgb[1]).max(rgb[2]);
+    if v < 1e-32 {
+        return [0, 0, 0, 0];
+    }
+    // v = m * 2^e with m in [0.5, 1)
+    let e = v.log2().floor() as i32 + 1;
+    let scale = 256. / (2. as Float).powi(e);
+    [
+        (rgb[0].max(0.) * scale) as u8,
+        (rgb[1].max(0.) * scale) as u8,
+        (rgb[2].max(0.) * scale) as u8,
+        (e + 128) as u8,
+    ]
+}
+
+// Radiance .hdr with run length encoded scanlines. We never emit runs, only
+// literal chunks, which every reader accepts and keeps the writer simple.
+pub fn write_hdr(path: &Path, width: u32, height: u32, layer: &ImageLayer) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    write!(file, "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n", height, width)?;
+    let rle = (8..0x8000).contains(&width);
+    let mut scanline = vec![[0u8; 4]; width as usize];
+    for y in 0..height {
+        for x in 0..width {
+            scanline[x as usize] = to_rgbe(layer.rgb((x + width * y) as usize));
+        }
+        if !rle {
+            for rgbe in &scanline {
+                file.write_all(rgbe)?;
+            }
+            continue;
+        }
+        file.write_all(&[2, 2, (width >> 8) as u8, (width & 0xff) as u8])?;
+        for c in 0..4 {
+            for chunk in scanline.chunks(128) {
+                file.write_all(&[chunk.len() as u8])?;
+                for rgbe in chunk {
+                    file.write_all(&[rgbe[c]])?;
+                }
+            }
+        }
+    }
+    file.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rgbe_round_trip() {
+        for rgb in [[1.0, 0.5, 0.25], [1000.0, 3.0, 0.0], [0.001, 0.002, 0.003]] {
+            let rgbe = to_rgbe(rgb);
+            let scale = (2. as Float).powi(rgbe[3] as i32 - 128 - 8);
+            for c in 0..3 {
+                let decoded = (rgbe[c] as Float + 0.5) * scale;
+                assert!((decoded - rgb[c]).abs() <= rgb[0].max(rgb[1]).max(rgb[2]) / 128.);
+            }
+        }
+    }
+
+    // A 2x2 image whose red channel numbers the pixels, top left first
+    fn numbered_layer() -> ImageLayer {
+        let data = (0..4).flat_map(|i| [i as Float, 0.5, -1.]).collect();
+        ImageLayer::new("", &["R", "G", "B"], data)
+    }
+
+    #[test]
+    fn test_pfm_layout() {
+        let path = std::env::temp_dir().join("imageio-test.pfm");
+        write_pfm(&path, 2, 2, &numbered_layer()).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // A negative scale means little endian
+        let header = b"PF\n2 2\n-1.0\n";
+        assert_eq!(&bytes[..header.len()], header);
+        let values: Vec<f32> = bytes[header.len()..]
+            .chunks_exact(4)
+            .map(|word| f32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+            .collect();
+        assert_eq!(values.len(), 2 * 2 * 3);
+        // Rows go bottom to top
+        let red: Vec<f32> = values.iter().step_by(3).copied().collect();
+        assert_eq!(red, vec![2., 3., 0., 1.]);
+        assert_eq!(&values[..3], &[2., 0.5, -1.]);
+    }
+
+    #[test]
+    fn test_exr_channels() {
+        let path = std::env::temp_dir().join("imageio-test.exr");
+        let depth = ImageLayer::new("depth", &["Z"], vec![10., 20., 30., 40.]);
+        write_exr(&path, 2, 2, &[numbered_layer(), depth], false).unwrap();
+        let image = exr::prelude::read_all_flat_layers_from_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // One layer, the beauty channels bare and the others prefixed
+        assert_eq!(image.layer_data.len(), 1);
+        let channels = &image.layer_data[0].channel_data.list;
+        let names: Vec<String> = channels.iter().map(|channel| channel.name.to_string()).collect();
+        assert_eq!(names, vec!["B", "G", "R", "depth.Z"]);
+        let samples = |name: &str| match &channels[names.iter().position(|n| n == name).unwrap()].sample_data {
+            FlatSamples::F32(samples) => samples.clone(),
+            other => panic!("expected f32 samples, got {:?}", other),
+        };
+        // Rows go top to bottom
+        assert_eq!(samples("R"), vec![0., 1., 2., 3.]);
+        assert_eq!(samples("depth.Z"), vec![10., 20., 30., 40.]);
+
+        write_exr(&path, 2, 2, &[numbered_layer()], true).unwrap();
+        let image = exr::prelude::read_all_flat_layers_from_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(image.layer_data[0].channel_data.list[0].sample_data, FlatSamples::F16(_)));
+    }
+}
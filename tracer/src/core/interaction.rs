@@ -1,14 +1,39 @@
+use std::sync::Arc;
+
 use math::{Float, Vec2, Vec3};
 
 use crate::core::primitive::Primitive;
+use crate::core::ray::{Ray, RayDifferential};
+use crate::materials::Material;
 
 pub struct SurfaceInteraction {
     pub t: Float,
     pub hit_point: Vec3,
     pub hit_normal: Vec3,
     pub hit_uv: Vec2,
+    // Hit point in the space of the shape, the same as `hit_point` unless
+    // the primitive is an instance. Solid textures follow the object with it.
+    pub hit_object_point: Vec3,
     pub hit_primitive: Option<Primitive>,
     pub hit_front_face: bool,
+    // Set by shapes made of parts with their own materials, it replaces the
+    // primitive's one
+    pub hit_material: Option<Arc<dyn Material>>,
+    // How the point and the normal change along u and v, zero for shapes
+    // that don't tell
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
+    pub dndu: Vec3,
+    pub dndv: Vec3,
+    // Footprint of the pixel on the surface, from the differentials of the
+    // ray that hit it. Zero without them, textures then take the finest
+    // detail.
+    pub dpdx: Vec3,
+    pub dpdy: Vec3,
+    pub dudx: Float,
+    pub dvdx: Float,
+    pub dudy: Float,
+    pub dvdy: Float,
 }
 
 impl SurfaceInteraction {
@@ -18,8 +43,107 @@ impl SurfaceInteraction {
             hit_point: Vec3::from(0.),
             hit_normal: Vec3::from(0.),
             hit_uv: Vec2::from(0.),
+            hit_object_point: Vec3::from(0.),
             hit_primitive: Option::None,
             hit_front_face: true,
+            hit_material: None,
+            dpdu: Vec3::from(0.),
+            dpdv: Vec3::from(0.),
+            dndu: Vec3::from(0.),
+            dndv: Vec3::from(0.),
+            dpdx: Vec3::from(0.),
+            dpdy: Vec3::from(0.),
+            dudx: 0.,
+            dvdx: 0.,
+            dudy: 0.,
+            dvdy: 0.,
+        }
+    }
+
+    // The geometry of the hit with other texture coordinates, for texture
+    // lookups. It doesn't know what was hit.
+    pub fn with_uv(&self, uv: Vec2) -> Self {
+        Self {
+            hit_uv: uv,
+            hit_primitive: None,
+            hit_material: None,
+            ..*self
         }
     }
+
+    // Footprint of the pixel from the offset rays of `ray`, where they cross
+    // the tangent plane of the hit. du/dx and the others come from the least
+    // squares fit of dpdx and dpdy by dpdu and dpdv.
+    pub fn compute_differentials(&mut self, ray: &Ray) {
+        let n = self.hit_normal;
+        let crossing = |origin: Vec3, direction: Vec3| {
+            let t = Vec3::dot(n, self.hit_point - origin) / Vec3::dot(n, direction);
+            t.is_finite().then(|| origin + direction * t - self.hit_point)
+        };
+        let footprint = ray.differential.and_then(|d| {
+            Some((crossing(d.rx_origin, d.rx_direction)?, crossing(d.ry_origin, d.ry_direction)?))
+        });
+        let Some((dpdx, dpdy)) = footprint else {
+            (self.dpdx, self.dpdy) = (Vec3::from(0.), Vec3::from(0.));
+            (self.dudx, self.dvdx, self.dudy, self.dvdy) = (0., 0., 0., 0.);
+            return;
+        };
+        (self.dpdx, self.dpdy) = (dpdx, dpdy);
+
+        let ata00 = Vec3::dot(self.dpdu, self.dpdu);
+        let ata01 = Vec3::dot(self.dpdu, self.dpdv);
+        let ata11 = Vec3::dot(self.dpdv, self.dpdv);
+        let inv_det = 1. / (ata00 * ata11 - ata01 * ata01);
+        let inv_det = if inv_det.is_finite() { inv_det } else { 0. };
+        let solve = |dp: Vec3| {
+            let (atb0, atb1) = (Vec3::dot(self.dpdu, dp), Vec3::dot(self.dpdv, dp));
+            let du = (ata11 * atb0 - ata01 * atb1) * inv_det;
+            let dv = (ata00 * atb1 - ata01 * atb0) * inv_det;
+            // Grazing angles give huge footprints, the caps keep them finite
+            (du.clamp(-1e8, 1e8), dv.clamp(-1e8, 1e8))
+        };
+        (self.dudx, self.dvdx) = solve(dpdx);
+        (self.dudy, self.dvdy) = solve(dpdy);
+    }
+
+    // Offset rays of a perfect reflection toward `wi` of `ray`, which came
+    // from the camera side
+    pub fn reflected_differential(&self, ray: &Ray, wi: Vec3) -> Option<RayDifferential> {
+        let d = ray.differential?;
+        let (n, wo, wi) = (self.hit_normal, -ray.direction.normalize(), wi.normalize());
+        let reflect = |dp: Vec3, dd: Vec3, du: Float, dv: Float| {
+            let dn = self.dndu * du + self.dndv * dv;
+            let dwo = -(dd + wo);
+            let dcos = Vec3::dot(dwo, n) + Vec3::dot(wo, dn);
+            (self.hit_point + dp, wi - dwo + 2. * (n * dcos + dn * Vec3::dot(wo, n)))
+        };
+        let (rx_origin, rx_direction) = reflect(self.dpdx, d.rx_direction, self.dudx, self.dvdx);
+        let (ry_origin, ry_direction) = reflect(self.dpdy, d.ry_direction, self.dudy, self.dvdy);
+        Some(RayDifferential { rx_origin, rx_direction, ry_origin, ry_direction })
+    }
+
+    // Offset rays of a refraction toward `wi`. `eta` is the index of the
+    // side `wi` goes into over the one `ray` comes from.
+    pub fn refracted_differential(&self, ray: &Ray, wi: Vec3, eta: Float) -> Option<RayDifferential> {
+        let d = ray.differential?;
+        let (wo, wi) = (-ray.direction.normalize(), wi.normalize());
+        // Normal on the side of wo
+        let (n, sign) = if Vec3::dot(wo, self.hit_normal) < 0. {
+            (-self.hit_normal, -1.)
+        } else {
+            (self.hit_normal, 1.)
+        };
+        let refract = |dp: Vec3, dd: Vec3, du: Float, dv: Float| {
+            let dn = (self.dndu * du + self.dndv * dv) * sign;
+            let dwo = -(dd + wo);
+            let dcos = Vec3::dot(dwo, n) + Vec3::dot(wo, dn);
+            let (cos_i, cos_t) = (Vec3::dot(wo, n), Vec3::dot(wi, n).abs());
+            let mu = cos_i / eta - cos_t;
+            let dmu = dcos * (1. / eta - cos_i / (eta * eta * cos_t.max(1e-4)));
+            (self.hit_point + dp, wi - dwo / eta + n * dmu + dn * mu)
+        };
+        let (rx_origin, rx_direction) = refract(self.dpdx, d.rx_direction, self.dudx, self.dvdx);
+        let (ry_origin, ry_direction) = refract(self.dpdy, d.ry_direction, self.dudy, self.dvdy);
+        Some(RayDifferential { rx_origin, rx_direction, ry_origin, ry_direction })
+    }
 }
@@ -1,12 +1,22 @@
+pub mod animated_transform;
+pub mod animation;
+pub mod aov;
 pub mod film;
+pub mod filter;
 pub mod bounds;
+pub mod bvh;
+pub mod color_space;
 pub mod geometry;
+pub mod image_pipeline;
+pub mod imageio;
 pub mod interaction;
 pub mod primitive;
 pub mod ray;
 pub mod sampler;
 pub mod scene;
+pub mod rgb_to_spectrum;
 pub mod shape;
+pub mod spectral;
 pub mod spectrum;
 pub mod texture;
 pub mod transform;
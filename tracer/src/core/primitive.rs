@@ -1,10 +1,12 @@
 use std::sync::Arc;
 
+use crate::core::animated_transform::AnimatedTransform;
 use crate::core::interaction::SurfaceInteraction;
 use crate::core::ray::Ray;
 use crate::core::shape::Shape;
 use crate::core::Transform;
 use crate::materials::Material;
+use crate::media::MediumInterface;
 
 trait Prim {
     fn intersect(&self, ray: &Ray, isect: &mut SurfaceInteraction) -> bool;
@@ -16,7 +18,15 @@ trait Prim {
 pub struct Primitive {
     pub shape: Shape,
     pub material: Option<Arc<dyn Material>>,
+    // Set by the scene when the primitive is added, 0 until then
+    pub id: u32,
+    pub material_id: u32,
+    // Media inside and outside of a closed shape. Without a material the
+    // shape is only the boundary between them.
+    pub medium_interface: Option<MediumInterface>,
     transform: Transform,
+    // Object to world of instances, their shape is in object space
+    object_to_world: Option<AnimatedTransform>,
 }
 
 impl Primitive {
@@ -24,7 +34,20 @@ impl Primitive {
         Primitive {
             shape,
             material,
+            id: 0,
+            material_id: 0,
+            medium_interface: None,
             transform: Transform::default(),
+            object_to_world: None,
+        }
+    }
+
+    // Shape given in object space and placed in the world by a transform,
+    // which moves it over the shutter interval when it is animated
+    pub fn instance(shape: Shape, material: Option<Arc<dyn Material>>, object_to_world: AnimatedTransform) -> Self {
+        Primitive {
+            object_to_world: Some(object_to_world),
+            ..Primitive::new(shape, material)
         }
     }
 
@@ -45,10 +68,82 @@ impl Primitive {
     }
 
     pub fn intersect(&self, ray: &Ray, isect: &mut SurfaceInteraction) -> bool {
-        self.shape.intersect(ray, isect)
+        let Some(object_to_world) = &self.object_to_world else {
+            if !self.shape.intersect(ray, isect) {
+                return false;
+            }
+            isect.hit_object_point = isect.hit_point;
+            return true;
+        };
+
+        // Intersect in object space where the primitive was at the time of
+        // the ray. The direction isn't normalized again, so the distance
+        // along both rays is the same.
+        let world_from_object = object_to_world.interpolate(ray.time);
+        let Some(object_from_world) = world_from_object.inverse() else {
+            return false;
+        };
+        let object_ray = Ray {
+            origin: object_from_world * ray.origin,
+            direction: object_from_world.transform_vector(ray.direction),
+            time: ray.time,
+            differential: None,
+        };
+        if !self.shape.intersect(&object_ray, isect) {
+            return false;
+        }
+        isect.hit_object_point = isect.hit_point;
+        isect.hit_point = ray.point_at(isect.t);
+        isect.hit_normal = object_from_world
+            .transpose()
+            .transform_vector(isect.hit_normal)
+            .normalize();
+        isect.dpdu = world_from_object.transform_vector(isect.dpdu);
+        isect.dpdv = world_from_object.transform_vector(isect.dpdv);
+        isect.dndu = object_from_world.transpose().transform_vector(isect.dndu);
+        isect.dndv = object_from_world.transpose().transform_vector(isect.dndv);
+        // The shape already told which side was hit, triangles have their
+        // normal facing the ray either way
+        true
     }
 
+    // World space box, moving primitives are bounded over their whole motion
     pub fn bounds(&self) -> crate::core::bounds::Bounds3f {
-        self.shape.world_bound()
+        match &self.object_to_world {
+            Some(object_to_world) => object_to_world.motion_bounds(&self.shape.world_bound()),
+            None => self.shape.world_bound(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use math::{Float, Vec3};
+
+    use super::*;
+    use crate::shapes::sphere::Sphere;
+
+    #[test]
+    fn test_moving_sphere() {
+        let start = Transform::translate(Vec3::new(-2., 0., 0.)).matrix;
+        let end = Transform::translate(Vec3::new(2., 0., 0.)).matrix;
+        let sphere = Primitive::instance(
+            Shape::Sphere(Sphere::new(Vec3::zero(), 0.5)),
+            None,
+            AnimatedTransform::new(&[(0., start), (1., end)]),
+        );
+
+        // A ray down the z axis at x = -2 only sees the sphere early on
+        let ray = |time: Float| Ray::new(Vec3::new(-2., 0., -5.), Vec3::new(0., 0., 1.)).at_time(time);
+        let mut isect = SurfaceInteraction::new();
+        assert!(sphere.intersect(&ray(0.), &mut isect));
+        assert!((isect.t - 4.5).abs() < 1e-4);
+        assert!((isect.hit_normal - Vec3::new(0., 0., -1.)).length() < 1e-4);
+        assert!(!sphere.intersect(&ray(1.), &mut SurfaceInteraction::new()));
+
+        // The box covers the sphere over the whole motion
+        let bounds = sphere.bounds();
+        assert!((bounds.p_min - Vec3::new(-2.5, -0.5, -0.5)).length() < 1e-4);
+        assert!((bounds.p_max - Vec3::new(2.5, 0.5, 0.5)).length() < 1e-4);
     }
 }
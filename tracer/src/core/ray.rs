@@ -5,6 +5,20 @@ use math::Vec3;
 pub struct Ray {
     pub origin: Vec3,
     pub direction: Vec3,
+    // Instant in the shutter interval the ray samples
+    pub time: Float,
+    // Rays through the neighbouring pixels, for the footprint of the pixel
+    // on the surfaces it hits
+    pub differential: Option<RayDifferential>,
+}
+
+// Offset rays one pixel over along x and along y of the film
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RayDifferential {
+    pub rx_origin: Vec3,
+    pub rx_direction: Vec3,
+    pub ry_origin: Vec3,
+    pub ry_direction: Vec3,
 }
 
 impl Default for Ray {
@@ -12,6 +26,8 @@ impl Default for Ray {
         Self {
             origin: Vec3::from(0.0),
             direction: Vec3::new(0.0, 0.0, 1.0),
+            time: 0.0,
+            differential: None,
         }
     }
 }
@@ -21,6 +37,30 @@ impl Ray {
         Self {
             origin,
             direction: d.normalize(),
+            time: 0.0,
+            differential: None,
+        }
+    }
+
+    pub fn at_time(mut self, time: Float) -> Self {
+        self.time = time;
+        self
+    }
+
+    pub fn with_differential(mut self, differential: RayDifferential) -> Self {
+        self.differential = Some(differential);
+        self
+    }
+
+    // Brings the offset rays closer to the main one. With many samples per
+    // pixel each covers less than a pixel, like pbrt the footprint shrinks
+    // with the square root of their number.
+    pub fn scale_differentials(&mut self, scale: Float) {
+        if let Some(d) = self.differential.as_mut() {
+            d.rx_origin = self.origin + (d.rx_origin - self.origin) * scale;
+            d.ry_origin = self.origin + (d.ry_origin - self.origin) * scale;
+            d.rx_direction = self.direction + (d.rx_direction - self.direction) * scale;
+            d.ry_direction = self.direction + (d.ry_direction - self.direction) * scale;
         }
     }
 
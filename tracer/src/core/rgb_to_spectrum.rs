@@ -0,0 +1,260 @@
+use std::sync::OnceLock;
+
+use math::{Float, Vec3};
+use rayon::prelude::*;
+
+use crate::core::spectral::{cie_xyz, xyz_to_linear_srgb, Illuminant, LAMBDA_MAX, LAMBDA_MIN};
+
+// Smooth spectrum of a color, after Jakob and Hanika 2019: a sigmoid of a
+// quadratic over the visible range, always in [0, 1]
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct SigmoidPolynomial {
+    pub c0: Float,
+    pub c1: Float,
+    pub c2: Float,
+}
+
+impl SigmoidPolynomial {
+    pub fn new(c0: Float, c1: Float, c2: Float) -> Self {
+        Self { c0, c1, c2 }
+    }
+
+    pub fn evaluate(&self, lambda: Float) -> Float {
+        sigmoid(self.quadratic(normalized_lambda(lambda)))
+    }
+
+    fn quadratic(&self, x: Float) -> Float {
+        (self.c0 * x + self.c1) * x + self.c2
+    }
+}
+
+fn normalized_lambda(lambda: Float) -> Float {
+    (lambda - LAMBDA_MIN) / (LAMBDA_MAX - LAMBDA_MIN)
+}
+
+fn sigmoid(z: Float) -> Float {
+    if z.is_infinite() {
+        return if z > 0. { 1. } else { 0. };
+    }
+    0.5 + z / (2. * (1. + z * z).sqrt())
+}
+
+// Table resolution along each axis
+const RESOLUTION: usize = 32;
+// Wavelength step of the fits, in nanometers
+const FIT_STEP: Float = 5.;
+
+// Sigmoid coefficients fitted ahead of time for colors in [0, 1]. Colors are
+// indexed by their largest channel, its value and the two others relative to
+// it, so the fits only need to cover a cube per channel.
+pub struct RgbToSpectrumTable {
+    // Values of the largest channel at the nodes, denser near black and white
+    z_nodes: Vec<Float>,
+    // [channel][z][y][x]
+    coefficients: Vec<[Float; 3]>,
+}
+
+impl RgbToSpectrumTable {
+    // Fitted on first use, this takes a moment
+    pub fn get() -> &'static RgbToSpectrumTable {
+        static TABLE: OnceLock<RgbToSpectrumTable> = OnceLock::new();
+        TABLE.get_or_init(RgbToSpectrumTable::fit)
+    }
+
+    fn index(channel: usize, z: usize, y: usize, x: usize) -> usize {
+        ((channel * RESOLUTION + z) * RESOLUTION + y) * RESOLUTION + x
+    }
+
+    fn fit() -> Self {
+        let smoothstep = |x: Float| x * x * (3. - 2. * x);
+        let z_nodes: Vec<Float> = (0..RESOLUTION)
+            .map(|k| smoothstep(smoothstep(k as Float / (RESOLUTION - 1) as Float)))
+            .collect();
+        let weights = FitWeights::new();
+
+        // Neighbouring fits start from each other, from a middle brightness
+        // up to white and down to black
+        let start = RESOLUTION / 5;
+        let columns: Vec<(usize, usize, usize)> = (0..3)
+            .flat_map(|l| (0..RESOLUTION).flat_map(move |j| (0..RESOLUTION).map(move |i| (l, j, i))))
+            .collect();
+        let fitted: Vec<Vec<(usize, [Float; 3])>> = columns
+            .par_iter()
+            .map(|&(l, j, i)| {
+                let x = i as Float / (RESOLUTION - 1) as Float;
+                let y = j as Float / (RESOLUTION - 1) as Float;
+                let target = |k: usize| {
+                    let z = z_nodes[k];
+                    let mut rgb = Vec3::zero();
+                    rgb[l] = z;
+                    rgb[(l + 1) % 3] = x * z;
+                    rgb[(l + 2) % 3] = y * z;
+                    rgb
+                };
+                let mut column = Vec::with_capacity(RESOLUTION);
+                let mut coefficients = [0.; 3];
+                for k in start..RESOLUTION {
+                    coefficients = weights.gauss_newton(target(k), coefficients);
+                    column.push((RgbToSpectrumTable::index(l, k, j, i), coefficients));
+                }
+                coefficients = column[0].1;
+                for k in (0..start).rev() {
+                    coefficients = weights.gauss_newton(target(k), coefficients);
+                    column.push((RgbToSpectrumTable::index(l, k, j, i), coefficients));
+                }
+                column
+            })
+            .collect();
+
+        let mut coefficients = vec![[0.; 3]; 3 * RESOLUTION * RESOLUTION * RESOLUTION];
+        for (index, c) in fitted.into_iter().flatten() {
+            coefficients[index] = c;
+        }
+        Self {
+            z_nodes,
+            coefficients,
+        }
+    }
+
+    // Spectrum of a color in [0, 1]
+    pub fn polynomial(&self, rgb: Vec3) -> SigmoidPolynomial {
+        if rgb.x == rgb.y && rgb.y == rgb.z {
+            // Constant spectra, exactly
+            let v = rgb.x.clamp(1e-6, 1. - 1e-6);
+            return SigmoidPolynomial::new(0., 0., (v - 0.5) / (v * (1. - v)).sqrt());
+        }
+
+        let channel = if rgb.x > rgb.y {
+            if rgb.x > rgb.z { 0 } else { 2 }
+        } else if rgb.y > rgb.z {
+            1
+        } else {
+            2
+        };
+        let z = rgb[channel];
+        let scale = (RESOLUTION - 1) as Float / z;
+        let x = rgb[(channel + 1) % 3] * scale;
+        let y = rgb[(channel + 2) % 3] * scale;
+
+        let zi = self.z_nodes.partition_point(|node| *node <= z).clamp(1, RESOLUTION - 1) - 1;
+        let dz = ((z - self.z_nodes[zi]) / (self.z_nodes[zi + 1] - self.z_nodes[zi])).clamp(0., 1.);
+        let xi = (x as usize).min(RESOLUTION - 2);
+        let yi = (y as usize).min(RESOLUTION - 2);
+        let (dx, dy) = (x - xi as Float, y - yi as Float);
+
+        let mut c = [0.; 3];
+        for (corner, weight) in [
+            ((0, 0, 0), (1. - dz) * (1. - dy) * (1. - dx)),
+            ((0, 0, 1), (1. - dz) * (1. - dy) * dx),
+            ((0, 1, 0), (1. - dz) * dy * (1. - dx)),
+            ((0, 1, 1), (1. - dz) * dy * dx),
+            ((1, 0, 0), dz * (1. - dy) * (1. - dx)),
+            ((1, 0, 1), dz * (1. - dy) * dx),
+            ((1, 1, 0), dz * dy * (1. - dx)),
+            ((1, 1, 1), dz * dy * dx),
+        ] {
+            let node = self.coefficients[RgbToSpectrumTable::index(channel, zi + corner.0, yi + corner.1, xi + corner.2)];
+            for n in 0..3 {
+                c[n] += weight * node[n];
+            }
+        }
+        SigmoidPolynomial::new(c[0], c[1], c[2])
+    }
+}
+
+// How much each wavelength adds to each sRGB channel of a reflectance lit by
+// D65, a reflectance of 1 is white
+struct FitWeights {
+    x: Vec<Float>,
+    rgb: Vec<Vec3>,
+}
+
+impl FitWeights {
+    fn new() -> Self {
+        let count = ((LAMBDA_MAX - LAMBDA_MIN) / FIT_STEP) as usize + 1;
+        let lambdas: Vec<Float> = (0..count).map(|i| LAMBDA_MIN + i as Float * FIT_STEP).collect();
+        let illuminant = Illuminant::D65.spectrum();
+        let xyz: Vec<Vec3> = lambdas.iter().map(|l| illuminant.value(*l) * cie_xyz(*l)).collect();
+        let y: Float = xyz.iter().map(|v| v.y).sum();
+        Self {
+            x: lambdas.iter().map(|l| normalized_lambda(*l)).collect(),
+            rgb: xyz.iter().map(|v| xyz_to_linear_srgb(*v / y)).collect(),
+        }
+    }
+
+    // Color of the sigmoid spectrum and its derivatives by the coefficients
+    fn evaluate(&self, c: [Float; 3]) -> (Vec3, [Vec3; 3]) {
+        let polynomial = SigmoidPolynomial::new(c[0], c[1], c[2]);
+        let mut rgb = Vec3::zero();
+        let mut jacobian = [Vec3::zero(); 3];
+        for (x, weight) in self.x.iter().zip(&self.rgb) {
+            let z = polynomial.quadratic(*x);
+            rgb += sigmoid(z) * *weight;
+            let slope = 0.5 / (1. + z * z).powf(1.5);
+            jacobian[0] += slope * x * x * *weight;
+            jacobian[1] += slope * x * *weight;
+            jacobian[2] += slope * *weight;
+        }
+        (rgb, jacobian)
+    }
+
+    fn gauss_newton(&self, target: Vec3, start: [Float; 3]) -> [Float; 3] {
+        let mut c = start;
+        for _ in 0..20 {
+            let (rgb, jacobian) = self.evaluate(c);
+            let residual = rgb - target;
+            if residual.length() < 1e-5 {
+                break;
+            }
+            let Some(step) = solve3(jacobian, residual) else {
+                break;
+            };
+            // Damped, the sigmoid flattens out and full steps overshoot
+            let length = (step[0] * step[0] + step[1] * step[1] + step[2] * step[2]).sqrt();
+            let damping = if length > 20. { 20. / length } else { 1. };
+            let next = [c[0] - damping * step[0], c[1] - damping * step[1], c[2] - damping * step[2]];
+            if next.iter().any(|v| !v.is_finite() || v.abs() > 1e4) {
+                break;
+            }
+            c = next;
+        }
+        c
+    }
+}
+
+// Solves columns[0] a + columns[1] b + columns[2] c = rhs by Cramer's rule
+fn solve3(columns: [Vec3; 3], rhs: Vec3) -> Option<[Float; 3]> {
+    let determinant = |a: Vec3, b: Vec3, c: Vec3| Vec3::dot(a, Vec3::cross(b, c));
+    let d = determinant(columns[0], columns[1], columns[2]);
+    if d.abs() < 1e-20 {
+        return None;
+    }
+    Some([
+        determinant(rhs, columns[1], columns[2]) / d,
+        determinant(columns[0], rhs, columns[2]) / d,
+        determinant(columns[0], columns[1], rhs) / d,
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rgb_to_spectrum() {
+        let weights = FitWeights::new();
+        let table = RgbToSpectrumTable::get();
+        // Greys are flat, and colors inside the gamut come back from their
+        // spectra
+        let grey = table.polynomial(Vec3::from(0.25));
+        assert!((grey.evaluate(450.) - 0.25).abs() < 1e-4 && (grey.evaluate(700.) - 0.25).abs() < 1e-4);
+        for rgb in [Vec3::new(0.8, 0.2, 0.1), Vec3::new(0.1, 0.5, 0.3), Vec3::new(0.3, 0.35, 0.7), Vec3::from(1.)] {
+            let p = table.polynomial(rgb);
+            let (fitted, _) = weights.evaluate([p.c0, p.c1, p.c2]);
+            assert!((fitted - rgb).length() < 0.01, "{:?} came back as {:?}", rgb, fitted);
+            for lambda in [360., 500., 830.] {
+                assert!((0. ..=1.).contains(&p.evaluate(lambda)));
+            }
+        }
+    }
+}
@@ -1,46 +1,99 @@
 use math::{Float, Vec2, Vec3};
-use rand::prelude::*;
 
-pub struct Sampler {}
+// Counter-based sampler. Every random number is a pure function of the global
+// seed, the pixel coordinate, the sample index, the bounce index and the
+// dimension consumed so far within that bounce. There is no hidden generator
+// state shared between pixels, so the same seed renders the same image no
+// matter how many threads are used or in which order pixels are scheduled.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Sampler {
+    seed: u64,
+    pixel_x: u32,
+    pixel_y: u32,
+    sample_index: u32,
+    bounce: u32,
+    dimension: u32,
+}
+
+impl Default for Sampler {
+    fn default() -> Self {
+        Sampler::new(0)
+    }
+}
+
+// SplitMix64 finalizer. Good avalanche behaviour, which is all we need to turn
+// a structured counter into uniformly distributed bits.
+fn mix64(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
 
 impl Sampler {
-    pub fn random_0_1() -> Float {
-        let mut rng_generator = rand::thread_rng();
-        rng_generator
-            .sample(rand::distributions::Uniform::new(0., 1.))
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            pixel_x: 0,
+            pixel_y: 0,
+            sample_index: 0,
+            bounce: 0,
+            dimension: 0,
+        }
     }
 
-    pub fn random_vec2_0_1() -> Vec2 {
-        let mut rng_generator = rand::thread_rng();
-        let ru = rng_generator.gen_range(0.0..1.0);
-        let rv = rng_generator.gen_range(0.0..1.0);
+    // Start drawing numbers for a new camera sample. Bounce 0 is reserved for
+    // the camera (pixel jitter, lens), path vertices use bounce 1 and up.
+    pub fn start_pixel_sample(&mut self, x: u32, y: u32, sample_index: u32) {
+        self.pixel_x = x;
+        self.pixel_y = y;
+        self.sample_index = sample_index;
+        self.bounce = 0;
+        self.dimension = 0;
+    }
+
+    pub fn start_bounce(&mut self, bounce: u32) {
+        self.bounce = bounce;
+        self.dimension = 0;
+    }
+
+    fn hash(&self) -> u64 {
+        let pixel = (self.pixel_x as u64) | ((self.pixel_y as u64) << 32);
+        let sample = (self.sample_index as u64) | ((self.bounce as u64) << 32);
+        let mut h = mix64(self.seed ^ 0x9e3779b97f4a7c15);
+        h = mix64(h ^ pixel);
+        h = mix64(h ^ sample);
+        mix64(h ^ self.dimension as u64)
+    }
+
+    pub fn random_0_1(&mut self) -> Float {
+        let bits = self.hash();
+        self.dimension += 1;
+        // Keep the 24 high bits so the result is exactly representable and < 1
+        (bits >> 40) as Float * (1.0 / (1u32 << 24) as Float)
+    }
+
+    pub fn random_vec2_0_1(&mut self) -> Vec2 {
+        let ru = self.random_0_1();
+        let rv = self.random_0_1();
         return Vec2 { 0: ru, 1: rv };
     }
 
-    pub fn sample_from_pixel(point: Vec2, width: u32, height: u32) -> Vec2 {
-        let ru = Sampler::random_0_1();
-        let rv = Sampler::random_0_1();
+    pub fn sample_from_pixel(&mut self, point: Vec2, width: u32, height: u32) -> Vec2 {
+        let ru = self.random_0_1();
+        let rv = self.random_0_1();
         let u = (point.0 + ru) / width as Float;
         let v = (point.1 + rv) / height as Float;
         Vec2 { 0: u, 1: v }
     }
 
-    pub fn sample_unit_disk() -> Vec2 {
-        let mut point: Vec2 =
-            2. * Vec2 {
-                0: Sampler::random_0_1(),
-                1: Sampler::random_0_1(),
-            } - Vec2::from(1.);
+    pub fn sample_unit_disk(&mut self) -> Vec2 {
+        let mut point: Vec2 = 2. * self.random_vec2_0_1() - Vec2::from(1.);
         loop {
             // dot product with itself is squared length
             if Vec2::dot(point, point) < 1. {
                 break;
             }
-            point =
-                2. * Vec2 {
-                    0: Sampler::random_0_1(),
-                    1: Sampler::random_0_1(),
-                } - Vec2::from(1.);
+            point = 2. * self.random_vec2_0_1() - Vec2::from(1.);
         }
         point
     }
@@ -67,11 +120,11 @@ impl Sampler {
         };
     }
 
-    pub fn sample_from_unit_sphere() -> Vec3 {
+    pub fn sample_from_unit_sphere(&mut self) -> Vec3 {
         let mut point = Vec3 {
-            x: Sampler::random_0_1(),
-            y: Sampler::random_0_1(),
-            z: Sampler::random_0_1(),
+            x: self.random_0_1(),
+            y: self.random_0_1(),
+            z: self.random_0_1(),
         };
         loop {
             if Vec3::length2(&point) < 1. {
@@ -79,9 +132,9 @@ impl Sampler {
             }
             point =
                 2. * Vec3 {
-                    x: Sampler::random_0_1(),
-                    y: Sampler::random_0_1(),
-                    z: Sampler::random_0_1(),
+                    x: self.random_0_1(),
+                    y: self.random_0_1(),
+                    z: self.random_0_1(),
                 } - Vec3::from(1.); // Scale to -1 , 1 range
         }
 
@@ -91,7 +144,7 @@ impl Sampler {
     // Find a random direction, cosine weighted, with z axis as normal
     // If we sample the variables with cosine weighted, then we can use
     // our pdf as cos(theta) / pi
-    pub fn sample_cosine_direction() -> Vec3 {
+    pub fn sample_cosine_direction(&mut self) -> Vec3 {
         // Sampling with 2 variables over a cosine weighted direction
         // r1 = Integral_0_phi(1 /(2 * PI)) -> phi = 2 * PI * r1
         // r2 = Integral_0_theta(2 * PI * f(t) * sin(t)) with f(t) = cos(theta) / PI
@@ -108,7 +161,7 @@ impl Sampler {
         // Vec3::new(x, y, z)
 
         // Malley's method: sample from concentric disk, then project upward
-        let random = Sampler::random_vec2_0_1();
+        let random = self.random_vec2_0_1();
         let r = Sampler::sample_unit_disk_concentric(random);
         let z = Float::max(0.0, 1.0 - r.x() * r.x() - r.y() * r.y());
         return Vec3 {
@@ -118,3 +171,49 @@ impl Sampler {
         };
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_same_counter_same_sequence() {
+        let mut s1 = Sampler::new(7);
+        let mut s2 = Sampler::new(7);
+        s1.start_pixel_sample(12, 34, 5);
+        s2.start_pixel_sample(12, 34, 5);
+        s1.start_bounce(2);
+        s2.start_bounce(2);
+        for _ in 0..16 {
+            assert_eq!(s1.random_0_1(), s2.random_0_1());
+        }
+    }
+
+    #[test]
+    fn test_streams_are_decorrelated() {
+        let first = |seed: u64, x: u32, sample: u32, bounce: u32| {
+            let mut s = Sampler::new(seed);
+            s.start_pixel_sample(x, 0, sample);
+            s.start_bounce(bounce);
+            s.random_0_1()
+        };
+        let reference = first(1, 0, 0, 0);
+        assert_ne!(reference, first(2, 0, 0, 0));
+        assert_ne!(reference, first(1, 1, 0, 0));
+        assert_ne!(reference, first(1, 0, 1, 0));
+        assert_ne!(reference, first(1, 0, 0, 1));
+    }
+
+    #[test]
+    fn test_random_range() {
+        let mut s = Sampler::new(3);
+        s.start_pixel_sample(1, 2, 3);
+        let mut sum = 0.0;
+        for _ in 0..10000 {
+            let r = s.random_0_1();
+            assert!((0.0..1.0).contains(&r));
+            sum += r;
+        }
+        assert!((sum / 10000.0 - 0.5).abs() < 0.02);
+    }
+}
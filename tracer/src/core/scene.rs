@@ -1,26 +1,31 @@
 use std::path::Path;
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
 
-use math::{Float, Vec3};
+use math::{Float, Vec2, Vec3};
 
-use crate::cameras::perspective::PerspectiveCamera;
+use crate::cameras::{lens::ThinLens, perspective::PerspectiveCamera, Camera};
+use crate::core::bvh::Bvh;
 use crate::core::interaction::SurfaceInteraction;
 use crate::core::primitive::Primitive;
 use crate::core::ray::Ray;
-use crate::core::shape::Shape;
+use crate::core::sampler::Sampler;
 use crate::core::spectrum::Spectrum;
-use crate::core::transform::Transform;
-use crate::loaders::gltf_loader::GData;
-use crate::materials::LambertMaterial;
-use crate::shapes::mesh::Mesh;
+use crate::loaders::gltf_loader::GltfScene;
+use crate::media::Medium;
 
-use log::info;
-
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, Debug)]
 pub struct Scene {
     pub primitives: Vec<Primitive>,
     pub environment_light: fn(&Ray) -> Spectrum,
-    pub persp_camera: PerspectiveCamera,
+    pub camera: Arc<dyn Camera>,
+    // Output file requested by the scene description, if any
+    pub output_filename: Option<String>,
+    // Medium the camera sits in, it fills the space outside of the shapes
+    // with a medium interface
+    pub camera_medium: Option<Arc<dyn Medium>>,
+    // Built over the primitives by the first intersection, adding one
+    // starts it over
+    bvh: OnceLock<Bvh>,
 }
 
 impl Default for Scene {
@@ -28,86 +33,10 @@ impl Default for Scene {
         Self {
             primitives: Vec::default(),
             environment_light: |_| Spectrum::ColorRGB(Vec3::from(0.)),
-            persp_camera: PerspectiveCamera::default(),
-        }
-    }
-}
-
-impl Scene {
-    fn parse_gltf<P>(path: P) -> Self
-    where
-        P: AsRef<Path>,
-    {
-        let (doc, buffers, images) = gltf::import(path).unwrap();
-        let data = GData {
-            doc,
-            buffers,
-            images,
-        };
-
-        let mut scene = Scene::default();
-
-        for s in data.doc.scenes() {
-            // TODO: We shouldn't have to apply manual transfomration here.
-            let rotate_180_z = Transform::rotate_z(std::f32::consts::PI);
-            let shift_x = Transform::translate(Vec3::new(0.0, -0.5, 0.));
-
-            Scene::parse_gltf_node(
-                &mut scene,
-                &data,
-                &s.nodes().next().unwrap(),
-                shift_x * rotate_180_z,
-            );
-        }
-
-        for node in data.doc.nodes() {}
-
-        for image in data.images {}
-
-        return scene;
-    }
-
-    fn parse_gltf_node(
-        scene: &mut Scene,
-        data: &GData,
-        node: &gltf::Node,
-        parent_xform: Transform,
-    ) {
-        let xform = parent_xform * Transform::from(&node.transform());
-
-        info!("Node: {:?} - Transform: {}", node.name(), xform);
-
-        if let Some(mesh) = node.mesh() {
-            info!("-- Node has mesh {:?}", mesh.name());
-            Scene::parse_gltf_mesh(scene, data, &mesh, xform);
-        } else if let Some(_) = node.camera() {
-            scene.persp_camera.set_position(&xform.get_position());
-        } else {
-            info!("-- Node has no mesh");
-        }
-
-        if node.children().len() == 0 {
-            info!("-- Node has no children");
-        } else {
-            info!("-- Node has children");
-        }
-
-        for child_node in node.children() {
-            info!("---- Children: {:?}", child_node.name());
-            Scene::parse_gltf_node(scene, data, &child_node, xform);
-        }
-    }
-
-    fn parse_gltf_mesh(scene: &mut Scene, data: &GData, mesh: &gltf::Mesh, xform: Transform) {
-        for primitive in mesh.primitives() {
-            let mesh = Mesh::from_gltf(&primitive, &data);
-            let color = Spectrum::ColorRGB(Vec3::from(1.0));
-            let mut primitive = Primitive::new(
-                Shape::Mesh(mesh),
-                Option::Some(Arc::new(LambertMaterial::new(color))),
-            );
-            primitive.apply_transform(xform);
-            scene.add(primitive);
+            camera: Arc::new(PerspectiveCamera::default()),
+            output_filename: None,
+            camera_medium: None,
+            bvh: OnceLock::new(),
         }
     }
 }
@@ -119,7 +48,7 @@ where
     fn from(path: P) -> Self {
         if let Some(extension) = path.as_ref().extension() {
             if extension == "gltf" || extension == "glb" {
-                return Scene::parse_gltf(path);
+                return GltfScene::load(path).scene_at(0.);
             }
         }
 
@@ -128,28 +57,120 @@ where
 }
 
 impl Scene {
-    pub fn add(&mut self, primitive: Primitive) {
+    // Add a primitive and give it an id. Materials are identified by
+    // instance, primitives sharing a material share its id.
+    pub fn add(&mut self, mut primitive: Primitive) {
+        primitive.id = self.primitives.len() as u32 + 1;
+        if let Some(material) = &primitive.material {
+            let shared = self.primitives.iter().find(|other| match &other.material {
+                Some(other_material) => Arc::ptr_eq(material, other_material),
+                None => false,
+            });
+            primitive.material_id = match shared {
+                Some(other) => other.material_id,
+                None => 1 + self.primitives.iter().map(|other| other.material_id).max().unwrap_or(0),
+            };
+        }
         self.primitives.push(primitive);
+        self.bvh = OnceLock::new();
+    }
+
+    // Focus the camera on the surface seen through the film position `uv`.
+    // Returns the new focus distance, None if the camera has no lens or
+    // nothing is there.
+    pub fn autofocus(&mut self, uv: &Vec2) -> Option<Float> {
+        let lens = self.camera.lens()?;
+        let pinhole = self.camera.with_lens(ThinLens {
+            f_stop: Float::INFINITY,
+            ..lens
+        })?;
+        let mut sampler = Sampler::default();
+        let forward = pinhole.generate_ray(&Vec2::from(0.5), &mut sampler)?.direction;
+        let ray = pinhole.generate_ray(uv, &mut sampler)?;
+        let mut isect = SurfaceInteraction::new();
+        if !self.intersect(&ray, &mut isect) {
+            return None;
+        }
+        // The plane in focus is perpendicular to the view direction
+        let focus_distance = isect.t * Vec3::dot(ray.direction, forward);
+        self.camera = self.camera.with_lens(ThinLens {
+            focus_distance,
+            ..lens
+        })?;
+        Some(focus_distance)
     }
 
     pub fn intersect(&self, ray: &Ray, closest_isect: &mut SurfaceInteraction) -> bool {
-        const MAX_T: Float = funty::Floating::MAX;
-        let mut closest_t = MAX_T;
-        for primitive in self.primitives.iter() {
+        let bvh = self.bvh.get_or_init(|| {
+            let bounds: Vec<_> = self.primitives.iter().map(|primitive| primitive.bounds()).collect();
+            Bvh::new(&bounds)
+        });
+
+        let mut closest: Option<(usize, SurfaceInteraction)> = None;
+        bvh.intersect(ray, Float::MAX, |index| {
             let mut isect = SurfaceInteraction::new();
-            let hit = primitive.intersect(&ray, &mut isect);
-            if hit && isect.t < closest_t {
-                closest_t = isect.t;
-                closest_isect.hit_normal = isect.hit_normal;
-                closest_isect.hit_point = isect.hit_point;
-                closest_isect.hit_uv = isect.hit_uv;
-                closest_isect.hit_primitive = Some(primitive.clone());
+            if !self.primitives[index].intersect(ray, &mut isect) || isect.t <= 1e-5 {
+                return None;
             }
-        }
-        if closest_t < MAX_T && closest_t > 1e-5 {
-            return true;
-        } else {
+            if let Some((_, closest_isect)) = &closest {
+                if isect.t >= closest_isect.t {
+                    return None;
+                }
+            }
+            let t = isect.t;
+            closest = Some((index, isect));
+            Some(t)
+        });
+
+        let Some((index, isect)) = closest else {
             return false;
+        };
+        closest_isect.t = isect.t;
+        closest_isect.hit_front_face = isect.hit_front_face;
+        closest_isect.hit_normal = isect.hit_normal;
+        closest_isect.hit_point = isect.hit_point;
+        closest_isect.hit_uv = isect.hit_uv;
+        closest_isect.hit_object_point = isect.hit_object_point;
+        closest_isect.dpdu = isect.dpdu;
+        closest_isect.dpdv = isect.dpdv;
+        closest_isect.dndu = isect.dndu;
+        closest_isect.dndv = isect.dndv;
+        let mut primitive = self.primitives[index].clone();
+        if let Some(material) = &isect.hit_material {
+            primitive.material = Some(material.clone());
+        }
+        closest_isect.hit_primitive = Some(primitive);
+        closest_isect.hit_material = isect.hit_material;
+        true
+    }
+
+    // Fraction of the light reaching the origin of `ray` from infinitely far
+    // along it, starting in `medium`. Medium boundaries are crossed, any
+    // surface with a material blocks the light.
+    pub fn transmittance(&self, ray: &Ray, medium: Option<Arc<dyn Medium>>, sampler: &mut Sampler) -> Spectrum {
+        const MAX_CROSSINGS: usize = 100;
+
+        let mut transmittance = Spectrum::ColorRGB(Vec3::from(1.));
+        let mut ray = *ray;
+        let mut medium = medium;
+        for _ in 0..MAX_CROSSINGS {
+            let mut isect = SurfaceInteraction::new();
+            let hit = self.intersect(&ray, &mut isect);
+            let t_max = if hit { isect.t } else { Float::INFINITY };
+            if let Some(medium) = &medium {
+                transmittance = transmittance * medium.transmittance(&ray, t_max, sampler);
+            }
+            let Some(primitive) = isect.hit_primitive.filter(|_| hit) else {
+                return transmittance;
+            };
+            if primitive.material.is_some() || transmittance.to_rgb() == Vec3::from(0.) {
+                return Spectrum::ColorRGB(Vec3::from(0.));
+            }
+            if let Some(interface) = &primitive.medium_interface {
+                medium = interface.entered(isect.hit_front_face);
+            }
+            ray.origin = isect.hit_point + ray.direction * 1e-3;
         }
+        Spectrum::ColorRGB(Vec3::from(0.))
     }
 }
@@ -1,26 +1,57 @@
+use std::sync::Arc;
+
+use math::{Float, Vec2, Vec3};
+
+use crate::core::bounds::Bounds3f;
 use crate::core::interaction::SurfaceInteraction;
 use crate::core::ray::Ray;
+use crate::shapes::cone::Cone;
+use crate::shapes::csg::Csg;
+use crate::shapes::cylinder::Cylinder;
+use crate::shapes::disk::Disk;
+use crate::shapes::displaced::DisplacedMesh;
 use crate::shapes::mesh::Mesh;
+use crate::shapes::paraboloid::Paraboloid;
+use crate::shapes::quad::Quad;
+use crate::shapes::sdf::Sdf;
 use crate::shapes::sphere::Sphere;
+use crate::shapes::torus::Torus;
 use crate::shapes::triangle::Triangle;
 
 use super::Transform;
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum Shape {
-    Mesh(Mesh),
+    // Shared by the instances of a mesh and the poses of an animation
+    Mesh(Arc<Mesh>),
+    // Tessellated and displaced when first hit
+    Displaced(Arc<DisplacedMesh>),
     Sphere(Sphere),
     Triangle(Triangle),
+    Disk(Disk),
+    Cylinder(Cylinder),
+    Cone(Cone),
+    Paraboloid(Paraboloid),
+    Quad(Quad),
+    Torus(Torus),
+    Sdf(Sdf),
+    Csg(Csg),
+}
+
+// Point picked uniformly over the area of a shape, with its outward normal.
+// The pdf is per unit area.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ShapeSample {
+    pub point: Vec3,
+    pub normal: Vec3,
+    pub pdf: Float,
 }
 
 impl Shape {
     pub fn apply_transform(&mut self, transform: &Transform) {
         match self {
-            Shape::Mesh(shape) => {
-                for position in shape.positions.iter_mut() {
-                    *position = transform.matrix * (*position);
-                }
-            }
+            Shape::Mesh(shape) => Arc::make_mut(shape).transform(&transform.matrix),
+            Shape::Displaced(shape) => Arc::make_mut(shape).transform(&transform.matrix),
             Shape::Sphere(shape) => {
                 shape.center = transform.get_position() + shape.center;
             }
@@ -29,22 +60,95 @@ impl Shape {
                 shape.v1 = transform.matrix * shape.v1;
                 shape.v2 = transform.matrix * shape.v2;
             }
+            // Flattening transforms leave the shape where it was
+            Shape::Disk(Disk { placement, .. })
+            | Shape::Cylinder(Cylinder { placement, .. })
+            | Shape::Cone(Cone { placement, .. })
+            | Shape::Paraboloid(Paraboloid { placement, .. })
+            | Shape::Torus(Torus { placement, .. })
+            | Shape::Sdf(Sdf { placement, .. }) => {
+                if let Some(moved) = placement.then(&transform.matrix) {
+                    *placement = moved;
+                }
+            }
+            Shape::Csg(shape) => shape.apply_transform(transform),
+            Shape::Quad(shape) => {
+                shape.corner = transform.matrix * shape.corner;
+                shape.edge_u = transform.matrix.transform_vector(shape.edge_u);
+                shape.edge_v = transform.matrix.transform_vector(shape.edge_v);
+            }
         }
     }
 
     pub fn intersect(&self, ray: &Ray, isect: &mut SurfaceInteraction) -> bool {
         match self {
             Shape::Mesh(shape) => shape.intersect(ray, isect),
+            Shape::Displaced(shape) => shape.intersect(ray, isect),
             Shape::Sphere(shape) => shape.intersect(ray, isect),
             Shape::Triangle(shape) => shape.intersect(ray, isect),
+            Shape::Disk(shape) => shape.intersect(ray, isect),
+            Shape::Cylinder(shape) => shape.intersect(ray, isect),
+            Shape::Cone(shape) => shape.intersect(ray, isect),
+            Shape::Paraboloid(shape) => shape.intersect(ray, isect),
+            Shape::Quad(shape) => shape.intersect(ray, isect),
+            Shape::Torus(shape) => shape.intersect(ray, isect),
+            Shape::Sdf(shape) => shape.intersect(ray, isect),
+            Shape::Csg(shape) => shape.intersect(ray, isect),
         }
     }
 
-    pub fn world_bound(&self) -> crate::core::bounds::Bounds3f {
+    pub fn world_bound(&self) -> Bounds3f {
         match self {
             Shape::Mesh(shape) => shape.world_bound(),
+            Shape::Displaced(shape) => shape.world_bound(),
             Shape::Sphere(shape) => shape.world_bound(),
             Shape::Triangle(shape) => shape.world_bound(),
+            Shape::Disk(shape) => shape.world_bound(),
+            Shape::Cylinder(shape) => shape.world_bound(),
+            Shape::Cone(shape) => shape.world_bound(),
+            Shape::Paraboloid(shape) => shape.world_bound(),
+            Shape::Quad(shape) => shape.world_bound(),
+            Shape::Torus(shape) => shape.world_bound(),
+            Shape::Sdf(shape) => shape.world_bound(),
+            Shape::Csg(shape) => shape.world_bound(),
+        }
+    }
+
+    pub fn area(&self) -> Float {
+        match self {
+            Shape::Mesh(shape) => shape.area(),
+            Shape::Displaced(shape) => shape.area(),
+            Shape::Sphere(shape) => shape.area(),
+            Shape::Triangle(shape) => shape.area(),
+            Shape::Disk(shape) => shape.area(),
+            Shape::Cylinder(shape) => shape.area(),
+            Shape::Cone(shape) => shape.area(),
+            Shape::Paraboloid(shape) => shape.area(),
+            Shape::Quad(shape) => shape.area(),
+            Shape::Torus(shape) => shape.area(),
+            // No closed form, distance fields and CSG solids can't be lights
+            Shape::Sdf(_) | Shape::Csg(_) => 0.,
+        }
+    }
+
+    // `u` is uniform in [0, 1)^2
+    pub fn sample(&self, u: &Vec2) -> ShapeSample {
+        match self {
+            Shape::Mesh(shape) => shape.sample(u),
+            Shape::Displaced(shape) => shape.sample(u),
+            Shape::Sphere(shape) => shape.sample(u),
+            Shape::Triangle(shape) => shape.sample(u),
+            Shape::Disk(shape) => shape.sample(u),
+            Shape::Cylinder(shape) => shape.sample(u),
+            Shape::Cone(shape) => shape.sample(u),
+            Shape::Paraboloid(shape) => shape.sample(u),
+            Shape::Quad(shape) => shape.sample(u),
+            Shape::Torus(shape) => shape.sample(u),
+            Shape::Sdf(_) | Shape::Csg(_) => ShapeSample {
+                point: self.world_bound().centroid(),
+                normal: Vec3::new(0., 1., 0.),
+                pdf: 0.,
+            },
         }
     }
 }
@@ -0,0 +1,387 @@
+use std::sync::OnceLock;
+
+use math::{Float, Vec3};
+use strum_macros::{Display, EnumIter, EnumString};
+
+use crate::core::rgb_to_spectrum::{RgbToSpectrumTable, SigmoidPolynomial};
+use crate::core::spectrum::Spectrum;
+
+// Visible range, in nanometers
+pub const LAMBDA_MIN: Float = 360.;
+pub const LAMBDA_MAX: Float = 830.;
+// Hero wavelength and its companions
+pub const N_SPECTRUM_SAMPLES: usize = 4;
+// Integral of the y matching function, a spectrum of 1 everywhere has a
+// luminance of 1
+pub const CIE_Y_INTEGRAL: Float = 106.856895;
+
+// CIE 1931 matching functions, from the multi-lobe fit of Wyman et al. 2013
+fn lobe(lambda: Float, mu: Float, sigma_below: Float, sigma_above: Float) -> Float {
+    let t = (lambda - mu) / if lambda < mu { sigma_below } else { sigma_above };
+    (-0.5 * t * t).exp()
+}
+
+pub fn cie_x(lambda: Float) -> Float {
+    1.056 * lobe(lambda, 599.8, 37.9, 31.0) + 0.362 * lobe(lambda, 442.0, 16.0, 26.7)
+        - 0.065 * lobe(lambda, 501.1, 20.4, 26.2)
+}
+
+pub fn cie_y(lambda: Float) -> Float {
+    0.821 * lobe(lambda, 568.8, 46.9, 40.5) + 0.286 * lobe(lambda, 530.9, 16.3, 31.1)
+}
+
+pub fn cie_z(lambda: Float) -> Float {
+    1.217 * lobe(lambda, 437.0, 11.8, 36.0) + 0.681 * lobe(lambda, 459.0, 26.0, 13.8)
+}
+
+pub fn cie_xyz(lambda: Float) -> Vec3 {
+    Vec3::new(cie_x(lambda), cie_y(lambda), cie_z(lambda))
+}
+
+// XYZ of a spectrum given as a function of the wavelength, integrated every
+// nanometer
+pub fn spectrum_to_xyz<F>(spectrum: F) -> Vec3
+where
+    F: Fn(Float) -> Float,
+{
+    let mut xyz = Vec3::zero();
+    let mut lambda = LAMBDA_MIN;
+    while lambda <= LAMBDA_MAX {
+        xyz += spectrum(lambda) * cie_xyz(lambda);
+        lambda += 1.;
+    }
+    xyz / CIE_Y_INTEGRAL
+}
+
+// Linear sRGB, or Rec. 709, with a D65 white point
+pub fn xyz_to_linear_srgb(xyz: Vec3) -> Vec3 {
+    Vec3::new(
+        3.2404542 * xyz.x - 1.5371385 * xyz.y - 0.4985314 * xyz.z,
+        -0.969266 * xyz.x + 1.8760108 * xyz.y + 0.0415560 * xyz.z,
+        0.0556434 * xyz.x - 0.2040259 * xyz.y + 1.0572252 * xyz.z,
+    )
+}
+
+pub fn linear_srgb_to_xyz(rgb: Vec3) -> Vec3 {
+    Vec3::new(
+        0.4124564 * rgb.x + 0.3575761 * rgb.y + 0.1804375 * rgb.z,
+        0.2126729 * rgb.x + 0.7151522 * rgb.y + 0.0721750 * rgb.z,
+        0.0193339 * rgb.x + 0.119192 * rgb.y + 0.9503041 * rgb.z,
+    )
+}
+
+// Planck's law, radiance of a black body at `kelvin`
+pub fn blackbody(lambda: Float, kelvin: Float) -> Float {
+    if kelvin <= 0. {
+        return 0.;
+    }
+    const C: f64 = 299792458.;
+    const H: f64 = 6.62606957e-34;
+    const KB: f64 = 1.3806488e-23;
+    let l = lambda as f64 * 1e-9;
+    let radiance = (2. * H * C * C) / (l.powi(5) * (((H * C) / (l * KB * kelvin as f64)).exp() - 1.));
+    radiance as Float
+}
+
+// Black body spectrum scaled so its peak, given by Wien's displacement law,
+// is 1
+pub fn blackbody_normalized(lambda: Float, kelvin: Float) -> Float {
+    let peak_lambda = 2.8977721e-3 / kelvin as f64 * 1e9;
+    blackbody(lambda, kelvin) / blackbody(peak_lambda as Float, kelvin)
+}
+
+// Spectrum sampled every nanometer over the visible range
+#[derive(Clone, PartialEq, Debug)]
+pub struct DenseSpectrum {
+    pub values: Vec<Float>,
+}
+
+impl DenseSpectrum {
+    pub fn new<F>(spectrum: F) -> Self
+    where
+        F: Fn(Float) -> Float,
+    {
+        let count = (LAMBDA_MAX - LAMBDA_MIN) as usize + 1;
+        Self {
+            values: (0..count).map(|i| spectrum(LAMBDA_MIN + i as Float)).collect(),
+        }
+    }
+
+    // Tabulated every `step` nanometers from `first`, linear in between and
+    // 0 past the ends
+    pub fn from_table(first: Float, step: Float, table: &[Float]) -> Self {
+        DenseSpectrum::new(|lambda| {
+            let x = (lambda - first) / step;
+            if x < 0. || x > (table.len() - 1) as Float {
+                return 0.;
+            }
+            let i = (x as usize).min(table.len() - 2);
+            let t = x - i as Float;
+            (1. - t) * table[i] + t * table[i + 1]
+        })
+    }
+
+    pub fn value(&self, lambda: Float) -> Float {
+        let x = lambda - LAMBDA_MIN;
+        if x < 0. || x > (self.values.len() - 1) as Float {
+            return 0.;
+        }
+        let i = (x as usize).min(self.values.len() - 2);
+        let t = x - i as Float;
+        (1. - t) * self.values[i] + t * self.values[i + 1]
+    }
+
+    // Scaled to a luminance of 1
+    pub fn normalized(self) -> Self {
+        let y = spectrum_to_xyz(|lambda| self.value(lambda)).y;
+        Self {
+            values: self.values.iter().map(|value| value / y).collect(),
+        }
+    }
+}
+
+// CIE standard illuminant D65, every 10 nm from 360 nm
+const D65: [Float; 48] = [
+    46.6383, 52.0891, 49.9755, 54.6482, 82.7549, 91.486, 93.4318, 86.6823, 104.865, 117.008, 117.812, 114.861,
+    115.923, 108.811, 109.354, 107.802, 104.79, 107.689, 104.405, 104.046, 100., 96.3342, 95.788, 88.6856, 90.0062,
+    89.5991, 87.6987, 83.2886, 83.6992, 80.0268, 80.2146, 82.2778, 78.2842, 69.7213, 71.6091, 74.349, 61.604,
+    69.8856, 75.087, 63.5927, 46.4182, 66.8054, 63.3828, 64.304, 59.4519, 51.959, 57.4406, 60.3125,
+];
+
+// Named light spectra, all scaled to a luminance of 1
+#[derive(Debug, EnumIter, EnumString, PartialEq, Eq, Clone, Copy, Display, Hash)]
+#[strum(ascii_case_insensitive)]
+pub enum Illuminant {
+    // Average daylight, the white of sRGB
+    D65,
+    // Incandescent tungsten
+    A,
+    // Cool white, daylight and narrow tri-band fluorescent lamps
+    F2,
+    F7,
+    F11,
+}
+
+impl Illuminant {
+    pub fn spectrum(&self) -> &'static DenseSpectrum {
+        static SPECTRA: OnceLock<Vec<DenseSpectrum>> = OnceLock::new();
+        let spectra = SPECTRA.get_or_init(|| {
+            [Illuminant::D65, Illuminant::A, Illuminant::F2, Illuminant::F7, Illuminant::F11]
+                .iter()
+                .map(|illuminant| illuminant.build().normalized())
+                .collect()
+        });
+        &spectra[*self as usize]
+    }
+
+    fn build(&self) -> DenseSpectrum {
+        // Fluorescent lamps are modelled from the bands of their phosphors
+        // and the mercury lines, not from the tabulated CIE data
+        let band = |lambda: Float, center: Float, width: Float| lobe(lambda, center, width, width);
+        let mercury = |lambda: Float| {
+            [(404.7, 0.4), (435.8, 1.), (546.1, 1.2), (578., 0.5)]
+                .iter()
+                .map(|(line, strength)| strength * band(lambda, *line, 1.5))
+                .sum::<Float>()
+        };
+        match self {
+            Illuminant::D65 => DenseSpectrum::from_table(360., 10., &D65),
+            // Planck's law at 2856 K with the constants CIE defines A with
+            Illuminant::A => DenseSpectrum::new(|lambda| {
+                let c2: Float = 1.435e7;
+                100. * (560. / lambda).powi(5) * ((c2 / (2848. * 560.)).exp() - 1.) / ((c2 / (2848. * lambda)).exp() - 1.)
+            }),
+            Illuminant::F2 => DenseSpectrum::new(|lambda| {
+                band(lambda, 590., 55.) + 0.35 * band(lambda, 480., 40.) + 0.3 * mercury(lambda)
+            }),
+            Illuminant::F7 => DenseSpectrum::new(|lambda| {
+                band(lambda, 470., 60.) + 0.9 * band(lambda, 590., 70.) + 0.3 * mercury(lambda)
+            }),
+            Illuminant::F11 => DenseSpectrum::new(|lambda| {
+                0.5 * band(lambda, 450., 12.)
+                    + 1.4 * band(lambda, 544., 4.)
+                    + 1.2 * band(lambda, 611., 3.)
+                    + 0.15 * band(lambda, 590., 40.)
+                    + 0.2 * mercury(lambda)
+            }),
+        }
+    }
+}
+
+// Density of the wavelengths sampled, more of them where the eye is more
+// sensitive (pbrt-v4)
+pub fn visible_wavelengths_pdf(lambda: Float) -> Float {
+    if !(LAMBDA_MIN..=LAMBDA_MAX).contains(&lambda) {
+        return 0.;
+    }
+    0.003939804 / (0.0072 * (lambda - 538.)).cosh().powi(2)
+}
+
+pub fn sample_visible_wavelength(u: Float) -> Float {
+    538. - 138.88889 * (0.85691062 - 1.827502 * u).atanh()
+}
+
+// Wavelengths one path carries, the hero wavelength first. Companions whose
+// pdf is 0 were dropped.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct SampledWavelengths {
+    pub lambda: [Float; N_SPECTRUM_SAMPLES],
+    pub pdf: [Float; N_SPECTRUM_SAMPLES],
+}
+
+impl SampledWavelengths {
+    // The companions are spread evenly from the hero wavelength
+    pub fn sample_visible(u: Float) -> Self {
+        let mut lambda = [0.; N_SPECTRUM_SAMPLES];
+        let mut pdf = [0.; N_SPECTRUM_SAMPLES];
+        for i in 0..N_SPECTRUM_SAMPLES {
+            let up = (u + i as Float / N_SPECTRUM_SAMPLES as Float).fract();
+            lambda[i] = sample_visible_wavelength(up).clamp(LAMBDA_MIN, LAMBDA_MAX);
+            pdf[i] = visible_wavelengths_pdf(lambda[i]);
+        }
+        Self { lambda, pdf }
+    }
+
+    // Keep only the hero wavelength, for paths that went one way for it and
+    // another for the others. It stands for all of them in the estimate.
+    pub fn terminate_secondary(&mut self) {
+        if self.secondary_terminated() {
+            return;
+        }
+        for i in 1..N_SPECTRUM_SAMPLES {
+            self.pdf[i] = 0.;
+        }
+        self.pdf[0] /= N_SPECTRUM_SAMPLES as Float;
+    }
+
+    pub fn secondary_terminated(&self) -> bool {
+        self.pdf[1..].iter().all(|pdf| *pdf == 0.)
+    }
+
+    // Evaluate a spectrum given as a function of the wavelength
+    pub fn sample<F>(&self, spectrum: F) -> Spectrum
+    where
+        F: Fn(Float) -> Float,
+    {
+        Spectrum::Sampled(self.lambda.map(spectrum))
+    }
+
+    // Reflectance spectrum matching `rgb` in [0, 1]
+    pub fn albedo(&self, rgb: Vec3) -> Spectrum {
+        let polynomial = RgbToSpectrumTable::get().polynomial(rgb.clamp(0., 1.));
+        self.sample(|lambda| polynomial.evaluate(lambda))
+    }
+
+    // Spectrum matching `rgb` with no upper bound, the shape of the half
+    // brightest color scaled back up
+    pub fn unbounded(&self, rgb: Vec3) -> Spectrum {
+        let (polynomial, scale) = unbounded_polynomial(rgb);
+        self.sample(|lambda| scale * polynomial.evaluate(lambda))
+    }
+
+    // Light spectrum that looks like `rgb` in sRGB, lit by its white point
+    pub fn illuminant(&self, rgb: Vec3) -> Spectrum {
+        let (polynomial, scale) = unbounded_polynomial(rgb);
+        let white = Illuminant::D65.spectrum();
+        self.sample(|lambda| scale * polynomial.evaluate(lambda) * white.value(lambda))
+    }
+
+    // Reflectances above 1 are upsampled without a bound, spectra already
+    // sampled are kept
+    pub fn reflectance(&self, spectrum: Spectrum) -> Spectrum {
+        match spectrum {
+            Spectrum::ColorRGB(rgb) if rgb.max3() <= 1. => self.albedo(rgb),
+            Spectrum::ColorRGB(rgb) => self.unbounded(rgb),
+            Spectrum::Sampled(_) => spectrum,
+        }
+    }
+
+    pub fn light(&self, spectrum: Spectrum) -> Spectrum {
+        match spectrum {
+            Spectrum::ColorRGB(rgb) => self.illuminant(rgb),
+            Spectrum::Sampled(_) => spectrum,
+        }
+    }
+
+    // Monte Carlo estimate of the XYZ color of `spectrum`
+    pub fn to_xyz(&self, spectrum: Spectrum) -> Vec3 {
+        let values = match spectrum {
+            Spectrum::ColorRGB(rgb) => return linear_srgb_to_xyz(rgb),
+            Spectrum::Sampled(values) => values,
+        };
+        let mut xyz = Vec3::zero();
+        for ((value, &pdf), &lambda) in values.iter().zip(&self.pdf).zip(&self.lambda) {
+            if pdf > 0. {
+                xyz += *value / pdf * cie_xyz(lambda);
+            }
+        }
+        xyz / (N_SPECTRUM_SAMPLES as Float * CIE_Y_INTEGRAL)
+    }
+
+    pub fn to_rgb(&self, spectrum: Spectrum) -> Vec3 {
+        match spectrum {
+            Spectrum::ColorRGB(rgb) => rgb,
+            Spectrum::Sampled(_) => xyz_to_linear_srgb(self.to_xyz(spectrum)),
+        }
+    }
+}
+
+fn unbounded_polynomial(rgb: Vec3) -> (SigmoidPolynomial, Float) {
+    let rgb = Vec3::component_max(rgb, Vec3::zero());
+    let scale = 2. * rgb.max3();
+    if scale == 0. {
+        return (SigmoidPolynomial::default(), 0.);
+    }
+    (RgbToSpectrumTable::get().polynomial(rgb / scale), scale)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sampled_wavelengths() {
+        // Wavelength sampling inverts its pdf
+        let lambda = sample_visible_wavelength(0.3);
+        let steps = 10000;
+        let below: Float = (0..steps)
+            .map(|i| {
+                let l = LAMBDA_MIN + (lambda - LAMBDA_MIN) * (i as Float + 0.5) / steps as Float;
+                visible_wavelengths_pdf(l) * (lambda - LAMBDA_MIN) / steps as Float
+            })
+            .sum();
+        assert!((below - 0.3).abs() < 1e-3);
+
+        // White light on a grey surface comes back grey on average, and a
+        // red light stays red
+        let count = 4000;
+        let (mut grey, mut red) = (Vec3::zero(), Vec3::zero());
+        for i in 0..count {
+            let wavelengths = SampledWavelengths::sample_visible((i as Float + 0.5) / count as Float);
+            let light = wavelengths.illuminant(Vec3::from(1.));
+            grey += wavelengths.to_rgb(light * wavelengths.albedo(Vec3::from(0.5))) / count as Float;
+            red += wavelengths.to_rgb(wavelengths.illuminant(Vec3::new(0.8, 0.1, 0.1))) / count as Float;
+        }
+        assert!((grey - Vec3::from(0.5)).length() < 0.02);
+        assert!((red - Vec3::new(0.8, 0.1, 0.1)).length() < 0.05);
+
+        // The hero wavelength alone still averages to the same color
+        let mut hero = Vec3::zero();
+        for i in 0..count {
+            let mut wavelengths = SampledWavelengths::sample_visible((i as Float + 0.5) / count as Float);
+            let light = wavelengths.illuminant(Vec3::new(0.8, 0.1, 0.1));
+            wavelengths.terminate_secondary();
+            wavelengths.terminate_secondary();
+            hero += wavelengths.to_rgb(light) / count as Float;
+        }
+        assert!((hero - red).length() < 0.02);
+
+        // Named illuminants have a luminance of 1, incandescent light is warm
+        for illuminant in [Illuminant::D65, Illuminant::A, Illuminant::F11] {
+            let xyz = spectrum_to_xyz(|lambda| illuminant.spectrum().value(lambda));
+            assert!((xyz.y - 1.).abs() < 1e-3);
+        }
+        let a = xyz_to_linear_srgb(spectrum_to_xyz(|lambda| Illuminant::A.spectrum().value(lambda)));
+        assert!(a.x > a.y && a.y > a.z);
+    }
+}
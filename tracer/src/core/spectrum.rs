@@ -1,9 +1,30 @@
 use math::{Float, Vec3};
 use std::ops;
 
+// Values at the wavelengths a path carries in spectral mode
+pub type SampledValues = [Float; 4];
+
 #[derive(Copy, Clone, PartialEq, Debug)]
 pub enum Spectrum {
     ColorRGB(Vec3),
+    // Only meaningful with the wavelengths it was sampled at, see
+    // core::spectral::SampledWavelengths
+    Sampled(SampledValues),
+}
+
+// Mixing colors and sampled spectra is a bug, upsample the color first
+fn combine(s1: Spectrum, s2: Spectrum, rgb: fn(Vec3, Vec3) -> Vec3, sampled: fn(Float, Float) -> Float) -> Spectrum {
+    match (s1, s2) {
+        (Spectrum::ColorRGB(v1), Spectrum::ColorRGB(v2)) => Spectrum::ColorRGB(rgb(v1, v2)),
+        (Spectrum::Sampled(v1), Spectrum::Sampled(v2)) => {
+            Spectrum::Sampled([sampled(v1[0], v2[0]), sampled(v1[1], v2[1]), sampled(v1[2], v2[2]), sampled(v1[3], v2[3])])
+        }
+        _ => panic!("cannot combine an RGB color with a sampled spectrum"),
+    }
+}
+
+fn mean(values: &SampledValues) -> Float {
+    values.iter().sum::<Float>() / values.len() as Float
 }
 
 impl Default for Spectrum {
@@ -16,26 +37,56 @@ impl Spectrum {
     pub fn clamp(&self, min: Float, max: Float) -> Vec3 {
         match self {
             Spectrum::ColorRGB(spectrum) => spectrum.clamp(min, max),
+            Spectrum::Sampled(values) => Vec3::from(mean(values)).clamp(min, max),
         }
     }
 
     pub fn dot(s1: Spectrum, s2: Spectrum) -> Float {
-        match s1 {
-            Spectrum::ColorRGB(v1) => match s2 {
-                Spectrum::ColorRGB(v2) => Vec3::dot(v1, v2),
-            },
+        match (s1, s2) {
+            (Spectrum::ColorRGB(v1), Spectrum::ColorRGB(v2)) => Vec3::dot(v1, v2),
+            (Spectrum::Sampled(v1), Spectrum::Sampled(v2)) => v1.iter().zip(&v2).map(|(a, b)| a * b).sum(),
+            _ => panic!("cannot combine an RGB color with a sampled spectrum"),
+        }
+    }
+
+    pub fn is_black(&self) -> bool {
+        match self {
+            Spectrum::ColorRGB(spectrum) => spectrum.x == 0. && spectrum.y == 0. && spectrum.z == 0.,
+            Spectrum::Sampled(values) => values.iter().all(|v| *v == 0.),
         }
     }
 
+    // Sampled spectra come back grey, converting them properly needs their
+    // wavelengths
     pub fn to_rgb(&self) -> Vec3 {
         match self {
             Spectrum::ColorRGB(spectrum) => *spectrum,
+            Spectrum::Sampled(values) => Vec3::from(mean(values)),
+        }
+    }
+
+    // Relative luminance of linear sRGB
+    pub fn luminance(&self) -> Float {
+        match self {
+            Spectrum::ColorRGB(spectrum) => {
+                0.2126 * spectrum.x + 0.7152 * spectrum.y + 0.0722 * spectrum.z
+            }
+            Spectrum::Sampled(values) => mean(values),
+        }
+    }
+
+    // Same function on every channel
+    pub fn map(&self, f: impl Fn(Float) -> Float) -> Spectrum {
+        match self {
+            Spectrum::ColorRGB(spectrum) => Spectrum::ColorRGB(Vec3::new(f(spectrum.x), f(spectrum.y), f(spectrum.z))),
+            Spectrum::Sampled(values) => Spectrum::Sampled(values.map(f)),
         }
     }
 
     pub fn to_float_array(&self) -> [Float; 3] {
         match self {
             Spectrum::ColorRGB(spectrum) => [spectrum.x, spectrum.y, spectrum.z],
+            Spectrum::Sampled(values) => [mean(values); 3],
         }
     }
 }
@@ -46,6 +97,7 @@ impl ops::Add<Vec3> for Spectrum {
     fn add(self, _rhs: Vec3) -> Self::Output {
         match self {
             Spectrum::ColorRGB(spectrum) => Spectrum::ColorRGB(spectrum.add(_rhs)),
+            Spectrum::Sampled(_) => panic!("cannot combine an RGB color with a sampled spectrum"),
         }
     }
 }
@@ -54,11 +106,7 @@ impl ops::Add<Spectrum> for Spectrum {
     type Output = Spectrum;
 
     fn add(self, _rhs: Spectrum) -> Self::Output {
-        match self {
-            Spectrum::ColorRGB(spectrum) => match _rhs {
-                Spectrum::ColorRGB(_rhs) => Spectrum::ColorRGB(spectrum + _rhs),
-            },
-        }
+        combine(self, _rhs, |a, b| a + b, |a, b| a + b)
     }
 }
 
@@ -68,6 +116,7 @@ impl ops::Mul<Vec3> for Spectrum {
     fn mul(self, _rhs: Vec3) -> Self::Output {
         match self {
             Spectrum::ColorRGB(spectrum) => Spectrum::ColorRGB(spectrum.mul(_rhs)),
+            Spectrum::Sampled(_) => panic!("cannot combine an RGB color with a sampled spectrum"),
         }
     }
 }
@@ -76,11 +125,7 @@ impl ops::Mul<Spectrum> for Spectrum {
     type Output = Spectrum;
 
     fn mul(self, _rhs: Spectrum) -> Self::Output {
-        match self {
-            Spectrum::ColorRGB(spectrum) => match _rhs {
-                Spectrum::ColorRGB(_rhs) => Spectrum::ColorRGB(spectrum * _rhs),
-            },
-        }
+        combine(self, _rhs, |a, b| a * b, |a, b| a * b)
     }
 }
 
@@ -90,6 +135,7 @@ impl ops::Mul<Float> for Spectrum {
     fn mul(self, _rhs: Float) -> Self::Output {
         match self {
             Spectrum::ColorRGB(spectrum) => Spectrum::ColorRGB(spectrum.mul(_rhs)),
+            Spectrum::Sampled(values) => Spectrum::Sampled(values.map(|v| v * _rhs)),
         }
     }
 }
@@ -100,6 +146,7 @@ impl ops::Mul<Spectrum> for Float {
     fn mul(self, _rhs: Spectrum) -> Self::Output {
         match _rhs {
             Spectrum::ColorRGB(_rhs) => Spectrum::ColorRGB(self * _rhs),
+            Spectrum::Sampled(values) => Spectrum::Sampled(values.map(|v| self * v)),
         }
     }
 }
@@ -110,6 +157,7 @@ impl ops::Div<Float> for Spectrum {
     fn div(self, _rhs: Float) -> Self::Output {
         match self {
             Spectrum::ColorRGB(spectrum) => Spectrum::ColorRGB(spectrum.div(_rhs)),
+            Spectrum::Sampled(values) => Spectrum::Sampled(values.map(|v| v / _rhs)),
         }
     }
 }
@@ -1,5 +1,314 @@
+use std::ops;
+use std::sync::Arc;
+
+use math::{Float, Mat4, Vec2, Vec3};
+use strum_macros::{Display, EnumIter, EnumString};
+
+use crate::core::color_space::ColorEncoding;
 use crate::core::interaction::SurfaceInteraction;
+use crate::core::spectrum::Spectrum;
 
 pub trait Texture<T> {
     fn evaluate(&self, si: &SurfaceInteraction) -> T;
 }
+
+// Textures as materials and texture nodes hold them, shared between the
+// ones using them
+pub type TextureRef<T> = Arc<dyn Texture<T> + Sync + Send>;
+pub type SpectrumTexture = TextureRef<Spectrum>;
+pub type FloatTexture = TextureRef<Float>;
+
+// What textures can be made of: values that add, multiply and have
+// channels to map one by one
+pub trait TextureValue:
+    Copy + Send + Sync + 'static + ops::Add<Output = Self> + ops::Mul<Output = Self> + ops::Mul<Float, Output = Self>
+{
+    fn map(&self, f: impl Fn(Float) -> Float) -> Self;
+}
+
+impl TextureValue for Float {
+    fn map(&self, f: impl Fn(Float) -> Float) -> Self {
+        f(*self)
+    }
+}
+
+impl TextureValue for Spectrum {
+    fn map(&self, f: impl Fn(Float) -> Float) -> Self {
+        Spectrum::map(self, f)
+    }
+}
+
+// Texture coordinates of a hit. Solid textures use the whole point, flat
+// ones only x and y, which are u and v for the uv mapping.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum TextureMapping {
+    // uv of the hit, scaled then offset
+    Uv { scale: Vec2, offset: Vec2 },
+    // Point in the space of the shape, the pattern moves with instances.
+    // The matrix takes it to texture space.
+    Object(Mat4),
+    World(Mat4),
+}
+
+impl Default for TextureMapping {
+    fn default() -> Self {
+        TextureMapping::Uv {
+            scale: Vec2::from(1.),
+            offset: Vec2::from(0.),
+        }
+    }
+}
+
+impl TextureMapping {
+    pub fn point(&self, si: &SurfaceInteraction) -> Vec3 {
+        match self {
+            TextureMapping::Uv { scale, offset } => Vec3::new(
+                si.hit_uv.0 * scale.0 + offset.0,
+                si.hit_uv.1 * scale.1 + offset.1,
+                0.,
+            ),
+            TextureMapping::Object(texture_from_object) => *texture_from_object * si.hit_object_point,
+            TextureMapping::World(texture_from_world) => *texture_from_world * si.hit_point,
+        }
+    }
+}
+
+// How image textures average the texels under the footprint of a pixel
+#[derive(Debug, Default, EnumIter, EnumString, PartialEq, Eq, Clone, Copy, Display, Hash)]
+#[strum(ascii_case_insensitive)]
+pub enum TextureFilter {
+    // Nearest texel of the full image, aliases when far away
+    Nearest,
+    Bilinear,
+    // Bilinear on the two mip levels closest to the footprint's width
+    Trilinear,
+    // Elliptically weighted average, sharp at grazing angles where the
+    // footprint is long and thin
+    #[default]
+    Ewa,
+}
+
+// Footprints longer than this many times their width get wider, EWA would
+// read too many texels otherwise
+const MAX_ANISOTROPY: Float = 8.;
+
+// One level of a mip pyramid, texel rows go down the image
+#[derive(Clone, PartialEq, Debug)]
+pub struct MipLevel {
+    pub width: u32,
+    pub height: u32,
+    pub texels: Vec<Vec3>,
+}
+
+impl MipLevel {
+    // Wraps around outside of the image
+    pub fn texel(&self, x: i64, y: i64) -> Vec3 {
+        let x = x.rem_euclid(self.width as i64);
+        let y = y.rem_euclid(self.height as i64);
+        self.texels[(x + y * self.width as i64) as usize]
+    }
+
+    // Box filtered to half the size, rounded up. Odd sizes repeat their last
+    // row or column.
+    fn downsample(&self) -> MipLevel {
+        let (width, height) = (self.width.div_ceil(2).max(1), self.height.div_ceil(2).max(1));
+        let mut texels = Vec::with_capacity((width * height) as usize);
+        for y in 0..height as i64 {
+            for x in 0..width as i64 {
+                let at = |dx: i64, dy: i64| {
+                    self.texel((2 * x + dx).min(self.width as i64 - 1), (2 * y + dy).min(self.height as i64 - 1))
+                };
+                texels.push((at(0, 0) + at(1, 0) + at(0, 1) + at(1, 1)) * 0.25);
+            }
+        }
+        MipLevel { width, height, texels }
+    }
+
+    fn nearest(&self, uv: Vec2) -> Vec3 {
+        let x = (uv.0 * self.width as Float).floor() as i64;
+        let y = (uv.1 * self.height as Float).floor() as i64;
+        self.texel(x, y)
+    }
+
+    fn bilinear(&self, uv: Vec2) -> Vec3 {
+        let (x, y) = (uv.0 * self.width as Float - 0.5, uv.1 * self.height as Float - 0.5);
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        (self.texel(x0, y0) * (1. - fx) + self.texel(x0 + 1, y0) * fx) * (1. - fy)
+            + (self.texel(x0, y0 + 1) * (1. - fx) + self.texel(x0 + 1, y0 + 1) * fx) * fy
+    }
+
+    // Gaussian weighted average over the ellipse with axes `d0` and `d1` in
+    // uv, from pbrt
+    fn ewa(&self, uv: Vec2, d0: Vec2, d1: Vec2) -> Vec3 {
+        let (w, h) = (self.width as Float, self.height as Float);
+        let (s, t) = (uv.0 * w - 0.5, uv.1 * h - 0.5);
+        let (d0, d1) = (Vec2::new(&[d0.0 * w, d0.1 * h]), Vec2::new(&[d1.0 * w, d1.1 * h]));
+
+        // Implicit ellipse a s^2 + b s t + c t^2 = 1, grown a bit so it
+        // covers at least a texel
+        let mut a = d0.1 * d0.1 + d1.1 * d1.1 + 1.;
+        let mut b = -2. * (d0.0 * d0.1 + d1.0 * d1.1);
+        let mut c = d0.0 * d0.0 + d1.0 * d1.0 + 1.;
+        let inv_f = 1. / (a * c - b * b * 0.25);
+        (a, b, c) = (a * inv_f, b * inv_f, c * inv_f);
+
+        // Its bounding box
+        let det = -b * b + 4. * a * c;
+        let (s_radius, t_radius) = (2. * (det * c).sqrt() / det, 2. * (a * det).sqrt() / det);
+        let (s0, s1) = ((s - s_radius).ceil() as i64, (s + s_radius).floor() as i64);
+        let (t0, t1) = ((t - t_radius).ceil() as i64, (t + t_radius).floor() as i64);
+
+        const ALPHA: Float = 2.;
+        let mut sum = Vec3::from(0.);
+        let mut weights = 0.;
+        for it in t0..=t1 {
+            let tt = it as Float - t;
+            for is in s0..=s1 {
+                let ss = is as Float - s;
+                let r2 = a * ss * ss + b * ss * tt + c * tt * tt;
+                if r2 < 1. {
+                    let weight = (-ALPHA * r2).exp() - (-ALPHA).exp();
+                    sum += self.texel(is, it) * weight;
+                    weights += weight;
+                }
+            }
+        }
+        if weights > 0. {
+            sum / weights
+        } else {
+            self.bilinear(uv)
+        }
+    }
+}
+
+// Image looked up at the uv of the hit, filtered over the footprint of the
+// pixel. Values are decoded to linear sRGB when the image is loaded.
+#[derive(Clone, PartialEq, Debug)]
+pub struct ImageTexture {
+    // The image then each level half the size of the one before, down to
+    // a single texel
+    pub levels: Vec<MipLevel>,
+    // How the file was encoded
+    pub encoding: ColorEncoding,
+    pub filter: TextureFilter,
+}
+
+impl ImageTexture {
+    pub fn new(image: &image::DynamicImage, encoding: ColorEncoding) -> Self {
+        let rgb = image.to_rgb8();
+        let texels = rgb
+            .pixels()
+            .map(|pixel| Vec3::new(encoding.decode_u8(pixel[0]), encoding.decode_u8(pixel[1]), encoding.decode_u8(pixel[2])))
+            .collect();
+        ImageTexture::from_texels(rgb.width(), rgb.height(), texels, encoding)
+    }
+
+    pub fn from_texels(width: u32, height: u32, texels: Vec<Vec3>, encoding: ColorEncoding) -> Self {
+        let mut levels = vec![MipLevel { width, height, texels }];
+        while let Some(last) = levels.last().filter(|level| level.width > 1 || level.height > 1) {
+            levels.push(last.downsample());
+        }
+        Self {
+            levels,
+            encoding,
+            filter: TextureFilter::default(),
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.levels[0].width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.levels[0].height
+    }
+
+    // Nearest texel of the image, wraps around outside of [0, 1]
+    pub fn lookup(&self, uv: Vec2) -> Vec3 {
+        self.levels[0].nearest(uv)
+    }
+
+    // Mip level where a texel is `width` wide in uv, fractional between two
+    fn level_of(&self, width: Float) -> Float {
+        let size = self.width().max(self.height()) as Float;
+        ((width * size).max(1e-8).log2()).clamp(0., (self.levels.len() - 1) as Float)
+    }
+
+    // Blend of two neighbouring levels
+    fn between_levels(&self, level: Float, lookup: impl Fn(&MipLevel) -> Vec3) -> Vec3 {
+        let below = level.floor() as usize;
+        let t = level - below as Float;
+        if t == 0. || below + 1 >= self.levels.len() {
+            return lookup(&self.levels[below]);
+        }
+        lookup(&self.levels[below]) * (1. - t) + lookup(&self.levels[below + 1]) * t
+    }
+
+    // Filtered over the parallelogram spanned by the uv derivatives along x
+    // and y of the film
+    pub fn filter(&self, uv: Vec2, duv_dx: Vec2, duv_dy: Vec2) -> Vec3 {
+        let length = |d: Vec2| (d.0 * d.0 + d.1 * d.1).sqrt();
+        match self.filter {
+            TextureFilter::Nearest => self.lookup(uv),
+            TextureFilter::Bilinear => self.levels[0].bilinear(uv),
+            TextureFilter::Trilinear => {
+                let width = 2. * [duv_dx.0, duv_dx.1, duv_dy.0, duv_dy.1].iter().fold(0., |m: Float, d| m.max(d.abs()));
+                self.between_levels(self.level_of(width), |level| level.bilinear(uv))
+            }
+            TextureFilter::Ewa => {
+                let (mut major, mut minor) = (duv_dx, duv_dy);
+                if length(major) < length(minor) {
+                    (major, minor) = (minor, major);
+                }
+                let (longer, mut shorter) = (length(major), length(minor));
+                if shorter * MAX_ANISOTROPY < longer && shorter > 0. {
+                    let scale = longer / (shorter * MAX_ANISOTROPY);
+                    minor *= scale;
+                    shorter *= scale;
+                }
+                if shorter == 0. {
+                    return self.levels[0].bilinear(uv);
+                }
+                self.between_levels(self.level_of(shorter), |level| level.ewa(uv, major, minor))
+            }
+        }
+    }
+}
+
+impl Texture<Spectrum> for ImageTexture {
+    fn evaluate(&self, si: &SurfaceInteraction) -> Spectrum {
+        let (duv_dx, duv_dy) = (Vec2::new(&[si.dudx, si.dvdx]), Vec2::new(&[si.dudy, si.dvdy]));
+        Spectrum::ColorRGB(self.filter(si.hit_uv, duv_dx, duv_dy))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_image_filtering() {
+        // Texel wide black and white stripes
+        let texels = (0..64).map(|i| Vec3::from(((i % 8) % 2) as Float)).collect();
+        let mut stripes = ImageTexture::from_texels(8, 8, texels, ColorEncoding::Linear);
+        let sizes: Vec<(u32, u32)> = stripes.levels.iter().map(|level| (level.width, level.height)).collect();
+        assert_eq!(sizes, vec![(8, 8), (4, 4), (2, 2), (1, 1)]);
+        assert_eq!(stripes.levels[3].texels[0], Vec3::from(0.5));
+
+        // Without a footprint the stripes stay sharp, a pixel covering many
+        // of them sees their average
+        let uv = Vec2::new(&[1.5 / 8., 0.5]);
+        let (zero, wide) = (Vec2::from(0.), Vec2::new(&[0.5, 0.]));
+        for filter in [TextureFilter::Trilinear, TextureFilter::Ewa] {
+            stripes.filter = filter;
+            assert_eq!(stripes.filter(uv, zero, zero), Vec3::from(1.));
+            let average = stripes.filter(uv, wide, Vec2::new(&[0., 0.5]));
+            assert!((average.x - 0.5).abs() < 0.05, "{} {:?}", filter, average);
+        }
+        // Long footprints along v don't blur the stripes across u as much
+        let thin = stripes.filter(uv, Vec2::new(&[0., 0.5]), Vec2::new(&[0.01, 0.]));
+        assert!(thin.x > 0.75);
+    }
+}
@@ -0,0 +1,271 @@
+use math::{Float, Vec3};
+use rayon::prelude::*;
+
+use crate::core::aov::AovType;
+use crate::core::spectrum::Spectrum;
+
+// Feature buffers the denoiser needs from the film
+pub const DENOISER_FEATURES: [AovType; 3] = [AovType::Albedo, AovType::Normal, AovType::Depth];
+
+// First hit features of every pixel, rows top to bottom. A zero normal marks
+// a pixel that didn't hit anything.
+pub struct DenoiserFeatures {
+    pub albedo: Vec<Vec3>,
+    pub normal: Vec<Vec3>,
+    pub depth: Vec<Float>,
+}
+
+// Edge-avoiding à-trous wavelet filter (Dammertz et al. 2010) with the
+// variance guided luminance weight of SVGF (Schied et al. 2017).
+//
+// Texture detail is kept out of the filter by dividing the color by the
+// albedo, filtering the remaining illumination and multiplying the albedo
+// back. Each iteration applies a 5x5 B3 spline kernel whose taps are spread
+// 2^i pixels apart, weighted down across normal, depth and luminance edges.
+// The luminance tolerance follows the estimated noise of each pixel, so
+// converged pixels are left alone and noisy ones are smoothed the most.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AtrousDenoiser {
+    pub iterations: u32,
+    // Luminance tolerance, in standard deviations of the noise
+    pub sigma_color: Float,
+    // Exponent of the normal similarity
+    pub sigma_normal: Float,
+    // Depth tolerance, relative to the depth of the center pixel
+    pub sigma_depth: Float,
+}
+
+impl Default for AtrousDenoiser {
+    fn default() -> Self {
+        Self {
+            iterations: 5,
+            sigma_color: 4.,
+            sigma_normal: 128.,
+            sigma_depth: 0.1,
+        }
+    }
+}
+
+const KERNEL: [Float; 5] = [1. / 16., 1. / 4., 3. / 8., 1. / 4., 1. / 16.];
+
+fn luminance(rgb: Vec3) -> Float {
+    0.2126 * rgb.x + 0.7152 * rgb.y + 0.0722 * rgb.z
+}
+
+// Normals averaged over a pixel are shorter than one, zero stays zero
+fn unit(normal: Vec3) -> Vec3 {
+    if normal.length2() > 0. {
+        normal.normalize()
+    } else {
+        normal
+    }
+}
+
+// Albedo used to demodulate, black and missing albedo leave the color as is
+fn demodulation_albedo(albedo: Vec3) -> Vec3 {
+    let safe = |a: Float| if a < 1e-3 { 1. } else { a };
+    Vec3::new(safe(albedo.x), safe(albedo.y), safe(albedo.z))
+}
+
+impl AtrousDenoiser {
+    // `variance` is the variance of the estimated luminance of each pixel.
+    // Pixels without an estimate (a single sample) fall back to the
+    // luminance variance of their neighbourhood.
+    pub fn denoise(
+        &self,
+        color: &[Spectrum],
+        variance: &[Float],
+        features: &DenoiserFeatures,
+        width: u32,
+        height: u32,
+    ) -> Vec<Spectrum> {
+        let albedo: Vec<Vec3> = features.albedo.iter().map(|a| demodulation_albedo(*a)).collect();
+        let mut illumination: Vec<Vec3> = color
+            .iter()
+            .zip(&albedo)
+            .map(|(c, a)| {
+                let c = c.to_rgb();
+                Vec3::new(c.x / a.x, c.y / a.y, c.z / a.z)
+            })
+            .collect();
+        let spatial = spatial_variance(&illumination, width, height);
+        let mut variance: Vec<Float> = variance
+            .iter()
+            .zip(&albedo)
+            .zip(&spatial)
+            .map(|((v, a), s)| {
+                if *v > 0. {
+                    v / luminance(*a).powi(2)
+                } else {
+                    *s
+                }
+            })
+            .collect();
+
+        for i in 0..self.iterations {
+            let (filtered, filtered_variance) =
+                self.iterate(&illumination, &variance, features, width, height, 1 << i);
+            illumination = filtered;
+            variance = filtered_variance;
+        }
+
+        illumination
+            .iter()
+            .zip(&albedo)
+            .map(|(c, a)| Spectrum::ColorRGB(*c * *a))
+            .collect()
+    }
+
+    fn iterate(
+        &self,
+        illumination: &[Vec3],
+        variance: &[Float],
+        features: &DenoiserFeatures,
+        width: u32,
+        height: u32,
+        step: i64,
+    ) -> (Vec<Vec3>, Vec<Float>) {
+        let (w, h) = (width as i64, height as i64);
+        (0..illumination.len())
+            .into_par_iter()
+            .map(|p| {
+                let (px, py) = (p as i64 % w, p as i64 / w);
+                let color_p = illumination[p];
+                let normal_p = unit(features.normal[p]);
+                let depth_p = features.depth[p];
+                let luminance_p = luminance(color_p);
+
+                // Noise estimate of the center pixel, blurred over 3x3
+                let mut local_variance = 0.;
+                let mut local_weight = 0.;
+                for y in (py - 1).max(0)..=(py + 1).min(h - 1) {
+                    for x in (px - 1).max(0)..=(px + 1).min(w - 1) {
+                        let k = KERNEL[(x - px + 2) as usize] * KERNEL[(y - py + 2) as usize];
+                        local_variance += k * variance[(x + y * w) as usize];
+                        local_weight += k;
+                    }
+                }
+                let sigma_luminance =
+                    self.sigma_color * (local_variance / local_weight).max(0.).sqrt() + 1e-6;
+
+                let mut sum_color = Vec3::from(0.);
+                let mut sum_variance = 0.;
+                let mut sum_weight = 0.;
+                for ky in 0..5 {
+                    let y = py + (ky - 2) * step;
+                    if y < 0 || y >= h {
+                        continue;
+                    }
+                    for kx in 0..5 {
+                        let x = px + (kx - 2) * step;
+                        if x < 0 || x >= w {
+                            continue;
+                        }
+                        let q = (x + y * w) as usize;
+                        let normal_q = unit(features.normal[q]);
+
+                        let weight_normal = match (normal_p.length2() > 0., normal_q.length2() > 0.) {
+                            (true, true) => Vec3::dot(normal_p, normal_q).max(0.).powf(self.sigma_normal),
+                            (false, false) => 1.,
+                            _ => 0.,
+                        };
+                        let weight_depth = (-(depth_p - features.depth[q]).abs()
+                            / (self.sigma_depth * depth_p.abs() * step as Float + 1e-6))
+                            .exp();
+                        let weight_luminance =
+                            (-(luminance_p - luminance(illumination[q])).abs() / sigma_luminance).exp();
+
+                        let weight = KERNEL[kx as usize] * KERNEL[ky as usize]
+                            * weight_normal
+                            * weight_depth
+                            * weight_luminance;
+                        sum_color += illumination[q] * weight;
+                        sum_variance += weight * weight * variance[q];
+                        sum_weight += weight;
+                    }
+                }
+
+                if sum_weight <= 0. {
+                    return (color_p, variance[p]);
+                }
+                (sum_color / sum_weight, sum_variance / (sum_weight * sum_weight))
+            })
+            .unzip()
+    }
+}
+
+// Luminance variance of the 5x5 neighbourhood of each pixel
+fn spatial_variance(illumination: &[Vec3], width: u32, height: u32) -> Vec<Float> {
+    let (w, h) = (width as i64, height as i64);
+    (0..illumination.len())
+        .into_par_iter()
+        .map(|p| {
+            let (px, py) = (p as i64 % w, p as i64 / w);
+            let mut sum = 0.;
+            let mut sum2 = 0.;
+            let mut n = 0.;
+            for y in (py - 2).max(0)..=(py + 2).min(h - 1) {
+                for x in (px - 2).max(0)..=(px + 2).min(w - 1) {
+                    let l = luminance(illumination[(x + y * w) as usize]);
+                    sum += l;
+                    sum2 += l * l;
+                    n += 1.;
+                }
+            }
+            let mean = sum / n;
+            (sum2 / n - mean * mean).max(0.)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flat_features(width: u32, height: u32) -> DenoiserFeatures {
+        let n = (width * height) as usize;
+        DenoiserFeatures {
+            albedo: vec![Vec3::from(0.5); n],
+            normal: vec![Vec3::new(0., 1., 0.); n],
+            depth: vec![2.; n],
+        }
+    }
+
+    #[test]
+    fn test_noise_is_reduced_and_edges_kept() {
+        let (width, height) = (32, 32);
+        let mut features = flat_features(width, height);
+        // Left half faces another way, the filter must not bleed across
+        for (i, normal) in features.normal.iter_mut().enumerate() {
+            if i as u32 % width < width / 2 {
+                *normal = Vec3::new(1., 0., 0.);
+            }
+        }
+        let color: Vec<Spectrum> = (0..width * height)
+            .map(|i| {
+                let base = if i % width < width / 2 { 0.1 } else { 0.4 };
+                let noise = ((i * 7919) % 13) as Float / 13. - 0.5;
+                Spectrum::ColorRGB(Vec3::from(base + 0.1 * noise))
+            })
+            .collect();
+        let variance = vec![0.01 / 16.; color.len()];
+
+        let denoised = AtrousDenoiser::default().denoise(&color, &variance, &features, width, height);
+
+        let error = |image: &[Spectrum]| -> Float {
+            image
+                .iter()
+                .enumerate()
+                .map(|(i, c)| {
+                    let base = if i as u32 % width < width / 2 { 0.1 } else { 0.4 };
+                    (c.to_rgb().x - base).abs()
+                })
+                .sum()
+        };
+        assert!(error(&denoised) < 0.5 * error(&color));
+        // Pixels right next to the edge keep their side's value
+        let row = 16 * width as usize;
+        assert!((denoised[row + 15].to_rgb().x - 0.1).abs() < 0.03);
+        assert!((denoised[row + 16].to_rgb().x - 0.4).abs() < 0.03);
+    }
+}
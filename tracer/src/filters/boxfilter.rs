@@ -0,0 +1,22 @@
+use math::{Float, Vec2};
+
+// Equal weight for every sample inside the radius. With a radius of half a
+// pixel every sample only lands in the pixel it was taken in.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct BoxFilter {
+    pub radius: Vec2,
+}
+
+impl BoxFilter {
+    pub fn new(radius: Vec2) -> Self {
+        Self { radius }
+    }
+
+    pub fn evaluate(&self, p: &Vec2) -> Float {
+        if p.x().abs() <= self.radius.x() && p.y().abs() <= self.radius.y() {
+            1.
+        } else {
+            0.
+        }
+    }
+}
@@ -0,0 +1,34 @@
+use math::{Float, Vec2};
+
+fn gaussian(x: Float, sigma: Float) -> Float {
+    1. / (2. * std::f32::consts::PI * sigma * sigma).sqrt() * (-x * x / (2. * sigma * sigma)).exp()
+}
+
+// Gaussian bump shifted down so it reaches exactly zero at the radius,
+// otherwise there would be a visible discontinuity at the filter edge.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct GaussianFilter {
+    pub radius: Vec2,
+    pub sigma: Float,
+    exp_x: Float,
+    exp_y: Float,
+}
+
+impl GaussianFilter {
+    pub fn new(radius: Vec2, sigma: Float) -> Self {
+        Self {
+            radius,
+            sigma,
+            exp_x: gaussian(radius.x(), sigma),
+            exp_y: gaussian(radius.y(), sigma),
+        }
+    }
+
+    pub fn evaluate(&self, p: &Vec2) -> Float {
+        if p.x().abs() > self.radius.x() || p.y().abs() > self.radius.y() {
+            return 0.;
+        }
+        Float::max(0., gaussian(p.x(), self.sigma) - self.exp_x)
+            * Float::max(0., gaussian(p.y(), self.sigma) - self.exp_y)
+    }
+}
@@ -0,0 +1,34 @@
+use math::{Float, Vec2};
+
+fn sinc(x: Float) -> Float {
+    if x.abs() < 1e-5 {
+        return 1.;
+    }
+    let px = std::f32::consts::PI * x;
+    px.sin() / px
+}
+
+// Sinc windowed by a wider sinc (Lanczos window), `tau` is the number of
+// sinc lobes kept inside the window.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct LanczosSincFilter {
+    pub radius: Vec2,
+    pub tau: Float,
+}
+
+impl LanczosSincFilter {
+    pub fn new(radius: Vec2, tau: Float) -> Self {
+        Self { radius, tau }
+    }
+
+    fn windowed_sinc(&self, x: Float, radius: Float) -> Float {
+        if x.abs() > radius {
+            return 0.;
+        }
+        sinc(x) * sinc(x / self.tau)
+    }
+
+    pub fn evaluate(&self, p: &Vec2) -> Float {
+        self.windowed_sinc(p.x(), self.radius.x()) * self.windowed_sinc(p.y(), self.radius.y())
+    }
+}
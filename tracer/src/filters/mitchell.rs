@@ -0,0 +1,42 @@
+use math::{Float, Vec2};
+
+// Mitchell-Netravali cubic. B = C = 1/3 is the pair recommended in the paper,
+// trading a little blurring for a little ringing.
+// Ref: Mitchell and Netravali, "Reconstruction Filters in Computer Graphics"
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct MitchellFilter {
+    pub radius: Vec2,
+    pub b: Float,
+    pub c: Float,
+}
+
+impl MitchellFilter {
+    pub fn new(radius: Vec2, b: Float, c: Float) -> Self {
+        Self { radius, b, c }
+    }
+
+    // Cubic defined over [-2, 2]
+    fn mitchell_1d(&self, x: Float) -> Float {
+        let x = x.abs();
+        let (b, c) = (self.b, self.c);
+        if x <= 1. {
+            ((12. - 9. * b - 6. * c) * x * x * x
+                + (-18. + 12. * b + 6. * c) * x * x
+                + (6. - 2. * b))
+                / 6.
+        } else if x <= 2. {
+            ((-b - 6. * c) * x * x * x
+                + (6. * b + 30. * c) * x * x
+                + (-12. * b - 48. * c) * x
+                + (8. * b + 24. * c))
+                / 6.
+        } else {
+            0.
+        }
+    }
+
+    pub fn evaluate(&self, p: &Vec2) -> Float {
+        // Remap [-radius, radius] to the [-2, 2] support of the cubic
+        self.mitchell_1d(2. * p.x() / self.radius.x()) * self.mitchell_1d(2. * p.y() / self.radius.y())
+    }
+}
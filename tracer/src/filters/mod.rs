@@ -0,0 +1,5 @@
+pub mod boxfilter;
+pub mod gaussian;
+pub mod lanczos;
+pub mod mitchell;
+pub mod tent;
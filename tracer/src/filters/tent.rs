@@ -0,0 +1,17 @@
+use math::{Float, Vec2};
+
+// Linear falloff from the center to zero at the radius (a.k.a. triangle filter)
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct TentFilter {
+    pub radius: Vec2,
+}
+
+impl TentFilter {
+    pub fn new(radius: Vec2) -> Self {
+        Self { radius }
+    }
+
+    pub fn evaluate(&self, p: &Vec2) -> Float {
+        Float::max(0., self.radius.x() - p.x().abs()) * Float::max(0., self.radius.y() - p.y().abs())
+    }
+}
@@ -1,4 +1,5 @@
 use std::f32::consts::PI;
+use std::sync::Arc;
 
 use log::info;
 use rayon::prelude::*;
@@ -6,21 +7,40 @@ use rayon::prelude::*;
 use math::{Float, Vec2, Vec3};
 
 use crate::core::{
+    aov::AovSample, color_space::ColorSpace, film::{Film, FilmSample}, filter::Filter, imageio::ImageFormat,
     interaction::SurfaceInteraction, ray::Ray, sampler::Sampler, scene::Scene, shape::Shape,
-    spectrum::Spectrum, view::View,
+    spectral::SampledWavelengths, spectrum::Spectrum, view::View,
 };
+use crate::core::texture::Texture;
+use crate::materials::ScatterLobe;
+use crate::media::Medium;
 
 pub const SCREEN_WIDTH: u32 = 1280;
 pub const SCREEN_HEIGHT: u32 = 720;
-pub const SAMPLES_PER_PIXEL: u8 = 5;
+pub const SAMPLES_PER_PIXEL: u32 = 5;
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct RenderSettings {
     pub single_thread: bool,
     pub write_to_file: bool,
     pub skycolor_tint: [f32; 3],
     pub current_depth: i32,
-    pub sample_per_pixel: u8,
+    pub sample_per_pixel: u32,
+    pub filter: Filter,
+    // Global seed every random decision of the render is derived from
+    pub seed: u64,
+    // Used when the scene doesn't ask for a specific output file
+    pub output_format: ImageFormat,
+    // Stop sampling pixels once their relative error is below
+    // `noise_threshold`, `sample_per_pixel` is then the maximum
+    pub adaptive_sampling: bool,
+    pub noise_threshold: Float,
+    // Samples taken before the error estimate is trusted
+    pub min_samples: u32,
+    // Trace a hero wavelength and its companions per path instead of RGB
+    pub spectral: bool,
+    // Color space the light is computed in, the film is in it too
+    pub working_space: ColorSpace,
 }
 
 impl Default for RenderSettings {
@@ -30,34 +50,69 @@ impl Default for RenderSettings {
             write_to_file: false,
             skycolor_tint: [1.0, 1.0, 1.0],
             current_depth: 0,
-            sample_per_pixel: SAMPLES_PER_PIXEL
+            sample_per_pixel: SAMPLES_PER_PIXEL,
+            filter: Filter::default(),
+            seed: 0,
+            output_format: ImageFormat::Png,
+            adaptive_sampling: false,
+            noise_threshold: 0.02,
+            min_samples: 8,
+            spectral: false,
+            working_space: ColorSpace::LinearSrgb,
         }
     }
 }
 
 
 // A width x height framebuffer to track the progressive render state.
-// Stores color, ray, depth, and termination state of each fragment.
+// Stores the accumulated film, ray, depth, and termination state of each
+// fragment. A terminated fragment has converged and isn't sampled anymore.
 #[derive(Clone, Debug)]
 pub struct FrameBuffer {
     pub depths: Vec<u32>,
-    pub spectrums: Vec<Spectrum>,
+    pub film: Film,
     pub rays: Vec<Ray>,
     pub terminated: Vec<bool>,
-    pub current_sample: u8,
+    pub current_sample: u32,
 }
 
 impl FrameBuffer {
-    pub fn new(width: u32, height: u32) -> Self {
+    pub fn new(width: u32, height: u32, filter: Filter) -> Self {
         let num_fragments = (width * height) as usize;
         Self {
             depths: vec![0; num_fragments],
-            spectrums: vec![Spectrum::default(); num_fragments],
+            film: Film::with_filter(width, height, "image", filter),
             rays: vec![Ray::default(); num_fragments],
             terminated: vec![false; num_fragments],
             current_sample: 0
         }
     }
+
+    // Stop sampling the pixels whose estimated relative error is below the
+    // noise threshold. The error of a pixel is the largest one of its 3x3
+    // neighbourhood, since a handful of samples can underestimate it.
+    pub fn update_converged(&mut self, settings: &RenderSettings) {
+        let film = &self.film;
+        let (width, height) = (film.width as i64, film.height as i64);
+        let errors: Vec<Float> = film.variance.iter().map(|variance| variance.relative_error()).collect();
+        for (index, terminated) in self.terminated.iter_mut().enumerate() {
+            if *terminated || film.variance[index].count < settings.min_samples {
+                continue;
+            }
+            let (px, py) = (index as i64 % width, index as i64 / width);
+            let mut error: Float = 0.;
+            for y in (py - 1).max(0)..=(py + 1).min(height - 1) {
+                for x in (px - 1).max(0)..=(px + 1).min(width - 1) {
+                    error = error.max(errors[(x + y * width) as usize]);
+                }
+            }
+            *terminated = error < settings.noise_threshold;
+        }
+    }
+
+    pub fn all_converged(&self) -> bool {
+        self.terminated.iter().all(|terminated| *terminated)
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -66,6 +121,25 @@ pub struct Fragment {
     pub acc_spectrum: Spectrum,
     pub ray: Ray,
     pub terminate: bool,
+    // Where the camera sample of this fragment landed on the film
+    pub p_film: Vec2,
+    // First hit and light split recorded for the AOVs
+    pub aov: AovSample,
+    // Lobe of the first scattering event, if any
+    pub lobe: Option<ScatterLobe>,
+    // Medium the ray travels through
+    pub medium: Option<Arc<dyn Medium>>,
+    // Light gathered by next event estimation and emitted by media along the
+    // path so far
+    pub nee_radiance: Spectrum,
+    // Density of the direction of the ray when it was sampled from a phase
+    // function, to weight the environment against next event estimation
+    pub phase_pdf: Option<Float>,
+    // Wavelengths the path carries in spectral mode, the spectra of the
+    // fragment are then sampled at them until the path is done
+    pub wavelengths: Option<SampledWavelengths>,
+    // Working space colors are multiplied in
+    pub color_space: ColorSpace,
 }
 
 impl Default for Fragment {
@@ -75,6 +149,14 @@ impl Default for Fragment {
             acc_spectrum: Spectrum::ColorRGB(Vec3::from(0.0)),
             ray: Ray::default(),
             terminate: false,
+            p_film: Vec2::from(0.0),
+            aov: AovSample::default(),
+            lobe: None,
+            medium: None,
+            nee_radiance: Spectrum::ColorRGB(Vec3::from(0.0)),
+            phase_pdf: None,
+            wavelengths: None,
+            color_space: ColorSpace::default(),
         }
     }
 }
@@ -87,15 +169,27 @@ impl DirectLightingIntegrator {
     }
     
     // Raytrace one fragment (pixel)
-    fn li(fragment: &Fragment, scene: &Scene) -> Fragment {
+    fn li(fragment: &Fragment, scene: &Scene, sampler: &mut Sampler) -> Fragment {
         // TODO: Turn depth into a paramter
         const MAX_DEPTH: u32 = 100;
 
-        let mut new_fragment = Fragment::default();
-        new_fragment.ray = fragment.ray;
+        let (one, zero) = match fragment.wavelengths {
+            Some(_) => (Spectrum::Sampled([1.; 4]), Spectrum::Sampled([0.; 4])),
+            None => (Spectrum::ColorRGB(Vec3::from(1.0)), Spectrum::ColorRGB(Vec3::from(0.0))),
+        };
+        let mut new_fragment = Fragment {
+            ray: fragment.ray,
+            medium: fragment.medium.clone(),
+            acc_spectrum: one,
+            nee_radiance: zero,
+            wavelengths: fragment.wavelengths,
+            color_space: fragment.color_space,
+            ..Default::default()
+        };
         for depth in 0..MAX_DEPTH {
             new_fragment.depth = depth;
-            new_fragment = DirectLightingIntegrator::li_one_bounce(&new_fragment, &scene.clone());
+            sampler.start_bounce(depth + 1);
+            new_fragment = DirectLightingIntegrator::li_one_bounce(&new_fragment, scene, sampler);
             if new_fragment.terminate {
                 break;
             }
@@ -103,179 +197,462 @@ impl DirectLightingIntegrator {
 
         // Finished rendering past depth
         new_fragment.terminate = true;
+        new_fragment.acc_spectrum = new_fragment.acc_spectrum + new_fragment.nee_radiance;
+        // The film and the AOVs stay in RGB
+        if let Some(wavelengths) = new_fragment.wavelengths {
+            let rgb = wavelengths.to_rgb(new_fragment.acc_spectrum);
+            new_fragment.acc_spectrum = new_fragment.color_space.from_srgb(Spectrum::ColorRGB(rgb));
+            new_fragment.nee_radiance = Spectrum::ColorRGB(Vec3::from(0.0));
+        }
+        DirectLightingIntegrator::split_light(&mut new_fragment);
         return new_fragment;
     }
 
+    // Attribute the radiance of a finished path to one of the light AOVs.
+    // Paths that reached the light right after their first scattering event
+    // are direct lighting, the ones that bounced more are indirect.
+    fn split_light(fragment: &mut Fragment) {
+        let radiance = fragment.acc_spectrum;
+        let direct = fragment.depth <= 1;
+        match fragment.lobe {
+            None => fragment.aov.emission = radiance,
+            Some(ScatterLobe::Diffuse) if direct => fragment.aov.diffuse_direct = radiance,
+            Some(ScatterLobe::Diffuse) => fragment.aov.diffuse_indirect = radiance,
+            Some(ScatterLobe::Specular) if direct => fragment.aov.specular_direct = radiance,
+            Some(ScatterLobe::Specular) => fragment.aov.specular_indirect = radiance,
+        }
+    }
+
+    // Light and reflectance of the scene, which are linear sRGB, at the
+    // wavelengths of the path in spectral mode and in the working space
+    // otherwise
+    fn light(fragment: &Fragment, spectrum: Spectrum) -> Spectrum {
+        match fragment.wavelengths {
+            Some(wavelengths) => wavelengths.light(spectrum),
+            None => fragment.color_space.from_srgb(spectrum),
+        }
+    }
+
+    fn reflectance(fragment: &Fragment, spectrum: Spectrum) -> Spectrum {
+        match fragment.wavelengths {
+            Some(wavelengths) => wavelengths.reflectance(spectrum),
+            None => fragment.color_space.from_srgb(spectrum),
+        }
+    }
+
+    // Balance the densities of two ways of sampling the same direction
+    fn power_heuristic(pdf: Float, other_pdf: Float) -> Float {
+        let (pdf, other_pdf) = (pdf * pdf, other_pdf * other_pdf);
+        if pdf + other_pdf == 0. {
+            return 0.;
+        }
+        pdf / (pdf + other_pdf)
+    }
+
+    // Scatter the ray in a medium at `point`. The environment is sampled
+    // directly through the media in between, then the phase function picks
+    // where the path goes on.
+    fn scatter_in_medium(
+        fragment: &mut Fragment,
+        medium: &Arc<dyn Medium>,
+        point: Vec3,
+        scene: &Scene,
+        sampler: &mut Sampler,
+    ) {
+        let ray = fragment.ray;
+        let phase = medium.phase();
+
+        // Uniform directions over the sphere
+        let u = sampler.random_vec2_0_1();
+        let z = 1. - 2. * u.0;
+        let r = (1. - z * z).max(0.).sqrt();
+        let phi = 2. * PI * u.1;
+        let light_pdf = 1. / (4. * PI);
+        let shadow_ray = Ray::new(point, Vec3::new(r * phi.cos(), r * phi.sin(), z)).at_time(ray.time);
+        let phase_value = phase.p(ray.direction, shadow_ray.direction);
+        let transmittance = scene.transmittance(&shadow_ray, Some(medium.clone()), sampler);
+        if !transmittance.is_black() {
+            let weight = DirectLightingIntegrator::power_heuristic(light_pdf, phase_value) * phase_value / light_pdf;
+            let transmittance = DirectLightingIntegrator::reflectance(fragment, transmittance);
+            let environment_spectrum =
+                DirectLightingIntegrator::light(fragment, (scene.environment_light)(&shadow_ray));
+            fragment.nee_radiance =
+                fragment.nee_radiance + fragment.acc_spectrum * transmittance * environment_spectrum * weight;
+        }
+
+        // Sampling the phase function exactly leaves the throughput as is
+        let (direction, pdf) = phase.sample(ray.direction, &sampler.random_vec2_0_1());
+        fragment.ray = Ray::new(point, direction).at_time(ray.time);
+        fragment.phase_pdf = Some(pdf);
+        if fragment.lobe.is_none() {
+            fragment.lobe = Some(ScatterLobe::Diffuse);
+        }
+    }
+
     // Ray trace one bounce
     fn li_one_bounce( 
         current_fragment: &Fragment,
-        scene: &Scene
+        scene: &Scene,
+        sampler: &mut Sampler,
     ) -> Fragment {
             let mut new_fragment = current_fragment.clone();
             let mut isect = SurfaceInteraction::new();
             let hit = scene.intersect(&current_fragment.ray, &mut isect);
+            if hit {
+                isect.compute_differentials(&current_fragment.ray);
+            }
+
+            // The medium may scatter the ray before it gets to the surface
+            if let Some(medium) = &current_fragment.medium {
+                let t_max = if hit { isect.t } else { Float::INFINITY };
+                let sample = medium.sample_distance(&current_fragment.ray, t_max, sampler);
+                let emission = DirectLightingIntegrator::light(current_fragment, sample.emission);
+                let weight = DirectLightingIntegrator::reflectance(current_fragment, sample.weight);
+                new_fragment.nee_radiance = new_fragment.nee_radiance + current_fragment.acc_spectrum * emission;
+                new_fragment.acc_spectrum = new_fragment.acc_spectrum * weight;
+                // Absorbed on the way
+                if new_fragment.acc_spectrum.is_black() {
+                    new_fragment.terminate = true;
+                    return new_fragment;
+                }
+                if let Some(t) = sample.scatter_t {
+                    let point = current_fragment.ray.point_at(t);
+                    DirectLightingIntegrator::scatter_in_medium(&mut new_fragment, medium, point, scene, sampler);
+                    return new_fragment;
+                }
+            }
 
             // Failed to hit anything. Return
             if !hit {
                 new_fragment.terminate = true;
 
-                // Blend with the environment light
+                // Blend with the environment light. Directions a medium
+                // scattered into were also reached by next event estimation.
                 let environment_spectrum = (scene.environment_light)(&current_fragment.ray);// * Spectrum::ColorRGB(Vec3::from(&render_setings.skycolor_tint));
-                if current_fragment.depth == 0 {
-                    new_fragment.acc_spectrum = environment_spectrum;
-                } else {
-                    new_fragment.acc_spectrum = current_fragment.acc_spectrum.clone() * environment_spectrum;
-                }
+                let environment_spectrum = DirectLightingIntegrator::light(current_fragment, environment_spectrum);
+                let weight = match current_fragment.phase_pdf {
+                    Some(pdf) => DirectLightingIntegrator::power_heuristic(pdf, 1. / (4. * PI)),
+                    None => 1.,
+                };
+                new_fragment.acc_spectrum = current_fragment.acc_spectrum * environment_spectrum * weight;
                 return new_fragment;
             }
 
+            // Surfaces without a material only separate two media, the ray
+            // goes on straight through
+            if let Some(ref primitive) = isect.hit_primitive {
+                if primitive.material.is_none() {
+                    if let Some(ref interface) = primitive.medium_interface {
+                        new_fragment.medium = interface.entered(isect.hit_front_face);
+                    }
+                    new_fragment.ray.origin = isect.hit_point + current_fragment.ray.direction * 1e-3;
+                    return new_fragment;
+                }
+            }
+
             // Hit something. Determines color now.
             let mut material_color = Spectrum::ColorRGB(Vec3::from(1.0));
+            let mut path_color = DirectLightingIntegrator::reflectance(current_fragment, material_color);
 
             if let Some(ref primitive) = isect.hit_primitive {
                 if let Some(ref material) = primitive.material {
-                    material_color = material.value().clone();
-                    let scatter_result = material.scatter(&current_fragment.ray, &mut material_color, &isect);
-                    // New ray
-                    new_fragment.ray.direction = scatter_result.ray.direction.normalize();
-                    new_fragment.ray.origin = isect.hit_point + new_fragment.ray.direction * 1e-3;
-
+                    material_color = material.evaluate(&isect);
+                    // Dispersive materials may drop all but the hero wavelength
+                    let scatter_result = match new_fragment.wavelengths.as_mut() {
+                        Some(wavelengths) => {
+                            let result = material.sampled_scatter(wavelengths, &current_fragment.ray, &material_color, &isect, sampler);
+                            path_color = material.sampled_value(wavelengths, &current_fragment.ray, &isect);
+                            result
+                        }
+                        None => {
+                            path_color = DirectLightingIntegrator::reflectance(current_fragment, material_color);
+                            material.scatter(&current_fragment.ray, &mut material_color, &isect, sampler)
+                        }
+                    };
+                    // Textured meshes take their color from the texture, still
+                    // weighted by the scattering
                     match &primitive.shape {
                         Shape::Mesh(mesh) => {
-                            // TODO: Debug UV
-                            let uv = isect.hit_uv;
-                            // material_color = Spectrum::ColorRGB(Vec3::new(isect.hit_uv.x(), isect.hit_uv.y(), 0.0));
-                            let x = (uv.x() * mesh.base_color_texture.width() as Float) as u32;
-                            let y = (uv.y() * mesh.base_color_texture.height() as Float) as u32;
-                            let base_color_texture = mesh.base_color_texture.clone().into_rgb8();
-                            if base_color_texture.width() > 1 && base_color_texture.height() > 1 {
-                                let rgb = base_color_texture.get_pixel(x, y);
-                                material_color = Spectrum::ColorRGB(Vec3::new(
-                                    rgb[0] as f32,
-                                    rgb[1] as f32,
-                                    rgb[2] as f32,
-                                ));
+                            if let Some(ref texture) = mesh.base_color_texture {
+                                material_color = texture.evaluate(&isect);
+                                path_color = DirectLightingIntegrator::reflectance(current_fragment, material_color);
                             }
                         }
                         _ => {}
                     }
+                    path_color = path_color * scatter_result.weight;
+                    // New ray
+                    new_fragment.ray.direction = scatter_result.ray.direction.normalize();
+                    new_fragment.ray.origin = isect.hit_point + new_fragment.ray.direction * 1e-3;
+                    // Only mirrors and glass keep the footprint of the pixel
+                    // coherent, the material gives the new offset rays
+                    new_fragment.ray.differential = scatter_result.ray.differential;
+                    new_fragment.phase_pdf = None;
+
+                    // Going through the surface enters the medium on the
+                    // other side
+                    if let Some(ref interface) = primitive.medium_interface {
+                        let incoming = Vec3::dot(current_fragment.ray.direction, isect.hit_normal);
+                        let outgoing = Vec3::dot(new_fragment.ray.direction, isect.hit_normal);
+                        if incoming * outgoing > 0. {
+                            new_fragment.medium = interface.entered(isect.hit_front_face);
+                        }
+                    }
+                }
+            }
+
+            if let Some(ref primitive) = isect.hit_primitive {
+                if new_fragment.lobe.is_none() {
+                    new_fragment.lobe = primitive.material.as_ref().map(|material| material.lobe());
+                }
+            }
+
+            // Record the first surface seen from the camera
+            if current_fragment.depth == 0 {
+                if let Some(ref primitive) = isect.hit_primitive {
+                    new_fragment.aov = AovSample {
+                        hit: true,
+                        depth: isect.t,
+                        position: isect.hit_point,
+                        normal: isect.hit_normal,
+                        albedo: current_fragment.color_space.from_srgb(material_color),
+                        uv: isect.hit_uv,
+                        primitive_id: primitive.id,
+                        material_id: primitive.material_id,
+                        ..Default::default()
+                    };
                 }
             }
 
             // let n_dot_l = Float::clamp(Vec3::dot(isect.hit_normal, new_fragment.ray.direction), 0., 1.);
             // new_fragment.acc_spectrum = DirectLightingIntegrator::brdf_lambert(material_color) * n_dot_l * 2.0 * PI;
-            if new_fragment.depth == 0 {
-                new_fragment.acc_spectrum = material_color;
-            } else {
-                new_fragment.acc_spectrum = current_fragment.acc_spectrum * material_color;
-            }
+            new_fragment.acc_spectrum = new_fragment.acc_spectrum * path_color;
             return new_fragment;
     }
 
-    fn render_single_thread(
+    // Render one new sample of the fragment at `frag_index`. Every random
+    // decision comes from a sampler keyed on the seed, the pixel and the
+    // sample index, so the result doesn't depend on which thread runs it.
+    fn render_fragment(
         scene: &Scene,
         view: &View,
         framebuffer: &FrameBuffer,
-    ) -> FrameBuffer {
-        let mut new_frame = FrameBuffer::new(view.width, view.height);
-        new_frame.current_sample = framebuffer.current_sample;
-
-        // Single threaded version
-        for y in 0..view.height {
-            for x in 0..view.width {
-
-                // Initialize fragment
-                let frag_index = (x + (view.height - y - 1) * view.width) as usize;
-                let mut fragment = Fragment {
-                    depth: framebuffer.depths[frag_index],
-                    acc_spectrum: framebuffer.spectrums[frag_index],
-                    ray: framebuffer.rays[frag_index],
-                    terminate: framebuffer.terminated[frag_index]
-
-                };
-                
-                let Spectrum::ColorRGB(mut acc_spectrum) = fragment.acc_spectrum;
-                let uv: Vec2 = Sampler::sample_from_pixel(
-                    Vec2 {
-                        0: x as Float,
-                        1: y as Float,
-                    },
-                    view.width,
-                    view.height,
-                );
-
-                fragment.ray = scene.persp_camera.get_ray(&uv);
-                let new_fragment = DirectLightingIntegrator::li(&fragment, &scene);
-
-                let Spectrum::ColorRGB(new_spectrum) = new_fragment.acc_spectrum;
-
-                // Copy the terminate states of this fragment to new framebuffer,
-                new_frame.terminated[frag_index] = new_fragment.terminate;
-                acc_spectrum = acc_spectrum * (framebuffer.current_sample - 1) as Float;
-                acc_spectrum = (acc_spectrum + new_spectrum) / (framebuffer.current_sample as Float);                    
-                acc_spectrum = acc_spectrum.clamp(0., 1.);
-                new_frame.spectrums[frag_index] = Spectrum::ColorRGB(acc_spectrum);
+        settings: &RenderSettings,
+        frag_index: usize,
+    ) -> Fragment {
+        let x: u32 = frag_index as u32 % view.width;
+        let y: u32 = view.height - (frag_index as u32 / view.width) - 1;
+
+        let mut sampler = Sampler::new(settings.seed);
+        sampler.start_pixel_sample(x, y, framebuffer.current_sample);
+
+        // Initialize fragment
+        let mut fragment = Fragment {
+            depth: framebuffer.depths[frag_index],
+            ray: framebuffer.rays[frag_index],
+            terminate: framebuffer.terminated[frag_index],
+            medium: scene.camera_medium.clone(),
+            color_space: settings.working_space,
+            ..Default::default()
+        };
+
+        let uv: Vec2 = sampler.sample_from_pixel(
+            Vec2 {
+                0: x as Float,
+                1: y as Float,
+            },
+            view.width,
+            view.height,
+        );
+
+        // Film outside of what the camera sees stays black
+        let pixel = Vec2::new(&[1. / view.width as Float, 1. / view.height as Float]);
+        let mut new_fragment = match scene.camera.generate_ray_differential(&uv, pixel, &mut sampler) {
+            Some(mut ray) => {
+                ray.scale_differentials((1. / (settings.sample_per_pixel as Float).sqrt()).max(0.125));
+                fragment.ray = ray;
+                if settings.spectral {
+                    fragment.wavelengths = Some(SampledWavelengths::sample_visible(sampler.random_0_1()));
+                }
+                DirectLightingIntegrator::li(&fragment, scene, &mut sampler)
             }
-        }
-        return new_frame;
-    }
-
-    pub fn render_parallel(
-        scene: &Scene,
-        view: &View,
-        framebuffer: &FrameBuffer
-    ) -> FrameBuffer {
-        let mut new_frame = FrameBuffer::new(view.width, view.height);
-        new_frame.current_sample = framebuffer.current_sample;
-
-        let mut total_spectrums = framebuffer.spectrums.clone();
-        total_spectrums.par_iter_mut().enumerate().for_each(|(i, acc_spectrum)| {
-            // For each pixel
-            let x: u32 = i as u32 % view.width;
-            let y: u32 = view.height - (i as u32 / view.width) - 1;
-
-            // Initialize fragment
-            let frag_index = (x + (view.height - y - 1) * view.width) as usize;
-            let mut fragment = Fragment {
-                depth: framebuffer.depths[frag_index],
-                acc_spectrum: *acc_spectrum,
-                ray: framebuffer.rays[frag_index],
-                terminate: framebuffer.terminated[frag_index]
-
-            };
-                            
-            let uv: Vec2 = Sampler::sample_from_pixel(
-                Vec2 {
-                    0: x as Float,
-                    1: y as Float,
-                },
-                view.width,
-                view.height,
-            );
-
-            fragment.ray = scene.persp_camera.get_ray(&uv);
-            let new_fragment = DirectLightingIntegrator::li(&fragment, &scene);
-
-            let Spectrum::ColorRGB(new_spectrum) = new_fragment.acc_spectrum;
-
-            *acc_spectrum = *acc_spectrum * (framebuffer.current_sample - 1) as Float;
-            *acc_spectrum = (*acc_spectrum + new_spectrum) / (framebuffer.current_sample as Float);
-        });
-
-        new_frame.spectrums = total_spectrums;
-        return new_frame;   
+            None => Fragment::default(),
+        };
+        // uv has v going up, the film has rows going down
+        new_fragment.p_film = Vec2 {
+            0: uv.0 * view.width as Float,
+            1: (1. - uv.1) * view.height as Float,
+        };
+        new_fragment
     }
 
     pub fn render(
         scene: &Scene,
         view: &View,
-        framebuffer: &FrameBuffer,
+        framebuffer: &mut FrameBuffer,
         render_setings: &RenderSettings
-    ) -> FrameBuffer {
-        if render_setings.single_thread {
-            DirectLightingIntegrator::render_single_thread(scene, view, framebuffer)
+    ) {
+        let num_fragments = (view.width * view.height) as usize;
+        // Converged pixels don't get new samples
+        let render_fragment = |frag_index| {
+            if framebuffer.terminated[frag_index] {
+                return None;
+            }
+            Some(DirectLightingIntegrator::render_fragment(scene, view, framebuffer, render_setings, frag_index))
+        };
+        let fragments: Vec<Option<Fragment>> = if render_setings.single_thread {
+            (0..num_fragments).map(render_fragment).collect()
         } else {
-            DirectLightingIntegrator::render_parallel(scene, view, framebuffer)
+            (0..num_fragments).into_par_iter().map(render_fragment).collect()
+        };
+
+        let samples: Vec<Option<FilmSample>> = fragments
+            .iter()
+            .map(|fragment| {
+                fragment.as_ref().map(|fragment| FilmSample {
+                    p_film: fragment.p_film,
+                    radiance: fragment.acc_spectrum,
+                })
+            })
+            .collect();
+        framebuffer.film.add_pass(&samples, !render_setings.single_thread);
+        if !framebuffer.film.aovs.is_empty() {
+            let aov_samples: Vec<AovSample> = fragments
+                .iter()
+                .map(|fragment| fragment.as_ref().map(|fragment| fragment.aov).unwrap_or_default())
+                .collect();
+            framebuffer.film.add_aov_pass(&samples, &aov_samples, !render_setings.single_thread);
         }
+
+        if render_setings.adaptive_sampling {
+            framebuffer.update_converged(render_setings);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use crate::cameras::{lens::ThinLens, perspective::PerspectiveCamera};
+    use crate::core::aov::AovType;
+    use crate::core::filter::FilterType;
+    use strum::IntoEnumIterator;
+    use crate::core::primitive::Primitive;
+    use crate::materials::{LambertMaterial, MetalMaterial};
+    use crate::shapes::sphere::Sphere;
+
+    fn test_scene(width: u32, height: u32) -> Scene {
+        let mut scene = Scene::default();
+        scene.camera = Arc::new(PerspectiveCamera::look_at(
+            width,
+            height,
+            Vec3::new(0., 0.5, -5.5),
+            Vec3::new(0., 0., -1.),
+            ThinLens::default(),
+        ));
+        scene.environment_light = |ray| Spectrum::ColorRGB(Vec3::from(0.5 * ray.direction.y + 1.0));
+        scene.add(Primitive::new(
+            Shape::Sphere(Sphere::new(Vec3::new(0., 0., -1.), 0.5)),
+            Some(Arc::new(LambertMaterial::new(Spectrum::ColorRGB(Vec3::from(0.5))))),
+        ));
+        scene.add(Primitive::new(
+            Shape::Sphere(Sphere::new(Vec3::new(1., 0., -1.), 0.5)),
+            Some(Arc::new(MetalMaterial::new(Spectrum::ColorRGB(Vec3::from(0.8))))),
+        ));
+        scene
+    }
+
+    fn render_samples(scene: &Scene, view: &View, settings: &RenderSettings, spp: u32) -> Vec<Spectrum> {
+        let mut framebuffer = FrameBuffer::new(view.width, view.height, settings.filter);
+        for _ in 0..spp {
+            framebuffer.current_sample += 1;
+            DirectLightingIntegrator::render(scene, view, &mut framebuffer, settings);
+        }
+        framebuffer.film.get_pixels()
+    }
+
+    #[test]
+    fn test_single_and_multi_thread_renders_match() {
+        let view = View::new(32, 18);
+        let scene = test_scene(view.width, view.height);
+        let mut settings = RenderSettings {
+            seed: 42,
+            filter: Filter::from(FilterType::Mitchell),
+            ..Default::default()
+        };
+
+        settings.single_thread = true;
+        let single = render_samples(&scene, &view, &settings, 3);
+        settings.single_thread = false;
+        let parallel = render_samples(&scene, &view, &settings, 3);
+        let parallel_again = render_samples(&scene, &view, &settings, 3);
+
+        assert_eq!(single, parallel);
+        assert_eq!(parallel, parallel_again);
+
+        settings.seed = 43;
+        let reseeded = render_samples(&scene, &view, &settings, 3);
+        assert_ne!(parallel, reseeded);
+    }
+
+    #[test]
+    fn test_adaptive_sampling_stops_converged_pixels() {
+        let view = View::new(32, 18);
+        let scene = test_scene(view.width, view.height);
+        let settings = RenderSettings {
+            adaptive_sampling: true,
+            noise_threshold: 0.05,
+            min_samples: 4,
+            sample_per_pixel: 32,
+            ..Default::default()
+        };
+        let mut framebuffer = FrameBuffer::new(view.width, view.height, settings.filter);
+        while framebuffer.current_sample < settings.sample_per_pixel && !framebuffer.all_converged() {
+            framebuffer.current_sample += 1;
+            DirectLightingIntegrator::render(&scene, &view, &mut framebuffer, &settings);
+        }
+
+        let counts: Vec<u32> = framebuffer.film.variance.iter().map(|variance| variance.count).collect();
+        // The sky converges right away, the diffuse sphere needs more samples
+        assert_eq!(counts[0], settings.min_samples);
+        assert!(counts.iter().any(|count| *count > settings.min_samples));
+        assert!(counts.iter().all(|count| *count <= settings.sample_per_pixel));
+        for (index, terminated) in framebuffer.terminated.iter().enumerate() {
+            if *terminated {
+                assert!(framebuffer.film.variance[index].relative_error() < settings.noise_threshold);
+            }
+        }
+    }
+
+    #[test]
+    fn test_light_aovs_add_up_to_beauty() {
+        let view = View::new(32, 18);
+        let scene = test_scene(view.width, view.height);
+        let settings = RenderSettings {
+            filter: Filter::from(FilterType::Gaussian),
+            ..Default::default()
+        };
+        let mut framebuffer = FrameBuffer::new(view.width, view.height, settings.filter);
+        let aovs: Vec<AovType> = AovType::iter().collect();
+        framebuffer.film.enable_aovs(&aovs);
+        for _ in 0..2 {
+            framebuffer.current_sample += 1;
+            DirectLightingIntegrator::render(&scene, &view, &mut framebuffer, &settings);
+        }
+
+        let film = &framebuffer.film;
+        for (index, beauty) in film.get_pixels().iter().enumerate() {
+            let mut sum = Vec3::from(0.);
+            for buffer in film.aovs.iter().filter(|buffer| buffer.aov.is_light()) {
+                sum += buffer.pixels[index].value().to_rgb();
+            }
+            assert!((sum - beauty.to_rgb()).length() < 1e-4);
+        }
+
+        let ids = &film.aovs.iter().find(|buffer| buffer.aov == AovType::PrimitiveId).unwrap().pixels;
+        assert!(ids.iter().all(|pixel| [0., 1., 2.].contains(&pixel.value().to_rgb().x)));
+        assert!(ids.iter().any(|pixel| pixel.value().to_rgb().x == 2.));
     }
 }
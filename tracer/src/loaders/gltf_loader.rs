@@ -1,10 +1,27 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
 use gltf;
 use log::{debug, info};
+use math::{Float, Mat4, Quaternion, Vec3};
+
+use crate::cameras::{
+    lens::ThinLens, orthographic::OrthographicCamera, perspective::PerspectiveCamera, Camera, CameraFrame, Shutter,
+};
+use crate::core::animated_transform::AnimatedTransform;
+use crate::core::animation::{Animation, NodePose};
+use crate::core::{primitive::Primitive, scene::Scene, shape::Shape, spectrum::Spectrum, transform::Transform};
+use crate::integrators::direct_lighting::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::materials::LambertMaterial;
+use crate::shapes::mesh::Mesh;
+use crate::shapes::skinning::{MeshDeformation, SkinningMethod};
 
 pub struct GData {
     pub doc: gltf::Document,
     pub buffers: Vec<gltf::buffer::Data>,
     pub images: Vec<gltf::image::Data>,
+    // Folder of the glTF file, where its external files are
+    pub directory: PathBuf,
 }
 
 pub fn load_gltf(path: &str) -> GData {
@@ -66,5 +83,276 @@ pub fn load_gltf(path: &str) -> GData {
         doc,
         buffers,
         images,
+        directory: Path::new(path).parent().unwrap_or(Path::new("")).to_path_buf(),
     };
 }
+
+// glTF cameras, kept until they are placed by a pose
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum GltfCamera {
+    Perspective { yfov: Float },
+    Orthographic { xmag: Float, ymag: Float },
+}
+
+#[derive(Clone, Debug, Default)]
+struct GltfNode {
+    children: Vec<usize>,
+    // Shapes in the node's space, with the joints and morph targets moving
+    // them away from it
+    primitives: Vec<(Primitive, MeshDeformation)>,
+    camera: Option<GltfCamera>,
+    skin: Option<usize>,
+}
+
+// Joints of a skinned mesh. The inverse bind matrices take the mesh from
+// where it was bound to the space of each joint.
+#[derive(Clone, Debug, Default)]
+struct Skin {
+    joints: Vec<usize>,
+    inverse_bind_matrices: Vec<Mat4>,
+}
+
+impl Skin {
+    fn from_gltf(skin: &gltf::Skin, data: &GData) -> Self {
+        let joints: Vec<usize> = skin.joints().map(|joint| joint.index()).collect();
+        let reader = skin.reader(|buffer| Some(&data.buffers[buffer.index()]));
+        let inverse_bind_matrices = match reader.read_inverse_bind_matrices() {
+            // Listed column by column
+            Some(matrices) => matrices.map(|m| Mat4::from_array(m).transpose()).collect(),
+            None => vec![Mat4::identity(); joints.len()],
+        };
+        Self {
+            joints,
+            inverse_bind_matrices,
+        }
+    }
+
+    // Bind pose to world of every joint, given the world matrices of the nodes
+    fn joint_matrices(&self, world: &[Mat4]) -> Vec<Mat4> {
+        self.joints
+            .iter()
+            .zip(self.inverse_bind_matrices.iter())
+            .map(|(&joint, inverse_bind_matrix)| world[joint] * *inverse_bind_matrix)
+            .collect()
+    }
+}
+
+// A glTF scene kept as its node hierarchy so it can be posed at any time of
+// its animations. The meshes are read once and shared by every pose.
+#[derive(Clone, Debug)]
+pub struct GltfScene {
+    nodes: Vec<GltfNode>,
+    roots: Vec<usize>,
+    // Local transforms and morph weights before any animation
+    rest_pose: Vec<NodePose>,
+    skins: Vec<Skin>,
+    pub animations: Vec<Animation>,
+    pub skinning: SkinningMethod,
+}
+
+impl GltfScene {
+    pub fn load<P>(path: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        let directory = path.as_ref().parent().unwrap_or(Path::new("")).to_path_buf();
+        let (doc, buffers, images) = gltf::import(path).unwrap();
+        let data = GData {
+            doc,
+            buffers,
+            images,
+            directory,
+        };
+
+        let mut nodes = Vec::new();
+        let mut rest_pose = Vec::new();
+        for node in data.doc.nodes() {
+            let (translation, rotation, scale) = node.transform().decomposed();
+            let weights = node.weights().or_else(|| node.mesh().and_then(|mesh| mesh.weights()));
+            rest_pose.push(NodePose {
+                translation: Vec3::from(&translation),
+                rotation: Quaternion::from(&rotation),
+                scale: Vec3::from(&scale),
+                weights: weights.unwrap_or_default().to_vec(),
+            });
+
+            let mut primitives = Vec::new();
+            if let Some(mesh) = node.mesh() {
+                info!("-- Node {:?} has mesh {:?}", node.name(), mesh.name());
+                for primitive in mesh.primitives() {
+                    let color = Spectrum::ColorRGB(Vec3::from(1.0));
+                    primitives.push((
+                        Primitive::new(
+                            Shape::Mesh(Arc::new(Mesh::from_gltf(&primitive, &data))),
+                            Some(Arc::new(LambertMaterial::new(color))),
+                        ),
+                        MeshDeformation::from_gltf(&primitive, &data),
+                    ));
+                }
+            }
+            let camera = node.camera().map(|camera| match camera.projection() {
+                gltf::camera::Projection::Perspective(perspective) => GltfCamera::Perspective {
+                    yfov: perspective.yfov(),
+                },
+                gltf::camera::Projection::Orthographic(orthographic) => GltfCamera::Orthographic {
+                    xmag: orthographic.xmag(),
+                    ymag: orthographic.ymag(),
+                },
+            });
+            nodes.push(GltfNode {
+                children: node.children().map(|child| child.index()).collect(),
+                primitives,
+                camera,
+                skin: node.skin().map(|skin| skin.index()),
+            });
+        }
+
+        let roots = match data.doc.default_scene().or_else(|| data.doc.scenes().next()) {
+            Some(scene) => scene.nodes().map(|node| node.index()).collect(),
+            None => Vec::new(),
+        };
+        let skins = data.doc.skins().map(|skin| Skin::from_gltf(&skin, &data)).collect();
+        let animations = data
+            .doc
+            .animations()
+            .map(|animation| Animation::from_gltf(&animation, &data.buffers))
+            .collect();
+
+        Self {
+            nodes,
+            roots,
+            rest_pose,
+            skins,
+            animations,
+            skinning: SkinningMethod::default(),
+        }
+    }
+
+    // Time of the last keyframe of all the animations, in seconds
+    pub fn duration(&self) -> Float {
+        self.animations
+            .iter()
+            .fold(0., |duration: Float, animation| duration.max(animation.duration()))
+    }
+
+    // Local pose of every node with all the animations played to `time`
+    fn local_pose(&self, time: Float) -> Vec<NodePose> {
+        let mut local = self.rest_pose.clone();
+        for animation in self.animations.iter() {
+            animation.apply(time, &mut local);
+        }
+        local
+    }
+
+    // World matrix of every node at `time`
+    pub fn pose(&self, time: Float) -> Vec<Mat4> {
+        self.world_matrices(&self.local_pose(time))
+    }
+
+    fn world_matrices(&self, local: &[NodePose]) -> Vec<Mat4> {
+        // TODO: We shouldn't have to apply manual transfomration here.
+        let shift = Transform::translate(Vec3::new(0.0, -0.5, 0.)).matrix;
+        let mut world = vec![Mat4::identity(); self.nodes.len()];
+        let mut stack: Vec<(usize, Mat4)> = self.roots.iter().map(|&root| (root, shift)).collect();
+        while let Some((node, parent)) = stack.pop() {
+            world[node] = parent * local[node].matrix();
+            stack.extend(self.nodes[node].children.iter().map(|&child| (child, world[node])));
+        }
+        world
+    }
+
+    // Scene with the nodes posed at `time`. Its primitives are instances of
+    // the loaded meshes, or new meshes for the skinned and morphed ones.
+    pub fn scene_at(&self, time: Float) -> Scene {
+        let mut scene = Scene::default();
+        let local = self.local_pose(time);
+        let world = self.world_matrices(&local);
+        for (i, node) in self.nodes.iter().enumerate() {
+            let joint_matrices = match node.skin {
+                Some(skin) => self.skins[skin].joint_matrices(&world),
+                None => Vec::new(),
+            };
+            for (primitive, deformation) in node.primitives.iter() {
+                let material = primitive.material.clone();
+                let Shape::Mesh(mesh) = &primitive.shape else {
+                    continue;
+                };
+                if deformation.is_empty() {
+                    let object_to_world = AnimatedTransform::new(&[(time, world[i])]);
+                    scene.add(Primitive::instance(primitive.shape.clone(), material, object_to_world));
+                    continue;
+                }
+
+                let deformed = deformation.deform(mesh, &local[i].weights, &joint_matrices, self.skinning);
+                let shape = Shape::Mesh(Arc::new(deformed));
+                // Skinned meshes follow their joints, the node they are on
+                // doesn't move them
+                if joint_matrices.is_empty() {
+                    scene.add(Primitive::instance(shape, material, AnimatedTransform::new(&[(time, world[i])])));
+                } else {
+                    scene.add(Primitive::new(shape, material));
+                }
+            }
+            if let Some(camera) = node.camera {
+                scene.camera = gltf_camera(camera, &world[i]);
+            }
+        }
+        scene
+    }
+}
+
+// glTF cameras look down -z with +y up
+fn gltf_camera(camera: GltfCamera, world: &Mat4) -> Arc<dyn Camera> {
+    let frame = CameraFrame {
+        eye: *world * Vec3::zero(),
+        forward: world.transform_vector(Vec3::new(0., 0., -1.)).normalize(),
+        right: world.transform_vector(Vec3::new(1., 0., 0.)).normalize(),
+        up: world.transform_vector(Vec3::new(0., 1., 0.)).normalize(),
+        shutter: Shutter::default(),
+    };
+    match camera {
+        // yfov is across the height, the shorter side of our film
+        GltfCamera::Perspective { yfov } => {
+            let lens = ThinLens {
+                f_stop: Float::INFINITY,
+                ..ThinLens::default()
+            };
+            Arc::new(PerspectiveCamera::new(
+                SCREEN_WIDTH,
+                SCREEN_HEIGHT,
+                frame,
+                lens.with_field_of_view(yfov.to_degrees(), SCREEN_WIDTH, SCREEN_HEIGHT),
+            ))
+        }
+        GltfCamera::Orthographic { xmag, ymag } => Arc::new(OrthographicCamera::new(frame, [-xmag, xmag, -ymag, ymag])),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_truck_wheels_spin() {
+        let truck = GltfScene::load(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../assets/glTF/CesiumMilkTruck/glTF/CesiumMilkTruck.gltf"
+        ));
+        assert_eq!(truck.animations.len(), 1);
+        assert!((truck.duration() - 1.25).abs() < 1e-5);
+
+        // The wheels turn, the body stays where it is
+        let (start, later) = (truck.pose(0.), truck.pose(0.3));
+        assert_ne!(start[0], later[0]);
+        assert_ne!(start[2], later[2]);
+        assert_eq!(start[4], later[4]);
+
+        // Frames share the meshes
+        let (a, b) = (truck.scene_at(0.), truck.scene_at(0.3));
+        assert_eq!(a.primitives.len(), 5);
+        match (&a.primitives[0].shape, &b.primitives[0].shape) {
+            (Shape::Mesh(a), Shape::Mesh(b)) => assert!(Arc::ptr_eq(a, b)),
+            _ => panic!("expected meshes"),
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use std::fs;
+use std::path::Path;
+
+use math::Float;
+
+use crate::media::grid::VoxelGrid;
+
+// Raw voxel grids, little endian:
+//   "VGRD", then nx, ny, nz and the number of channels as u32,
+//   then the f32 values of each channel in turn, x varying fastest.
+// The first channel is the density, the optional second one the temperature.
+const MAGIC: &[u8; 4] = b"VGRD";
+const HEADER_SIZE: usize = 20;
+
+pub fn load_grid<P>(path: P) -> Result<(VoxelGrid, Option<VoxelGrid>), String>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let bytes = fs::read(path).map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+    parse_grid(&bytes).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+pub fn parse_grid(bytes: &[u8]) -> Result<(VoxelGrid, Option<VoxelGrid>), String> {
+    if bytes.len() < HEADER_SIZE || &bytes[0..4] != MAGIC {
+        return Err("not a voxel grid".to_string());
+    }
+    let header: Vec<usize> = bytes[4..HEADER_SIZE]
+        .chunks_exact(4)
+        .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]) as usize)
+        .collect();
+    let resolution = [header[0], header[1], header[2]];
+    let channels = header[3];
+    if channels != 1 && channels != 2 {
+        return Err(format!("{} channels, expected 1 or 2", channels));
+    }
+    // The header may be corrupt, sizes it gives must not overflow
+    let too_large = || "grid too large".to_string();
+    let count = resolution[0]
+        .checked_mul(resolution[1])
+        .and_then(|count| count.checked_mul(resolution[2]))
+        .ok_or_else(too_large)?;
+    let size = count
+        .checked_mul(4 * channels)
+        .and_then(|size| size.checked_add(HEADER_SIZE))
+        .ok_or_else(too_large)?;
+    if bytes.len() != size {
+        return Err(format!("expected {} values, the file is {} bytes", count * channels, bytes.len()));
+    }
+
+    let mut values = bytes[HEADER_SIZE..]
+        .chunks_exact(4)
+        .map(|word| f32::from_le_bytes([word[0], word[1], word[2], word[3]]) as Float);
+    let density = VoxelGrid::new(resolution, values.by_ref().take(count).collect())?;
+    let temperature = match channels {
+        2 => Some(VoxelGrid::new(resolution, values.collect())?),
+        _ => None,
+    };
+    Ok((density, temperature))
+}
+
+// Temperatures must have the resolution of the density
+pub fn write_grid<P>(path: P, density: &VoxelGrid, temperature: Option<&VoxelGrid>) -> Result<(), String>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    fs::write(path, grid_bytes(density, temperature)?).map_err(|e| format!("failed to write {}: {}", path.display(), e))
+}
+
+pub fn grid_bytes(density: &VoxelGrid, temperature: Option<&VoxelGrid>) -> Result<Vec<u8>, String> {
+    let channels: Vec<&VoxelGrid> = std::iter::once(density).chain(temperature).collect();
+    if channels.iter().any(|grid| grid.resolution != density.resolution) {
+        return Err("the temperature and density grids have different resolutions".to_string());
+    }
+    let mut bytes = MAGIC.to_vec();
+    for word in density.resolution.iter().chain(&[channels.len()]) {
+        bytes.extend_from_slice(&(*word as u32).to_le_bytes());
+    }
+    for grid in channels {
+        for value in &grid.values {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_grid_round_trip() {
+        let density = VoxelGrid::new([2, 1, 3], vec![0., 0.5, 1., 1.5, 2., 2.5]).unwrap();
+        let temperature = VoxelGrid::new([2, 1, 3], vec![300., 400., 500., 600., 700., 800.]).unwrap();
+
+        let bytes = grid_bytes(&density, Some(&temperature)).unwrap();
+        assert_eq!(parse_grid(&bytes).unwrap(), (density.clone(), Some(temperature)));
+        let bytes = grid_bytes(&density, None).unwrap();
+        assert_eq!(parse_grid(&bytes).unwrap(), (density, None));
+
+        assert!(parse_grid(&bytes[..bytes.len() - 1]).is_err());
+        assert!(parse_grid(b"VDB!").is_err());
+
+        // A corrupt header whose sizes overflow
+        let mut corrupt = bytes.clone();
+        corrupt[4..16].fill(0xff);
+        assert_eq!(parse_grid(&corrupt), Err("grid too large".to_string()));
+    }
+}
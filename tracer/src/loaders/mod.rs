@@ -1 +1,3 @@
 pub mod gltf_loader;
+pub mod grid_loader;
+pub mod pbrt_loader;
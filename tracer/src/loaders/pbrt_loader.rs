@@ -0,0 +1,1173 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use log::{info, warn};
+use math::{Float, Mat4, Vec2, Vec3};
+use mesh_loader::{parse_obj, parse_ply, PolygonMesh};
+use pbrt4::{
+    param::{ParamList, ParamType},
+    Element, Parser,
+};
+
+use crate::cameras::{
+    equirectangular::EquirectangularCamera,
+    fisheye::FisheyeCamera,
+    lens::{ApertureShape, ThinLens},
+    orthographic::OrthographicCamera,
+    perspective::PerspectiveCamera,
+    Camera, CameraFrame, Shutter,
+};
+use crate::core::{
+    animated_transform::AnimatedTransform,
+    bounds::Bounds3f,
+    primitive::Primitive,
+    shape::Shape,
+    spectrum::Spectrum,
+    texture::{FloatTexture, SpectrumTexture, Texture, TextureMapping, TextureRef, TextureValue},
+    transform::Transform,
+};
+use crate::materials::bump::BumpMaterial;
+use crate::materials::conductor::{ConductorMaterial, Metal};
+use crate::materials::ior::{Glass, IorModel};
+use crate::materials::matte::MatteMaterial;
+use crate::materials::{DieletricMaterial, LambertMaterial, Material, MetalMaterial};
+use crate::loaders::grid_loader::load_grid;
+use crate::media::{
+    grid::{GridMedium, VoxelGrid},
+    homogeneous::HomogeneousMedium,
+    Medium, MediumInterface,
+};
+use crate::shapes::displaced::{DisplacedMesh, Displacement, EdgeMetric};
+use crate::shapes::subdivision::{SubdivisionLevels, SubdivisionScheme, SubdivisionSurface};
+use crate::shapes::{
+    analytic::Placement, cone::Cone, cylinder::Cylinder, disk::Disk, mesh::Mesh, paraboloid::Paraboloid, sphere::Sphere,
+};
+use crate::textures::{
+    checkerboard::CheckerboardTexture,
+    constant::ConstantTexture,
+    marble::MarbleTexture,
+    noise::{NoiseKind, NoiseTexture},
+    nodes::{
+        CombineTexture, CurveTexture, InvertTexture, MixTexture, Operation, RampTexture, RemapTexture, ScaleTexture,
+        SwizzleTexture, UvTransformTexture,
+    },
+    ramp::{ColorRamp, GradientKind, GradientTexture},
+    voronoi::{VoronoiOutput, VoronoiTexture},
+    wood::WoodTexture,
+};
+
+// What the tracer supports of a pbrt-v4 scene file. Directives it can't
+// represent yet are logged and skipped.
+pub struct PbrtScene {
+    pub camera: Option<Arc<dyn Camera>>,
+    // Film position, v going up, the camera focuses on once the scene is built
+    pub focus_point: Option<Vec2>,
+    pub output_filename: Option<String>,
+    pub primitives: Vec<Primitive>,
+    // Exterior medium when the camera was declared
+    pub camera_medium: Option<Arc<dyn Medium>>,
+}
+
+#[derive(Clone)]
+struct GraphicsState {
+    // Current transformation matrices, object to world at the start and end
+    // of the transform times
+    transforms: [Mat4; 2],
+    // Which of them the transform directives change
+    active_transforms: [bool; 2],
+    // None for the "interface" material of medium boundaries
+    material: Option<Arc<dyn Material>>,
+    // Named media inside and outside of the next shapes, "" for none
+    medium_interface: (String, String),
+}
+
+struct PbrtLoader {
+    width: u32,
+    height: u32,
+    state: GraphicsState,
+    stack: Vec<GraphicsState>,
+    transform_times: [Float; 2],
+    named_coordinate_systems: HashMap<String, [Mat4; 2]>,
+    named_materials: HashMap<String, Option<Arc<dyn Material>>>,
+    named_media: HashMap<String, Arc<dyn Medium>>,
+    named_spectrum_textures: HashMap<String, SpectrumTexture>,
+    named_float_textures: HashMap<String, FloatTexture>,
+    // Shapes of the object being defined, then of every named object
+    current_object: Option<(String, Vec<Primitive>)>,
+    objects: HashMap<String, Vec<Primitive>>,
+    scene: PbrtScene,
+}
+
+// Read a pbrt-v4 scene, `width` and `height` are the film resolution the
+// cameras are set up for
+pub fn load_pbrt<P>(path: P, width: u32, height: u32) -> Result<PbrtScene, String>
+where
+    P: AsRef<Path>,
+{
+    let mut loader = PbrtLoader {
+        width,
+        height,
+        state: GraphicsState {
+            transforms: [Mat4::identity(); 2],
+            active_transforms: [true; 2],
+            material: Some(default_material()),
+            medium_interface: (String::new(), String::new()),
+        },
+        stack: Vec::new(),
+        transform_times: [0., 1.],
+        named_coordinate_systems: HashMap::new(),
+        named_materials: HashMap::new(),
+        named_media: HashMap::new(),
+        named_spectrum_textures: HashMap::new(),
+        named_float_textures: HashMap::new(),
+        current_object: None,
+        objects: HashMap::new(),
+        scene: PbrtScene {
+            camera: None,
+            focus_point: None,
+            output_filename: None,
+            primitives: Vec::new(),
+            camera_medium: None,
+        },
+    };
+    loader.load_file(path.as_ref())?;
+    Ok(loader.scene)
+}
+
+fn default_material() -> Arc<dyn Material> {
+    Arc::new(LambertMaterial::new(Spectrum::ColorRGB(Vec3::from(0.5))))
+}
+
+// String parameters keep their quotes
+fn string_param<'a>(params: &'a ParamList, name: &str) -> Option<&'a str> {
+    params.string(name).map(|value| value.trim().trim_matches('"'))
+}
+
+fn float_param(params: &ParamList, name: &str, default: Float) -> Result<Float, String> {
+    params
+        .float(name, default)
+        .map_err(|e| format!("invalid value for '{}': {}", name, e))
+}
+
+fn integer_param(params: &ParamList, name: &str, default: i32) -> Result<i32, String> {
+    params
+        .integer(name, default)
+        .map_err(|e| format!("invalid value for '{}': {}", name, e))
+}
+
+// Quoted or not
+fn bool_param(params: &ParamList, name: &str, default: bool) -> Result<bool, String> {
+    match string_param(params, name) {
+        Some("true") => Ok(true),
+        Some("false") => Ok(false),
+        Some(value) => Err(format!("invalid value for '{}': {}", name, value)),
+        None => Ok(default),
+    }
+}
+
+fn pair_param(params: &ParamList, name: &str, default: (Float, Float)) -> Result<(Float, Float), String> {
+    match params.floats(name).map_err(|e| format!("invalid value for '{}': {}", name, e))?.as_deref() {
+        Some([a, b]) => Ok((*a, *b)),
+        Some(_) => Err(format!("'{}' needs two values", name)),
+        None => Ok(default),
+    }
+}
+
+// One of the names of a strum enum
+fn enum_param<T: FromStr>(params: &ParamList, name: &str, default: T) -> Result<T, String> {
+    match string_param(params, name) {
+        Some(value) => value.parse().map_err(|_| format!("invalid value for '{}': {}", name, value)),
+        None => Ok(default),
+    }
+}
+
+// Ramp of the "colors" of a scalar texture, at their "positions" or evenly
+// spread
+fn ramp_param(params: &ParamList, default: ColorRamp) -> Result<ColorRamp, String> {
+    let invalid = |e| format!("invalid value for 'colors': {}", e);
+    let Some(values) = params.floats("colors").map_err(invalid)? else {
+        return Ok(default);
+    };
+    if values.is_empty() || values.len() % 3 != 0 {
+        return Err("'colors' needs RGB triples".to_string());
+    }
+    let colors: Vec<Vec3> = values.chunks(3).map(|rgb| Vec3::new(rgb[0], rgb[1], rgb[2])).collect();
+    match params.floats("positions").map_err(|e| format!("invalid value for 'positions': {}", e))? {
+        Some(positions) if positions.len() == colors.len() => Ok(ColorRamp::new(positions.into_iter().zip(colors).collect())),
+        Some(_) => Err("'positions' needs one value per color".to_string()),
+        None => Ok(ColorRamp::evenly_spaced(&colors)),
+    }
+}
+
+fn is_texture(params: &ParamList, name: &str) -> bool {
+    params.get(name).is_some_and(|param| param.ty == ParamType::Texture)
+}
+
+// Parameters of texture nodes, which take textures of their own type
+trait TextureParam: TextureValue {
+    fn texture(loader: &PbrtLoader, params: &ParamList, name: &str, default: Float) -> Result<TextureRef<Self>, String>;
+}
+
+impl TextureParam for Float {
+    fn texture(loader: &PbrtLoader, params: &ParamList, name: &str, default: Float) -> Result<TextureRef<Self>, String> {
+        loader.float_texture(params, name, default)
+    }
+}
+
+impl TextureParam for Spectrum {
+    fn texture(loader: &PbrtLoader, params: &ParamList, name: &str, default: Float) -> Result<TextureRef<Self>, String> {
+        loader.spectrum_texture(params, name, Vec3::from(default))
+    }
+}
+
+// Spectra given other ways than RGB, like textures or named spectra, fall
+// back to the default
+fn rgb_param(params: &ParamList, name: &str, default: Vec3) -> Result<Vec3, String> {
+    match params.get(name) {
+        Some(param) if param.ty == ParamType::Rgb => {
+            let rgb = param.rgb().map_err(|e| format!("invalid value for '{}': {:?}", name, e))?;
+            Ok(Vec3::from(&rgb))
+        }
+        Some(param) => {
+            warn!("Only RGB values are supported for '{}', got {:?}", name, param.ty);
+            Ok(default)
+        }
+        None => Ok(default),
+    }
+}
+
+fn rotate(angle_degrees: Float, axis: Vec3) -> Mat4 {
+    let a = axis.normalize();
+    let (sin, cos) = angle_degrees.to_radians().sin_cos();
+    Mat4::from_array([
+        [
+            a.x * a.x + (1. - a.x * a.x) * cos,
+            a.x * a.y * (1. - cos) - a.z * sin,
+            a.x * a.z * (1. - cos) + a.y * sin,
+            0.,
+        ],
+        [
+            a.x * a.y * (1. - cos) + a.z * sin,
+            a.y * a.y + (1. - a.y * a.y) * cos,
+            a.y * a.z * (1. - cos) - a.x * sin,
+            0.,
+        ],
+        [
+            a.x * a.z * (1. - cos) - a.y * sin,
+            a.y * a.z * (1. - cos) + a.x * sin,
+            a.z * a.z + (1. - a.z * a.z) * cos,
+            0.,
+        ],
+        [0., 0., 0., 1.],
+    ])
+}
+
+// Camera from world matrix of pbrt's LookAt
+fn look_at(eye: Vec3, look_at: Vec3, up: Vec3) -> Result<Mat4, String> {
+    let forward = (look_at - eye).normalize();
+    let right = Vec3::cross(up.normalize(), forward).normalize();
+    let up = Vec3::cross(forward, right);
+    let world_from_camera = Mat4::from_array([
+        [right.x, up.x, forward.x, eye.x],
+        [right.y, up.y, forward.y, eye.y],
+        [right.z, up.z, forward.z, eye.z],
+        [0., 0., 0., 1.],
+    ]);
+    world_from_camera
+        .inverse()
+        .ok_or("LookAt has parallel up and view directions".to_string())
+}
+
+// pbrt4 reads the argument of ActiveTransform as a quoted string, pbrt
+// writes it bare
+fn quote_active_transforms(source: &str) -> String {
+    let mut quoted = String::with_capacity(source.len());
+    let mut rest = source;
+    while let Some(i) = rest.find("ActiveTransform") {
+        let (before, after) = rest.split_at(i + "ActiveTransform".len());
+        quoted.push_str(before);
+        let argument = after.trim_start();
+        quoted.push_str(&after[..after.len() - argument.len()]);
+        let end = argument.find(char::is_whitespace).unwrap_or(argument.len());
+        let word = &argument[..end];
+        if ["StartTime", "EndTime", "All"].contains(&word) {
+            quoted.push_str(&format!("\"{}\"", word));
+        } else {
+            quoted.push_str(word);
+        }
+        rest = &argument[end..];
+    }
+    quoted.push_str(rest);
+    quoted
+}
+
+// pbrt lists matrices column by column
+fn from_columns(m: &[f32; 16]) -> Mat4 {
+    let mut matrix = Mat4::zero();
+    for (i, value) in m.iter().enumerate() {
+        matrix.m[i % 4][i / 4] = *value;
+    }
+    matrix
+}
+
+impl PbrtLoader {
+    fn load_file(&mut self, path: &Path) -> Result<(), String> {
+        info!("Loading pbrt scene: {}", path.display());
+        let source = std::fs::read_to_string(path)
+            .map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+        let directory = path.parent().unwrap_or(Path::new(""));
+
+        let source = quote_active_transforms(&source);
+        let mut parser = Parser::new(&source);
+        loop {
+            let element = match parser.parse_next() {
+                Ok(element) => element,
+                Err(pbrt4::Error::EndOfFile) => return Ok(()),
+                Err(e) => return Err(format!("{}: {:?}", path.display(), e)),
+            };
+            self.element(element, directory)?;
+        }
+    }
+
+    // Apply `f` to the transforms ActiveTransform selected
+    fn update_transforms<F>(&mut self, f: F)
+    where
+        F: Fn(&Mat4) -> Mat4,
+    {
+        for (transform, active) in self.state.transforms.iter_mut().zip(self.state.active_transforms) {
+            if active {
+                *transform = f(transform);
+            }
+        }
+    }
+
+    fn element(&mut self, element: Element, directory: &Path) -> Result<(), String> {
+        match element {
+            Element::Include(file) => self.load_file(&directory.join(file))?,
+            Element::Identity => self.update_transforms(|_| Mat4::identity()),
+            Element::Translate { v } => {
+                let translate = Transform::translate(Vec3::from(&v)).matrix;
+                self.update_transforms(|transform| *transform * translate)
+            }
+            Element::Scale { v } => {
+                let scale = Transform::scale(Vec3::from(&v)).matrix;
+                self.update_transforms(|transform| *transform * scale)
+            }
+            Element::Rotate { angle, v } => {
+                let rotation = rotate(angle, Vec3::from(&v));
+                self.update_transforms(|transform| *transform * rotation)
+            }
+            Element::LookAt { eye, look_at: target, up } => {
+                let camera_from_world = look_at(Vec3::from(&eye), Vec3::from(&target), Vec3::from(&up))?;
+                self.update_transforms(|transform| *transform * camera_from_world)
+            }
+            Element::Transform { m } => self.update_transforms(|_| from_columns(&m)),
+            Element::ConcatTransform { m } => self.update_transforms(|transform| *transform * from_columns(&m)),
+            Element::TransformTimes { start, end } => self.transform_times = [start, end],
+            Element::ActiveTransform { ty } => {
+                self.state.active_transforms = match ty {
+                    "StartTime" => [true, false],
+                    "EndTime" => [false, true],
+                    "All" => [true, true],
+                    _ => return Err(format!("unknown ActiveTransform '{}'", ty)),
+                }
+            }
+            Element::CoordinateSystem { name } => {
+                self.named_coordinate_systems.insert(name.to_string(), self.state.transforms);
+            }
+            Element::CoordSysTransform { name } => match self.named_coordinate_systems.get(name) {
+                Some(named) => self.state.transforms = *named,
+                None => warn!("Unknown coordinate system '{}'", name),
+            },
+            Element::AttributeBegin => self.stack.push(self.state.clone()),
+            Element::AttributeEnd => {
+                self.state = self.stack.pop().ok_or("unmatched AttributeEnd".to_string())?;
+            }
+            // Camera space has its own coordinate system, and the world
+            // starts again from the identity
+            Element::Camera { ty, params } => {
+                let [start, end] = self.state.transforms;
+                if start != end {
+                    warn!("Moving cameras are not supported, using the start transform");
+                }
+                let world_from_camera = start
+                    .inverse()
+                    .ok_or("the camera transform is not invertible".to_string())?;
+                self.named_coordinate_systems.insert("camera".to_string(), [world_from_camera; 2]);
+                self.scene.camera = Some(self.camera(ty, &params, &world_from_camera)?);
+                self.scene.camera_medium = self.medium(&self.state.medium_interface.1);
+                // Film position to focus on, from the top left corner
+                if let Some([u, v]) = params.floats("focuspoint").map_err(|e| e.to_string())?.as_deref() {
+                    self.scene.focus_point = Some(Vec2::new(&[*u, 1. - *v]));
+                }
+            }
+            Element::WorldBegin => {
+                self.state.transforms = [Mat4::identity(); 2];
+                self.state.active_transforms = [true; 2];
+                self.named_coordinate_systems.insert("world".to_string(), [Mat4::identity(); 2]);
+            }
+            Element::Film { params, .. } => {
+                if let Some(filename) = string_param(&params, "filename") {
+                    if !filename.is_empty() {
+                        self.scene.output_filename = Some(filename.to_string());
+                    }
+                }
+            }
+            Element::Texture { name, ty, class, params } => self.make_texture(name, ty, class, &params)?,
+            Element::Material { ty, params } => self.state.material = self.material(ty, &params)?,
+            Element::MakeNamedMaterial { name, params } => {
+                let ty = string_param(&params, "type").unwrap_or("diffuse");
+                let material = self.material(ty, &params)?;
+                self.named_materials.insert(name.to_string(), material);
+            }
+            Element::NamedMaterial { name } => match self.named_materials.get(name) {
+                Some(material) => self.state.material = material.clone(),
+                None => warn!("Unknown material '{}'", name),
+            },
+            Element::MakeNamedMedium { name, params } => {
+                let medium = self.make_medium(&params, directory)?;
+                if let Some(medium) = medium {
+                    self.named_media.insert(name.to_string(), medium);
+                }
+            }
+            Element::MediumInterface { interior, exterior } => {
+                self.state.medium_interface = (interior.to_string(), exterior.to_string());
+            }
+            Element::Shape { name, params } => self.shape(name, &params, directory)?,
+            // Shapes of an object are kept aside until it is instanced
+            Element::ObjectBegin { name } => {
+                self.stack.push(self.state.clone());
+                self.current_object = Some((name.to_string(), Vec::new()));
+            }
+            Element::ObjectEnd => {
+                let (name, primitives) = self.current_object.take().ok_or("unmatched ObjectEnd".to_string())?;
+                self.objects.insert(name, primitives);
+                self.state = self.stack.pop().ok_or("unmatched ObjectEnd".to_string())?;
+            }
+            Element::ObjectInstance { name } => {
+                let Some(primitives) = self.objects.get(name) else {
+                    warn!("Unknown object '{}'", name);
+                    return Ok(());
+                };
+                let object_to_world = self.animated_transform();
+                for primitive in primitives {
+                    let mut instance = Primitive::instance(
+                        primitive.shape.clone(),
+                        primitive.material.clone(),
+                        object_to_world.clone(),
+                    );
+                    instance.medium_interface = primitive.medium_interface.clone();
+                    self.scene.primitives.push(instance);
+                }
+            }
+            other => info!("Skipping unsupported pbrt directive {:?}", other),
+        }
+        Ok(())
+    }
+
+    fn camera(&self, ty: &str, params: &ParamList, world_from_camera: &Mat4) -> Result<Arc<dyn Camera>, String> {
+        let frame = CameraFrame {
+            shutter: Shutter {
+                open: float_param(params, "shutteropen", 0.)?,
+                close: float_param(params, "shutterclose", 1.)?,
+            },
+            ..CameraFrame::from_matrix(world_from_camera)
+        };
+        let camera: Arc<dyn Camera> = match ty {
+            // pbrt's field of view and lens radius, or the sensor, focal
+            // length and f-stop of a real camera
+            "perspective" => {
+                let sensor = params.floats("sensor").map_err(|e| e.to_string())?;
+                let mut lens = match sensor.as_deref() {
+                    Some([width, height]) => ThinLens {
+                        sensor_width: *width,
+                        sensor_height: *height,
+                        ..ThinLens::default()
+                    },
+                    _ => ThinLens::default(),
+                };
+                lens = match params.get("focallength") {
+                    Some(_) => ThinLens {
+                        focal_length: float_param(params, "focallength", lens.focal_length)?,
+                        ..lens
+                    },
+                    None => lens.with_field_of_view(float_param(params, "fov", 90.)?, self.width, self.height),
+                };
+                let lens_radius = float_param(params, "lensradius", 0.)?;
+                let f_stop = if lens_radius > 0. {
+                    0.5 * lens.focal_length / 1000. / lens_radius
+                } else {
+                    Float::INFINITY
+                };
+                let blades = params.integer("blades", 0).map_err(|e| e.to_string())?;
+                Arc::new(PerspectiveCamera::new(
+                    self.width,
+                    self.height,
+                    frame,
+                    ThinLens {
+                        f_stop: float_param(params, "fstop", f_stop)?,
+                        focus_distance: float_param(params, "focaldistance", 1e6)?,
+                        aperture: if blades > 0 {
+                            ApertureShape::Polygonal {
+                                blades: blades as u32,
+                                rotation: float_param(params, "bladerotation", 0.)?,
+                            }
+                        } else {
+                            ApertureShape::Circular
+                        },
+                        ..lens
+                    },
+                ))
+            }
+            "orthographic" => {
+                let screen_window = match params.floats("screenwindow").map_err(|e| e.to_string())? {
+                    Some(window) if window.len() == 4 => [window[0], window[1], window[2], window[3]],
+                    _ => OrthographicCamera::fit_screen_window(self.width, self.height, 1.),
+                };
+                Arc::new(OrthographicCamera::new(frame, screen_window))
+            }
+            // Not a pbrt camera, the equidistant fisheye is our own
+            "fisheye" => Arc::new(FisheyeCamera::new(
+                self.width,
+                self.height,
+                frame,
+                float_param(params, "fov", 180.)?,
+            )),
+            "spherical" => {
+                let mapping = string_param(params, "mapping").unwrap_or("equalarea");
+                if mapping != "equirectangular" {
+                    warn!("Spherical mapping '{}' is not supported, using equirectangular", mapping);
+                }
+                Arc::new(EquirectangularCamera::new(frame))
+            }
+            _ => return Err(format!("unsupported camera '{}'", ty)),
+        };
+        Ok(camera)
+    }
+
+    // Medium boundaries have no material
+    fn material(&self, ty: &str, params: &ParamList) -> Result<Option<Arc<dyn Material>>, String> {
+        let material: Arc<dyn Material> = match ty {
+            "interface" => return Ok(None),
+            // Textured or rough surfaces need the matte material, sigma is
+            // the roughness of pbrt-v3's one
+            "diffuse" if is_texture(params, "reflectance") || params.get("sigma").is_some() => Arc::new(MatteMaterial::new(
+                self.spectrum_texture(params, "reflectance", Vec3::from(0.5))?,
+                self.float_texture(params, "sigma", 0.)?,
+            )),
+            "diffuse" => {
+                let reflectance = rgb_param(params, "reflectance", Vec3::from(0.5))?;
+                Arc::new(LambertMaterial::new(Spectrum::ColorRGB(reflectance)))
+            }
+            // Measured metals are picked by the named spectrum of their eta,
+            // pbrt's default is copper
+            "conductor" if params.get("reflectance").is_some() => {
+                let reflectance = rgb_param(params, "reflectance", Vec3::from(0.8))?;
+                Arc::new(MetalMaterial::new(Spectrum::ColorRGB(reflectance)))
+            }
+            "conductor" => {
+                let eta = string_param(params, "eta").unwrap_or("metal-Cu-eta");
+                let metal = Metal::from_pbrt_name(eta).unwrap_or_else(|| {
+                    warn!("Conductor eta '{}' is not supported, using copper", eta);
+                    Metal::Copper
+                });
+                Arc::new(ConductorMaterial::new(metal))
+            }
+            // A constant eta, or a named glass for dispersion
+            "dielectric" => {
+                let ior = match params.get("eta") {
+                    Some(param) if param.ty == ParamType::Float => IorModel::Constant(float_param(params, "eta", 1.5)?),
+                    Some(_) => {
+                        let name = string_param(params, "eta").unwrap_or("");
+                        Glass::from_name(name).map(|glass| glass.ior()).unwrap_or_else(|| {
+                            warn!("Dielectric eta '{}' is not supported, using 1.5", name);
+                            IorModel::default()
+                        })
+                    }
+                    None => IorModel::default(),
+                };
+                Arc::new(DieletricMaterial::new(Spectrum::ColorRGB(Vec3::from(1.))).with_ior(ior))
+            }
+            _ => {
+                warn!("Material '{}' is not supported, using diffuse", ty);
+                default_material()
+            }
+        };
+        // Bump mapped by pbrt's displacement, flat when its texture wasn't
+        // made
+        if params.get("displacement").is_some() {
+            let texture = string_param(params, "displacement").unwrap_or("");
+            if is_texture(params, "displacement") && !self.named_float_textures.contains_key(texture) {
+                warn!("Unknown displacement texture '{}', material '{}' is not bump mapped", texture, ty);
+                return Ok(Some(material));
+            }
+            let displacement = self.float_texture(params, "displacement", 0.)?;
+            return Ok(Some(Arc::new(BumpMaterial::new(material, displacement))));
+        }
+        Ok(Some(material))
+    }
+
+    // Polygons of a PLY or OBJ file, or given by their corners: "indices"
+    // lists them one after the other, "faceverts" has the number of corners
+    // of each, all triangles without it. Transformed by `transform`.
+    fn polygons(&self, params: &ParamList, directory: &Path, transform: &Mat4) -> Result<PolygonMesh, String> {
+        let mut polygons = match string_param(params, "filename") {
+            Some(filename) if filename.to_ascii_lowercase().ends_with(".obj") => parse_obj(&directory.join(filename))?,
+            Some(filename) => parse_ply(&directory.join(filename))?,
+            None => {
+                let positions: Vec<Vec3> = params
+                    .floats("P")
+                    .map_err(|e| e.to_string())?
+                    .ok_or("mesh without positions".to_string())?
+                    .chunks_exact(3)
+                    .map(|p| Vec3::new(p[0], p[1], p[2]))
+                    .collect();
+                let indices: Vec<u32> = match params.integers("indices").map_err(|e| e.to_string())? {
+                    Some(indices) => indices.iter().map(|&index| index as u32).collect(),
+                    None if positions.len() == 3 => vec![0, 1, 2],
+                    None => return Err("mesh without indices".to_string()),
+                };
+                let counts: Vec<usize> = match params.integers("faceverts").map_err(|e| e.to_string())? {
+                    Some(counts) => counts.iter().map(|&count| count.max(0) as usize).collect(),
+                    None => vec![3; indices.len() / 3],
+                };
+                if counts.iter().sum::<usize>() != indices.len() {
+                    return Err("faceverts doesn't add up to the number of indices".to_string());
+                }
+                let mut faces = Vec::with_capacity(counts.len());
+                let mut start = 0;
+                for count in counts {
+                    faces.push(indices[start..start + count].to_vec());
+                    start += count;
+                }
+                let uv = match params.floats("uv").map_err(|e| e.to_string())? {
+                    Some(uv) => uv.chunks_exact(2).map(|uv| Vec2::new(&[uv[0], uv[1]])).collect(),
+                    None => Vec::new(),
+                };
+                let normals = match params.floats("N").map_err(|e| e.to_string())? {
+                    Some(normals) => normals.chunks_exact(3).map(|n| Vec3::new(n[0], n[1], n[2])).collect(),
+                    None => Vec::new(),
+                };
+                PolygonMesh { positions, normals, uv, faces }
+            }
+        };
+        if polygons.faces.iter().flatten().any(|&index| index as usize >= polygons.positions.len()) {
+            return Err("mesh index out of range".to_string());
+        }
+        if !polygons.uv.is_empty() && polygons.uv.len() != polygons.positions.len() {
+            warn!("mesh has {} uvs for {} positions, ignoring them", polygons.uv.len(), polygons.positions.len());
+            polygons.uv.clear();
+        }
+        if !polygons.normals.is_empty() && polygons.normals.len() != polygons.positions.len() {
+            warn!("mesh has {} normals for {} positions, ignoring them", polygons.normals.len(), polygons.positions.len());
+            polygons.normals.clear();
+        }
+        let normal_transform = transform.inverse().map(|inverse| inverse.transpose());
+        for position in polygons.positions.iter_mut() {
+            *position = *transform * *position;
+        }
+        if let Some(normal_transform) = normal_transform {
+            for normal in polygons.normals.iter_mut() {
+                *normal = normal_transform.transform_vector(*normal).normalize();
+            }
+        }
+        Ok(polygons)
+    }
+
+    // Levels, or the edge length in pixels to refine to for the camera.
+    // "creases" pairs vertices with a sharpness each in "creasesharpness",
+    // infinitely sharp without it. "corners" lists vertices that stay put.
+    fn subdivision_surface(&self, scheme: SubdivisionScheme, polygons: &PolygonMesh, params: &ParamList) -> Result<SubdivisionSurface, String> {
+        let levels = integer_param(params, "levels", 3)?.max(0) as u32;
+        let metric = self
+            .scene
+            .camera
+            .as_ref()
+            .and_then(|camera| EdgeMetric::from_camera(camera.as_ref(), self.width));
+        let levels = match (params.get("edgelength"), metric) {
+            (Some(_), Some(metric)) => SubdivisionLevels::Adaptive {
+                edge_length: float_param(params, "edgelength", 1.)?,
+                max_level: integer_param(params, "maxlevel", 6)?.max(0) as u32,
+                metric,
+            },
+            _ => SubdivisionLevels::Uniform(levels),
+        };
+        let mut surface = SubdivisionSurface::new(scheme, polygons, levels);
+
+        let creases = params.integers("creases").map_err(|e| e.to_string())?.unwrap_or_default();
+        let sharpness = params.floats("creasesharpness").map_err(|e| e.to_string())?.unwrap_or_default();
+        for (i, pair) in creases.chunks_exact(2).enumerate() {
+            let sharpness = match sharpness.as_slice() {
+                [] => Float::INFINITY,
+                [all] => *all,
+                each => *each.get(i).ok_or("fewer creasesharpness than creases".to_string())?,
+            };
+            surface.add_crease(pair[0] as u32, pair[1] as u32, sharpness);
+        }
+        let corners = params.integers("corners").map_err(|e| e.to_string())?.unwrap_or_default();
+        surface.corners = corners.iter().map(|&corner| corner as u32).collect();
+        Ok(surface)
+    }
+
+    // True displacement of a mesh, tessellated for the camera. Points can't
+    // move further than `displacementbound`.
+    fn displacement(&self, params: &ParamList) -> Result<Displacement, String> {
+        let bound = match params.get("displacementbound") {
+            Some(_) => float_param(params, "displacementbound", 0.)?.abs(),
+            None => return Err("displacement without a displacementbound".to_string()),
+        };
+        let mut displacement = Displacement::new(self.float_texture(params, "displacement", 0.)?, bound);
+        displacement.edge_length = float_param(params, "edgelength", displacement.edge_length)?.max(1e-3);
+        displacement.max_level = integer_param(params, "maxlevel", displacement.max_level as i32)?.max(0) as u32;
+        displacement.metric = self
+            .scene
+            .camera
+            .as_ref()
+            .and_then(|camera| EdgeMetric::from_camera(camera.as_ref(), self.width));
+        Ok(displacement)
+    }
+
+    // A named texture, or a constant one of the value. Textures that
+    // weren't made, like unsupported ones, are replaced by the default.
+    fn spectrum_texture(&self, params: &ParamList, name: &str, default: Vec3) -> Result<SpectrumTexture, String> {
+        if is_texture(params, name) {
+            let texture = string_param(params, name).unwrap_or("");
+            return Ok(match self.named_spectrum_textures.get(texture) {
+                Some(texture) => texture.clone(),
+                None => {
+                    warn!("Unknown spectrum texture '{}' for '{}', using rgb {} {} {}", texture, name, default.x, default.y, default.z);
+                    Arc::new(ConstantTexture::new(Spectrum::ColorRGB(default)))
+                }
+            });
+        }
+        Ok(Arc::new(ConstantTexture::new(Spectrum::ColorRGB(rgb_param(params, name, default)?))))
+    }
+
+    fn float_texture(&self, params: &ParamList, name: &str, default: Float) -> Result<FloatTexture, String> {
+        if is_texture(params, name) {
+            let texture = string_param(params, name).unwrap_or("");
+            return Ok(match self.named_float_textures.get(texture) {
+                Some(texture) => texture.clone(),
+                None => {
+                    warn!("Unknown float texture '{}' for '{}', using {}", texture, name, default);
+                    Arc::new(ConstantTexture::new(default))
+                }
+            });
+        }
+        Ok(Arc::new(ConstantTexture::new(float_param(params, name, default)?)))
+    }
+
+    // Solid textures look up the world point in the space of the current
+    // transform, like pbrt's. Flat ones use the uv of the hit by default.
+    fn texture_mapping(&self, params: &ParamList, solid: bool) -> Result<TextureMapping, String> {
+        let texture_from_world = || {
+            self.state.transforms[0]
+                .inverse()
+                .ok_or("the texture transform is not invertible".to_string())
+        };
+        let mapping = match string_param(params, "mapping").unwrap_or(if solid { "world" } else { "uv" }) {
+            "uv" => TextureMapping::Uv {
+                scale: Vec2::new(&[float_param(params, "uscale", 1.)?, float_param(params, "vscale", 1.)?]),
+                offset: Vec2::new(&[float_param(params, "udelta", 0.)?, float_param(params, "vdelta", 0.)?]),
+            },
+            "object" => TextureMapping::Object(texture_from_world()?),
+            "world" => TextureMapping::World(texture_from_world()?),
+            other => {
+                warn!("Texture mapping '{}' is not supported, using uv", other);
+                TextureMapping::default()
+            }
+        };
+        Ok(mapping)
+    }
+
+    fn add_texture<T>(&mut self, name: &str, ty: &str, texture: T)
+    where
+        T: Texture<Float> + Texture<Spectrum> + Sync + Send + 'static,
+    {
+        if ty == "float" {
+            self.named_float_textures.insert(name.to_string(), Arc::new(texture));
+        } else {
+            self.named_spectrum_textures.insert(name.to_string(), Arc::new(texture));
+        }
+    }
+
+    // pbrt's procedural textures, and wood, voronoi and gradient ones.
+    // Scalar textures give colors through a ramp.
+    fn make_texture(&mut self, name: &str, ty: &str, class: &str, params: &ParamList) -> Result<(), String> {
+        if ty != "float" && ty != "spectrum" {
+            return Err(format!("unknown texture type '{}'", ty));
+        }
+        match class {
+            "constant" if ty == "float" => {
+                let texture = ConstantTexture::new(float_param(params, "value", 1.)?);
+                self.named_float_textures.insert(name.to_string(), Arc::new(texture));
+            }
+            "constant" => {
+                let texture = ConstantTexture::new(Spectrum::ColorRGB(rgb_param(params, "value", Vec3::from(1.))?));
+                self.named_spectrum_textures.insert(name.to_string(), Arc::new(texture));
+            }
+            "checkerboard" => {
+                let mapping = self.texture_mapping(params, integer_param(params, "dimension", 2)? == 3)?;
+                if ty == "float" {
+                    let (even, odd) = (float_param(params, "tex1", 1.)?, float_param(params, "tex2", 0.)?);
+                    self.named_float_textures
+                        .insert(name.to_string(), Arc::new(CheckerboardTexture::new(mapping, even, odd)));
+                } else {
+                    let even = Spectrum::ColorRGB(rgb_param(params, "tex1", Vec3::from(1.))?);
+                    let odd = Spectrum::ColorRGB(rgb_param(params, "tex2", Vec3::from(0.))?);
+                    self.named_spectrum_textures
+                        .insert(name.to_string(), Arc::new(CheckerboardTexture::new(mapping, even, odd)));
+                }
+            }
+            "fbm" | "wrinkled" => {
+                let kind = if class == "fbm" { NoiseKind::Fbm } else { NoiseKind::Turbulence };
+                let mut texture = NoiseTexture::new(self.texture_mapping(params, true)?, kind);
+                texture.basis = enum_param(params, "basis", texture.basis)?;
+                texture.octaves = integer_param(params, "octaves", 8)?.max(1) as u32;
+                texture.roughness = float_param(params, "roughness", texture.roughness)?;
+                texture.ramp = ramp_param(params, texture.ramp)?;
+                self.add_texture(name, ty, texture);
+            }
+            "marble" => {
+                let mut texture = MarbleTexture::new(self.texture_mapping(params, true)?);
+                texture.octaves = integer_param(params, "octaves", 8)?.max(1) as u32;
+                texture.roughness = float_param(params, "roughness", texture.roughness)?;
+                texture.scale = float_param(params, "scale", texture.scale)?;
+                texture.variation = float_param(params, "variation", texture.variation)?;
+                texture.ramp = ramp_param(params, texture.ramp)?;
+                self.add_texture(name, ty, texture);
+            }
+            "wood" => {
+                let mut texture = WoodTexture::new(self.texture_mapping(params, true)?);
+                texture.rings = float_param(params, "rings", texture.rings)?;
+                texture.distortion = float_param(params, "distortion", texture.distortion)?;
+                texture.octaves = integer_param(params, "octaves", 4)?.max(1) as u32;
+                texture.ramp = ramp_param(params, texture.ramp)?;
+                self.add_texture(name, ty, texture);
+            }
+            "voronoi" => {
+                let output = enum_param(params, "output", VoronoiOutput::Distance)?;
+                let mut texture = VoronoiTexture::new(self.texture_mapping(params, true)?, output);
+                texture.jitter = float_param(params, "jitter", texture.jitter)?;
+                texture.ramp = ramp_param(params, texture.ramp)?;
+                self.add_texture(name, ty, texture);
+            }
+            "gradient" => {
+                let kind = enum_param(params, "gradient", GradientKind::Linear)?;
+                let mut texture = GradientTexture::new(self.texture_mapping(params, false)?, kind);
+                texture.ramp = ramp_param(params, texture.ramp)?;
+                self.add_texture(name, ty, texture);
+            }
+            "ramp" if ty == "spectrum" => {
+                let texture = RampTexture {
+                    input: self.float_texture(params, "tex", 0.)?,
+                    ramp: ramp_param(params, ColorRamp::default())?,
+                };
+                self.named_spectrum_textures.insert(name.to_string(), Arc::new(texture));
+            }
+            "swizzle" => {
+                let input = self.spectrum_texture(params, "tex", Vec3::from(1.))?;
+                let texture = SwizzleTexture::new(input, string_param(params, "channels").unwrap_or("rgb"))?;
+                self.add_texture(name, ty, texture);
+            }
+            "mix" | "scale" | "add" | "multiply" | "invert" | "remap" | "curve" | "uvtransform" => {
+                if ty == "float" {
+                    let texture = self.texture_node::<Float>(class, params)?;
+                    self.named_float_textures.insert(name.to_string(), texture);
+                } else {
+                    let texture = self.texture_node::<Spectrum>(class, params)?;
+                    self.named_spectrum_textures.insert(name.to_string(), texture);
+                }
+            }
+            _ => warn!("Texture '{}' of type '{}' is not supported", class, ty),
+        }
+        Ok(())
+    }
+
+    // Nodes over other textures of the same type, or constants. The uv
+    // transform is glTF's, its rotation in radians.
+    fn texture_node<T: TextureParam>(&self, class: &str, params: &ParamList) -> Result<TextureRef<T>, String> {
+        let texture = |name: &str, default: Float| T::texture(self, params, name, default);
+        let node: TextureRef<T> = match class {
+            "mix" => Arc::new(MixTexture {
+                a: texture("tex1", 0.)?,
+                b: texture("tex2", 1.)?,
+                amount: self.float_texture(params, "amount", 0.5)?,
+            }),
+            "scale" => Arc::new(ScaleTexture {
+                texture: texture("tex", 1.)?,
+                scale: self.float_texture(params, "scale", 1.)?,
+            }),
+            "add" => Arc::new(CombineTexture {
+                a: texture("tex1", 0.)?,
+                b: texture("tex2", 0.)?,
+                operation: Operation::Add,
+            }),
+            "multiply" => Arc::new(CombineTexture {
+                a: texture("tex1", 1.)?,
+                b: texture("tex2", 1.)?,
+                operation: Operation::Multiply,
+            }),
+            "invert" => Arc::new(InvertTexture { texture: texture("tex", 0.)? }),
+            "remap" => Arc::new(RemapTexture {
+                texture: texture("tex", 0.)?,
+                from: pair_param(params, "from", (0., 1.))?,
+                to: pair_param(params, "to", (0., 1.))?,
+                clamp: bool_param(params, "clamp", false)?,
+            }),
+            "curve" => {
+                let values = params
+                    .floats("points")
+                    .map_err(|e| format!("invalid value for 'points': {}", e))?
+                    .unwrap_or(vec![0., 0., 1., 1.]);
+                if values.is_empty() || values.len() % 2 != 0 {
+                    return Err("'points' needs (x, y) pairs".to_string());
+                }
+                let points = values.chunks(2).map(|point| (point[0], point[1])).collect();
+                Arc::new(CurveTexture::new(texture("tex", 0.)?, points))
+            }
+            "uvtransform" => {
+                let (offset, scale) = (pair_param(params, "offset", (0., 0.))?, pair_param(params, "scale", (1., 1.))?);
+                Arc::new(UvTransformTexture {
+                    texture: texture("tex", 1.)?,
+                    offset: Vec2::new(&[offset.0, offset.1]),
+                    rotation: float_param(params, "rotation", 0.)?,
+                    scale: Vec2::new(&[scale.0, scale.1]),
+                })
+            }
+            _ => return Err(format!("unknown texture node '{}'", class)),
+        };
+        Ok(node)
+    }
+
+    // Media are in the coordinate system current when they are made
+    fn make_medium(&self, params: &ParamList, directory: &Path) -> Result<Option<Arc<dyn Medium>>, String> {
+        let ty = string_param(params, "type").unwrap_or("");
+        let scale = float_param(params, "scale", 1.)?;
+        let sigma_a = Spectrum::ColorRGB(rgb_param(params, "sigma_a", Vec3::from(1.))? * scale);
+        let sigma_s = Spectrum::ColorRGB(rgb_param(params, "sigma_s", Vec3::from(1.))? * scale);
+        let g = float_param(params, "g", 0.)?;
+        match ty {
+            "homogeneous" => Ok(Some(Arc::new(HomogeneousMedium::new(sigma_a, sigma_s, g)))),
+            "uniformgrid" => {
+                // The grids come inline, or from a raw grid file
+                let (density, temperature) = match string_param(params, "filename") {
+                    Some(filename) => load_grid(directory.join(filename))?,
+                    None => {
+                        let resolution = [
+                            params.integer("nx", 1).map_err(|e| e.to_string())?.max(0) as usize,
+                            params.integer("ny", 1).map_err(|e| e.to_string())?.max(0) as usize,
+                            params.integer("nz", 1).map_err(|e| e.to_string())?.max(0) as usize,
+                        ];
+                        let grid = |name: &str| -> Result<Option<VoxelGrid>, String> {
+                            match params.floats(name).map_err(|e| e.to_string())? {
+                                Some(values) => Ok(Some(
+                                    VoxelGrid::new(resolution, values).map_err(|e| format!("'{}': {}", name, e))?,
+                                )),
+                                None => Ok(None),
+                            }
+                        };
+                        let density = grid("density")?.ok_or("uniformgrid medium without density".to_string())?;
+                        (density, grid("temperature")?)
+                    }
+                };
+                let point = |name: &str, default: Vec3| -> Result<Vec3, String> {
+                    match params.floats(name).map_err(|e| e.to_string())?.as_deref() {
+                        Some([x, y, z]) => Ok(Vec3::new(*x, *y, *z)),
+                        Some(_) => Err(format!("'{}' should be a point", name)),
+                        None => Ok(default),
+                    }
+                };
+                let bounds = Bounds3f {
+                    p_min: point("p0", Vec3::zero())?,
+                    p_max: point("p1", Vec3::from(1.))?,
+                };
+                let placement =
+                    Placement::new(self.state.transforms[0]).ok_or("medium with a flat transform".to_string())?;
+                let mut medium = GridMedium::new(bounds, placement, density, sigma_a, sigma_s, g);
+                if let Some(temperature) = temperature {
+                    medium = medium.with_temperature(
+                        temperature,
+                        float_param(params, "temperaturecutoff", 0.)?,
+                        float_param(params, "temperaturescale", 1.)?,
+                        float_param(params, "Lescale", 1.)?,
+                    );
+                }
+                Ok(Some(Arc::new(medium)))
+            }
+            _ => {
+                warn!("Medium '{}' is not supported", ty);
+                Ok(None)
+            }
+        }
+    }
+
+    // "" is no medium
+    fn medium(&self, name: &str) -> Option<Arc<dyn Medium>> {
+        if name.is_empty() {
+            return None;
+        }
+        let medium = self.named_media.get(name).cloned();
+        if medium.is_none() {
+            warn!("Unknown medium '{}'", name);
+        }
+        medium
+    }
+
+    fn animated_transform(&self) -> AnimatedTransform {
+        let [start_time, end_time] = self.transform_times;
+        let [start, end] = self.state.transforms;
+        AnimatedTransform::new(&[(start_time, start), (end_time, end)])
+    }
+
+    fn shape(&mut self, name: &str, params: &ParamList, directory: &Path) -> Result<(), String> {
+        // Shapes moving over the transform times stay in object space, the
+        // others are put in the world
+        let [start, end] = self.state.transforms;
+        let moving = start != end && self.current_object.is_none();
+        let transform = if moving { Mat4::identity() } else { start };
+        let placement = || Placement::new(transform).ok_or(format!("{} with a flat transform", name));
+
+        let shape = match name {
+            // Uniform scaling only unless it moves, a sphere can't be
+            // squashed in world space
+            "sphere" => {
+                let center = transform * Vec3::zero();
+                let scale = transform.transform_vector(Vec3::new(1., 0., 0.)).length();
+                let radius = float_param(params, "radius", 1.)? * scale;
+                Shape::Sphere(Sphere::new(center, radius))
+            }
+            "disk" => Shape::Disk(Disk::new(
+                float_param(params, "height", 0.)?,
+                float_param(params, "radius", 1.)?,
+                float_param(params, "innerradius", 0.)?,
+                float_param(params, "phimax", 360.)?,
+                placement()?,
+            )),
+            "cylinder" => Shape::Cylinder(Cylinder::new(
+                float_param(params, "radius", 1.)?,
+                float_param(params, "zmin", -1.)?,
+                float_param(params, "zmax", 1.)?,
+                float_param(params, "phimax", 360.)?,
+                placement()?,
+            )),
+            "cone" => Shape::Cone(Cone::new(
+                float_param(params, "height", 1.)?,
+                float_param(params, "radius", 1.)?,
+                float_param(params, "phimax", 360.)?,
+                placement()?,
+            )),
+            "paraboloid" => Shape::Paraboloid(Paraboloid::new(
+                float_param(params, "radius", 1.)?,
+                float_param(params, "zmin", 0.)?,
+                float_param(params, "zmax", 1.)?,
+                float_param(params, "phimax", 360.)?,
+                placement()?,
+            )),
+            "trianglemesh" => {
+                let positions: Vec<Vec3> = params
+                    .floats("P")
+                    .map_err(|e| e.to_string())?
+                    .ok_or("trianglemesh without positions".to_string())?
+                    .chunks_exact(3)
+                    .map(|p| transform * Vec3::new(p[0], p[1], p[2]))
+                    .collect();
+                let indices = match params.integers("indices").map_err(|e| e.to_string())? {
+                    Some(indices) => indices.iter().map(|&index| index as u32).collect(),
+                    // A single triangle can leave them out
+                    None if positions.len() == 3 => vec![0, 1, 2],
+                    None => return Err("trianglemesh without indices".to_string()),
+                };
+                if indices.iter().any(|&index| index as usize >= positions.len()) {
+                    return Err("trianglemesh index out of range".to_string());
+                }
+                let mut mesh = Mesh::new(positions, indices);
+                if let Some(uv) = params.floats("uv").map_err(|e| e.to_string())? {
+                    mesh.uv = uv.chunks_exact(2).map(|uv| Vec2::new(&[uv[0], uv[1]])).collect();
+                    if mesh.uv.len() != mesh.positions.len() {
+                        warn!("trianglemesh has {} uvs for {} positions, ignoring them", mesh.uv.len(), mesh.positions.len());
+                        mesh.uv.clear();
+                    }
+                }
+                match params.get("displacement") {
+                    Some(_) => Shape::Displaced(Arc::new(DisplacedMesh::new(Arc::new(mesh), self.displacement(params)?))),
+                    None => Shape::Mesh(Arc::new(mesh)),
+                }
+            }
+            "plymesh" => {
+                let mesh = Mesh::from_polygons(&self.polygons(params, directory, &transform)?);
+                match params.get("displacement") {
+                    Some(_) => Shape::Displaced(Arc::new(DisplacedMesh::new(Arc::new(mesh), self.displacement(params)?))),
+                    None => Shape::Mesh(Arc::new(mesh)),
+                }
+            }
+            // Loop's subdivision of a triangle mesh is pbrt's, Catmull-Clark
+            // takes any polygons
+            "loopsubdiv" | "catmullclark" => {
+                let scheme = match name {
+                    "loopsubdiv" => SubdivisionScheme::Loop,
+                    _ => SubdivisionScheme::CatmullClark,
+                };
+                let polygons = self.polygons(params, directory, &transform)?;
+                let surface = self.subdivision_surface(scheme, &polygons, params)?;
+                Shape::Mesh(Arc::new(surface.refine()))
+            }
+            _ => {
+                warn!("Shape '{}' is not supported", name);
+                return Ok(());
+            }
+        };
+
+        let material = self.state.material.clone();
+        let mut primitive = if moving {
+            Primitive::instance(shape, material, self.animated_transform())
+        } else {
+            Primitive::new(shape, material)
+        };
+        let (interior, exterior) = &self.state.medium_interface;
+        if !interior.is_empty() || !exterior.is_empty() {
+            primitive.medium_interface = Some(MediumInterface::new(self.medium(interior), self.medium(exterior)));
+        }
+        match &mut self.current_object {
+            Some((_, primitives)) => primitives.push(primitive),
+            None => self.scene.primitives.push(primitive),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_look_at() {
+        let camera_from_world = look_at(Vec3::new(1., 2., 3.), Vec3::new(1., 2., 10.), Vec3::new(0., 1., 0.)).unwrap();
+        let frame = CameraFrame::from_matrix(&camera_from_world.inverse().unwrap());
+        assert!((frame.eye - Vec3::new(1., 2., 3.)).length() < 1e-5);
+        assert!((frame.forward - Vec3::new(0., 0., 1.)).length() < 1e-5);
+        assert!((frame.right - Vec3::new(1., 0., 0.)).length() < 1e-5);
+    }
+
+    #[test]
+    fn test_load_book() {
+        // Its image textures aren't supported, the textures made of them
+        // fall back to constants
+        let scene = load_pbrt("../assets/pbrt4/pbrt-book/book.pbrt", 64, 36).unwrap();
+        assert!(scene.camera.is_some());
+        assert!(!scene.primitives.is_empty());
+
+        // Nor are materials bump mapped by a texture that is missing
+        let path = std::env::temp_dir().join("pbrt-loader-missing-bump.pbrt");
+        std::fs::write(
+            &path,
+            "WorldBegin\nMaterial \"diffuse\" \"texture displacement\" [ \"missing\" ]\nShape \"sphere\"\n",
+        )
+        .unwrap();
+        let scene = load_pbrt(&path, 64, 36).unwrap();
+        assert_eq!(scene.primitives.len(), 1);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
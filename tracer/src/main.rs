@@ -1,11 +1,16 @@
 use env_logger;
 
 pub mod app;
+pub mod cli;
 pub mod cameras;
 pub mod core;
+pub mod denoisers;
+pub mod filters;
 pub mod integrators;
 pub mod loaders;
 pub mod materials;
+pub mod media;
+pub mod scenes;
 pub mod shapes;
 pub mod textures;
 
@@ -35,6 +40,30 @@ fn main() {
 
     env_logger::init();
 
+    // Any argument renders from the command line instead of opening the UI
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        match cli::parse_args(&args) {
+            Ok(Some(options)) => match cli::render(&options) {
+                Ok(paths) => {
+                    for path in paths {
+                        log::info!("Image written to: {}", path);
+                    }
+                }
+                Err(err) => {
+                    eprintln!("error: {}", err);
+                    std::process::exit(1);
+                }
+            },
+            Ok(None) => print!("{}", cli::USAGE),
+            Err(err) => {
+                eprint!("error: {}\n\n{}", err, cli::USAGE);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
     let app = Box::<RustracerApp>::default();
     let ui_result = init_ui(app);
     match ui_result {
@@ -0,0 +1,148 @@
+use std::sync::Arc;
+
+use math::{Float, Vec2, Vec3};
+
+use crate::core::interaction::SurfaceInteraction;
+use crate::core::ray::Ray;
+use crate::core::sampler::Sampler;
+use crate::core::spectral::SampledWavelengths;
+use crate::core::spectrum::Spectrum;
+use crate::core::texture::FloatTexture;
+use crate::materials::{Material, MaterialScatterResult, ScatterLobe};
+
+// Shading normal of a surface displaced along its normal by `displacement`,
+// from pbrt's bump mapping. The displacement is looked up half a pixel away
+// along u and v to get its slope, surfaces without uv derivatives keep their
+// normal.
+pub fn bump(displacement: &FloatTexture, si: &SurfaceInteraction) -> Vec3 {
+    let n = si.hit_normal;
+    if Vec3::cross(si.dpdu, si.dpdv).length2() == 0. {
+        return n;
+    }
+    let shifted = |du: Float, dv: Float| {
+        let offset = si.dpdu * du + si.dpdv * dv;
+        let mut at = si.with_uv(Vec2::new(&[si.hit_uv.0 + du, si.hit_uv.1 + dv]));
+        at.hit_point = si.hit_point + offset;
+        at.hit_object_point = si.hit_object_point + offset;
+        displacement.evaluate(&at)
+    };
+    // Without a footprint a small fixed step
+    let du = match 0.5 * (si.dudx.abs() + si.dudy.abs()) {
+        du if du > 0. => du,
+        _ => 5e-4,
+    };
+    let dv = match 0.5 * (si.dvdx.abs() + si.dvdy.abs()) {
+        dv if dv > 0. => dv,
+        _ => 5e-4,
+    };
+    let d = displacement.evaluate(si);
+    let dpdu = si.dpdu + n * ((shifted(du, 0.) - d) / du) + si.dndu * d;
+    let dpdv = si.dpdv + n * ((shifted(0., dv) - d) / dv) + si.dndv * d;
+    let bumped = Vec3::cross(dpdu, dpdv).normalize();
+    if !bumped.x.is_finite() {
+        return n;
+    }
+    // Same side as the normal of the surface
+    if Vec3::dot(bumped, n) < 0. {
+        -bumped
+    } else {
+        bumped
+    }
+}
+
+// Any material over a bumpy surface. It sees the interaction with the
+// bumped normal, the geometry stays flat.
+#[derive(Clone)]
+pub struct BumpMaterial {
+    pub material: Arc<dyn Material>,
+    pub displacement: FloatTexture,
+}
+
+impl BumpMaterial {
+    pub fn new(material: Arc<dyn Material>, displacement: FloatTexture) -> Self {
+        BumpMaterial { material, displacement }
+    }
+
+    fn bumped(&self, interaction: &SurfaceInteraction) -> SurfaceInteraction {
+        SurfaceInteraction {
+            hit_normal: bump(&self.displacement, interaction),
+            hit_primitive: interaction.hit_primitive.clone(),
+            hit_material: interaction.hit_material.clone(),
+            ..*interaction
+        }
+    }
+}
+
+impl Material for BumpMaterial {
+    fn value(&self) -> Spectrum {
+        self.material.value()
+    }
+
+    fn evaluate(&self, interaction: &SurfaceInteraction) -> Spectrum {
+        self.material.evaluate(&self.bumped(interaction))
+    }
+
+    fn sampled_value(&self, wavelengths: &SampledWavelengths, ray: &Ray, interaction: &SurfaceInteraction) -> Spectrum {
+        self.material.sampled_value(wavelengths, ray, &self.bumped(interaction))
+    }
+
+    fn sampled_scatter(
+        &self,
+        wavelengths: &mut SampledWavelengths,
+        ray: &Ray,
+        attenuation: &Spectrum,
+        interaction: &SurfaceInteraction,
+        sampler: &mut Sampler,
+    ) -> MaterialScatterResult {
+        self.material.sampled_scatter(wavelengths, ray, attenuation, &self.bumped(interaction), sampler)
+    }
+
+    fn lobe(&self) -> ScatterLobe {
+        self.material.lobe()
+    }
+
+    fn scatter(
+        &self,
+        ray: &Ray,
+        attenuation: &Spectrum,
+        interaction: &SurfaceInteraction,
+        sampler: &mut Sampler,
+    ) -> MaterialScatterResult {
+        self.material.scatter(ray, attenuation, &self.bumped(interaction), sampler)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::texture::{Texture, TextureMapping};
+    use crate::textures::constant::ConstantTexture;
+    use crate::textures::nodes::ScaleTexture;
+    use crate::textures::ramp::{GradientKind, GradientTexture};
+
+    #[test]
+    fn test_bump() {
+        // A flat quad along x and y facing z
+        let mut si = SurfaceInteraction::new();
+        si.hit_normal = Vec3::new(0., 0., 1.);
+        si.dpdu = Vec3::new(1., 0., 0.);
+        si.dpdv = Vec3::new(0., 1., 0.);
+        si.hit_uv = Vec2::new(&[0.5, 0.5]);
+
+        // Constant heights don't tilt anything
+        let flat: FloatTexture = Arc::new(ConstantTexture::new(0.3));
+        assert!((bump(&flat, &si) - si.hit_normal).length() < 1e-5);
+
+        // Heights rising along u by half a unit per unit tilt the normal
+        // back toward -x
+        let ramp = GradientTexture::new(TextureMapping::default(), GradientKind::Linear);
+        assert!((Texture::<Float>::evaluate(&ramp, &si) - 0.5).abs() < 1e-6);
+        let slope: FloatTexture = Arc::new(ScaleTexture { texture: Arc::new(ramp), scale: Arc::new(ConstantTexture::new(0.5)) });
+        let bumped = bump(&slope, &si);
+        assert!((bumped - Vec3::new(-0.5, 0., 1.).normalize()).length() < 1e-3, "{:?}", bumped);
+
+        // Shapes without derivatives stay smooth
+        si.dpdu = Vec3::from(0.);
+        assert_eq!(bump(&slope, &si), si.hit_normal);
+    }
+}
@@ -0,0 +1,197 @@
+use math::{Float, Vec3};
+use strum_macros::{Display, EnumIter, EnumString};
+
+use crate::core::interaction::SurfaceInteraction;
+use crate::core::ray::Ray;
+use crate::core::sampler::Sampler;
+use crate::core::spectral::{spectrum_to_xyz, xyz_to_linear_srgb, Illuminant, SampledWavelengths};
+use crate::core::spectrum::Spectrum;
+use crate::materials::{Material, MaterialScatterResult, ScatterLobe};
+
+// Metals with measured complex indices of refraction
+#[derive(Debug, EnumIter, EnumString, PartialEq, Eq, Clone, Copy, Display, Hash)]
+#[strum(ascii_case_insensitive)]
+pub enum Metal {
+    Gold,
+    Silver,
+    Copper,
+    Aluminium,
+}
+
+// (wavelength, n, k) every 50 nm, read off Johnson and Christy 1972 for
+// gold, silver and copper and Rakić 1995 for aluminium. The values are
+// rounded, good enough for the color of the metal.
+const GOLD: [(Float, Float, Float); 9] = [
+    (400., 1.66, 1.96),
+    (450., 1.50, 1.88),
+    (500., 0.97, 1.87),
+    (550., 0.43, 2.46),
+    (600., 0.25, 2.98),
+    (650., 0.17, 3.45),
+    (700., 0.16, 3.95),
+    (750., 0.16, 4.40),
+    (800., 0.16, 4.84),
+];
+const SILVER: [(Float, Float, Float); 9] = [
+    (400., 0.05, 2.07),
+    (450., 0.04, 2.65),
+    (500., 0.05, 3.13),
+    (550., 0.06, 3.59),
+    (600., 0.06, 4.01),
+    (650., 0.05, 4.42),
+    (700., 0.04, 4.83),
+    (750., 0.03, 5.24),
+    (800., 0.03, 5.61),
+];
+const COPPER: [(Float, Float, Float); 9] = [
+    (400., 1.18, 2.21),
+    (450., 1.24, 2.40),
+    (500., 1.12, 2.56),
+    (550., 1.01, 2.58),
+    (600., 0.27, 3.24),
+    (650., 0.21, 3.67),
+    (700., 0.21, 4.21),
+    (750., 0.22, 4.67),
+    (800., 0.26, 5.03),
+];
+const ALUMINIUM: [(Float, Float, Float); 9] = [
+    (400., 0.49, 4.86),
+    (450., 0.62, 5.47),
+    (500., 0.77, 6.08),
+    (550., 0.96, 6.69),
+    (600., 1.20, 7.26),
+    (650., 1.49, 7.79),
+    (700., 1.83, 8.31),
+    (750., 2.40, 8.62),
+    (800., 2.80, 8.45),
+];
+
+impl Metal {
+    // Metal of a pbrt-v4 named spectrum like "metal-Cu-eta"
+    pub fn from_pbrt_name(name: &str) -> Option<Metal> {
+        let element = name.strip_prefix("metal-")?.split('-').next()?;
+        match element {
+            "Au" => Some(Metal::Gold),
+            "Ag" => Some(Metal::Silver),
+            "Cu" => Some(Metal::Copper),
+            "Al" => Some(Metal::Aluminium),
+            _ => None,
+        }
+    }
+
+    // Index of refraction and extinction coefficient at `lambda`, constant
+    // past the ends of the table
+    pub fn eta_k(&self, lambda: Float) -> (Float, Float) {
+        let table = match self {
+            Metal::Gold => &GOLD,
+            Metal::Silver => &SILVER,
+            Metal::Copper => &COPPER,
+            Metal::Aluminium => &ALUMINIUM,
+        };
+        let x = ((lambda - table[0].0) / 50.).clamp(0., (table.len() - 1) as Float);
+        let i = (x as usize).min(table.len() - 2);
+        let t = x - i as Float;
+        let (a, b) = (table[i], table[i + 1]);
+        ((1. - t) * a.1 + t * b.1, (1. - t) * a.2 + t * b.2)
+    }
+
+    pub fn reflectance(&self, lambda: Float, cos_theta: Float) -> Float {
+        let (eta, k) = self.eta_k(lambda);
+        fresnel_conductor(cos_theta, eta, k)
+    }
+}
+
+// Fresnel reflectance of an air to conductor interface, averaged over both
+// polarizations
+pub fn fresnel_conductor(cos_theta: Float, eta: Float, k: Float) -> Float {
+    let cos2 = cos_theta.clamp(0., 1.).powi(2);
+    let sin2 = 1. - cos2;
+    let (eta2, k2) = (eta * eta, k * k);
+    let t0 = eta2 - k2 - sin2;
+    let a2_plus_b2 = (t0 * t0 + 4. * eta2 * k2).sqrt();
+    let t1 = a2_plus_b2 + cos2;
+    let a = (0.5 * (a2_plus_b2 + t0)).max(0.).sqrt();
+    let t2 = 2. * cos_theta.clamp(0., 1.) * a;
+    let rs = (t1 - t2) / (t1 + t2);
+    let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+    0.5 * (rp + rs)
+}
+
+// Perfectly smooth metal. Its RGB color is the reflectance at normal
+// incidence under daylight, spectral renders get the Fresnel reflectance of
+// each wavelength at the angle of the ray.
+#[derive(Copy, Clone, Debug)]
+pub struct ConductorMaterial {
+    pub metal: Metal,
+    pub color: Spectrum,
+}
+
+impl ConductorMaterial {
+    pub fn new(metal: Metal) -> Self {
+        let white = Illuminant::D65.spectrum();
+        let xyz = spectrum_to_xyz(|lambda| metal.reflectance(lambda, 1.) * white.value(lambda));
+        ConductorMaterial {
+            metal,
+            color: Spectrum::ColorRGB(Vec3::component_max(xyz_to_linear_srgb(xyz), Vec3::zero())),
+        }
+    }
+}
+
+impl Material for ConductorMaterial {
+    fn value(&self) -> Spectrum {
+        self.color
+    }
+
+    fn sampled_value(&self, wavelengths: &SampledWavelengths, ray: &Ray, interaction: &SurfaceInteraction) -> Spectrum {
+        let cos_theta = Vec3::dot(ray.direction.normalize(), interaction.hit_normal).abs();
+        wavelengths.sample(|lambda| self.metal.reflectance(lambda, cos_theta))
+    }
+
+    fn lobe(&self) -> ScatterLobe {
+        ScatterLobe::Specular
+    }
+
+    fn scatter(
+        &self,
+        ray: &Ray,
+        _attenuation: &Spectrum,
+        interaction: &SurfaceInteraction,
+        _sampler: &mut Sampler,
+    ) -> MaterialScatterResult {
+        let mut result = MaterialScatterResult::default();
+        result.ray.origin = interaction.hit_point;
+        result.ray.direction = Vec3::reflect(ray.direction, interaction.hit_normal);
+        result.ray.differential = interaction.reflected_differential(ray, result.ray.direction);
+        result.attenuation = self.color;
+        result.success = true;
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conductor() {
+        // ((n - 1)^2 + k^2) / ((n + 1)^2 + k^2) at normal incidence, all of
+        // the light at grazing angles
+        let (n, k) = Metal::Gold.eta_k(650.);
+        let normal = ((n - 1.) * (n - 1.) + k * k) / ((n + 1.) * (n + 1.) + k * k);
+        assert!((Metal::Gold.reflectance(650., 1.) - normal).abs() < 1e-4);
+        assert!((Metal::Copper.reflectance(500., 0.) - 1.).abs() < 1e-4);
+        // A perfect dielectric at normal incidence
+        assert!((fresnel_conductor(1., 1.5, 0.) - 0.04).abs() < 1e-4);
+
+        // Gold is yellow, silver close to white
+        let gold = ConductorMaterial::new(Metal::Gold).value().to_rgb();
+        assert!(gold.x > gold.y && gold.y > gold.z);
+        let silver = ConductorMaterial::new(Metal::Silver).value().to_rgb();
+        assert!(silver.x > 0.85 && silver.z > 0.8);
+
+        assert_eq!(Metal::from_pbrt_name("metal-Cu-eta"), Some(Metal::Copper));
+        assert_eq!(Metal::from_pbrt_name("glass-BK7"), None);
+    }
+}
@@ -0,0 +1,116 @@
+use math::Float;
+use strum_macros::{Display, EnumIter, EnumString};
+
+// Wavelength of the helium d line, where catalogs give the index of glasses
+pub const LAMBDA_D: Float = 587.56;
+
+// Index of refraction as a function of the wavelength. Cauchy and Sellmeier
+// coefficients take wavelengths in micrometers, like the glass catalogs.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum IorModel {
+    Constant(Float),
+    // n = a + b / l^2
+    Cauchy { a: Float, b: Float },
+    // n^2 = 1 + sum of b l^2 / (l^2 - c)
+    Sellmeier { b: [Float; 3], c: [Float; 3] },
+}
+
+impl Default for IorModel {
+    fn default() -> Self {
+        IorModel::Constant(1.5)
+    }
+}
+
+impl IorModel {
+    pub fn eta(&self, lambda: Float) -> Float {
+        let l2 = (lambda * 1e-3) * (lambda * 1e-3);
+        match self {
+            IorModel::Constant(eta) => *eta,
+            IorModel::Cauchy { a, b } => a + b / l2,
+            IorModel::Sellmeier { b, c } => {
+                let sum: Float = (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum();
+                (1. + sum).sqrt()
+            }
+        }
+    }
+
+    // Light of different wavelengths refracts in different directions
+    pub fn is_dispersive(&self) -> bool {
+        !matches!(self, IorModel::Constant(_))
+    }
+}
+
+// Glasses and crystals with published Sellmeier coefficients
+#[derive(Debug, EnumIter, EnumString, PartialEq, Eq, Clone, Copy, Display, Hash)]
+#[strum(ascii_case_insensitive)]
+pub enum Glass {
+    // Schott N-BK7, the common crown glass
+    BK7,
+    // Malitson 1965
+    FusedSilica,
+    // Schott N-SF11, a dense flint with a strong dispersion
+    SF11,
+    // Peter 1923
+    Diamond,
+}
+
+impl Glass {
+    pub fn ior(&self) -> IorModel {
+        // Coefficients as the catalogues give them
+        let (b, c): ([f64; 3], [f64; 3]) = match self {
+            Glass::BK7 => ([1.03961212, 0.231792344, 1.01046945], [0.00600069867, 0.0200179144, 103.560653]),
+            Glass::FusedSilica => ([0.6961663, 0.4079426, 0.8974794], [0.00467914826, 0.0135120631, 97.9340025]),
+            Glass::SF11 => ([1.73759695, 0.313747346, 1.89878101], [0.013188707, 0.0623068142, 155.23629]),
+            Glass::Diamond => ([0.3306, 4.3356, 0.], [0.030625, 0.011236, 0.]),
+        };
+        IorModel::Sellmeier {
+            b: b.map(|b| b as Float),
+            c: c.map(|c| c as Float),
+        }
+    }
+
+    // Glass of a pbrt-v4 named spectrum like "glass-BK7", or a preset name
+    pub fn from_name(name: &str) -> Option<Glass> {
+        let name = name.strip_prefix("glass-").unwrap_or(name);
+        name.parse().ok()
+    }
+}
+
+// Fresnel reflectance of unpolarized light leaving a medium for one whose
+// index is `eta` times larger, 1 under total internal reflection
+pub fn fresnel_dielectric(cos_theta_i: Float, eta: Float) -> Float {
+    let cos_i = cos_theta_i.clamp(0., 1.);
+    let sin2_t = (1. - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1. {
+        return 1.;
+    }
+    let cos_t = (1. - sin2_t).sqrt();
+    let parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    0.5 * (parallel * parallel + perpendicular * perpendicular)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ior() {
+        // Catalog indices at the d line
+        for (glass, n_d) in [(Glass::BK7, 1.5168), (Glass::FusedSilica, 1.4585), (Glass::SF11, 1.7847), (Glass::Diamond, 2.4175)] {
+            assert!((glass.ior().eta(LAMBDA_D) - n_d).abs() < 2e-3, "{} {}", glass, glass.ior().eta(LAMBDA_D));
+        }
+        // Blue bends more than red
+        let cauchy = IorModel::Cauchy { a: 1.5046, b: 0.0042 };
+        assert!(cauchy.eta(450.) > cauchy.eta(650.));
+        assert!(Glass::Diamond.ior().eta(450.) > Glass::Diamond.ior().eta(650.));
+        assert!(cauchy.is_dispersive() && !IorModel::Constant(1.5).is_dispersive());
+
+        assert_eq!(Glass::from_name("glass-BK7"), Some(Glass::BK7));
+        assert_eq!(Glass::from_name("fusedsilica"), Some(Glass::FusedSilica));
+        assert_eq!(Glass::from_name("metal-Cu-eta"), None);
+
+        assert!((fresnel_dielectric(1., 1.5) - 0.04).abs() < 1e-4);
+        assert_eq!(fresnel_dielectric(0.1, 1. / 1.5), 1.);
+    }
+}
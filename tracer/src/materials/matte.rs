@@ -1,31 +1,125 @@
 use std::sync::Arc;
 
+use math::{Float, Vec3};
+
 use crate::core::interaction::SurfaceInteraction;
 use crate::core::ray::Ray;
+use crate::core::sampler::Sampler;
 use crate::core::spectrum::Spectrum;
-use crate::core::texture::Texture;
+use crate::core::texture::{FloatTexture, SpectrumTexture};
+use crate::materials::pdf::{Pdf, UniformPdf};
+use crate::materials::{Material, MaterialScatterResult};
+use crate::textures::constant::ConstantTexture;
 
+// Diffuse surface with its color and roughness given by textures. Rough
+// surfaces follow the Oren-Nayar model, they look flatter than Lambertian
+// ones and brighter toward the light.
 #[derive(Clone)]
 pub struct MatteMaterial {
-    kd: Arc<dyn Texture<Spectrum> + Sync + Send>,
-    sigma: Arc<dyn Texture<f64> + Sync + Send>,
+    pub kd: SpectrumTexture,
+    // Standard deviation of the angle of the microfacets, in degrees. 0 is
+    // Lambertian.
+    pub sigma: FloatTexture,
 }
 
 impl MatteMaterial {
-    pub fn scatter(_ray: &Ray, _surface: &SurfaceInteraction) {
+    pub fn new(kd: SpectrumTexture, sigma: FloatTexture) -> Self {
+        MatteMaterial { kd, sigma }
+    }
+
+    pub fn lambertian(kd: SpectrumTexture) -> Self {
+        MatteMaterial::new(kd, Arc::new(ConstantTexture::new(0.)))
+    }
+}
+
+// Ratio of the Oren-Nayar reflectance to the Lambertian one for light coming
+// from `wi` and leaving toward `wo`
+pub fn oren_nayar(sigma_degrees: Float, normal: Vec3, wo: Vec3, wi: Vec3) -> Float {
+    let sigma = sigma_degrees.to_radians();
+    if sigma <= 0. {
+        return 1.;
+    }
+    let sigma2 = sigma * sigma;
+    let a = 1. - sigma2 / (2. * (sigma2 + 0.33));
+    let b = 0.45 * sigma2 / (sigma2 + 0.09);
+
+    let (cos_i, cos_o) = (Vec3::dot(wi, normal).abs(), Vec3::dot(wo, normal).abs());
+    let (sin_i, sin_o) = ((1. - cos_i * cos_i).max(0.).sqrt(), (1. - cos_o * cos_o).max(0.).sqrt());
+    // Cosine of the azimuth between the two directions
+    let cos_phi = if sin_i > 1e-4 && sin_o > 1e-4 {
+        let tangent_i = (wi - normal * Vec3::dot(wi, normal)).normalize();
+        let tangent_o = (wo - normal * Vec3::dot(wo, normal)).normalize();
+        Vec3::dot(tangent_i, tangent_o).max(0.)
+    } else {
+        0.
+    };
+    // Sine of the larger angle to the normal, tangent of the smaller one
+    let (sin_alpha, tan_beta) = if cos_i > cos_o {
+        (sin_o, sin_i / cos_i)
+    } else {
+        (sin_i, sin_o / cos_o.max(1e-4))
+    };
+    a + b * cos_phi * sin_alpha * tan_beta
+}
+
+impl Material for MatteMaterial {
+    // Without a hit, the color at the origin of the texture
+    fn value(&self) -> Spectrum {
+        self.kd.evaluate(&SurfaceInteraction::new())
+    }
+
+    fn evaluate(&self, interaction: &SurfaceInteraction) -> Spectrum {
+        self.kd.evaluate(interaction)
+    }
+
+    // Cosine sampled like a Lambertian surface, the weight corrects for
+    // the roughness
+    fn scatter(
+        &self,
+        ray: &Ray,
+        _attenuation: &Spectrum,
+        interaction: &SurfaceInteraction,
+        sampler: &mut Sampler,
+    ) -> MaterialScatterResult {
+        let mut result = MaterialScatterResult::default();
+        let uniform_pdf = UniformPdf::new(&interaction.hit_normal);
+        result.ray.origin = interaction.hit_point;
+        result.ray.direction = uniform_pdf.sample_wi(sampler).normalize();
+        let wo = -ray.direction.normalize();
+        result.weight = oren_nayar(self.sigma.evaluate(interaction), interaction.hit_normal, wo, result.ray.direction);
+        result.attenuation = self.kd.evaluate(interaction) * result.weight;
+        result.success = true;
+        result
+    }
+}
 
-        // Scatter toward a random point inside a unit sphere tangent to the point of intersection.
-        // vec3 newTarget = intersect.P + intersect.N + Sampler::RandomSampleInUnitSphere();
-        // scatterRay = Ray(intersect.P, newTarget - intersect.P, ray.time);
-        // pdf = dot(intersect.N, scatterRay.direction) / M_PI;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::texture::TextureMapping;
+    use crate::textures::checkerboard::CheckerboardTexture;
 
-        // ONB uvw;
-        // uvw.BuildFromW(intersect.N);
-        // vec3 direction = uvw.Local(Sampler::RandomCosineDirection());
-        // scatterRay = Ray(intersect.P, direction, ray.time);
+    #[test]
+    fn test_matte() {
+        let normal = Vec3::new(0., 0., 1.);
+        let grazing = Vec3::new(0.8, 0., 0.6);
+        let back = Vec3::new(-0.8, 0., 0.6);
+        assert_eq!(oren_nayar(0., normal, grazing, normal), 1.);
+        // Rough surfaces send more light back toward the light than to the
+        // other side, and less straight up
+        assert!(oren_nayar(20., normal, grazing, grazing) > oren_nayar(20., normal, back, grazing));
+        assert!(oren_nayar(20., normal, normal, normal) < 1.);
 
-        // let uvw: super::ONB = ;
-        // uvw.build_from(&surface.hit_normal);
-        // let direction = uvw.local(&Sampler::sample_cosine_direction());
+        // The color comes from the texture at the hit
+        let checker: CheckerboardTexture<Spectrum> = CheckerboardTexture::new(
+            TextureMapping::default(),
+            Spectrum::ColorRGB(Vec3::from(1.)),
+            Spectrum::ColorRGB(Vec3::from(0.)),
+        );
+        let matte = MatteMaterial::lambertian(Arc::new(checker));
+        let mut si = SurfaceInteraction::new();
+        si.hit_uv = math::Vec2::new(&[1.5, 0.5]);
+        assert_eq!(matte.evaluate(&si), Spectrum::ColorRGB(Vec3::from(0.)));
+        assert_eq!(matte.value(), Spectrum::ColorRGB(Vec3::from(1.)));
     }
 }
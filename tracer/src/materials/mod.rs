@@ -1,3 +1,6 @@
+pub mod bump;
+pub mod conductor;
+pub mod ior;
 pub mod matte;
 pub mod pdf;
 
@@ -6,16 +9,21 @@ use math::Float;
 use math::Vec3;
 use math::Vector3;
 
+use crate::materials::ior::{fresnel_dielectric, IorModel, LAMBDA_D};
 use crate::materials::{pdf::Pdf, pdf::UniformPdf};
 
 use crate::core::{
-    interaction::SurfaceInteraction, ray::Ray, sampler::Sampler, spectrum::Spectrum,
+    interaction::SurfaceInteraction, ray::Ray, sampler::Sampler, spectral::SampledWavelengths,
+    spectrum::Spectrum,
 };
 
 pub struct MaterialScatterResult
 {
     pub ray: Ray,
     pub attenuation: Spectrum,
+    // Grey factor on the color for the sampled direction, for materials
+    // that reflect more than a cosine lobe in some directions
+    pub weight: Float,
     pub success: bool
 }
 
@@ -24,18 +32,51 @@ impl Default for MaterialScatterResult {
         Self {
             ray: Ray::default(),
             attenuation: Spectrum::ColorRGB(Vector3::new(0.0, 0.0, 0.0)),
+            weight: 1.,
             success: true
         }
     }
 }
 
+// Kind of scattering a material does, used to split the light AOVs
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ScatterLobe {
+    Diffuse,
+    Specular,
+}
+
 pub trait Material: Send + Sync {
     fn value(&self) -> Spectrum;
+    // Color at a hit, textured materials vary it over the surface
+    fn evaluate(&self, _interaction: &SurfaceInteraction) -> Spectrum {
+        self.value()
+    }
+    // Reflectance at the wavelengths of a spectral path, the color upsampled
+    // unless the material knows better
+    fn sampled_value(&self, wavelengths: &SampledWavelengths, _ray: &Ray, interaction: &SurfaceInteraction) -> Spectrum {
+        wavelengths.reflectance(self.evaluate(interaction))
+    }
+    // Scatter a spectral path. Materials that send each wavelength its own
+    // way keep only the hero one.
+    fn sampled_scatter(
+        &self,
+        _wavelengths: &mut SampledWavelengths,
+        ray: &Ray,
+        attenuation: &Spectrum,
+        interaction: &SurfaceInteraction,
+        sampler: &mut Sampler,
+    ) -> MaterialScatterResult {
+        self.scatter(ray, attenuation, interaction, sampler)
+    }
+    fn lobe(&self) -> ScatterLobe {
+        ScatterLobe::Diffuse
+    }
     fn scatter(
         &self,
         ray: &Ray,
         attenuation: &Spectrum,
         interaction: &SurfaceInteraction,
+        sampler: &mut Sampler,
     ) -> MaterialScatterResult;
 }
 
@@ -71,11 +112,12 @@ impl Material for ConstantMaterial {
         ray: &Ray,
         _attenuation: &Spectrum,
         interaction: &SurfaceInteraction,
+        sampler: &mut Sampler,
     ) -> MaterialScatterResult {
         let mut result = MaterialScatterResult::default();
         let uniform_pdf = UniformPdf::new(&interaction.hit_normal);
         result.ray.origin = ray.origin;
-        result.ray.direction = uniform_pdf.sample_wi();
+        result.ray.direction = uniform_pdf.sample_wi(sampler);
         result.attenuation = self.color;
         result.success = true;
         return result;
@@ -98,15 +140,21 @@ impl Material for MetalMaterial {
         self.color
     }
 
+    fn lobe(&self) -> ScatterLobe {
+        ScatterLobe::Specular
+    }
+
     fn scatter(
         &self,
         ray: &Ray,
         _attenuation: &Spectrum,
-        interaction: &SurfaceInteraction
+        interaction: &SurfaceInteraction,
+        _sampler: &mut Sampler,
     ) -> MaterialScatterResult {
         let mut result = MaterialScatterResult::default();
         result.ray.origin = interaction.hit_point.clone();
         result.ray.direction = Vec3::reflect(ray.direction, interaction.hit_normal.clone());
+        result.ray.differential = interaction.reflected_differential(ray, result.ray.direction);
         result.attenuation = self.color;
         result.success = true;
         return result;
@@ -137,12 +185,13 @@ impl Material for LambertMaterial {
         &self,
         ray: &Ray,
         _attenuation: &Spectrum,
-        interaction: &SurfaceInteraction
+        interaction: &SurfaceInteraction,
+        sampler: &mut Sampler,
     ) -> MaterialScatterResult {
         let mut result = MaterialScatterResult::default();
         let uniform_pdf = UniformPdf::new(&interaction.hit_normal);
         result.ray.origin = ray.origin;
-        result.ray.direction = uniform_pdf.sample_wi();
+        result.ray.direction = uniform_pdf.sample_wi(sampler);
         result.attenuation = self.color;
         result.success = true;
         return result;
@@ -151,7 +200,7 @@ impl Material for LambertMaterial {
 
 #[derive(Clone, Debug)]
 pub struct DieletricMaterial {
-    pub eta: Float,
+    pub ior: IorModel,
     pub color: Spectrum,
     pub base_color_texture: Option<image::DynamicImage>,
 }
@@ -159,11 +208,42 @@ pub struct DieletricMaterial {
 impl DieletricMaterial {
     pub fn new(color: Spectrum) -> Self {
         DieletricMaterial {
-            eta: 1.5,
+            ior: IorModel::default(),
             color: color,
             base_color_texture: None,
         }
     }
+
+    pub fn with_ior(mut self, ior: IorModel) -> Self {
+        self.ior = ior;
+        self
+    }
+
+    // Reflect or refract, picked by the Fresnel reflectance so the weight
+    // stays the color
+    fn scatter_with_eta(&self, ray: &Ray, interaction: &SurfaceInteraction, sampler: &mut Sampler, eta: Float) -> MaterialScatterResult {
+        let mut result = MaterialScatterResult::default();
+        result.ray.origin = interaction.hit_point;
+
+        let direction = ray.direction.normalize();
+        let entering = Vec3::dot(direction, interaction.hit_normal) < 0.;
+        let (normal, eta) = if entering {
+            (interaction.hit_normal, eta)
+        } else {
+            (-interaction.hit_normal, 1. / eta)
+        };
+        let cos_i = Vec3::dot(-direction, normal).min(1.);
+        if sampler.random_0_1() < fresnel_dielectric(cos_i, eta) {
+            result.ray.direction = Vec3::reflect(direction, normal);
+            result.ray.differential = interaction.reflected_differential(ray, result.ray.direction);
+        } else {
+            result.ray.direction = Vec3::refract(direction, normal, 1. / eta);
+            result.ray.differential = interaction.refracted_differential(ray, result.ray.direction, eta);
+        }
+        result.attenuation = self.color;
+        result.success = true;
+        result
+    }
 }
 
 impl Material for DieletricMaterial {
@@ -171,31 +251,32 @@ impl Material for DieletricMaterial {
         self.color
     }
 
+    fn lobe(&self) -> ScatterLobe {
+        ScatterLobe::Specular
+    }
+
+    // RGB renders refract every channel at the index of the d line
     fn scatter(
         &self,
         ray: &Ray,
-        attenuation: &Spectrum,
-        interaction: &SurfaceInteraction
+        _attenuation: &Spectrum,
+        interaction: &SurfaceInteraction,
+        sampler: &mut Sampler,
     ) -> MaterialScatterResult {
-        let mut result = MaterialScatterResult::default();
-        result.ray.origin = interaction.hit_point.clone() + Sampler::sample_from_unit_sphere() * 0.1;
+        self.scatter_with_eta(ray, interaction, sampler, self.ior.eta(LAMBDA_D))
+    }
 
-        let ir = 1.5;
-        let refraction_ratio = if interaction.hit_front_face {
-            1.0 / ir
-        } else {
-            ir
-        };
-        let normal = if interaction.hit_front_face {
-            interaction.hit_normal.clone()
-        } else {
-            -interaction.hit_normal.clone()
-        };
-        // result.ray.direction = Vec3::refract(ray.direction, normal, refraction_ratio);
-        result.ray.direction = ray.direction + Sampler::sample_from_unit_sphere() * 0.1;
-        result.ray.direction = result.ray.direction.normalize();
-        //result.attenuation = Spectrum::ColorRGB(Vec3::from(1.0)) * *attenuation;
-        result.success = true;
-        return result;
+    fn sampled_scatter(
+        &self,
+        wavelengths: &mut SampledWavelengths,
+        ray: &Ray,
+        _attenuation: &Spectrum,
+        interaction: &SurfaceInteraction,
+        sampler: &mut Sampler,
+    ) -> MaterialScatterResult {
+        if self.ior.is_dispersive() {
+            wavelengths.terminate_secondary();
+        }
+        self.scatter_with_eta(ray, interaction, sampler, self.ior.eta(wavelengths.lambda[0]))
     }
 }
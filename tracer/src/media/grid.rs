@@ -0,0 +1,396 @@
+use math::{Float, Vec3};
+
+use crate::core::bounds::Bounds3f;
+use crate::core::ray::Ray;
+use crate::core::sampler::Sampler;
+use crate::core::spectral::{blackbody_normalized, spectrum_to_xyz, xyz_to_linear_srgb};
+use crate::core::spectrum::Spectrum;
+use crate::media::{HenyeyGreenstein, Medium, MediumSample};
+use crate::shapes::analytic::Placement;
+
+// Dense grid of values at the voxel centers, x varying fastest
+#[derive(Clone, PartialEq, Debug)]
+pub struct VoxelGrid {
+    pub resolution: [usize; 3],
+    pub values: Vec<Float>,
+}
+
+impl VoxelGrid {
+    pub fn new(resolution: [usize; 3], values: Vec<Float>) -> Result<Self, String> {
+        let count = resolution[0] * resolution[1] * resolution[2];
+        if count == 0 || values.len() != count {
+            return Err(format!(
+                "a {}x{}x{} grid needs {} values, got {}",
+                resolution[0],
+                resolution[1],
+                resolution[2],
+                count,
+                values.len()
+            ));
+        }
+        Ok(Self { resolution, values })
+    }
+
+    // Voxels past the edges are 0
+    pub fn voxel(&self, x: i64, y: i64, z: i64) -> Float {
+        let [nx, ny, nz] = self.resolution.map(|n| n as i64);
+        if x < 0 || y < 0 || z < 0 || x >= nx || y >= ny || z >= nz {
+            return 0.;
+        }
+        self.values[(x + nx * (y + ny * z)) as usize]
+    }
+
+    // Trilinear interpolation at `p` in [0, 1]^3
+    pub fn lookup(&self, p: Vec3) -> Float {
+        let mut base = [0_i64; 3];
+        let mut frac = [0. as Float; 3];
+        for axis in 0..3 {
+            let g = p[axis] * self.resolution[axis] as Float - 0.5;
+            base[axis] = g.floor() as i64;
+            frac[axis] = g - g.floor();
+        }
+        let mut value = 0.;
+        for corner in 0..8 {
+            let offset = [corner & 1, (corner >> 1) & 1, (corner >> 2) & 1];
+            let weight: Float = (0..3)
+                .map(|axis| if offset[axis] == 1 { frac[axis] } else { 1. - frac[axis] })
+                .product();
+            if weight > 0. {
+                value += weight * self.voxel(base[0] + offset[0], base[1] + offset[1], base[2] + offset[2]);
+            }
+        }
+        value
+    }
+
+    // Largest value the lookup can return in the box from `lo` to `hi`, in
+    // [0, 1]^3
+    pub fn max_value(&self, lo: Vec3, hi: Vec3) -> Float {
+        let range = |axis: usize| {
+            let n = self.resolution[axis] as Float;
+            let first = ((lo[axis] * n - 0.5).floor() as i64).max(0);
+            let last = ((hi[axis] * n - 0.5).floor() as i64 + 1).min(self.resolution[axis] as i64 - 1);
+            first..=last
+        };
+        let mut max: Float = 0.;
+        for z in range(2) {
+            for y in range(1) {
+                for x in range(0) {
+                    max = max.max(self.voxel(x, y, z));
+                }
+            }
+        }
+        max
+    }
+}
+
+// Coarse grid bounding the density from above, so free flights take long
+// steps through the thin parts of the volume
+#[derive(Clone, PartialEq, Debug)]
+pub struct MajorantGrid {
+    pub resolution: [usize; 3],
+    pub values: Vec<Float>,
+}
+
+impl MajorantGrid {
+    pub fn new(density: &VoxelGrid, resolution: [usize; 3]) -> Self {
+        let [nx, ny, nz] = resolution;
+        let mut values = Vec::with_capacity(nx * ny * nz);
+        for z in 0..nz {
+            for y in 0..ny {
+                for x in 0..nx {
+                    let corner = |i: [usize; 3]| {
+                        Vec3::new(
+                            i[0] as Float / nx as Float,
+                            i[1] as Float / ny as Float,
+                            i[2] as Float / nz as Float,
+                        )
+                    };
+                    values.push(density.max_value(corner([x, y, z]), corner([x + 1, y + 1, z + 1])));
+                }
+            }
+        }
+        Self { resolution, values }
+    }
+
+    pub fn value(&self, cell: [usize; 3]) -> Float {
+        let [nx, ny, _] = self.resolution;
+        self.values[cell[0] + nx * (cell[1] + ny * cell[2])]
+    }
+}
+
+// Medium whose density varies over a voxel grid filling `bounds` in its own
+// space, like clouds or simulated smoke. A temperature grid makes it glow
+// like a black body where it is hot enough.
+#[derive(Clone, PartialEq, Debug)]
+pub struct GridMedium {
+    pub bounds: Bounds3f,
+    pub placement: Placement,
+    pub density: VoxelGrid,
+    pub temperature: Option<VoxelGrid>,
+    // Coefficients where the density is 1
+    pub sigma_a: Spectrum,
+    pub sigma_s: Spectrum,
+    pub phase: HenyeyGreenstein,
+    // Kelvins are (temperature - cutoff) * scale
+    pub temperature_cutoff: Float,
+    pub temperature_scale: Float,
+    pub emission_scale: Float,
+    majorants: MajorantGrid,
+    // Largest extinction coefficient of the three channels
+    max_sigma_t: Float,
+}
+
+impl GridMedium {
+    pub fn new(
+        bounds: Bounds3f,
+        placement: Placement,
+        density: VoxelGrid,
+        sigma_a: Spectrum,
+        sigma_s: Spectrum,
+        g: Float,
+    ) -> Self {
+        let sigma_t = sigma_a.to_rgb() + sigma_s.to_rgb();
+        Self {
+            bounds,
+            placement,
+            majorants: MajorantGrid::new(&density, [16; 3]),
+            density,
+            temperature: None,
+            sigma_a,
+            sigma_s,
+            phase: HenyeyGreenstein::new(g),
+            temperature_cutoff: 0.,
+            temperature_scale: 1.,
+            emission_scale: 1.,
+            max_sigma_t: sigma_t.x.max(sigma_t.y).max(sigma_t.z).max(0.),
+        }
+    }
+
+    pub fn with_temperature(self, temperature: VoxelGrid, cutoff: Float, scale: Float, emission_scale: Float) -> Self {
+        Self {
+            temperature: Some(temperature),
+            temperature_cutoff: cutoff,
+            temperature_scale: scale,
+            emission_scale,
+            ..self
+        }
+    }
+
+    // Position in the grid, [0, 1]^3 inside the bounds
+    fn grid_point(&self, p: Vec3) -> Vec3 {
+        let diagonal = self.bounds.diagonal();
+        let offset = p - self.bounds.p_min;
+        Vec3::new(offset.x / diagonal.x, offset.y / diagonal.y, offset.z / diagonal.z)
+    }
+
+    // Radiance emitted at a point of the grid
+    fn emission(&self, grid_point: Vec3) -> Vec3 {
+        let Some(temperature) = &self.temperature else {
+            return Vec3::zero();
+        };
+        let kelvin = (temperature.lookup(grid_point) - self.temperature_cutoff) * self.temperature_scale;
+        // Too cold to see
+        if kelvin <= 100. {
+            return Vec3::zero();
+        }
+        self.emission_scale * blackbody_rgb(kelvin)
+    }
+
+    // Walk the majorant cells along the object space ray, from its origin to
+    // `t_max`, stopping at collisions sampled from their majorants. `visit`
+    // gets the distance along the ray, the point in the grid and the
+    // majorant, it returns false to stop there.
+    fn march<F>(&self, ray: &Ray, t_max: Float, sampler: &mut Sampler, mut visit: F)
+    where
+        F: FnMut(Float, Vec3, Float, &mut Sampler) -> bool,
+    {
+        let object_ray = self.placement.ray_to_object(ray);
+        let speed = object_ray.direction.length();
+        if speed == 0. || self.max_sigma_t == 0. {
+            return;
+        }
+        let Some((t_enter, t_exit)) = self.bounds.intersect_range(&object_ray, t_max) else {
+            return;
+        };
+
+        // Grid coordinates in majorant cells, and the ray through them
+        let resolution = self.majorants.resolution;
+        let to_cells = |p: Vec3| {
+            let g = self.grid_point(p);
+            Vec3::new(
+                g.x * resolution[0] as Float,
+                g.y * resolution[1] as Float,
+                g.z * resolution[2] as Float,
+            )
+        };
+        let origin = to_cells(object_ray.origin);
+        let direction = to_cells(object_ray.origin + object_ray.direction) - origin;
+        let start = origin + t_enter * direction;
+
+        let mut cell = [0_i64; 3];
+        let mut next_crossing = [Float::INFINITY; 3];
+        let mut delta = [Float::INFINITY; 3];
+        let mut step = [0_i64; 3];
+        for axis in 0..3 {
+            cell[axis] = (start[axis].floor() as i64).clamp(0, resolution[axis] as i64 - 1);
+            if direction[axis] > 0. {
+                step[axis] = 1;
+                delta[axis] = 1. / direction[axis];
+                next_crossing[axis] = t_enter + (cell[axis] as Float + 1. - start[axis]) / direction[axis];
+            } else if direction[axis] < 0. {
+                step[axis] = -1;
+                delta[axis] = -1. / direction[axis];
+                next_crossing[axis] = t_enter + (cell[axis] as Float - start[axis]) / direction[axis];
+            }
+        }
+
+        let mut t = t_enter;
+        loop {
+            let axis = (0..3)
+                .min_by(|&a, &b| next_crossing[a].total_cmp(&next_crossing[b]))
+                .unwrap_or(0);
+            let t_cell_exit = next_crossing[axis].min(t_exit);
+            let majorant = self.max_sigma_t * self.majorants.value(cell.map(|i| i as usize));
+            if majorant > 0. {
+                loop {
+                    let t_collision = t - (1. - sampler.random_0_1()).ln() / (majorant * speed);
+                    if t_collision >= t_cell_exit {
+                        break;
+                    }
+                    t = t_collision;
+                    if !visit(t, self.grid_point(object_ray.point_at(t)), majorant, sampler) {
+                        return;
+                    }
+                }
+            }
+            t = t_cell_exit;
+            if t >= t_exit {
+                return;
+            }
+            cell[axis] += step[axis];
+            if cell[axis] < 0 || cell[axis] >= resolution[axis] as i64 {
+                return;
+            }
+            next_crossing[axis] += delta[axis];
+        }
+    }
+}
+
+impl Medium for GridMedium {
+    // Delta tracking. Collisions with the majorant absorb, scatter or are
+    // null with the probabilities of the averaged coefficients, the weight
+    // makes up for the difference per channel.
+    fn sample_distance(&self, ray: &Ray, t_max: Float, sampler: &mut Sampler) -> MediumSample {
+        let (sigma_a, sigma_s) = (self.sigma_a.to_rgb(), self.sigma_s.to_rgb());
+        let mean = |v: Vec3| (v.x + v.y + v.z) / 3.;
+        let mut weight = Vec3::from(1.);
+        let mut emission = Vec3::zero();
+        let mut scatter_t = None;
+        self.march(ray, t_max, sampler, |t, p, majorant, sampler| {
+            let density = self.density.lookup(p);
+            let (absorption, scattering) = (density * sigma_a, density * sigma_s);
+            let null = Vec3::from(majorant) - absorption - scattering;
+            if self.temperature.is_some() {
+                emission += weight * absorption * self.emission(p) / majorant;
+            }
+
+            let u = sampler.random_0_1() * majorant;
+            if u < mean(absorption) {
+                weight = Vec3::zero();
+                return false;
+            }
+            if u < mean(absorption) + mean(scattering) {
+                weight = weight * scattering / mean(scattering);
+                scatter_t = Some(t);
+                return false;
+            }
+            let p_null = mean(null);
+            if p_null <= 0. {
+                weight = Vec3::zero();
+                return false;
+            }
+            weight = weight * null / p_null;
+            true
+        });
+        MediumSample {
+            scatter_t,
+            weight: Spectrum::ColorRGB(weight),
+            emission: Spectrum::ColorRGB(emission),
+        }
+    }
+
+    // Ratio tracking, every collision keeps the fraction of null density
+    fn transmittance(&self, ray: &Ray, t_max: Float, sampler: &mut Sampler) -> Spectrum {
+        let sigma_t = self.sigma_a.to_rgb() + self.sigma_s.to_rgb();
+        let mut transmittance = Vec3::from(1.);
+        self.march(ray, t_max, sampler, |_, p, majorant, _| {
+            let null = Vec3::from(majorant) - self.density.lookup(p) * sigma_t;
+            transmittance = transmittance * null / majorant;
+            // Nothing gets through past this point
+            transmittance.x.max(transmittance.y).max(transmittance.z) > 0.
+        });
+        Spectrum::ColorRGB(transmittance)
+    }
+
+    fn phase(&self) -> HenyeyGreenstein {
+        self.phase
+    }
+}
+
+// Linear sRGB color of a black body at `kelvin`, normalized so the peak of
+// its spectrum is 1 like pbrt's
+pub fn blackbody_rgb(kelvin: Float) -> Vec3 {
+    let xyz = spectrum_to_xyz(|lambda| blackbody_normalized(lambda, kelvin));
+    Vec3::component_max(xyz_to_linear_srgb(xyz), Vec3::zero())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_grid_medium() {
+        // Trilinear between voxel centers, 0 past the outer ones
+        let ramp = VoxelGrid::new([2, 1, 1], vec![0., 1.]).unwrap();
+        assert!((ramp.lookup(Vec3::new(0.5, 0.5, 0.5)) - 0.5).abs() < 1e-6);
+        assert!((ramp.lookup(Vec3::new(0.75, 0.5, 0.5)) - 1.).abs() < 1e-6);
+        assert_eq!(ramp.max_value(Vec3::zero(), Vec3::new(0.2, 1., 1.)), 0.);
+        assert_eq!(ramp.max_value(Vec3::zero(), Vec3::new(0.4, 1., 1.)), 1.);
+
+        // A constant grid matches the homogeneous medium between the outer
+        // voxel centers
+        let bounds = Bounds3f {
+            p_min: Vec3::from(-1.),
+            p_max: Vec3::from(1.),
+        };
+        let medium = GridMedium::new(
+            bounds,
+            Placement::default(),
+            VoxelGrid::new([4, 4, 4], vec![1.; 64]).unwrap(),
+            Spectrum::ColorRGB(Vec3::new(0., 0.5, 1.)),
+            Spectrum::ColorRGB(Vec3::from(0.5)),
+            0.,
+        );
+        let ray = Ray::new(Vec3::new(0., 0., -0.5), Vec3::new(0., 0., 1.));
+        let expected = Vec3::new((-0.5 as Float).exp(), (-1. as Float).exp(), (-1.5 as Float).exp());
+        let mut sampler = Sampler::new(5);
+        let count = 20000;
+        let (mut transmittance, mut through) = (Vec3::zero(), Vec3::zero());
+        for _ in 0..count {
+            transmittance += medium.transmittance(&ray, 1., &mut sampler).to_rgb() / count as Float;
+            let sample = medium.sample_distance(&ray, 1., &mut sampler);
+            if let Some(t) = sample.scatter_t {
+                assert!(t < 1.);
+            } else {
+                through += sample.weight.to_rgb() / count as Float;
+            }
+        }
+        assert!((transmittance - expected).length() < 0.02);
+        assert!((through - expected).length() < 0.03);
+
+        // Hot spots glow, hotter is bluer
+        let warm = blackbody_rgb(2000.);
+        let hot = blackbody_rgb(9000.);
+        assert!(warm.x > warm.z && hot.z > hot.x);
+    }
+}
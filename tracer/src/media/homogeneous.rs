@@ -0,0 +1,118 @@
+use math::{Float, Vec3};
+
+use crate::core::ray::Ray;
+use crate::core::sampler::Sampler;
+use crate::core::spectrum::Spectrum;
+use crate::media::{beer_lambert, HenyeyGreenstein, Medium, MediumSample};
+
+// Medium with the same absorption and scattering everywhere, like fog or
+// tinted glass. Coefficients are per unit of distance.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct HomogeneousMedium {
+    pub sigma_a: Spectrum,
+    pub sigma_s: Spectrum,
+    pub phase: HenyeyGreenstein,
+}
+
+impl HomogeneousMedium {
+    pub fn new(sigma_a: Spectrum, sigma_s: Spectrum, g: Float) -> Self {
+        Self {
+            sigma_a,
+            sigma_s,
+            phase: HenyeyGreenstein::new(g),
+        }
+    }
+
+    fn sigma_t(&self) -> Vec3 {
+        self.sigma_a.to_rgb() + self.sigma_s.to_rgb()
+    }
+}
+
+impl Medium for HomogeneousMedium {
+    // Distances are picked for one of the channels at random, the weight
+    // divides by the density averaged over the three
+    fn sample_distance(&self, ray: &Ray, t_max: Float, sampler: &mut Sampler) -> MediumSample {
+        let sigma_t = self.sigma_t();
+        let speed = ray.direction.length();
+        let channel = ((sampler.random_0_1() * 3.) as usize).min(2);
+        let distance = if sigma_t[channel] > 0. {
+            -(1. - sampler.random_0_1()).ln() / sigma_t[channel]
+        } else {
+            Float::INFINITY
+        };
+
+        let mean = |v: Vec3| (v.x + v.y + v.z) / 3.;
+        if distance < t_max * speed {
+            let transmittance = beer_lambert(sigma_t, distance);
+            let density = mean(sigma_t * transmittance);
+            let weight = if density > 0. { transmittance * self.sigma_s.to_rgb() / density } else { Vec3::zero() };
+            MediumSample {
+                scatter_t: Some(distance / speed),
+                weight: Spectrum::ColorRGB(weight),
+                emission: Spectrum::ColorRGB(Vec3::zero()),
+            }
+        } else {
+            let transmittance = beer_lambert(sigma_t, t_max * speed);
+            let probability = mean(transmittance);
+            let weight = if probability > 0. { transmittance / probability } else { Vec3::zero() };
+            MediumSample {
+                scatter_t: None,
+                weight: Spectrum::ColorRGB(weight),
+                emission: Spectrum::ColorRGB(Vec3::zero()),
+            }
+        }
+    }
+
+    fn transmittance(&self, ray: &Ray, t_max: Float, _sampler: &mut Sampler) -> Spectrum {
+        Spectrum::ColorRGB(beer_lambert(self.sigma_t(), t_max * ray.direction.length()))
+    }
+
+    fn phase(&self) -> HenyeyGreenstein {
+        self.phase
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_homogeneous_medium() {
+        // Red goes through, blue is absorbed
+        let medium = HomogeneousMedium::new(
+            Spectrum::ColorRGB(Vec3::new(0., 0.5, 1.)),
+            Spectrum::ColorRGB(Vec3::from(0.5)),
+            0.,
+        );
+        let ray = Ray::new(Vec3::zero(), Vec3::new(1., 0., 0.));
+        let mut sampler = Sampler::new(3);
+        let transmittance = medium.transmittance(&ray, 2., &mut sampler).to_rgb();
+        let expected = Vec3::new((-1. as Float).exp(), (-2. as Float).exp(), (-3. as Float).exp());
+        assert!((transmittance - expected).length() < 1e-5);
+
+        // Free flights are unbiased: the weights of the rays getting through
+        // average to the transmittance, and the scattered ones to the
+        // scattering along the way
+        let count = 20000;
+        let (mut through, mut scattered) = (Vec3::zero(), Vec3::zero());
+        for _ in 0..count {
+            let sample = medium.sample_distance(&ray, 2., &mut sampler);
+            match sample.scatter_t {
+                Some(t) => {
+                    assert!(t < 2.);
+                    scattered += sample.weight.to_rgb() / count as Float;
+                }
+                None => through += sample.weight.to_rgb() / count as Float,
+            }
+        }
+        assert!((through - expected).length() < 0.02);
+        // Integral of sigma_s exp(-sigma_t t) over [0, 2]
+        let sigma_t = Vec3::new(0.5, 1., 1.5);
+        let expected_scattered = Vec3::new(
+            0.5 / sigma_t.x * (1. - expected.x),
+            0.5 / sigma_t.y * (1. - expected.y),
+            0.5 / sigma_t.z * (1. - expected.z),
+        );
+        assert!((scattered - expected_scattered).length() < 0.02);
+    }
+}
@@ -0,0 +1,150 @@
+pub mod grid;
+pub mod homogeneous;
+
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use math::{Float, Vec2, Vec3};
+use std::f32::consts::PI;
+
+use crate::core::geometry::ONB;
+use crate::core::ray::Ray;
+use crate::core::sampler::Sampler;
+use crate::core::spectrum::Spectrum;
+
+// What happened to a ray travelling through a medium
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct MediumSample {
+    // Where it scattered, None if it made it through
+    pub scatter_t: Option<Float>,
+    // Multiplies the path throughput, transmittance over the pdf of the
+    // event, times the scattering coefficient when it scattered
+    pub weight: Spectrum,
+    // Light the medium emitted towards the origin of the ray along the way,
+    // it adds to the path's radiance times the throughput before the sample
+    pub emission: Spectrum,
+}
+
+// Participating medium filling the inside or the outside of shapes, or the
+// space around the camera
+pub trait Medium: Send + Sync + Debug {
+    // Free flight along `ray` up to `t_max`
+    fn sample_distance(&self, ray: &Ray, t_max: Float, sampler: &mut Sampler) -> MediumSample;
+    // Fraction of the light getting through along `ray` up to `t_max`
+    fn transmittance(&self, ray: &Ray, t_max: Float, sampler: &mut Sampler) -> Spectrum;
+    fn phase(&self) -> HenyeyGreenstein;
+}
+
+// Media on both sides of a surface. Shapes with an interface and no material
+// are only a boundary rays go through.
+#[derive(Clone, Debug, Default)]
+pub struct MediumInterface {
+    pub inside: Option<Arc<dyn Medium>>,
+    pub outside: Option<Arc<dyn Medium>>,
+}
+
+impl MediumInterface {
+    pub fn new(inside: Option<Arc<dyn Medium>>, outside: Option<Arc<dyn Medium>>) -> Self {
+        Self { inside, outside }
+    }
+
+    // Medium on the other side from where a ray came
+    pub fn entered(&self, from_outside: bool) -> Option<Arc<dyn Medium>> {
+        if from_outside {
+            self.inside.clone()
+        } else {
+            self.outside.clone()
+        }
+    }
+}
+
+// The same media, not media that look the same
+impl PartialEq for MediumInterface {
+    fn eq(&self, other: &Self) -> bool {
+        let same = |a: &Option<Arc<dyn Medium>>, b: &Option<Arc<dyn Medium>>| match (a, b) {
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            (None, None) => true,
+            _ => false,
+        };
+        same(&self.inside, &other.inside) && same(&self.outside, &other.outside)
+    }
+}
+
+// Phase function of the Henyey-Greenstein family. `g` is the mean cosine of
+// the scattering angle, positive values scatter forward.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct HenyeyGreenstein {
+    pub g: Float,
+}
+
+impl HenyeyGreenstein {
+    pub fn new(g: Float) -> Self {
+        Self { g: g.clamp(-0.99, 0.99) }
+    }
+
+    // Density of going on along `wi` for a ray travelling along `direction`
+    pub fn p(&self, direction: Vec3, wi: Vec3) -> Float {
+        let cos_theta = Vec3::dot(direction.normalize(), wi.normalize());
+        let g = self.g;
+        let denominator = 1. + g * g - 2. * g * cos_theta;
+        (1. - g * g) / (4. * PI * denominator * denominator.sqrt())
+    }
+
+    // New direction and its density
+    pub fn sample(&self, direction: Vec3, u: &Vec2) -> (Vec3, Float) {
+        let g = self.g;
+        let cos_theta = if g.abs() < 1e-3 {
+            1. - 2. * u.0
+        } else {
+            let s = (1. - g * g) / (1. - g + 2. * g * u.0);
+            (1. + g * g - s * s) / (2. * g)
+        }
+        .clamp(-1., 1.);
+        let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+        let phi = 2. * PI * u.1;
+        let local = Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+        let wi = ONB::from(&direction).from_local(&local).normalize();
+        (wi, self.p(direction, wi))
+    }
+}
+
+// exp(-sigma_t distance) per channel, channels that don't attenuate stay at 1
+// even over an infinite distance
+pub fn beer_lambert(sigma_t: Vec3, distance: Float) -> Vec3 {
+    let channel = |sigma: Float| if sigma == 0. { 1. } else { (-sigma * distance).exp() };
+    Vec3::new(channel(sigma_t.x), channel(sigma_t.y), channel(sigma_t.z))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_henyey_greenstein() {
+        // Integrates to 1 over the sphere and is isotropic for g = 0
+        let forward = HenyeyGreenstein::new(0.7);
+        let direction = Vec3::new(0., 0., 1.);
+        let steps = 2000;
+        let integral: Float = (0..steps)
+            .map(|i| {
+                let cos_theta = -1. + 2. * (i as Float + 0.5) / steps as Float;
+                let sin_theta = (1. - cos_theta * cos_theta).sqrt();
+                let wi = Vec3::new(sin_theta, 0., cos_theta);
+                2. * PI * forward.p(direction, wi) * 2. / steps as Float
+            })
+            .sum();
+        assert!((integral - 1.).abs() < 1e-2);
+        let isotropic = HenyeyGreenstein::new(0.);
+        assert!((isotropic.p(direction, Vec3::new(1., 0., 0.)) - 1. / (4. * PI)).abs() < 1e-6);
+
+        // Samples come back with their density and lean forward on average
+        let mut sampler = Sampler::new(1);
+        let mut mean_cos = 0.;
+        for _ in 0..4000 {
+            let (wi, pdf) = forward.sample(direction, &sampler.random_vec2_0_1());
+            assert!((pdf - forward.p(direction, wi)).abs() < 1e-3 * pdf.max(1.));
+            mean_cos += wi.z / 4000.;
+        }
+        assert!((mean_cos - 0.7).abs() < 0.05);
+    }
+}
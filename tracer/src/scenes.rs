@@ -0,0 +1,562 @@
+use std::sync::Arc;
+
+use math::{Float, Mat4, Vec3};
+use strum_macros::{Display, EnumIter, EnumString};
+
+use crate::cameras::{lens::ThinLens, perspective::PerspectiveCamera, CameraFrame};
+use crate::core::{bounds::Bounds3f, primitive::Primitive, scene::Scene, shape::Shape, spectrum::Spectrum, Transform};
+use crate::integrators::direct_lighting::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::loaders::gltf_loader::GltfScene;
+use crate::loaders::pbrt_loader::load_pbrt;
+use crate::materials::ior::Glass;
+use crate::materials::{ConstantMaterial, DieletricMaterial, LambertMaterial, Material, MetalMaterial};
+use crate::media::{
+    grid::{GridMedium, VoxelGrid},
+    homogeneous::HomogeneousMedium,
+    Medium, MediumInterface,
+};
+use crate::shapes::analytic::Placement;
+use crate::shapes::cone::Cone;
+use crate::shapes::csg::{Csg, CsgNode};
+use crate::shapes::cylinder::Cylinder;
+use crate::shapes::disk::Disk;
+use crate::shapes::paraboloid::Paraboloid;
+use crate::shapes::quad::Quad;
+use crate::shapes::sdf::{Sdf, SdfNode};
+use crate::shapes::skinning::SkinningMethod;
+use crate::shapes::sphere::Sphere;
+use crate::shapes::torus::Torus;
+
+#[derive(Debug, EnumIter, EnumString, PartialEq, Clone, Copy, Display)]
+#[strum(ascii_case_insensitive)]
+pub enum SceneOption {
+    Spheres,
+    Truck,
+    FurnaceTest,
+    Pbrt4,
+    Shapes,
+    Sdf,
+    Csg,
+    Media,
+    Smoke,
+}
+
+impl SceneOption {
+    pub fn create_scene(&self) -> Scene {
+        match self {
+            SceneOption::Spheres => raytracing_weekend_scene(),
+            SceneOption::Truck => gltf_scene(),
+            SceneOption::FurnaceTest => furnace_test(),
+            SceneOption::Pbrt4 => pbrt4_scene(),
+            SceneOption::Shapes => shapes_scene(),
+            SceneOption::Sdf => sdf_scene(),
+            SceneOption::Csg => csg_scene(),
+            SceneOption::Media => media_scene(),
+            SceneOption::Smoke => smoke_scene(),
+        }
+    }
+
+    // The scenes that move, loaded once for a sequence of frames
+    pub fn animated_scene(&self) -> Option<AnimatedScene> {
+        match self {
+            SceneOption::Truck => Some(truck_animation()),
+            _ => None,
+        }
+    }
+}
+
+pub fn pbrt4_scene() -> Scene {
+    pbrt_file_scene("assets/pbrt4/pbrt-book/book.pbrt")
+}
+
+// Scene described by a pbrt-v4 file. Without a camera in the file it is
+// looked at through pbrt's default, a 90 degree perspective camera at the
+// origin looking down +z.
+pub fn pbrt_file_scene(path: &str) -> Scene {
+    let pbrt_scene = match load_pbrt(path, SCREEN_WIDTH, SCREEN_HEIGHT) {
+        Ok(pbrt_scene) => pbrt_scene,
+        Err(e) => panic!("Failed to load {}: {}", path, e),
+    };
+
+    let mut scene = Scene::default();
+    scene.camera = match pbrt_scene.camera {
+        Some(camera) => camera,
+        None => Arc::new(PerspectiveCamera::new(
+            SCREEN_WIDTH,
+            SCREEN_HEIGHT,
+            CameraFrame::from_matrix(&Mat4::identity()),
+            ThinLens {
+                f_stop: Float::INFINITY,
+                ..ThinLens::default()
+            }
+            .with_field_of_view(90., SCREEN_WIDTH, SCREEN_HEIGHT),
+        )),
+    };
+    scene.output_filename = pbrt_scene.output_filename;
+    scene.camera_medium = pbrt_scene.camera_medium;
+    for primitive in pbrt_scene.primitives {
+        scene.add(primitive);
+    }
+    if let Some(focus_point) = pbrt_scene.focus_point {
+        match scene.autofocus(&focus_point) {
+            Some(distance) => log::info!("Focused at {} units", distance),
+            None => log::warn!("Nothing to focus on at {:?}", focus_point),
+        }
+    }
+
+    scene.environment_light = |ray| -> Spectrum {
+        let t = 0.5 * ray.direction.y + 1.0;
+        let sky_color = (1. - t) * Vec3::new(1., 1., 1.) + t * Vec3::new(0.5, 0.7, 1.);
+        let sky_environment = Spectrum::ColorRGB(sky_color);
+        return sky_environment;
+    };
+
+    return scene;
+}
+
+// Scene that can be posed at any time of its animation. The glTF file is
+// read once and every frame of a sequence shares its meshes.
+pub struct AnimatedScene {
+    gltf: GltfScene,
+    // Camera, environment and ground put around the glTF content
+    set_up: fn(&mut Scene),
+}
+
+impl AnimatedScene {
+    pub fn scene_at(&self, time: Float) -> Scene {
+        let mut scene = self.gltf.scene_at(time);
+        (self.set_up)(&mut scene);
+        scene
+    }
+
+    pub fn set_skinning(&mut self, method: SkinningMethod) {
+        self.gltf.skinning = method;
+    }
+
+    // Length of the animation in seconds
+    pub fn duration(&self) -> Float {
+        self.gltf.duration()
+    }
+}
+
+// glTF file lit by the sky, seen through its own camera when it has one
+pub fn gltf_file_scene(path: &str) -> AnimatedScene {
+    AnimatedScene {
+        gltf: GltfScene::load(path),
+        set_up: |scene| {
+            scene.environment_light = |ray| -> Spectrum {
+                let t = 0.5 * ray.direction.y + 1.0;
+                let sky_color = (1. - t) * Vec3::new(1., 1., 1.) + t * Vec3::new(0.5, 0.7, 1.);
+                Spectrum::ColorRGB(sky_color)
+            };
+        },
+    }
+}
+
+// The milk truck with its wheels spinning
+pub fn truck_animation() -> AnimatedScene {
+    AnimatedScene {
+        gltf: GltfScene::load("assets/glTF/CesiumMilkTruck/glTF/CesiumMilkTruck.gltf"),
+        set_up: set_up_truck,
+    }
+}
+
+pub fn gltf_scene() -> Scene {
+    truck_animation().scene_at(0.)
+}
+
+fn set_up_truck(scene: &mut Scene) {
+    let camera_position: Vec3 = Vec3::new(15., 2.5, 0.0);
+    // let mut camera_position: Vec3 = Vec3::new(0.,25.5,10.);
+    let camera_lookat: Vec3 = Vec3::new(0., 0., -1.);
+
+    scene.environment_light = |ray| -> Spectrum {
+        let t = 0.5 * ray.direction.y + 1.0;
+        let sky_color = (1. - t) * Vec3::new(1., 1., 1.) + t * Vec3::new(0.5, 0.7, 1.);
+        let sky_environment = Spectrum::ColorRGB(sky_color);
+        return sky_environment;
+    };
+
+    let cam = PerspectiveCamera::look_at(
+        SCREEN_WIDTH,
+        SCREEN_HEIGHT,
+        camera_position,
+        camera_lookat,
+        ThinLens::default(),
+    );
+    scene.camera = Arc::new(cam);
+
+    // Floor
+    scene.add(Primitive::new(
+        Shape::Sphere(Sphere::new(Vec3::new(0., -100.5, -1.), 100.)),
+        Option::Some(Arc::new(ConstantMaterial::new(Spectrum::ColorRGB(
+            Vec3::new(0.2, 0.2, 0.2),
+        )))),
+    ));
+}
+
+pub fn raytracing_weekend_scene() -> Scene {
+    let camera_position: Vec3 = Vec3::new(0., 0.5, -5.5);
+    let camera_lookat: Vec3 = Vec3::new(0., 0., -1.);
+
+    // Create new camera
+    let cam = PerspectiveCamera::look_at(
+        SCREEN_WIDTH,
+        SCREEN_HEIGHT,
+        camera_position,
+        camera_lookat,
+        ThinLens::default(),
+    );
+    let mut scene = Scene::default();
+    scene.camera = Arc::new(cam);
+
+    scene.environment_light = |ray| -> Spectrum {
+        let t = 0.5 * ray.direction.y + 1.0;
+        //let sky_color = (1. - t) * Vec3::new(1., 1., 1.) + t * Vec3::new(0.2, 0.2, 1.);
+        //let sky_color = (1. - t) * Vec3::new(1., 1., 1.) + t * Vec3::new(0.2, 0.2, 0.2);
+        let sky_color = (1. - t) * Vec3::new(1., 1., 1.) + t * Vec3::new(0.5, 0.7, 1.);
+        let sky_environment = Spectrum::ColorRGB(sky_color);
+        return sky_environment;
+    };
+
+    scene.add(Primitive::new(
+        Shape::Sphere(Sphere::new(Vec3::new(0., 0., -1.), 0.5)),
+        Option::Some(Arc::new(LambertMaterial::new(Spectrum::ColorRGB(
+            Vec3::new(0.5, 0.5, 0.5),
+        )))),
+    ));
+
+    scene.add(Primitive::new(
+        Shape::Sphere(Sphere::new(Vec3::new(1., 0., -1.), 0.5)),
+        Option::Some(Arc::new(MetalMaterial::new(Spectrum::ColorRGB(Vec3::new(
+            0.2, 0.5, 0.5,
+        ))))),
+    ));
+
+    scene.add(Primitive::new(
+        Shape::Sphere(Sphere::new(Vec3::new(-1., 0., -1.), 0.5)),
+        // Dense flint splits white light enough to see in spectral renders
+        Option::Some(Arc::new(
+            DieletricMaterial::new(Spectrum::ColorRGB(Vec3::new(1.0, 1.0, 1.0))).with_ior(Glass::SF11.ior()),
+        )),
+    ));
+
+    // Ground
+    scene.add(Primitive::new(
+        Shape::Sphere(Sphere::new(Vec3::new(0., -100.5, -1.), 100.)),
+        Option::Some(Arc::new(LambertMaterial::new(Spectrum::ColorRGB(
+            Vec3::new(0.2, 0.2, 0.2),
+        )))),
+    ));
+
+    return scene;
+}
+
+// Floor tile under the sky, seen from a little above, for the scenes of
+// shape kinds
+fn shapes_stage() -> Scene {
+    let cam = PerspectiveCamera::look_at(
+        SCREEN_WIDTH,
+        SCREEN_HEIGHT,
+        Vec3::new(0., 2.5, -7.),
+        Vec3::new(0., 0.3, 0.),
+        ThinLens::default(),
+    );
+    let mut scene = Scene::default();
+    scene.camera = Arc::new(cam);
+
+    scene.environment_light = |ray| -> Spectrum {
+        let t = 0.5 * ray.direction.y + 1.0;
+        let sky_color = (1. - t) * Vec3::new(1., 1., 1.) + t * Vec3::new(0.5, 0.7, 1.);
+        Spectrum::ColorRGB(sky_color)
+    };
+
+    scene.add(Primitive::new(
+        Shape::Quad(Quad::axis_aligned(Vec3::new(-5., 0., -5.), Vec3::new(5., 0., 5.))),
+        Some(Arc::new(LambertMaterial::new(Spectrum::ColorRGB(Vec3::new(0.5, 0.5, 0.5))))),
+    ));
+    scene
+}
+
+fn add_in_colors(scene: &mut Scene, shapes: Vec<Shape>) {
+    let colors = [
+        Vec3::new(0.8, 0.3, 0.3),
+        Vec3::new(0.3, 0.8, 0.3),
+        Vec3::new(0.3, 0.3, 0.8),
+        Vec3::new(0.8, 0.8, 0.3),
+        Vec3::new(0.8, 0.3, 0.8),
+    ];
+    for (shape, color) in shapes.into_iter().zip(colors.into_iter().cycle()) {
+        scene.add(Primitive::new(
+            shape,
+            Some(Arc::new(LambertMaterial::new(Spectrum::ColorRGB(color)))),
+        ));
+    }
+}
+
+// One of each analytic shape with their z axes turned up, some cut open to
+// show the inside
+pub fn shapes_scene() -> Scene {
+    let standing_at = |x: Float, y: Float| {
+        let upright = Transform::translate(Vec3::new(x, y, 0.)) * Transform::rotate_x(-std::f32::consts::FRAC_PI_2);
+        Placement::new(upright.matrix).unwrap_or_default()
+    };
+    let mut scene = shapes_stage();
+    add_in_colors(
+        &mut scene,
+        vec![
+            Shape::Disk(Disk::new(0.01, 0.6, 0.3, 360., standing_at(-3., 0.))),
+            Shape::Cylinder(Cylinder::new(0.5, 0., 1., 270., standing_at(-1.5, 0.))),
+            Shape::Cone(Cone::new(1.2, 0.5, 360., standing_at(0., 0.))),
+            Shape::Paraboloid(Paraboloid::new(0.6, 0., 1., 300., standing_at(1.5, 0.))),
+            Shape::Torus(Torus::new(0.45, 0.15, 360., standing_at(3., 0.15))),
+        ],
+    );
+    scene
+}
+
+// Distance field shapes: a twisted bar, two blended spheres, a drilled block
+// and a row of repeated capsules
+pub fn sdf_scene() -> Scene {
+    let bounded = |root: SdfNode, half_extents: Vec3, position: Vec3| {
+        let bounds = Bounds3f {
+            p_min: -1. * half_extents,
+            p_max: half_extents,
+        };
+        let placement = Placement::new(Transform::translate(position).matrix).unwrap_or_default();
+        Shape::Sdf(Sdf::new(root, bounds, placement))
+    };
+    let twisted_bar = SdfNode::Box {
+        half_extents: Vec3::new(0.3, 0.8, 0.3),
+    }
+    .twist(1.5);
+    let blob = SdfNode::Sphere { radius: 0.45 }
+        .translate(Vec3::new(0., -0.3, 0.))
+        .smooth_union(SdfNode::Sphere { radius: 0.3 }.translate(Vec3::new(0.2, 0.35, 0.)), 0.3);
+    let drilled_block = SdfNode::Box {
+        half_extents: Vec3::new(0.5, 0.5, 0.5),
+    }
+    .subtract(SdfNode::Capsule {
+        a: Vec3::new(0., 0., -1.),
+        b: Vec3::new(0., 0., 1.),
+        radius: 0.3,
+    })
+    .subtract(SdfNode::Torus {
+        major_radius: 0.6,
+        minor_radius: 0.15,
+    });
+    let capsules = SdfNode::Capsule {
+        a: Vec3::new(0., -0.2, 0.),
+        b: Vec3::new(0., 0.2, 0.),
+        radius: 0.1,
+    }
+    .repeat(Vec3::new(0., 0., 0.5));
+
+    let mut scene = shapes_stage();
+    add_in_colors(
+        &mut scene,
+        vec![
+            bounded(twisted_bar, Vec3::new(0.5, 0.8, 0.5), Vec3::new(-2.5, 0.8, 0.)),
+            bounded(blob, Vec3::new(0.6, 0.8, 0.6), Vec3::new(-0.8, 0.75, 0.)),
+            bounded(drilled_block, Vec3::new(0.5, 0.5, 0.5), Vec3::new(0.8, 0.5, 0.)),
+            bounded(capsules, Vec3::new(0.1, 0.3, 1.6), Vec3::new(2.5, 0.3, 0.)),
+        ],
+    );
+    scene
+}
+
+// Solids built from other ones: a block drilled along its three axes, with
+// the holes lined in another color, the lens between two spheres and a ball
+// with a corner cut out
+pub fn csg_scene() -> Scene {
+    let material = |color: Vec3| -> Option<Arc<dyn Material>> {
+        Some(Arc::new(LambertMaterial::new(Spectrum::ColorRGB(color))))
+    };
+    let placed = |transform: Transform| Placement::new(transform.matrix).unwrap_or_default();
+    let cube = |half: Float| Bounds3f {
+        p_min: Vec3::from(-half),
+        p_max: Vec3::from(half),
+    };
+    let (red, yellow, blue) = (Vec3::new(0.8, 0.3, 0.3), Vec3::new(0.8, 0.8, 0.3), Vec3::new(0.3, 0.3, 0.8));
+
+    let center = Transform::translate(Vec3::new(-2., 0.6, 0.));
+    let drill = |turn: Transform| CsgNode::cylinder(0.3, -1., 1., placed(center * turn), material(yellow));
+    let drilled_block = CsgNode::cuboid(&cube(0.6), placed(center), material(red))
+        .difference(drill(Transform::default()))
+        .difference(drill(Transform::rotate_x(std::f32::consts::FRAC_PI_2)))
+        .difference(drill(Transform::rotate_y(std::f32::consts::FRAC_PI_2)));
+
+    let lens = CsgNode::sphere(Sphere::new(Vec3::new(-0.5, 0.7, 0.), 1.), material(blue))
+        .intersection(CsgNode::sphere(Sphere::new(Vec3::new(0.5, 0.7, 0.), 1.), material(blue)));
+
+    let ball_center = Vec3::new(2., 0.7, 0.);
+    let cut_ball = CsgNode::sphere(Sphere::new(ball_center, 0.7), material(yellow)).difference(CsgNode::cuboid(
+        &cube(0.5),
+        placed(Transform::translate(ball_center + Vec3::new(0.5, 0.5, -0.5))),
+        material(red),
+    ));
+
+    let mut scene = shapes_stage();
+    for solid in [drilled_block, lens, cut_ball] {
+        scene.add(Primitive::new(Shape::Csg(Csg::new(solid)), material(Vec3::from(0.5))));
+    }
+    scene
+}
+
+// Participating media: a ball of smoke with only a boundary around it,
+// tinted glass absorbing what goes through it and a diffuse ball seen
+// through a haze
+pub fn media_scene() -> Scene {
+    let medium = |sigma_a: Vec3, sigma_s: Vec3, g: Float| -> Option<Arc<dyn Medium>> {
+        Some(Arc::new(HomogeneousMedium::new(
+            Spectrum::ColorRGB(sigma_a),
+            Spectrum::ColorRGB(sigma_s),
+            g,
+        )))
+    };
+    let inside = |mut primitive: Primitive, medium: Option<Arc<dyn Medium>>| {
+        primitive.medium_interface = Some(MediumInterface::new(medium, None));
+        primitive
+    };
+
+    let mut scene = shapes_stage();
+    let smoke = Primitive::new(Shape::Sphere(Sphere::new(Vec3::new(-2., 0.9, 0.), 0.9)), None);
+    scene.add(inside(smoke, medium(Vec3::from(0.1), Vec3::from(2.), 0.3)));
+
+    let glass = Primitive::new(
+        Shape::Sphere(Sphere::new(Vec3::new(0., 0.8, 0.), 0.8)),
+        Some(Arc::new(DieletricMaterial::new(Spectrum::ColorRGB(Vec3::from(1.))))),
+    );
+    scene.add(inside(glass, medium(Vec3::new(0.2, 1.5, 2.), Vec3::from(0.), 0.)));
+
+    let ball = Shape::Sphere(Sphere::new(Vec3::new(2., 0.5, 0.), 0.5));
+    add_in_colors(&mut scene, vec![ball]);
+    let haze = Primitive::new(Shape::Sphere(Sphere::new(Vec3::new(2., 0.5, 0.), 1.2)), None);
+    scene.add(inside(haze, medium(Vec3::from(0.), Vec3::new(0.3, 0.4, 0.6), -0.2)));
+    scene
+}
+
+// Plume of smoke rising from a fire, on a voxel grid. The puffs drift
+// sideways and spread as they go up, the bottom ones are hot enough to glow.
+pub fn smoke_scene() -> Scene {
+    const RESOLUTION: usize = 64;
+    let puffs: Vec<(Vec3, Float)> = (0..10)
+        .map(|i| {
+            let height = 0.15 + 0.16 * i as Float;
+            let center = Vec3::new(0.5 * (1.7 * height).sin(), height, 0.2 * (3. * height).cos() - 0.2);
+            (center, 0.15 + 0.025 * i as Float)
+        })
+        .collect();
+    let mut density = Vec::with_capacity(RESOLUTION.pow(3));
+    let mut temperature = Vec::with_capacity(RESOLUTION.pow(3));
+    for z in 0..RESOLUTION {
+        for y in 0..RESOLUTION {
+            for x in 0..RESOLUTION {
+                let voxel = |i: usize| (i as Float + 0.5) / RESOLUTION as Float;
+                let p = Vec3::new(2. * voxel(x) - 1., 2.5 * voxel(y), 2. * voxel(z) - 1.);
+                let puff: Float = puffs
+                    .iter()
+                    .map(|(center, radius)| (-(p - *center).length2() / (radius * radius)).exp())
+                    .sum();
+                // Wisps break the puffs up
+                let wisps = 0.7 + 0.3 * (11. * p.x).sin() * (9. * p.y).sin() * (10. * p.z).sin();
+                let value = (0.6 * puff * wisps).min(1.);
+                density.push(value);
+                temperature.push(4500. * puff.min(1.) * (-2.5 * p.y).exp());
+            }
+        }
+    }
+    let resolution = [RESOLUTION; 3];
+    let (Ok(density), Ok(temperature)) = (VoxelGrid::new(resolution, density), VoxelGrid::new(resolution, temperature)) else {
+        return shapes_stage();
+    };
+    let bounds = Bounds3f {
+        p_min: Vec3::new(-1., 0., -1.),
+        p_max: Vec3::new(1., 2.5, 1.),
+    };
+    let smoke = GridMedium::new(
+        bounds,
+        Placement::default(),
+        density,
+        Spectrum::ColorRGB(Vec3::from(1.)),
+        Spectrum::ColorRGB(Vec3::from(8.)),
+        0.2,
+    )
+    .with_temperature(temperature, 0., 1., 30.);
+
+    // There is no density outside of the grid, the smoke can fill the whole
+    // scene from around the camera
+    let mut scene = shapes_stage();
+    scene.camera_medium = Some(Arc::new(smoke));
+    scene
+}
+
+pub fn furnace_test() -> Scene {
+    let reveal = true;
+    let camera_position: Vec3 = Vec3::new(0., 5., -15.5);
+    let camera_lookat: Vec3 = Vec3::new(0., 0., 10.);
+
+    // Create new camera
+    let cam = PerspectiveCamera::look_at(
+        SCREEN_WIDTH,
+        SCREEN_HEIGHT,
+        camera_position,
+        camera_lookat,
+        ThinLens::default(),
+    );
+    let mut scene = Scene::default();
+    scene.camera = Arc::new(cam);
+
+    scene.environment_light = |_ray| -> Spectrum {
+        Spectrum::ColorRGB(Vec3 {
+            x: 0.5,
+            y: 0.5,
+            z: 0.5,
+        })
+    };
+
+    scene.add(Primitive::new(
+        Shape::Sphere(Sphere::new(Vec3::zero(), 2.)),
+        Option::Some(Arc::new(ConstantMaterial::new(Spectrum::ColorRGB(Vec3 {
+            x: 1.0,
+            y: 1.0,
+            z: 1.0,
+        })))),
+    ));
+
+    if reveal {
+        scene.add(Primitive::new(
+            Shape::Sphere(Sphere::new(
+                Vec3 {
+                    x: 3.,
+                    y: 0.,
+                    z: -0.5,
+                },
+                1.,
+            )),
+            Option::Some(Arc::new(ConstantMaterial::new(Spectrum::ColorRGB(Vec3 {
+                x: 0.8,
+                y: 0.0,
+                z: 0.0,
+            })))),
+        ));
+
+        scene.add(Primitive::new(
+            Shape::Sphere(Sphere::new(
+                Vec3 {
+                    x: 0.,
+                    y: 3.,
+                    z: -0.5,
+                },
+                1.,
+            )),
+            Option::Some(Arc::new(ConstantMaterial::new(Spectrum::ColorRGB(Vec3 {
+                x: 0.8,
+                y: 0.1,
+                z: 0.02,
+            })))),
+        ));
+    }
+
+    return scene;
+}
@@ -0,0 +1,119 @@
+use math::{Float, Mat4, Vec3};
+
+use crate::core::bounds::Bounds3f;
+use crate::core::interaction::SurfaceInteraction;
+use crate::core::ray::Ray;
+
+// Where an analytic shape is in the world. Like pbrt's quadrics the shapes
+// are defined around the z axis of their own space, and the placement moves,
+// turns and scales them from there.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Placement {
+    pub object_to_world: Mat4,
+    pub world_to_object: Mat4,
+}
+
+impl Default for Placement {
+    fn default() -> Self {
+        Self {
+            object_to_world: Mat4::identity(),
+            world_to_object: Mat4::identity(),
+        }
+    }
+}
+
+impl Placement {
+    // None if `object_to_world` flattens the shape
+    pub fn new(object_to_world: Mat4) -> Option<Self> {
+        Some(Self {
+            object_to_world,
+            world_to_object: object_to_world.inverse()?,
+        })
+    }
+
+    // Applied after the current placement
+    pub fn then(&self, matrix: &Mat4) -> Option<Self> {
+        Placement::new(*matrix * self.object_to_world)
+    }
+
+    // The direction isn't normalized again, distances along both rays are
+    // the same
+    pub fn ray_to_object(&self, ray: &Ray) -> Ray {
+        Ray {
+            origin: self.world_to_object * ray.origin,
+            direction: self.world_to_object.transform_vector(ray.direction),
+            time: ray.time,
+            differential: None,
+        }
+    }
+
+    pub fn point_to_world(&self, point: Vec3) -> Vec3 {
+        self.object_to_world * point
+    }
+
+    pub fn normal_to_world(&self, normal: Vec3) -> Vec3 {
+        self.world_to_object.transpose().transform_vector(normal).normalize()
+    }
+
+    pub fn bounds_to_world(&self, bounds: &Bounds3f) -> Bounds3f {
+        bounds.transform(&self.object_to_world)
+    }
+
+    // How much areas grow from object to world space. Exact for uniform
+    // scaling, an average otherwise.
+    pub fn area_scale(&self) -> Float {
+        let m = &self.object_to_world.m;
+        let determinant = m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0]);
+        determinant.abs().powf(2. / 3.)
+    }
+
+    // Fill in a hit found in object space, the normal is the outward one
+    pub fn record_hit(&self, ray: &Ray, t: Float, object_normal: Vec3, uv: (Float, Float), isect: &mut SurfaceInteraction) {
+        isect.t = t;
+        isect.hit_point = ray.point_at(t);
+        isect.hit_normal = self.normal_to_world(object_normal);
+        isect.hit_front_face = Vec3::dot(ray.direction, isect.hit_normal) < 0.;
+        isect.hit_uv = math::Vec2::new(&[uv.0, uv.1]);
+    }
+}
+
+// Angle of `point` around the z axis, in [0, 2 pi)
+pub fn phi_of(point: Vec3) -> Float {
+    let phi = point.y.atan2(point.x);
+    if phi < 0. {
+        phi + 2. * std::f32::consts::PI
+    } else {
+        phi
+    }
+}
+
+// Real roots of a t^2 + b t + c in increasing order. In double precision,
+// grazing rays lose too much to cancellation in single.
+pub fn solve_quadratic(a: Float, b: Float, c: Float) -> Option<(Float, Float)> {
+    let (a, b, c) = (a as f64, b as f64, c as f64);
+    if a == 0. {
+        if b == 0. {
+            return None;
+        }
+        let t = (-c / b) as Float;
+        return Some((t, t));
+    }
+    let discriminant = b * b - 4. * a * c;
+    if discriminant < 0. {
+        return None;
+    }
+    // Avoids subtracting close numbers
+    let q = if b < 0. {
+        -0.5 * (b - discriminant.sqrt())
+    } else {
+        -0.5 * (b + discriminant.sqrt())
+    };
+    let (t0, t1) = if q == 0. { (0., 0.) } else { (q / a, c / q) };
+    Some(if t0 <= t1 {
+        (t0 as Float, t1 as Float)
+    } else {
+        (t1 as Float, t0 as Float)
+    })
+}
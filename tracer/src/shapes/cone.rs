@@ -0,0 +1,120 @@
+use math::{Float, Vec2, Vec3};
+
+use crate::core::bounds::Bounds3f;
+use crate::core::interaction::SurfaceInteraction;
+use crate::core::ray::Ray;
+use crate::core::shape::ShapeSample;
+use crate::shapes::analytic::{phi_of, solve_quadratic, Placement};
+
+// Open cone with its base of `radius` at z = 0 and its tip at z = `height`,
+// swept up to `phi_max`
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Cone {
+    pub height: Float,
+    pub radius: Float,
+    pub phi_max: Float,
+    pub placement: Placement,
+}
+
+impl Cone {
+    pub fn new(height: Float, radius: Float, phi_max_degrees: Float, placement: Placement) -> Self {
+        Self {
+            height,
+            radius,
+            phi_max: phi_max_degrees.clamp(0., 360.).to_radians(),
+            placement,
+        }
+    }
+
+    // Outward normal at a point of the side
+    fn normal_at(&self, p: Vec3) -> Vec3 {
+        let k = (self.radius / self.height) * (self.radius / self.height);
+        Vec3::new(p.x, p.y, -k * (p.z - self.height))
+    }
+
+    pub fn intersect(&self, ray: &Ray, isect: &mut SurfaceInteraction) -> bool {
+        const T_MIN: Float = 1e-3;
+
+        // x^2 + y^2 = k (z - h)^2
+        let object_ray = self.placement.ray_to_object(ray);
+        let (o, d) = (object_ray.origin, object_ray.direction);
+        let k = (self.radius / self.height) * (self.radius / self.height);
+        let oz = o.z - self.height;
+        let a = d.x * d.x + d.y * d.y - k * d.z * d.z;
+        let b = 2. * (d.x * o.x + d.y * o.y - k * d.z * oz);
+        let c = o.x * o.x + o.y * o.y - k * oz * oz;
+        let Some((t0, t1)) = solve_quadratic(a, b, c) else {
+            return false;
+        };
+
+        for t in [t0, t1] {
+            if t <= T_MIN {
+                continue;
+            }
+            // Both nappes solve the equation, only the one below the tip is
+            // the cone
+            let p = object_ray.point_at(t);
+            let phi = phi_of(p);
+            if p.z < 0. || p.z > self.height || phi > self.phi_max {
+                continue;
+            }
+            let uv = (phi / self.phi_max, p.z / self.height);
+            self.placement.record_hit(ray, t, self.normal_at(p), uv, isect);
+            return true;
+        }
+        false
+    }
+
+    pub fn world_bound(&self) -> Bounds3f {
+        self.placement.bounds_to_world(&Bounds3f {
+            p_min: Vec3::new(-self.radius, -self.radius, 0.),
+            p_max: Vec3::new(self.radius, self.radius, self.height),
+        })
+    }
+
+    pub fn area(&self) -> Float {
+        let slant = (self.height * self.height + self.radius * self.radius).sqrt();
+        0.5 * self.radius * slant * self.phi_max * self.placement.area_scale()
+    }
+
+    // Uniform over the area, circles further from the tip are bigger so the
+    // distance to the tip goes with the square root
+    pub fn sample(&self, u: &Vec2) -> ShapeSample {
+        let s = u.0.sqrt();
+        let phi = u.1 * self.phi_max;
+        let p = Vec3::new(s * self.radius * phi.cos(), s * self.radius * phi.sin(), (1. - s) * self.height);
+        ShapeSample {
+            point: self.placement.point_to_world(p),
+            normal: self.placement.normal_to_world(self.normal_at(p)),
+            pdf: 1. / self.area(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    #[test]
+    fn test_cone() {
+        // 45 degree slope
+        let cone = Cone::new(1., 1., 360., Placement::default());
+        assert!((cone.area() - PI * (2. as Float).sqrt()).abs() < 1e-4);
+
+        // Halfway up the side, seen from +x
+        let mut isect = SurfaceInteraction::new();
+        assert!(cone.intersect(&Ray::new(Vec3::new(5., 0., 0.5), Vec3::new(-1., 0., 0.)), &mut isect));
+        assert!((isect.t - 4.5).abs() < 1e-4);
+        let slope = Vec3::new(1., 0., 1.).normalize();
+        assert!((isect.hit_normal - slope).length() < 1e-4);
+        assert!((isect.hit_uv.y() - 0.5).abs() < 1e-4);
+
+        // The mirrored nappe above the tip isn't there
+        assert!(!cone.intersect(&Ray::new(Vec3::new(5., 0., 1.5), Vec3::new(-1., 0., 0.)), &mut SurfaceInteraction::new()));
+
+        let sample = cone.sample(&Vec2::new(&[0.25, 0.5]));
+        assert!((sample.point - Vec3::new(-0.5, 0., 0.5)).length() < 1e-4);
+        assert!((sample.normal - Vec3::new(-1., 0., 1.).normalize()).length() < 1e-4);
+    }
+}
@@ -0,0 +1,375 @@
+use std::sync::Arc;
+
+use math::{Float, Vec2, Vec3};
+use strum_macros::{Display, EnumIter, EnumString};
+
+use crate::core::bounds::Bounds3f;
+use crate::core::interaction::SurfaceInteraction;
+use crate::core::ray::Ray;
+use crate::core::shape::Shape;
+use crate::core::Transform;
+use crate::materials::Material;
+use crate::shapes::analytic::Placement;
+use crate::shapes::cylinder::Cylinder;
+use crate::shapes::disk::Disk;
+use crate::shapes::mesh::Mesh;
+use crate::shapes::quad::Quad;
+use crate::shapes::sphere::Sphere;
+
+#[derive(Debug, EnumIter, EnumString, PartialEq, Clone, Copy, Display)]
+#[strum(ascii_case_insensitive)]
+pub enum CsgOperation {
+    Union,
+    Intersection,
+    // The first operand with the second one carved out
+    Difference,
+}
+
+impl CsgOperation {
+    fn inside(&self, in_a: bool, in_b: bool) -> bool {
+        match self {
+            CsgOperation::Union => in_a || in_b,
+            CsgOperation::Intersection => in_a && in_b,
+            CsgOperation::Difference => in_a && !in_b,
+        }
+    }
+}
+
+// Tree of solids. The leaves are surfaces closing a volume, with the material
+// their part of the result gets. Leaves without one take the primitive's.
+#[derive(Clone, PartialEq, Debug)]
+pub enum CsgNode {
+    Solid {
+        surfaces: Vec<Shape>,
+        material: Option<Arc<dyn Material>>,
+    },
+    Operation {
+        operation: CsgOperation,
+        a: Box<CsgNode>,
+        b: Box<CsgNode>,
+    },
+}
+
+// Where a ray goes in or out of a solid. The normal points out of the solid
+// the crossing is on.
+#[derive(Clone)]
+struct Crossing {
+    t: Float,
+    normal: Vec3,
+    uv: Vec2,
+    entering: bool,
+    material: Option<Arc<dyn Material>>,
+}
+
+// Crossings of a ray with a closed surface can't be endless, those past this
+// are dropped
+const MAX_CROSSINGS: usize = 64;
+
+impl CsgNode {
+    // The surfaces must close a volume, the inside is found by counting
+    // crossings
+    pub fn solid(surfaces: Vec<Shape>, material: Option<Arc<dyn Material>>) -> CsgNode {
+        CsgNode::Solid { surfaces, material }
+    }
+
+    pub fn sphere(sphere: Sphere, material: Option<Arc<dyn Material>>) -> CsgNode {
+        CsgNode::solid(vec![Shape::Sphere(sphere)], material)
+    }
+
+    // Tube around the z axis closed by disks at both ends
+    pub fn cylinder(
+        radius: Float,
+        z_min: Float,
+        z_max: Float,
+        placement: Placement,
+        material: Option<Arc<dyn Material>>,
+    ) -> CsgNode {
+        let surfaces = vec![
+            Shape::Cylinder(Cylinder::new(radius, z_min, z_max, 360., placement)),
+            Shape::Disk(Disk::new(z_min, radius, 0., 360., placement)),
+            Shape::Disk(Disk::new(z_max, radius, 0., 360., placement)),
+        ];
+        CsgNode::solid(surfaces, material)
+    }
+
+    // Box between two corners, then placed
+    pub fn cuboid(bounds: &Bounds3f, placement: Placement, material: Option<Arc<dyn Material>>) -> CsgNode {
+        let (p_min, p_max) = (bounds.p_min, bounds.p_max);
+        let faces = [
+            (p_min, Vec3::new(p_min.x, p_max.y, p_max.z)),
+            (Vec3::new(p_max.x, p_min.y, p_min.z), p_max),
+            (p_min, Vec3::new(p_max.x, p_min.y, p_max.z)),
+            (Vec3::new(p_min.x, p_max.y, p_min.z), p_max),
+            (p_min, Vec3::new(p_max.x, p_max.y, p_min.z)),
+            (Vec3::new(p_min.x, p_min.y, p_max.z), p_max),
+        ];
+        let surfaces = faces
+            .iter()
+            .map(|&(corner, opposite)| {
+                let quad = Quad::axis_aligned(corner, opposite);
+                let matrix = &placement.object_to_world;
+                Shape::Quad(Quad::new(
+                    *matrix * quad.corner,
+                    matrix.transform_vector(quad.edge_u),
+                    matrix.transform_vector(quad.edge_v),
+                ))
+            })
+            .collect();
+        CsgNode::solid(surfaces, material)
+    }
+
+    // The mesh must be watertight
+    pub fn mesh(mesh: Arc<Mesh>, material: Option<Arc<dyn Material>>) -> CsgNode {
+        CsgNode::solid(vec![Shape::Mesh(mesh)], material)
+    }
+
+    pub fn combine(self, operation: CsgOperation, other: CsgNode) -> CsgNode {
+        CsgNode::Operation {
+            operation,
+            a: Box::new(self),
+            b: Box::new(other),
+        }
+    }
+
+    pub fn union(self, other: CsgNode) -> CsgNode {
+        self.combine(CsgOperation::Union, other)
+    }
+
+    pub fn intersection(self, other: CsgNode) -> CsgNode {
+        self.combine(CsgOperation::Intersection, other)
+    }
+
+    pub fn difference(self, other: CsgNode) -> CsgNode {
+        self.combine(CsgOperation::Difference, other)
+    }
+
+    fn apply_transform(&mut self, transform: &Transform) {
+        match self {
+            CsgNode::Solid { surfaces, .. } => surfaces.iter_mut().for_each(|surface| surface.apply_transform(transform)),
+            CsgNode::Operation { a, b, .. } => {
+                a.apply_transform(transform);
+                b.apply_transform(transform);
+            }
+        }
+    }
+
+    // Box around the result, an intersection is within both operands and a
+    // difference within the first one
+    fn bounds(&self) -> Bounds3f {
+        match self {
+            CsgNode::Solid { surfaces, .. } => surfaces
+                .iter()
+                .fold(Bounds3f::default(), |bounds, surface| bounds.union(&surface.world_bound())),
+            CsgNode::Operation { operation, a, b } => {
+                let (a, b) = (a.bounds(), b.bounds());
+                match operation {
+                    CsgOperation::Union => a.union(&b),
+                    CsgOperation::Intersection => Bounds3f {
+                        p_min: Vec3::component_max(a.p_min, b.p_min),
+                        p_max: Vec3::component_min(a.p_max, b.p_max),
+                    },
+                    CsgOperation::Difference => a,
+                }
+            }
+        }
+    }
+
+    // Whether the ray starts inside, and where it goes in and out after that
+    fn crossings(&self, ray: &Ray) -> (bool, Vec<Crossing>) {
+        match self {
+            CsgNode::Solid { surfaces, material } => {
+                let mut hits = Vec::new();
+                for surface in surfaces {
+                    // Shapes give their nearest hit, the next ones are found
+                    // from there
+                    let mut t = 0.;
+                    while hits.len() < MAX_CROSSINGS {
+                        let from_last = Ray {
+                            origin: ray.point_at(t),
+                            direction: ray.direction,
+                            time: ray.time,
+                            differential: None,
+                        };
+                        let mut isect = SurfaceInteraction::new();
+                        if !surface.intersect(&from_last, &mut isect) {
+                            break;
+                        }
+                        t += isect.t;
+                        hits.push((t, isect.hit_normal, isect.hit_uv));
+                    }
+                }
+                hits.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+                // A closed surface is crossed an odd number of times from
+                // inside. Normals are turned outward from whether the ray goes
+                // in or out, meshes give them facing the ray.
+                let starts_inside = hits.len() % 2 == 1;
+                let mut inside = starts_inside;
+                let crossings = hits
+                    .into_iter()
+                    .map(|(t, normal, uv)| {
+                        inside = !inside;
+                        Crossing {
+                            t,
+                            normal: facing(normal, ray.direction, inside),
+                            uv,
+                            entering: inside,
+                            material: material.clone(),
+                        }
+                    })
+                    .collect();
+                (starts_inside, crossings)
+            }
+            CsgNode::Operation { operation, a, b } => {
+                let (mut in_a, crossings_a) = a.crossings(ray);
+                let (mut in_b, crossings_b) = b.crossings(ray);
+                let starts_inside = operation.inside(in_a, in_b);
+
+                // Walk both in order and keep where the result changes side
+                let mut inside = starts_inside;
+                let mut crossings = Vec::new();
+                let (mut i, mut j) = (0, 0);
+                while i < crossings_a.len() || j < crossings_b.len() {
+                    let from_a = j >= crossings_b.len() || (i < crossings_a.len() && crossings_a[i].t <= crossings_b[j].t);
+                    let crossing = if from_a {
+                        in_a = crossings_a[i].entering;
+                        i += 1;
+                        &crossings_a[i - 1]
+                    } else {
+                        in_b = crossings_b[j].entering;
+                        j += 1;
+                        &crossings_b[j - 1]
+                    };
+                    let now_inside = operation.inside(in_a, in_b);
+                    if now_inside != inside {
+                        inside = now_inside;
+                        // Surfaces of a carved out operand face the other way
+                        crossings.push(Crossing {
+                            normal: facing(crossing.normal, ray.direction, inside),
+                            entering: inside,
+                            ..crossing.clone()
+                        });
+                    }
+                }
+                (starts_inside, crossings)
+            }
+        }
+    }
+}
+
+// `normal` turned against the ray when it goes in, along it when it goes out
+fn facing(normal: Vec3, direction: Vec3, entering: bool) -> Vec3 {
+    if (Vec3::dot(normal, direction) < 0.) == entering {
+        normal
+    } else {
+        -normal
+    }
+}
+
+// Solid built from a CSG tree
+#[derive(Clone, PartialEq, Debug)]
+pub struct Csg {
+    pub root: Arc<CsgNode>,
+    bounds: Bounds3f,
+}
+
+impl Csg {
+    pub fn new(root: CsgNode) -> Self {
+        Self {
+            bounds: root.bounds(),
+            root: Arc::new(root),
+        }
+    }
+
+    pub fn apply_transform(&mut self, transform: &Transform) {
+        Arc::make_mut(&mut self.root).apply_transform(transform);
+        self.bounds = self.root.bounds();
+    }
+
+    pub fn intersect(&self, ray: &Ray, isect: &mut SurfaceInteraction) -> bool {
+        if !self.bounds.intersect_p(ray, Float::MAX) {
+            return false;
+        }
+        let (_, crossings) = self.root.crossings(ray);
+        let Some(first) = crossings.into_iter().next() else {
+            return false;
+        };
+        isect.t = first.t;
+        isect.hit_point = ray.point_at(first.t);
+        isect.hit_normal = first.normal;
+        isect.hit_front_face = first.entering;
+        isect.hit_uv = first.uv;
+        isect.hit_material = first.material;
+        true
+    }
+
+    pub fn world_bound(&self) -> Bounds3f {
+        self.bounds.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::spectrum::Spectrum;
+    use crate::materials::LambertMaterial;
+
+    #[test]
+    fn test_csg() {
+        let red: Arc<dyn Material> = Arc::new(LambertMaterial::new(Spectrum::ColorRGB(Vec3::new(1., 0., 0.))));
+        let green: Arc<dyn Material> = Arc::new(LambertMaterial::new(Spectrum::ColorRGB(Vec3::new(0., 1., 0.))));
+
+        // A block drilled along z
+        let block = CsgNode::cuboid(
+            &Bounds3f {
+                p_min: Vec3::new(-1., -1., -1.),
+                p_max: Vec3::new(1., 1., 1.),
+            },
+            Placement::default(),
+            Some(red.clone()),
+        );
+        let drill = CsgNode::cylinder(0.5, -2., 2., Placement::default(), Some(green.clone()));
+        let drilled = Csg::new(block.difference(drill));
+        let along_z = |x: Float| Ray::new(Vec3::new(x, 0., -5.), Vec3::new(0., 0., 1.));
+
+        // Through the hole
+        assert!(!drilled.intersect(&along_z(0.), &mut SurfaceInteraction::new()));
+        // Beside it on the front face
+        let mut isect = SurfaceInteraction::new();
+        assert!(drilled.intersect(&along_z(0.8), &mut isect));
+        assert!((isect.t - 4.).abs() < 1e-4);
+        assert_eq!(isect.hit_normal, Vec3::new(0., 0., -1.));
+        assert!(isect.hit_front_face);
+        assert_eq!(isect.hit_material, Some(red.clone()));
+        // Across the hole the wall faces its axis and has the drill's
+        // material
+        let mut isect = SurfaceInteraction::new();
+        assert!(drilled.intersect(&Ray::new(Vec3::new(0., 0., 0.), Vec3::new(1., 0., 0.)), &mut isect));
+        assert!((isect.t - 0.5).abs() < 1e-4);
+        assert!((isect.hit_normal - Vec3::new(-1., 0., 0.)).length() < 1e-4);
+        assert!(isect.hit_front_face);
+        assert_eq!(isect.hit_material, Some(green.clone()));
+
+        // Two overlapping spheres, the union hides the inner surfaces and
+        // the intersection is the lens between them
+        let spheres = || {
+            (
+                CsgNode::sphere(Sphere::new(Vec3::new(-0.5, 0., 0.), 1.), None),
+                CsgNode::sphere(Sphere::new(Vec3::new(0.5, 0., 0.), 1.), None),
+            )
+        };
+        let along_x = Ray::new(Vec3::new(-5., 0., 0.), Vec3::new(1., 0., 0.));
+        let (a, b) = spheres();
+        let (_, crossings) = a.union(b).crossings(&along_x);
+        let ts: Vec<Float> = crossings.iter().map(|crossing| crossing.t).collect();
+        assert_eq!(ts.len(), 2);
+        assert!((ts[0] - 3.5).abs() < 1e-4 && (ts[1] - 6.5).abs() < 1e-4);
+        let (a, b) = spheres();
+        let lens = Csg::new(a.intersection(b));
+        let mut isect = SurfaceInteraction::new();
+        assert!(lens.intersect(&along_x, &mut isect));
+        assert!((isect.t - 4.5).abs() < 1e-4);
+        assert!((isect.hit_normal - Vec3::new(-1., 0., 0.)).length() < 1e-4);
+        assert!((lens.world_bound().p_max.x - 0.5).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,116 @@
+use math::{Float, Vec2, Vec3};
+
+use crate::core::bounds::Bounds3f;
+use crate::core::interaction::SurfaceInteraction;
+use crate::core::ray::Ray;
+use crate::core::shape::ShapeSample;
+use crate::shapes::analytic::{phi_of, solve_quadratic, Placement};
+
+// Open tube around the z axis between `z_min` and `z_max`, swept up to
+// `phi_max`
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Cylinder {
+    pub radius: Float,
+    pub z_min: Float,
+    pub z_max: Float,
+    pub phi_max: Float,
+    pub placement: Placement,
+}
+
+impl Cylinder {
+    pub fn new(radius: Float, z_min: Float, z_max: Float, phi_max_degrees: Float, placement: Placement) -> Self {
+        Self {
+            radius,
+            z_min: z_min.min(z_max),
+            z_max: z_min.max(z_max),
+            phi_max: phi_max_degrees.clamp(0., 360.).to_radians(),
+            placement,
+        }
+    }
+
+    pub fn intersect(&self, ray: &Ray, isect: &mut SurfaceInteraction) -> bool {
+        const T_MIN: Float = 1e-3;
+
+        let object_ray = self.placement.ray_to_object(ray);
+        let (o, d) = (object_ray.origin, object_ray.direction);
+        let a = d.x * d.x + d.y * d.y;
+        let b = 2. * (d.x * o.x + d.y * o.y);
+        let c = o.x * o.x + o.y * o.y - self.radius * self.radius;
+        let Some((t0, t1)) = solve_quadratic(a, b, c) else {
+            return false;
+        };
+
+        // The near side may be cut away, then the far side shows
+        for t in [t0, t1] {
+            if t <= T_MIN {
+                continue;
+            }
+            let p = object_ray.point_at(t);
+            let phi = phi_of(p);
+            if p.z < self.z_min || p.z > self.z_max || phi > self.phi_max {
+                continue;
+            }
+            let uv = (phi / self.phi_max, (p.z - self.z_min) / (self.z_max - self.z_min));
+            self.placement.record_hit(ray, t, Vec3::new(p.x, p.y, 0.), uv, isect);
+            return true;
+        }
+        false
+    }
+
+    pub fn world_bound(&self) -> Bounds3f {
+        self.placement.bounds_to_world(&Bounds3f {
+            p_min: Vec3::new(-self.radius, -self.radius, self.z_min),
+            p_max: Vec3::new(self.radius, self.radius, self.z_max),
+        })
+    }
+
+    pub fn area(&self) -> Float {
+        (self.z_max - self.z_min) * self.radius * self.phi_max * self.placement.area_scale()
+    }
+
+    pub fn sample(&self, u: &Vec2) -> ShapeSample {
+        let z = self.z_min + u.0 * (self.z_max - self.z_min);
+        let phi = u.1 * self.phi_max;
+        let (x, y) = (self.radius * phi.cos(), self.radius * phi.sin());
+        ShapeSample {
+            point: self.placement.point_to_world(Vec3::new(x, y, z)),
+            normal: self.placement.normal_to_world(Vec3::new(x, y, 0.)),
+            pdf: 1. / self.area(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    #[test]
+    fn test_cylinder() {
+        // Three quarters of a tube, open towards +x -y
+        let cylinder = Cylinder::new(1., -1., 1., 270., Placement::default());
+        assert!((cylinder.area() - 2. * 1.5 * PI).abs() < 1e-4);
+
+        // From outside at +x the near wall is hit, facing the ray
+        let mut isect = SurfaceInteraction::new();
+        assert!(cylinder.intersect(&Ray::new(Vec3::new(5., 0.1, 0.5), Vec3::new(-1., 0., 0.)), &mut isect));
+        assert!((isect.t - (5. - (1. - 0.01 as Float).sqrt())).abs() < 1e-4);
+        assert!(isect.hit_front_face);
+        assert!((isect.hit_uv.y() - 0.75).abs() < 1e-4);
+
+        // From +x -y the missing quarter lets the ray through to the inside
+        // of the far wall
+        let mut isect = SurfaceInteraction::new();
+        let diagonal = Vec3::new(-1., 1., 0.).normalize();
+        assert!(cylinder.intersect(&Ray::new(-3. * diagonal, diagonal), &mut isect));
+        assert!((isect.t - 4.).abs() < 1e-4);
+        assert!(!isect.hit_front_face);
+
+        // Above the top
+        assert!(!cylinder.intersect(&Ray::new(Vec3::new(5., 0., 1.5), Vec3::new(-1., 0., 0.)), &mut SurfaceInteraction::new()));
+
+        let sample = cylinder.sample(&Vec2::new(&[0.25, 0.1]));
+        assert!((Vec3::new(sample.point.x, sample.point.y, 0.).length() - 1.).abs() < 1e-4);
+        assert!((sample.point.z + 0.5).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,114 @@
+use math::{Float, Vec2, Vec3};
+
+use crate::core::bounds::Bounds3f;
+use crate::core::interaction::SurfaceInteraction;
+use crate::core::ray::Ray;
+use crate::core::shape::ShapeSample;
+use crate::shapes::analytic::{phi_of, Placement};
+
+// Disk at `height` on the z axis facing +z, an annulus when the inner radius
+// isn't 0 and a sector when `phi_max` is less than a full turn
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Disk {
+    pub height: Float,
+    pub radius: Float,
+    pub inner_radius: Float,
+    pub phi_max: Float,
+    pub placement: Placement,
+}
+
+impl Disk {
+    pub fn new(height: Float, radius: Float, inner_radius: Float, phi_max_degrees: Float, placement: Placement) -> Self {
+        Self {
+            height,
+            radius,
+            inner_radius,
+            phi_max: phi_max_degrees.clamp(0., 360.).to_radians(),
+            placement,
+        }
+    }
+
+    pub fn intersect(&self, ray: &Ray, isect: &mut SurfaceInteraction) -> bool {
+        const T_MIN: Float = 1e-3;
+
+        let object_ray = self.placement.ray_to_object(ray);
+        if object_ray.direction.z == 0. {
+            return false;
+        }
+        let t = (self.height - object_ray.origin.z) / object_ray.direction.z;
+        if t <= T_MIN {
+            return false;
+        }
+        let p = object_ray.point_at(t);
+        let distance_sq = p.x * p.x + p.y * p.y;
+        if distance_sq > self.radius * self.radius || distance_sq < self.inner_radius * self.inner_radius {
+            return false;
+        }
+        let phi = phi_of(p);
+        if phi > self.phi_max {
+            return false;
+        }
+
+        let u = phi / self.phi_max;
+        let v = (self.radius - distance_sq.sqrt()) / (self.radius - self.inner_radius);
+        self.placement.record_hit(ray, t, Vec3::new(0., 0., 1.), (u, v), isect);
+        true
+    }
+
+    pub fn world_bound(&self) -> Bounds3f {
+        self.placement.bounds_to_world(&Bounds3f {
+            p_min: Vec3::new(-self.radius, -self.radius, self.height),
+            p_max: Vec3::new(self.radius, self.radius, self.height),
+        })
+    }
+
+    pub fn area(&self) -> Float {
+        let object_area = 0.5 * self.phi_max * (self.radius * self.radius - self.inner_radius * self.inner_radius);
+        object_area * self.placement.area_scale()
+    }
+
+    // Uniform over the area, radii are picked by their circumference
+    pub fn sample(&self, u: &Vec2) -> ShapeSample {
+        let inner_sq = self.inner_radius * self.inner_radius;
+        let r = (inner_sq + u.0 * (self.radius * self.radius - inner_sq)).sqrt();
+        let phi = u.1 * self.phi_max;
+        let point = Vec3::new(r * phi.cos(), r * phi.sin(), self.height);
+        ShapeSample {
+            point: self.placement.point_to_world(point),
+            normal: self.placement.normal_to_world(Vec3::new(0., 0., 1.)),
+            pdf: 1. / self.area(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::Transform;
+    use std::f32::consts::PI;
+
+    #[test]
+    fn test_disk() {
+        // Half an annulus lifted to y = 1, facing +y
+        let placement = Placement::new((Transform::translate(Vec3::new(0., 1., 0.)) * Transform::rotate_x(-PI / 2.)).matrix)
+            .unwrap();
+        let disk = Disk::new(0., 2., 1., 180., placement);
+        assert!((disk.area() - 0.5 * PI * 3.).abs() < 1e-4);
+
+        let down = |x: Float, z: Float| Ray::new(Vec3::new(x, 5., z), Vec3::new(0., -1., 0.));
+        let mut isect = SurfaceInteraction::new();
+        assert!(disk.intersect(&down(1.5, -0.1), &mut isect));
+        assert!((isect.t - 4.).abs() < 1e-4);
+        assert!((isect.hit_normal - Vec3::new(0., 1., 0.)).length() < 1e-4);
+        assert!(isect.hit_front_face);
+        assert!((isect.hit_uv.y() - 0.5).abs() < 1e-2);
+        // In the hole, and in the half that was swept away
+        assert!(!disk.intersect(&down(0.5, -0.1), &mut SurfaceInteraction::new()));
+        assert!(!disk.intersect(&down(1.5, 0.1), &mut SurfaceInteraction::new()));
+
+        let sample = disk.sample(&Vec2::new(&[0.3, 0.7]));
+        let mut isect = SurfaceInteraction::new();
+        assert!(disk.intersect(&down(sample.point.x, sample.point.z), &mut isect));
+        assert!((isect.hit_point - sample.point).length() < 1e-4);
+    }
+}
@@ -0,0 +1,320 @@
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock};
+
+use math::{Float, Vec2, Vec3};
+
+use crate::cameras::Camera;
+use crate::core::bounds::Bounds3f;
+use crate::core::interaction::SurfaceInteraction;
+use crate::core::ray::Ray;
+use crate::core::sampler::Sampler;
+use crate::core::shape::ShapeSample;
+use crate::core::texture::FloatTexture;
+use crate::shapes::mesh::Mesh;
+
+// Size in pixels of segments seen from the camera
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct EdgeMetric {
+    pub eye: Vec3,
+    // Width of a pixel at the eye, non zero for orthographic cameras
+    pub units_per_pixel: Float,
+    // Angle between the rays of neighbouring pixels, zero for orthographic
+    // cameras
+    pub radians_per_pixel: Float,
+}
+
+impl EdgeMetric {
+    // Measured from the rays through the center of the film and the pixel
+    // next to it
+    pub fn from_camera(camera: &dyn Camera, width: u32) -> Option<Self> {
+        let center = Vec2::new(&[0.5, 0.5]);
+        let next = Vec2::new(&[0.5 + 1. / width.max(1) as Float, 0.5]);
+        let a = camera.generate_ray(&center, &mut Sampler::default())?;
+        let b = camera.generate_ray(&next, &mut Sampler::default())?;
+        let cos = Vec3::dot(a.direction.normalize(), b.direction.normalize());
+        Some(EdgeMetric {
+            eye: a.origin,
+            units_per_pixel: (b.origin - a.origin).length(),
+            radians_per_pixel: cos.clamp(-1., 1.).acos(),
+        })
+    }
+
+    pub fn pixels(&self, a: Vec3, b: Vec3) -> Float {
+        let distance = ((a + b) * 0.5 - self.eye).length();
+        let pixel = self.units_per_pixel + distance * self.radians_per_pixel;
+        (a - b).length() / pixel.max(1e-8)
+    }
+}
+
+// Height of a surface along its normal given by a texture, for true
+// displacement
+#[derive(Clone)]
+pub struct Displacement {
+    pub texture: FloatTexture,
+    // Largest distance a point is moved, the texture is clamped to it. The
+    // bounds of the shape are grown by it before it is tessellated.
+    pub bound: Float,
+    // Edges are split until they are this many pixels long
+    pub edge_length: Float,
+    // Rounds of splitting at most, each one can make 4 triangles of one
+    pub max_level: u32,
+    // Without one every edge is split `max_level` times
+    pub metric: Option<EdgeMetric>,
+}
+
+impl Displacement {
+    pub fn new(texture: FloatTexture, bound: Float) -> Self {
+        Displacement {
+            texture,
+            bound,
+            edge_length: 1.,
+            max_level: 6,
+            metric: None,
+        }
+    }
+
+    fn should_split(&self, a: Vec3, b: Vec3) -> bool {
+        match &self.metric {
+            Some(metric) => metric.pixels(a, b) > self.edge_length,
+            None => true,
+        }
+    }
+}
+
+// Vertices of a mesh with a normal each, while it is refined
+struct Tessellation {
+    positions: Vec<Vec3>,
+    normals: Vec<Vec3>,
+    uv: Vec<Vec2>,
+    indices: Vec<u32>,
+}
+
+impl Tessellation {
+    // The normals of the mesh, or averaged over the faces around the
+    // vertices, weighted by their area. Faces wound counterclockwise face
+    // them.
+    fn new(mesh: &Mesh) -> Self {
+        if mesh.normals.len() == mesh.positions.len() {
+            return Tessellation {
+                positions: mesh.positions.clone(),
+                normals: mesh.normals.iter().map(|n| n.normalize()).collect(),
+                uv: mesh.uv.clone(),
+                indices: mesh.indices.clone(),
+            };
+        }
+        let mut normals = vec![Vec3::from(0.); mesh.positions.len()];
+        for face in mesh.indices.chunks_exact(3) {
+            let [a, b, c] = [face[0], face[1], face[2]].map(|i| mesh.positions[i as usize]);
+            let normal = Vec3::cross(b - a, c - a);
+            for &i in face {
+                normals[i as usize] += normal;
+            }
+        }
+        Tessellation {
+            positions: mesh.positions.clone(),
+            normals: normals.iter().map(|n| n.normalize()).collect(),
+            uv: mesh.uv.clone(),
+            indices: mesh.indices.clone(),
+        }
+    }
+
+    // Halves the edges `split` picks, shared edges get the same midpoint
+    // so no cracks open. Faces with one, two or three split edges are cut
+    // into two, three or four. Returns whether anything was split.
+    fn refine(&mut self, split: impl Fn(Vec3, Vec3) -> bool) -> bool {
+        let mut midpoints: HashMap<(u32, u32), u32> = HashMap::new();
+        let mut midpoint = |t: &mut Tessellation, i: u32, j: u32| -> Option<u32> {
+            let (pi, pj) = (t.positions[i as usize], t.positions[j as usize]);
+            if !split(pi, pj) {
+                return None;
+            }
+            let key = (i.min(j), i.max(j));
+            Some(*midpoints.entry(key).or_insert_with(|| {
+                t.positions.push((pi + pj) * 0.5);
+                let normal = t.normals[i as usize] + t.normals[j as usize];
+                t.normals.push(normal.normalize());
+                if !t.uv.is_empty() {
+                    t.uv.push((t.uv[i as usize] + t.uv[j as usize]) * 0.5);
+                }
+                (t.positions.len() - 1) as u32
+            }))
+        };
+
+        let faces = std::mem::take(&mut self.indices);
+        let mut indices = Vec::with_capacity(faces.len());
+        for face in faces.chunks_exact(3) {
+            let (a, b, c) = (face[0], face[1], face[2]);
+            let triangles: &[[u32; 3]] = match (midpoint(self, a, b), midpoint(self, b, c), midpoint(self, c, a)) {
+                (None, None, None) => &[[a, b, c]],
+                (Some(ab), None, None) => &[[a, ab, c], [ab, b, c]],
+                (None, Some(bc), None) => &[[a, b, bc], [a, bc, c]],
+                (None, None, Some(ca)) => &[[a, b, ca], [ca, b, c]],
+                (Some(ab), Some(bc), None) => &[[ab, b, bc], [a, ab, bc], [a, bc, c]],
+                (None, Some(bc), Some(ca)) => &[[ca, bc, c], [a, b, bc], [a, bc, ca]],
+                (Some(ab), None, Some(ca)) => &[[a, ab, ca], [ab, b, c], [ab, c, ca]],
+                (Some(ab), Some(bc), Some(ca)) => &[[a, ab, ca], [ab, b, bc], [ca, bc, c], [ab, bc, ca]],
+            };
+            indices.extend(triangles.iter().flatten());
+        }
+        let refined = indices.len() > faces.len();
+        self.indices = indices;
+        refined
+    }
+}
+
+// Mesh tessellated finely enough for the displacement to show, then moved
+// along its normals. Like the mesh BVH, the tessellation waits for the
+// first intersection.
+#[derive(Clone)]
+pub struct DisplacedMesh {
+    pub cage: Arc<Mesh>,
+    pub displacement: Displacement,
+    refined: OnceLock<Mesh>,
+}
+
+impl DisplacedMesh {
+    pub fn new(cage: Arc<Mesh>, displacement: Displacement) -> Self {
+        DisplacedMesh {
+            cage,
+            displacement,
+            refined: OnceLock::new(),
+        }
+    }
+
+    pub fn refined(&self) -> &Mesh {
+        self.refined.get_or_init(|| displace(&self.cage, &self.displacement))
+    }
+
+    pub fn transform(&mut self, matrix: &math::Mat4) {
+        Arc::make_mut(&mut self.cage).transform(matrix);
+        self.refined = OnceLock::new();
+    }
+
+    pub fn intersect(&self, ray: &Ray, isect: &mut SurfaceInteraction) -> bool {
+        self.refined().intersect(ray, isect)
+    }
+
+    // From the cage, so building the scene doesn't tessellate
+    pub fn world_bound(&self) -> Bounds3f {
+        self.cage.world_bound().expand(self.displacement.bound)
+    }
+
+    pub fn area(&self) -> Float {
+        self.refined().area()
+    }
+
+    pub fn sample(&self, u: &Vec2) -> ShapeSample {
+        self.refined().sample(u)
+    }
+}
+
+impl std::fmt::Debug for DisplacedMesh {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DisplacedMesh")
+            .field("cage", &self.cage)
+            .field("bound", &self.displacement.bound)
+            .field("edge_length", &self.displacement.edge_length)
+            .finish()
+    }
+}
+
+impl PartialEq for DisplacedMesh {
+    fn eq(&self, other: &Self) -> bool {
+        self.cage == other.cage
+            && Arc::ptr_eq(&self.displacement.texture, &other.displacement.texture)
+            && self.displacement.bound == other.displacement.bound
+    }
+}
+
+// Splits the triangles of `mesh` until their edges are short enough, then
+// moves every vertex along its normal by the displacement there
+pub fn displace(mesh: &Mesh, displacement: &Displacement) -> Mesh {
+    let mut tessellation = Tessellation::new(mesh);
+    for _ in 0..displacement.max_level {
+        if !tessellation.refine(|a, b| displacement.should_split(a, b)) {
+            break;
+        }
+    }
+
+    let Tessellation { positions, normals, uv, indices } = tessellation;
+    let positions = positions
+        .iter()
+        .zip(&normals)
+        .enumerate()
+        .map(|(i, (&point, &normal))| {
+            let mut si = SurfaceInteraction::new();
+            si.hit_point = point;
+            si.hit_object_point = point;
+            si.hit_normal = normal;
+            si.hit_uv = uv.get(i).copied().unwrap_or(Vec2::from(0.));
+            let height = displacement.texture.evaluate(&si);
+            point + normal * height.clamp(-displacement.bound, displacement.bound)
+        })
+        .collect();
+    let mut displaced = Mesh::new(positions, indices);
+    displaced.uv = uv;
+    displaced.base_color_texture = mesh.base_color_texture.clone();
+    displaced.metallic_roughness_texture = mesh.metallic_roughness_texture.clone();
+    displaced.normal_texture = mesh.normal_texture.clone();
+    displaced
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::textures::constant::ConstantTexture;
+
+    #[test]
+    fn test_displacement() {
+        // A unit square of two triangles facing z
+        let square = Mesh::new(
+            vec![Vec3::new(0., 0., 0.), Vec3::new(1., 0., 0.), Vec3::new(1., 1., 0.), Vec3::new(0., 1., 0.)],
+            vec![0, 1, 2, 0, 2, 3],
+        );
+        let mut displacement = Displacement::new(Arc::new(ConstantTexture::new(0.25)), 0.1);
+        displacement.max_level = 2;
+        let displaced = displace(&square, &displacement);
+        // Two rounds of splitting every edge, pushed up to the bound
+        assert_eq!(displaced.indices.len() / 3, 2 * 16);
+        assert_eq!(displaced.positions.len(), 25);
+        assert!(displaced.positions.iter().all(|p| (p.z - 0.1).abs() < 1e-6));
+        assert!((displaced.area() - 1.).abs() < 1e-4);
+
+        // Seen from close by only the near edges are split, and the shared
+        // ones the same way from both sides
+        displacement.max_level = 8;
+        displacement.metric = Some(EdgeMetric {
+            eye: Vec3::new(0., 0., 1.),
+            units_per_pixel: 0.,
+            radians_per_pixel: 0.02,
+        });
+        displacement.edge_length = 4.;
+        let adaptive = displace(&square, &displacement);
+        let mut edges: HashMap<(u32, u32), u32> = HashMap::new();
+        for face in adaptive.indices.chunks_exact(3) {
+            for k in 0..3 {
+                let (i, j) = (face[k], face[(k + 1) % 3]);
+                *edges.entry((i.min(j), i.max(j))).or_default() += 1;
+            }
+        }
+        // Inner edges have two faces, the boundary ones a single face. A
+        // T-junction would leave an inner edge with only one.
+        let boundary = |i: u32| {
+            let p = adaptive.positions[i as usize];
+            p.x.abs() < 1e-6 || (p.x - 1.).abs() < 1e-6 || p.y.abs() < 1e-6 || (p.y - 1.).abs() < 1e-6
+        };
+        for (&(i, j), &count) in &edges {
+            let on_boundary = boundary(i) && boundary(j) && {
+                let (a, b) = (adaptive.positions[i as usize], adaptive.positions[j as usize]);
+                (a.x - b.x).abs() < 1e-6 || (a.y - b.y).abs() < 1e-6
+            };
+            assert_eq!(count, if on_boundary { 1 } else { 2 });
+        }
+        let near = |p: &Vec3| p.x < 0.25 && p.y < 0.25;
+        let far = |p: &Vec3| p.x > 0.75 && p.y > 0.75;
+        assert!(adaptive.positions.iter().filter(|p| near(p)).count() > adaptive.positions.iter().filter(|p| far(p)).count());
+
+        let shape = DisplacedMesh::new(Arc::new(square), displacement);
+        assert_eq!(shape.world_bound().p_max.z, 0.1);
+    }
+}
@@ -1,18 +1,36 @@
+use std::sync::OnceLock;
+
+use crate::core::bounds::Bounds3f;
+use crate::core::bvh::Bvh;
+use crate::core::color_space::ColorEncoding;
 use crate::core::interaction::SurfaceInteraction;
 use crate::core::ray::Ray;
+use crate::core::shape::ShapeSample;
+use crate::core::texture::ImageTexture;
 use crate::loaders::gltf_loader::GData;
 use crate::shapes::triangle::Triangle;
 use gltf;
 use image::io::Reader as ImageReader;
 use log::info;
 use math::{Float, Vec2, Vec3};
+use mesh_loader::PolygonMesh;
 
 #[derive(Clone, PartialEq, Debug)]
 pub struct Mesh {
     pub indices: Vec<u32>,
     pub positions: Vec<Vec3>,
     pub uv: Vec<Vec2>,
-    pub base_color_texture: image::DynamicImage,
+    // Shading normals at the vertices, interpolated over the triangles.
+    // Without them the triangles look flat.
+    pub normals: Vec<Vec3>,
+    // Base color, sRGB encoded
+    pub base_color_texture: Option<ImageTexture>,
+    // Data maps, linear: roughness in green and metalness in blue, and
+    // tangent space normals. Loaded, not shaded with yet.
+    pub metallic_roughness_texture: Option<ImageTexture>,
+    pub normal_texture: Option<ImageTexture>,
+    // Over the triangles, built by the first intersection
+    bvh: OnceLock<Bvh>,
 }
 
 fn load_image_from_path(
@@ -28,13 +46,33 @@ fn load_image_from_path(
     ))
 }
 
+// Image of a glTF texture, files only, relative to the glTF file
+fn load_gltf_texture(texture: &gltf::Texture, data: &GData, encoding: ColorEncoding) -> Option<ImageTexture> {
+    match texture.source().source() {
+        gltf::image::Source::View { view, mime_type: _ } => {
+            info!("Image source (view): {:?}", view);
+            None
+        }
+        gltf::image::Source::Uri { uri, mime_type: _ } => {
+            let path = data.directory.join(uri);
+            info!("Image source (uri): {:?}", path.clone());
+            let image = ImageReader::open(path).unwrap().decode().unwrap();
+            Some(ImageTexture::new(&image, encoding))
+        }
+    }
+}
+
 impl Mesh {
     pub fn new(positions: Vec<Vec3>, indices: Vec<u32>) -> Self {
         Self {
             indices: indices,
             positions: positions,
             uv: Vec::new(),
-            base_color_texture: image::DynamicImage::new_rgb8(1, 1),
+            normals: Vec::new(),
+            base_color_texture: None,
+            metallic_roughness_texture: None,
+            normal_texture: None,
+            bvh: OnceLock::new(),
         }
     }
 
@@ -45,19 +83,17 @@ impl Mesh {
     //     shape.params
     // }
 
-    pub fn from_ply(filepath: &std::path::Path) {
+    // Faces with more than three corners are split in fans
+    pub fn from_polygons(polygons: &PolygonMesh) -> Self {
+        let mut mesh = Mesh::new(polygons.positions.clone(), polygons.triangulate());
+        mesh.uv = polygons.uv.clone();
+        mesh.normals = polygons.normals.clone();
+        mesh
+    }
+
+    pub fn from_ply(filepath: &std::path::Path) -> Result<Self, String> {
         info!("Parse ply model file path {:?}", filepath.to_str());
-        let ply_model = mesh_loader::parse_ply(filepath);
-        match ply_model {
-            Ok(mut ply_model) => {
-                for entry in ply_model.payload.entries().into_iter() {
-                    println!("K: {}, V: {:?}", entry.key(), entry.get().first());
-                }
-            }
-            Err(e) => {
-                println!("Error: {}", e);
-            }
-        }
+        Ok(Mesh::from_polygons(&mesh_loader::parse_ply(filepath)?))
     }
 
     pub fn from_gltf(primitive: &gltf::Primitive, data: &GData) -> Self {
@@ -66,6 +102,7 @@ impl Mesh {
         let mut positions: Vec<Vec3> = vec![];
         let mut indices: Vec<u32> = vec![];
         let mut uv: Vec<Vec2> = vec![];
+        let mut normals: Vec<Vec3> = vec![];
 
         let reader = primitive.reader(|buffer| Some(&data.buffers[buffer.index()]));
 
@@ -83,6 +120,13 @@ impl Mesh {
             }
         }
 
+        // Normals
+        if let Some(iter) = reader.read_normals() {
+            for normal in iter {
+                normals.push(Vec3::from(&normal));
+            }
+        }
+
         // UVs
         // TODO: Need to read from multiple UVs sets
         if let Some(read_tex_coords) = reader.read_tex_coords(0) {
@@ -111,75 +155,146 @@ impl Mesh {
         }
 
         // Textures
-        let mut base_color_texture = image::DynamicImage::new_rgb8(1, 1);
-        if let Some(texture) = primitive
-            .material()
-            .pbr_metallic_roughness()
+        // Color maps are sRGB encoded, data maps like roughness and normals
+        // are linear
+        // TODO: Support multiple uv sets, texture.tex_coord()
+        let material = primitive.material();
+        let pbr = material.pbr_metallic_roughness();
+        let base_color_texture = pbr
             .base_color_texture()
-        {
-            // TODO: Support multiple uv sets base_color_texture.tex_coord()
-            match texture.texture().source().source() {
-                gltf::image::Source::View { view, mime_type: _ } => {
-                    info!("Image source (view): {:?}", view);
-                }
-                gltf::image::Source::Uri { uri, mime_type: _ } => {
-                    // TODO: Convert source path to a parameter that's passed in for loading mesh.
-                    let path = "assets/glTF/CesiumMilkTruck/glTF/".to_owned() + uri;
-                    info!("Image source (uri): {:?}", path.clone());
-                    base_color_texture = ImageReader::open(path).unwrap().decode().unwrap();
-                    // let base_color = base_color_texture.clone().into_rgb8().get_pixel(1, 1);
-                    // let image_buffer = base_color_texture.to_rgba8();
-                    // let pixels = image_buffer.as_flat_samples();
-                }
-            }
-        }
+            .and_then(|info| load_gltf_texture(&info.texture(), data, ColorEncoding::Srgb));
+        let metallic_roughness_texture = pbr
+            .metallic_roughness_texture()
+            .and_then(|info| load_gltf_texture(&info.texture(), data, ColorEncoding::Linear));
+        let normal_texture = material
+            .normal_texture()
+            .and_then(|normal| load_gltf_texture(&normal.texture(), data, ColorEncoding::Linear));
 
         Self {
             indices,
             positions,
             uv,
+            normals,
             base_color_texture,
+            metallic_roughness_texture,
+            normal_texture,
+            bvh: OnceLock::new(),
         }
     }
 
-    pub fn intersect(&self, ray: &Ray, isect: &mut SurfaceInteraction) -> bool {
-        let mut hit = false;
-        let mut nearest_t = 9999.9;
-        let mut nearest_isect = SurfaceInteraction::new();
-        let max_index = self.indices.len() - 1;
+    // Moving the vertices after the first intersection needs a new BVH
+    pub fn transform(&mut self, matrix: &math::Mat4) {
+        for position in self.positions.iter_mut() {
+            *position = *matrix * (*position);
+        }
+        if let Some(inverse) = matrix.inverse() {
+            for normal in self.normals.iter_mut() {
+                *normal = inverse.transpose().transform_vector(*normal).normalize();
+            }
+        }
+        self.bvh = OnceLock::new();
+    }
 
-        for i in (0..max_index).step_by(3) {
-            let triangle = Triangle::new(
-                self.positions[self.indices[i] as usize],
-                self.positions[self.indices[i + 1] as usize],
-                self.positions[self.indices[i + 2] as usize],
-            );
+    // Same triangles with the vertices moved, like a deformed pose
+    pub fn with_vertices(&self, positions: Vec<Vec3>, normals: Vec<Vec3>) -> Mesh {
+        Mesh {
+            indices: self.indices.clone(),
+            positions,
+            uv: self.uv.clone(),
+            normals,
+            base_color_texture: self.base_color_texture.clone(),
+            metallic_roughness_texture: self.metallic_roughness_texture.clone(),
+            normal_texture: self.normal_texture.clone(),
+            bvh: OnceLock::new(),
+        }
+    }
 
-            let tri_hit = triangle.intersect(ray, isect);
-            if tri_hit && isect.t < nearest_t {
-                hit = true;
-                nearest_t = isect.t;
+    fn triangle(&self, face: usize) -> Triangle {
+        Triangle::new(
+            self.positions[self.indices[3 * face] as usize],
+            self.positions[self.indices[3 * face + 1] as usize],
+            self.positions[self.indices[3 * face + 2] as usize],
+        )
+    }
 
-                nearest_isect.t = isect.t;
-                nearest_isect.hit_point = isect.hit_point;
-                nearest_isect.hit_normal = isect.hit_normal;
+    // Derivatives of the position of a face along its texture coordinates,
+    // None when the uvs of its corners are degenerate
+    fn uv_derivatives(&self, face: usize, st0: Vec2, st1: Vec2, st2: Vec2) -> Option<(Vec3, Vec3)> {
+        let triangle = self.triangle(face);
+        let (duv02, duv12) = (st0 - st2, st1 - st2);
+        let (dp02, dp12) = (triangle.v0 - triangle.v2, triangle.v1 - triangle.v2);
+        let determinant = duv02.0 * duv12.1 - duv02.1 * duv12.0;
+        if determinant.abs() < 1e-9 {
+            return None;
+        }
+        let dpdu = (duv12.1 * dp02 - duv02.1 * dp12) / determinant;
+        let dpdv = (duv02.0 * dp12 - duv12.0 * dp02) / determinant;
+        Some((dpdu, dpdv))
+    }
 
-                // texture coordinates
+    pub fn intersect(&self, ray: &Ray, isect: &mut SurfaceInteraction) -> bool {
+        let bvh = self.bvh.get_or_init(|| {
+            let bounds: Vec<Bounds3f> = (0..self.indices.len() / 3)
+                .map(|face| self.triangle(face).world_bound())
+                .collect();
+            Bvh::new(&bounds)
+        });
+
+        let mut nearest_isect = SurfaceInteraction::new();
+        let hit = bvh.intersect(ray, Float::MAX, |face| {
+            let mut tri_isect = SurfaceInteraction::new();
+            if !self.triangle(face).intersect(ray, &mut tri_isect) {
+                return None;
+            }
+            if nearest_isect.t >= 0. && tri_isect.t >= nearest_isect.t {
+                return None;
+            }
+            nearest_isect.t = tri_isect.t;
+            nearest_isect.hit_point = tri_isect.hit_point;
+            nearest_isect.hit_normal = tri_isect.hit_normal;
+            nearest_isect.hit_front_face = tri_isect.hit_front_face;
+            (nearest_isect.dpdu, nearest_isect.dpdv) = (tri_isect.dpdu, tri_isect.dpdv);
+
+            // texture coordinates, meshes without them keep the barycentric
+            // ones
+            let (b1, b2) = (tri_isect.hit_uv.x(), tri_isect.hit_uv.y());
+            // Shading normal on the side of the geometric one, which faces
+            // the ray
+            if !self.normals.is_empty() {
+                let i = 3 * face;
+                let normal = (1.0 - b1 - b2) * self.normals[self.indices[i] as usize]
+                    + b1 * self.normals[self.indices[i + 1] as usize]
+                    + b2 * self.normals[self.indices[i + 2] as usize];
+                if normal.length2() > 0. {
+                    let normal = normal.normalize();
+                    nearest_isect.hit_normal = if Vec3::dot(normal, tri_isect.hit_normal) < 0. { -normal } else { normal };
+                }
+            }
+            nearest_isect.hit_uv = if self.uv.is_empty() {
+                tri_isect.hit_uv
+            } else {
+                let i = 3 * face;
                 let st0 = self.uv[self.indices[i] as usize];
                 let st1 = self.uv[self.indices[i + 1] as usize];
                 let st2 = self.uv[self.indices[i + 2] as usize];
-                let hit_uv = (1.0 - isect.hit_uv.x() - isect.hit_uv.y()) * st0
-                    + isect.hit_uv.x() * st1
-                    + isect.hit_uv.y() * st2;
-                nearest_isect.hit_uv = hit_uv;
-            }
-        }
+                if let Some((dpdu, dpdv)) = self.uv_derivatives(face, st0, st1, st2) {
+                    (nearest_isect.dpdu, nearest_isect.dpdv) = (dpdu, dpdv);
+                }
+                (1.0 - b1 - b2) * st0 + b1 * st1 + b2 * st2
+            };
+            Some(tri_isect.t)
+        });
 
-        isect.t = nearest_isect.t;
-        isect.hit_point = nearest_isect.hit_point;
-        isect.hit_normal = nearest_isect.hit_normal;
-        isect.hit_uv = nearest_isect.hit_uv;
-        return hit;
+        if hit {
+            isect.t = nearest_isect.t;
+            isect.hit_point = nearest_isect.hit_point;
+            isect.hit_normal = nearest_isect.hit_normal;
+            isect.hit_front_face = nearest_isect.hit_front_face;
+            isect.hit_uv = nearest_isect.hit_uv;
+            isect.dpdu = nearest_isect.dpdu;
+            isect.dpdv = nearest_isect.dpdv;
+        }
+        hit
     }
 
     pub fn world_bound(&self) -> crate::core::bounds::Bounds3f {
@@ -193,4 +308,34 @@ impl Mesh {
 
         crate::core::bounds::Bounds3f { p_min, p_max }
     }
+
+    pub fn area(&self) -> Float {
+        (0..self.indices.len() / 3).map(|face| self.triangle(face).area()).sum()
+    }
+
+    // Picks a triangle by its area with the first number, which is then
+    // stretched back to [0, 1) to place the point inside it
+    pub fn sample(&self, u: &Vec2) -> ShapeSample {
+        let face_count = self.indices.len() / 3;
+        let total_area = self.area();
+        let mut remaining = u.0 * total_area;
+        for face in 0..face_count {
+            let triangle = self.triangle(face);
+            let area = triangle.area();
+            if remaining < area || face == face_count - 1 {
+                let u0 = if area > 0. { (remaining / area).clamp(0., 1.) } else { 0. };
+                let sample = triangle.sample(&Vec2::new(&[u0, u.1]));
+                return ShapeSample {
+                    pdf: 1. / total_area,
+                    ..sample
+                };
+            }
+            remaining -= area;
+        }
+        ShapeSample {
+            point: Vec3::zero(),
+            normal: Vec3::new(0., 1., 0.),
+            pdf: 0.,
+        }
+    }
 }
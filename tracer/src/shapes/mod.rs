@@ -1,3 +1,15 @@
+pub mod analytic;
+pub mod cone;
+pub mod csg;
+pub mod cylinder;
+pub mod disk;
+pub mod displaced;
 pub mod mesh;
+pub mod paraboloid;
+pub mod quad;
+pub mod sdf;
+pub mod skinning;
 pub mod sphere;
+pub mod subdivision;
+pub mod torus;
 pub mod triangle;
@@ -0,0 +1,130 @@
+use math::{Float, Vec2, Vec3};
+
+use crate::core::bounds::Bounds3f;
+use crate::core::interaction::SurfaceInteraction;
+use crate::core::ray::Ray;
+use crate::core::shape::ShapeSample;
+use crate::shapes::analytic::{phi_of, solve_quadratic, Placement};
+
+// Bowl x^2 + y^2 = c z opening towards +z, `radius` wide at `z_max` and cut
+// off below `z_min`, swept up to `phi_max`
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Paraboloid {
+    pub radius: Float,
+    pub z_min: Float,
+    pub z_max: Float,
+    pub phi_max: Float,
+    pub placement: Placement,
+}
+
+impl Paraboloid {
+    pub fn new(radius: Float, z_min: Float, z_max: Float, phi_max_degrees: Float, placement: Placement) -> Self {
+        Self {
+            radius,
+            z_min: z_min.min(z_max).max(0.),
+            z_max: z_min.max(z_max),
+            phi_max: phi_max_degrees.clamp(0., 360.).to_radians(),
+            placement,
+        }
+    }
+
+    fn c(&self) -> Float {
+        self.radius * self.radius / self.z_max
+    }
+
+    // Outward normal, away from the inside of the bowl
+    fn normal_at(&self, p: Vec3) -> Vec3 {
+        Vec3::new(2. * p.x, 2. * p.y, -self.c())
+    }
+
+    pub fn intersect(&self, ray: &Ray, isect: &mut SurfaceInteraction) -> bool {
+        const T_MIN: Float = 1e-3;
+
+        let object_ray = self.placement.ray_to_object(ray);
+        let (o, d) = (object_ray.origin, object_ray.direction);
+        let c = self.c();
+        let Some((t0, t1)) = solve_quadratic(
+            d.x * d.x + d.y * d.y,
+            2. * (d.x * o.x + d.y * o.y) - c * d.z,
+            o.x * o.x + o.y * o.y - c * o.z,
+        ) else {
+            return false;
+        };
+
+        for t in [t0, t1] {
+            if t <= T_MIN {
+                continue;
+            }
+            let p = object_ray.point_at(t);
+            let phi = phi_of(p);
+            if p.z < self.z_min || p.z > self.z_max || phi > self.phi_max {
+                continue;
+            }
+            let uv = (phi / self.phi_max, (p.z - self.z_min) / (self.z_max - self.z_min));
+            self.placement.record_hit(ray, t, self.normal_at(p), uv, isect);
+            return true;
+        }
+        false
+    }
+
+    pub fn world_bound(&self) -> Bounds3f {
+        self.placement.bounds_to_world(&Bounds3f {
+            p_min: Vec3::new(-self.radius, -self.radius, self.z_min),
+            p_max: Vec3::new(self.radius, self.radius, self.z_max),
+        })
+    }
+
+    // Area of the surface up to height z, times 3 c / (2 phi_max)
+    fn area_to(&self, z: Float) -> Float {
+        let c = self.c();
+        (c * z + 0.25 * c * c).powf(1.5)
+    }
+
+    pub fn area(&self) -> Float {
+        let object_area = self.phi_max * 2. / (3. * self.c()) * (self.area_to(self.z_max) - self.area_to(self.z_min));
+        object_area * self.placement.area_scale()
+    }
+
+    // Uniform over the area, the height is found by inverting the area below
+    // it
+    pub fn sample(&self, u: &Vec2) -> ShapeSample {
+        let c = self.c();
+        let area_below = self.area_to(self.z_min) + u.0 * (self.area_to(self.z_max) - self.area_to(self.z_min));
+        let z = ((area_below.powf(2. / 3.) - 0.25 * c * c) / c).clamp(self.z_min, self.z_max);
+        let r = (c * z).sqrt();
+        let phi = u.1 * self.phi_max;
+        let p = Vec3::new(r * phi.cos(), r * phi.sin(), z);
+        ShapeSample {
+            point: self.placement.point_to_world(p),
+            normal: self.placement.normal_to_world(self.normal_at(p)),
+            pdf: 1. / self.area(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    #[test]
+    fn test_paraboloid() {
+        // z = x^2 + y^2 up to z = 1
+        let paraboloid = Paraboloid::new(1., 0., 1., 360., Placement::default());
+        let expected_area = PI / 6. * ((5. as Float).powf(1.5) - 1.);
+        assert!((paraboloid.area() - expected_area).abs() < 1e-4);
+
+        // Down into the bowl, it is seen from inside
+        let mut isect = SurfaceInteraction::new();
+        assert!(paraboloid.intersect(&Ray::new(Vec3::new(0.5, 0., 5.), Vec3::new(0., 0., -1.)), &mut isect));
+        assert!((isect.t - 4.75).abs() < 1e-4);
+        assert!(!isect.hit_front_face);
+        assert!((isect.hit_normal - Vec3::new(1., 0., -1.).normalize()).length() < 1e-4);
+
+        // Samples are on the surface and split the area in proportion
+        let half = paraboloid.sample(&Vec2::new(&[0.5, 0.]));
+        assert!((half.point.x * half.point.x - half.point.z).abs() < 1e-4);
+        let below = Paraboloid::new(half.point.z.sqrt(), 0., half.point.z, 360., Placement::default());
+        assert!((below.area() - 0.5 * expected_area).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,138 @@
+use math::{Float, Vec2, Vec3};
+
+use crate::core::bounds::Bounds3f;
+use crate::core::interaction::SurfaceInteraction;
+use crate::core::ray::Ray;
+use crate::core::shape::ShapeSample;
+
+// Parallelogram spanned by two edges from a corner, in world space. It faces
+// the side of `edge_u` x `edge_v`.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Quad {
+    pub corner: Vec3,
+    pub edge_u: Vec3,
+    pub edge_v: Vec3,
+}
+
+impl Quad {
+    pub fn new(corner: Vec3, edge_u: Vec3, edge_v: Vec3) -> Self {
+        Self { corner, edge_u, edge_v }
+    }
+
+    // Rectangle between two corners that are level along one axis, facing
+    // the positive side of that axis
+    pub fn axis_aligned(p_min: Vec3, p_max: Vec3) -> Self {
+        let extent = p_max - p_min;
+        let extents = [extent.x.abs(), extent.y.abs(), extent.z.abs()];
+        let flat = (0..3)
+            .min_by(|&a, &b| extents[a].total_cmp(&extents[b]))
+            .unwrap_or(2);
+        // The other two axes in cyclic order, so their cross product is the
+        // flat one
+        let axis = |i: usize| {
+            let mut edge = Vec3::zero();
+            match i % 3 {
+                0 => edge.x = extent.x,
+                1 => edge.y = extent.y,
+                _ => edge.z = extent.z,
+            }
+            edge
+        };
+        Self::new(p_min, axis(flat + 1), axis(flat + 2))
+    }
+
+    pub fn normal(&self) -> Vec3 {
+        Vec3::cross(self.edge_u, self.edge_v).normalize()
+    }
+
+    pub fn intersect(&self, ray: &Ray, isect: &mut SurfaceInteraction) -> bool {
+        const T_MIN: Float = 1e-3;
+
+        let n = Vec3::cross(self.edge_u, self.edge_v);
+        let denominator = Vec3::dot(n, ray.direction);
+        if denominator.abs() < 1e-12 {
+            return false;
+        }
+        let t = Vec3::dot(n, self.corner - ray.origin) / denominator;
+        if t <= T_MIN || t.is_nan() {
+            return false;
+        }
+
+        // Coordinates of the hit along the edges
+        let q = ray.point_at(t) - self.corner;
+        let w = n / Vec3::dot(n, n);
+        let alpha = Vec3::dot(w, Vec3::cross(q, self.edge_v));
+        let beta = Vec3::dot(w, Vec3::cross(self.edge_u, q));
+        if !(0. ..=1.).contains(&alpha) || !(0. ..=1.).contains(&beta) {
+            return false;
+        }
+
+        isect.t = t;
+        isect.hit_point = ray.point_at(t);
+        isect.hit_normal = n.normalize();
+        isect.hit_front_face = denominator < 0.;
+        isect.hit_uv = Vec2::new(&[alpha, beta]);
+        isect.dpdu = self.edge_u;
+        isect.dpdv = self.edge_v;
+        true
+    }
+
+    // Padded so axis-aligned quads have some thickness
+    pub fn world_bound(&self) -> Bounds3f {
+        let mut bounds = Bounds3f {
+            p_min: self.corner,
+            p_max: self.corner,
+        };
+        for point in [
+            self.corner + self.edge_u,
+            self.corner + self.edge_v,
+            self.corner + self.edge_u + self.edge_v,
+        ] {
+            bounds = bounds.union_point(point);
+        }
+        bounds.expand(1e-4)
+    }
+
+    pub fn area(&self) -> Float {
+        Vec3::cross(self.edge_u, self.edge_v).length()
+    }
+
+    pub fn sample(&self, u: &Vec2) -> ShapeSample {
+        ShapeSample {
+            point: self.corner + u.0 * self.edge_u + u.1 * self.edge_v,
+            normal: self.normal(),
+            pdf: 1. / self.area(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quad() {
+        // Floor tile facing up
+        let quad = Quad::axis_aligned(Vec3::new(-1., 0., -2.), Vec3::new(1., 0., 2.));
+        assert_eq!(quad.normal(), Vec3::new(0., 1., 0.));
+        assert_eq!(quad.area(), 8.);
+
+        let mut isect = SurfaceInteraction::new();
+        assert!(quad.intersect(&Ray::new(Vec3::new(0.5, 3., 1.), Vec3::new(0., -1., 0.)), &mut isect));
+        assert_eq!(isect.t, 3.);
+        assert!(isect.hit_front_face);
+        // u runs along z and v along x
+        assert!((isect.hit_uv.x() - 0.75).abs() < 1e-5);
+        assert!((isect.hit_uv.y() - 0.75).abs() < 1e-5);
+        assert!(!quad.intersect(&Ray::new(Vec3::new(1.5, 3., 1.), Vec3::new(0., -1., 0.)), &mut SurfaceInteraction::new()));
+
+        // A tilted one is hit where its edges say
+        let tilted = Quad::new(Vec3::zero(), Vec3::new(1., 1., 0.), Vec3::new(0., 0., 1.));
+        let sample = tilted.sample(&Vec2::new(&[0.5, 0.5]));
+        assert_eq!(sample.point, Vec3::new(0.5, 0.5, 0.5));
+        let mut isect = SurfaceInteraction::new();
+        assert!(tilted.intersect(&Ray::new(Vec3::new(2., -1., 0.5), Vec3::new(-1., 1., 0.).normalize()), &mut isect));
+        assert!((isect.hit_point - sample.point).length() < 1e-5);
+        assert!(isect.hit_front_face);
+    }
+}
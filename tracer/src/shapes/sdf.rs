@@ -0,0 +1,279 @@
+use std::sync::Arc;
+
+use math::{Float, Vec3};
+use std::f32::consts::PI;
+
+use crate::core::bounds::Bounds3f;
+use crate::core::interaction::SurfaceInteraction;
+use crate::core::ray::Ray;
+use crate::shapes::analytic::Placement;
+
+// Graph of a signed distance field, negative inside. Primitives are centered
+// on the origin, with the y axis up for the torus and the twist.
+#[derive(Clone, PartialEq, Debug)]
+pub enum SdfNode {
+    Sphere { radius: Float },
+    Box { half_extents: Vec3 },
+    Capsule { a: Vec3, b: Vec3, radius: Float },
+    Torus { major_radius: Float, minor_radius: Float },
+    Translate { offset: Vec3, node: Box<SdfNode> },
+    Union(Box<SdfNode>, Box<SdfNode>),
+    // The first one with the second one carved out
+    Subtraction(Box<SdfNode>, Box<SdfNode>),
+    Intersection(Box<SdfNode>, Box<SdfNode>),
+    // Union blended over `radius`
+    SmoothUnion { a: Box<SdfNode>, b: Box<SdfNode>, radius: Float },
+    // Copies of the node in cells of `period`, axes with a period of 0 aren't
+    // repeated. The node should fit in its cell.
+    Repeat { period: Vec3, node: Box<SdfNode> },
+    // Turned around y by `rate` radians per unit of height
+    Twist { rate: Float, node: Box<SdfNode> },
+}
+
+impl SdfNode {
+    pub fn translate(self, offset: Vec3) -> SdfNode {
+        SdfNode::Translate { offset, node: Box::new(self) }
+    }
+
+    pub fn union(self, other: SdfNode) -> SdfNode {
+        SdfNode::Union(Box::new(self), Box::new(other))
+    }
+
+    pub fn subtract(self, other: SdfNode) -> SdfNode {
+        SdfNode::Subtraction(Box::new(self), Box::new(other))
+    }
+
+    pub fn intersect(self, other: SdfNode) -> SdfNode {
+        SdfNode::Intersection(Box::new(self), Box::new(other))
+    }
+
+    pub fn smooth_union(self, other: SdfNode, radius: Float) -> SdfNode {
+        SdfNode::SmoothUnion {
+            a: Box::new(self),
+            b: Box::new(other),
+            radius,
+        }
+    }
+
+    pub fn repeat(self, period: Vec3) -> SdfNode {
+        SdfNode::Repeat { period, node: Box::new(self) }
+    }
+
+    pub fn twist(self, rate: Float) -> SdfNode {
+        SdfNode::Twist { rate, node: Box::new(self) }
+    }
+
+    pub fn distance(&self, p: Vec3) -> Float {
+        match self {
+            SdfNode::Sphere { radius } => p.length() - radius,
+            SdfNode::Box { half_extents } => {
+                let q = Vec3::new(p.x.abs(), p.y.abs(), p.z.abs()) - *half_extents;
+                let outside = Vec3::component_max(q, Vec3::zero()).length();
+                let inside = q.x.max(q.y).max(q.z).min(0.);
+                outside + inside
+            }
+            SdfNode::Capsule { a, b, radius } => {
+                let (pa, ba) = (p - *a, *b - *a);
+                let h = (Vec3::dot(pa, ba) / Vec3::dot(ba, ba)).clamp(0., 1.);
+                (pa - h * ba).length() - radius
+            }
+            SdfNode::Torus {
+                major_radius,
+                minor_radius,
+            } => {
+                let ring = (p.x * p.x + p.z * p.z).sqrt() - major_radius;
+                (ring * ring + p.y * p.y).sqrt() - minor_radius
+            }
+            SdfNode::Translate { offset, node } => node.distance(p - *offset),
+            SdfNode::Union(a, b) => a.distance(p).min(b.distance(p)),
+            SdfNode::Subtraction(a, b) => a.distance(p).max(-b.distance(p)),
+            SdfNode::Intersection(a, b) => a.distance(p).max(b.distance(p)),
+            SdfNode::SmoothUnion { a, b, radius } => {
+                // Polynomial smooth minimum
+                let (da, db) = (a.distance(p), b.distance(p));
+                if *radius <= 0. {
+                    return da.min(db);
+                }
+                let h = (0.5 + 0.5 * (db - da) / radius).clamp(0., 1.);
+                db + h * (da - db) - radius * h * (1. - h)
+            }
+            SdfNode::Repeat { period, node } => {
+                let wrap = |x: Float, period: Float| if period > 0. { x - period * (x / period).round() } else { x };
+                node.distance(Vec3::new(wrap(p.x, period.x), wrap(p.y, period.y), wrap(p.z, period.z)))
+            }
+            SdfNode::Twist { rate, node } => {
+                let (sin, cos) = (rate * p.y).sin_cos();
+                node.distance(Vec3::new(cos * p.x - sin * p.z, p.y, sin * p.x + cos * p.z))
+            }
+        }
+    }
+
+    // How much faster than the distance to the surface the field can change,
+    // for points up to `radius` away from the y axis. Twisting stretches the
+    // space the further out it is.
+    pub fn lipschitz(&self, radius: Float) -> Float {
+        match self {
+            SdfNode::Sphere { .. } | SdfNode::Box { .. } | SdfNode::Capsule { .. } | SdfNode::Torus { .. } => 1.,
+            SdfNode::Translate { offset, node } => {
+                let shift = (offset.x * offset.x + offset.z * offset.z).sqrt();
+                node.lipschitz(radius + shift)
+            }
+            SdfNode::Union(a, b) | SdfNode::Subtraction(a, b) | SdfNode::Intersection(a, b) => {
+                a.lipschitz(radius).max(b.lipschitz(radius))
+            }
+            SdfNode::SmoothUnion { a, b, .. } => a.lipschitz(radius).max(b.lipschitz(radius)),
+            SdfNode::Repeat { period, node } => {
+                // Each copy only sees its own cell
+                let half_cell = |period: Float| if period > 0. { 0.5 * period } else { radius };
+                let cell_radius = (half_cell(period.x).powi(2) + half_cell(period.z).powi(2)).sqrt();
+                node.lipschitz(radius.min(cell_radius))
+            }
+            SdfNode::Twist { rate, node } => (1. + (rate * radius) * (rate * radius)).sqrt() * node.lipschitz(radius),
+        }
+    }
+}
+
+// Shape whose surface is the zero set of a distance field, found by sphere
+// tracing. The field is only looked at inside `bounds`, which must hold the
+// whole surface.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Sdf {
+    pub root: Arc<SdfNode>,
+    pub bounds: Bounds3f,
+    pub lipschitz: Float,
+    pub placement: Placement,
+}
+
+impl Sdf {
+    pub fn new(root: SdfNode, bounds: Bounds3f, placement: Placement) -> Self {
+        let radius = (0..8)
+            .map(|i| {
+                let corner = bounds.corner(i);
+                (corner.x * corner.x + corner.z * corner.z).sqrt()
+            })
+            .fold(0., Float::max);
+        Self {
+            lipschitz: root.lipschitz(radius).max(1.),
+            root: Arc::new(root),
+            bounds,
+            placement,
+        }
+    }
+
+    // Gradient of the field by central differences on a tetrahedron
+    pub fn normal_at(&self, p: Vec3) -> Vec3 {
+        const H: Float = 1e-4;
+        let offsets = [
+            Vec3::new(1., -1., -1.),
+            Vec3::new(-1., -1., 1.),
+            Vec3::new(-1., 1., -1.),
+            Vec3::new(1., 1., 1.),
+        ];
+        offsets
+            .iter()
+            .fold(Vec3::zero(), |gradient, &offset| gradient + self.root.distance(p + H * offset) * offset)
+            .normalize()
+    }
+
+    pub fn intersect(&self, ray: &Ray, isect: &mut SurfaceInteraction) -> bool {
+        const T_MIN: Float = 1e-3;
+        const HIT_DISTANCE: Float = 1e-4;
+        const MAX_STEPS: usize = 512;
+
+        let object_ray = self.placement.ray_to_object(ray);
+        let Some((t_enter, t_exit)) = self.bounds.intersect_range(&object_ray, Float::MAX) else {
+            return false;
+        };
+        let speed = object_ray.direction.length();
+        if speed == 0. {
+            return false;
+        }
+
+        // Rays starting inside march out to where the field turns positive
+        let mut t = t_enter.max(T_MIN);
+        let side = if t_enter <= T_MIN && self.root.distance(object_ray.point_at(t)) < 0. { -1. } else { 1. };
+        for _ in 0..MAX_STEPS {
+            if t > t_exit {
+                return false;
+            }
+            let p = object_ray.point_at(t);
+            let distance = side * self.root.distance(p) / self.lipschitz;
+            if distance < HIT_DISTANCE {
+                let normal = self.normal_at(p);
+                // Spherical mapping of the normal
+                let u = 0.5 + normal.z.atan2(normal.x) / (2. * PI);
+                let v = 0.5 + normal.y.clamp(-1., 1.).asin() / PI;
+                self.placement.record_hit(ray, t, normal, (u, v), isect);
+                return true;
+            }
+            t += distance / speed;
+        }
+        false
+    }
+
+    pub fn world_bound(&self) -> Bounds3f {
+        self.placement.bounds_to_world(&self.bounds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sdf() {
+        let unit_box = || SdfNode::Box {
+            half_extents: Vec3::new(1., 1., 1.),
+        };
+        let cube = |root: SdfNode| {
+            let bounds = Bounds3f {
+                p_min: Vec3::new(-2., -2., -2.),
+                p_max: Vec3::new(2., 2., 2.),
+            };
+            Sdf::new(root, bounds, Placement::default())
+        };
+        let along_z = |x: Float, y: Float| Ray::new(Vec3::new(x, y, -5.), Vec3::new(0., 0., 1.));
+
+        // Same hit and normal as the analytic sphere
+        let sphere = cube(SdfNode::Sphere { radius: 1. });
+        let mut isect = SurfaceInteraction::new();
+        assert!(sphere.intersect(&along_z(0.6, 0.), &mut isect));
+        assert!((isect.t - (5. - 0.8)).abs() < 1e-3);
+        assert!((isect.hit_normal - Vec3::new(0.6, 0., -0.8)).length() < 1e-2);
+        assert!(isect.hit_front_face);
+
+        // A hole drilled through a box lets the ray through the middle but
+        // not beside it
+        let drilled = cube(unit_box().subtract(SdfNode::Capsule {
+            a: Vec3::new(0., 0., -2.),
+            b: Vec3::new(0., 0., 2.),
+            radius: 0.5,
+        }));
+        assert!(!drilled.intersect(&along_z(0., 0.), &mut SurfaceInteraction::new()));
+        let mut isect = SurfaceInteraction::new();
+        assert!(drilled.intersect(&along_z(0.7, 0.), &mut isect));
+        assert!((isect.t - 4.).abs() < 1e-3);
+
+        // From inside the ray finds its way out
+        let mut isect = SurfaceInteraction::new();
+        assert!(cube(unit_box()).intersect(&Ray::new(Vec3::zero(), Vec3::new(1., 0., 0.)), &mut isect));
+        assert!((isect.t - 1.).abs() < 1e-3);
+        assert!(!isect.hit_front_face);
+
+        // Blending two spheres fills the gap between them
+        let a = SdfNode::Sphere { radius: 0.5 }.translate(Vec3::new(-0.6, 0., 0.));
+        let b = SdfNode::Sphere { radius: 0.5 }.translate(Vec3::new(0.6, 0., 0.));
+        assert!(a.clone().union(b.clone()).distance(Vec3::zero()) > 0.);
+        assert!(a.smooth_union(b, 0.5).distance(Vec3::zero()) < 0.);
+
+        // Copies every 3 units, and a twist slows the march down
+        let spheres = SdfNode::Sphere { radius: 0.5 }.repeat(Vec3::new(3., 0., 0.));
+        assert!((spheres.distance(Vec3::new(6.2, 0., 0.)) + 0.3).abs() < 1e-5);
+        assert!((spheres.distance(Vec3::new(7.5, 0., 0.)) - 1.).abs() < 1e-5);
+        let twisted = cube(unit_box().twist(1.));
+        assert!(twisted.lipschitz > 1.);
+        let mut isect = SurfaceInteraction::new();
+        assert!(twisted.intersect(&along_z(0., 0.), &mut isect));
+        assert!((isect.t - 4.).abs() < 1e-3);
+    }
+}
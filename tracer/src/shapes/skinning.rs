@@ -0,0 +1,254 @@
+use math::{Float, Mat4, Quaternion, Vec3};
+use strum_macros::{Display, EnumIter, EnumString};
+
+use crate::loaders::gltf_loader::GData;
+use crate::shapes::mesh::Mesh;
+
+// How the joints of a skin move a vertex. Linear blending averages the joint
+// matrices, which collapses volume around twisting joints. Dual quaternions
+// keep it but ignore the scale of the joints.
+#[derive(Debug, EnumIter, EnumString, PartialEq, Clone, Copy, Display, Default)]
+#[strum(ascii_case_insensitive)]
+pub enum SkinningMethod {
+    #[default]
+    LinearBlend,
+    DualQuaternion,
+}
+
+// Per vertex data moving a mesh away from its bind pose: up to four joints
+// with their weights, and the position and normal offsets of every morph
+// target.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct MeshDeformation {
+    pub joints: Vec<[u16; 4]>,
+    pub weights: Vec<[Float; 4]>,
+    pub morph_targets: Vec<Vec<Vec3>>,
+    pub morph_normals: Vec<Vec<Vec3>>,
+}
+
+fn offsets(offsets: Option<impl Iterator<Item = [f32; 3]>>) -> Vec<Vec3> {
+    match offsets {
+        Some(offsets) => offsets.map(|offset| Vec3::from(&offset)).collect(),
+        None => Vec::new(),
+    }
+}
+
+impl MeshDeformation {
+    pub fn from_gltf(primitive: &gltf::Primitive, data: &GData) -> Self {
+        let reader = primitive.reader(|buffer| Some(&data.buffers[buffer.index()]));
+        let joints = match reader.read_joints(0) {
+            Some(joints) => joints.into_u16().collect(),
+            None => Vec::new(),
+        };
+        let weights = match reader.read_weights(0) {
+            Some(weights) => weights.into_f32().collect(),
+            None => Vec::new(),
+        };
+        let (morph_targets, morph_normals) = reader
+            .read_morph_targets()
+            .map(|(positions, normals, _)| (offsets(positions), offsets(normals)))
+            .unzip();
+        Self {
+            joints,
+            weights,
+            morph_targets,
+            morph_normals,
+        }
+    }
+
+    pub fn is_skinned(&self) -> bool {
+        !self.joints.is_empty() && self.joints.len() == self.weights.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        !self.is_skinned() && self.morph_targets.is_empty()
+    }
+
+    // `mesh` moved by the morph targets blended with `morph_weights`, then by
+    // the joints when it is skinned. `joint_matrices` take the bind pose to
+    // where the joints are, so skinned meshes come out in their space. The
+    // normals of the mesh follow.
+    pub fn deform(&self, mesh: &Mesh, morph_weights: &[Float], joint_matrices: &[Mat4], method: SkinningMethod) -> Mesh {
+        let mut positions = mesh.positions.clone();
+        let mut normals = mesh.normals.clone();
+        for (target, &weight) in self.morph_targets.iter().zip(morph_weights) {
+            if weight == 0. || target.len() != positions.len() {
+                continue;
+            }
+            for (position, offset) in positions.iter_mut().zip(target) {
+                *position += weight * *offset;
+            }
+        }
+        for (target, &weight) in self.morph_normals.iter().zip(morph_weights) {
+            if weight == 0. || target.len() != normals.len() {
+                continue;
+            }
+            for (normal, offset) in normals.iter_mut().zip(target) {
+                *normal += weight * *offset;
+            }
+        }
+        let skin_normals = normals.len() == positions.len();
+
+        if self.is_skinned() && self.joints.len() == positions.len() && !joint_matrices.is_empty() {
+            let dual_quaternions: Vec<DualQuaternion> = match method {
+                SkinningMethod::DualQuaternion => joint_matrices.iter().map(DualQuaternion::from).collect(),
+                SkinningMethod::LinearBlend => Vec::new(),
+            };
+            for (i, position) in positions.iter_mut().enumerate() {
+                let influences = self.joints[i].iter().map(|&joint| joint as usize).zip(self.weights[i]);
+                *position = match method {
+                    // Normals by the inverse transpose, in case the blend
+                    // scales unevenly
+                    SkinningMethod::LinearBlend => {
+                        let mut matrix = Mat4::zero();
+                        for (joint, weight) in influences {
+                            matrix = matrix + joint_matrices[joint] * weight;
+                        }
+                        if skin_normals {
+                            let normal_matrix = matrix.inverse().map_or(matrix, |inverse| inverse.transpose());
+                            normals[i] = normal_matrix.transform_vector(normals[i]);
+                        }
+                        matrix * *position
+                    }
+                    SkinningMethod::DualQuaternion => {
+                        // Blend on the same side of the sphere as the first
+                        // joint, q and -q are the same rotation
+                        let pivot = dual_quaternions[self.joints[i][0] as usize].real;
+                        let mut blend = DualQuaternion::zero();
+                        for (joint, weight) in influences {
+                            let dual_quaternion = &dual_quaternions[joint];
+                            let sign = if Quaternion::dot(pivot, dual_quaternion.real) < 0. { -1. } else { 1. };
+                            blend.add(dual_quaternion, sign * weight);
+                        }
+                        if skin_normals {
+                            normals[i] = blend.rotate_vector(normals[i]);
+                        }
+                        blend.transform_point(*position)
+                    }
+                };
+            }
+        }
+        for normal in normals.iter_mut() {
+            *normal = normal.normalize();
+        }
+        mesh.with_vertices(positions, normals)
+    }
+}
+
+// Rotation followed by a translation, `dual` is half the translation times
+// the rotation
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct DualQuaternion {
+    real: Quaternion<Float>,
+    dual: Quaternion<Float>,
+}
+
+impl From<&Mat4> for DualQuaternion {
+    fn from(matrix: &Mat4) -> Self {
+        // Scale is dropped, the rotation comes from the unit columns
+        let mut rotation = *matrix;
+        for c in 0..3 {
+            let length = Vec3::new(matrix.m[0][c], matrix.m[1][c], matrix.m[2][c]).length();
+            for r in 0..3 {
+                rotation.m[r][c] /= length;
+            }
+        }
+        let real = Quaternion::from(&rotation);
+        let translation = Quaternion {
+            x: matrix.m[0][3],
+            y: matrix.m[1][3],
+            z: matrix.m[2][3],
+            w: 0.,
+        };
+        let dual = translation * real;
+        Self {
+            real,
+            dual: Quaternion {
+                x: 0.5 * dual.x,
+                y: 0.5 * dual.y,
+                z: 0.5 * dual.z,
+                w: 0.5 * dual.w,
+            },
+        }
+    }
+}
+
+impl DualQuaternion {
+    fn zero() -> Self {
+        let zero = Quaternion { x: 0., y: 0., z: 0., w: 0. };
+        Self { real: zero, dual: zero }
+    }
+
+    fn add(&mut self, other: &DualQuaternion, weight: Float) {
+        for (sum, q) in [(&mut self.real, other.real), (&mut self.dual, other.dual)] {
+            sum.x += weight * q.x;
+            sum.y += weight * q.y;
+            sum.z += weight * q.z;
+            sum.w += weight * q.w;
+        }
+    }
+
+    fn rotate_vector(&self, vector: Vec3) -> Vec3 {
+        Mat4::from(&self.real.normalize()).transform_vector(vector)
+    }
+
+    fn transform_point(&self, point: Vec3) -> Vec3 {
+        let length = Quaternion::dot(self.real, self.real).sqrt();
+        let real = self.real.normalize();
+        let dual = Quaternion {
+            x: self.dual.x / length,
+            y: self.dual.y / length,
+            z: self.dual.z / length,
+            w: self.dual.w / length,
+        };
+        let translation = dual * real.conjugate();
+        Mat4::from(&real) * point + 2. * Vec3::new(translation.x, translation.y, translation.z)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::Transform;
+
+    #[test]
+    fn test_skinning() {
+        // A strip of two vertices, the second one morphed up and bound to a
+        // joint turning a quarter turn around z
+        let mut mesh = Mesh::new(vec![Vec3::new(1., 0., 0.), Vec3::new(2., 0., 0.)], vec![]);
+        mesh.normals = vec![Vec3::new(0., 1., 0.); 2];
+        let deformation = MeshDeformation {
+            joints: vec![[0, 0, 0, 0], [0, 1, 0, 0]],
+            weights: vec![[1., 0., 0., 0.], [0.5, 0.5, 0., 0.]],
+            morph_targets: vec![vec![Vec3::zero(), Vec3::new(0., 0., 1.)]],
+            morph_normals: vec![vec![Vec3::zero(), Vec3::new(0., -1., 1.)]],
+        };
+        let joints = [Mat4::identity(), Transform::rotate_z(std::f32::consts::FRAC_PI_2).matrix];
+
+        let morphed = deformation.deform(&mesh, &[0.5], &[Mat4::identity(); 2], SkinningMethod::LinearBlend);
+        assert_eq!(morphed.positions[1], Vec3::new(2., 0., 0.5));
+        let up = std::f32::consts::FRAC_1_SQRT_2;
+        assert!((morphed.normals[1] - Vec3::new(0., up, up)).length() < 1e-5);
+
+        // Averaging the matrices pulls the vertex in, dual quaternions keep
+        // it on the circle at 45 degrees
+        let linear = deformation.deform(&mesh, &[0.], &joints, SkinningMethod::LinearBlend);
+        assert_eq!(linear.positions[0], Vec3::new(1., 0., 0.));
+        assert!((linear.positions[1] - Vec3::new(1., 1., 0.)).length() < 1e-5);
+        let dual = deformation.deform(&mesh, &[0.], &joints, SkinningMethod::DualQuaternion);
+        let diagonal = std::f32::consts::SQRT_2;
+        assert!((dual.positions[0] - Vec3::new(1., 0., 0.)).length() < 1e-5);
+        assert!((dual.positions[1] - Vec3::new(diagonal, diagonal, 0.)).length() < 1e-5);
+
+        // The normals turn halfway with both
+        for skinned in [&linear, &dual] {
+            assert!((skinned.normals[0] - Vec3::new(0., 1., 0.)).length() < 1e-5);
+            assert!((skinned.normals[1] - Vec3::new(-up, up, 0.)).length() < 1e-5);
+        }
+
+        // Translated joints move the vertices along
+        let moved = [Transform::translate(Vec3::new(0., 3., 0.)).matrix; 2];
+        let dual = deformation.deform(&mesh, &[0.], &moved, SkinningMethod::DualQuaternion);
+        assert!((dual.positions[1] - Vec3::new(2., 3., 0.)).length() < 1e-5);
+    }
+}
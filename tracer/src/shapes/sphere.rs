@@ -3,6 +3,7 @@ use std::f32::consts::PI;
 
 use crate::core::interaction::SurfaceInteraction;
 use crate::core::ray::Ray;
+use crate::core::shape::ShapeSample;
 
 #[derive(Copy, Clone, PartialEq, Debug)]
 pub struct Sphere {
@@ -24,15 +25,28 @@ impl Sphere {
         (*point - self.center).normalize()
     }
 
-    // Get UV for a unit sphere
+    // Get UV of a point on the sphere, from its direction to the center
     pub fn uv_at(&self, point: &Vec3) -> Vec2 {
-        let phi = Float::atan2(point.z, point.x);
-        let theta = Float::asin(point.y);
+        let direction = self.normal_at(point);
+        let phi = Float::atan2(direction.z, direction.x);
+        let theta = Float::asin(direction.y.clamp(-1., 1.));
         let u = 1. - (phi + PI) / (2. * PI);
         let v = (theta + PI / 2.) / PI;
         Vec2 { 0: u, 1: v }
     }
 
+    // Derivatives along the uv of `uv_at`, u turns around y backward once
+    // and v goes from the bottom pole to the top one. The normal follows the
+    // point scaled by the radius.
+    fn set_derivatives(&self, isect: &mut SurfaceInteraction) {
+        let d = isect.hit_point - self.center;
+        let rho = (d.x * d.x + d.z * d.z).sqrt().max(1e-6);
+        isect.dpdu = 2. * PI * Vec3::new(d.z, 0., -d.x);
+        isect.dpdv = PI * Vec3::new(-d.y * d.x / rho, rho, -d.y * d.z / rho);
+        isect.dndu = isect.dpdu / self.radius;
+        isect.dndv = isect.dpdv / self.radius;
+    }
+
     pub fn intersect(&self, ray: &Ray, isect: &mut SurfaceInteraction) -> bool {
         // Sphere equation
         // (x - cx)^2 + (y - cy)^2 + (z - cz)^2 = R^2
@@ -56,7 +70,9 @@ impl Sphere {
                 isect.t = t;
                 isect.hit_point = ray.point_at(t);
                 isect.hit_normal = self.normal_at(&isect.hit_point);
+                isect.hit_front_face = Vec3::dot(ray.direction, isect.hit_normal) < 0.;
                 isect.hit_uv = self.uv_at(&isect.hit_point);
+                self.set_derivatives(isect);
                 return true;
             } else {
                 let t = (-b + Float::sqrt(discriminant)) / (2. * a);
@@ -66,6 +82,7 @@ impl Sphere {
                     isect.hit_normal = self.normal_at(&isect.hit_point);
                     isect.hit_front_face = Vec3::dot(ray.direction, isect.hit_normal) < 0.;
                     isect.hit_uv = self.uv_at(&isect.hit_point);
+                    self.set_derivatives(isect);
                     return true;
                 }
             }
@@ -78,4 +95,21 @@ impl Sphere {
         let p_max = self.center + Vec3::new(self.radius, self.radius, self.radius);
         crate::core::bounds::Bounds3f { p_min, p_max }
     }
+
+    pub fn area(&self) -> Float {
+        4. * PI * self.radius_sq
+    }
+
+    // Uniform over the sphere, z is uniform by Archimedes' hat-box theorem
+    pub fn sample(&self, u: &Vec2) -> ShapeSample {
+        let z = 1. - 2. * u.0;
+        let r = (1. - z * z).max(0.).sqrt();
+        let phi = 2. * PI * u.1;
+        let normal = Vec3::new(r * phi.cos(), r * phi.sin(), z);
+        ShapeSample {
+            point: self.center + self.radius * normal,
+            normal,
+            pdf: 1. / self.area(),
+        }
+    }
 }
@@ -0,0 +1,579 @@
+use std::collections::{HashMap, HashSet};
+use std::f32::consts::PI;
+
+use math::{Float, Vec2, Vec3};
+use mesh_loader::PolygonMesh;
+use strum_macros::{Display, EnumIter, EnumString};
+
+use crate::shapes::displaced::EdgeMetric;
+use crate::shapes::mesh::Mesh;
+
+#[derive(Debug, Default, EnumIter, EnumString, PartialEq, Eq, Clone, Copy, Display, Hash)]
+#[strum(ascii_case_insensitive)]
+pub enum SubdivisionScheme {
+    // Any polygons, quads after the first level
+    #[default]
+    CatmullClark,
+    // Triangles, other polygons are split in fans first
+    Loop,
+}
+
+// How many times the cage is subdivided
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SubdivisionLevels {
+    Uniform(u32),
+    // Enough levels for the longest edge of the cage to end up about
+    // `edge_length` pixels long as seen from the camera, at most `max_level`
+    Adaptive {
+        edge_length: Float,
+        max_level: u32,
+        metric: EdgeMetric,
+    },
+}
+
+// Polygon cage of a smooth surface. Sharp edges and corners are tagged,
+// boundaries are always sharp.
+#[derive(Clone, PartialEq, Debug)]
+pub struct SubdivisionSurface {
+    pub scheme: SubdivisionScheme,
+    pub positions: Vec<Vec3>,
+    // Per position, interpolated linearly
+    pub uv: Vec<Vec2>,
+    pub faces: Vec<Vec<u32>>,
+    // Sharpness of creased edges, by their vertices in increasing order.
+    // Each level of subdivision takes one off, infinite ones stay sharp.
+    pub creases: HashMap<(u32, u32), Float>,
+    // Vertices that stay where they are
+    pub corners: HashSet<u32>,
+    pub levels: SubdivisionLevels,
+}
+
+fn edge_key(a: u32, b: u32) -> (u32, u32) {
+    (a.min(b), a.max(b))
+}
+
+fn average(points: impl Iterator<Item = Vec3>) -> Vec3 {
+    let (sum, count) = points.fold((Vec3::from(0.), 0), |(sum, count), p| (sum + p, count + 1));
+    sum / count.max(1) as Float
+}
+
+// Area weighted normal of a polygon, Newell's method. Counterclockwise
+// corners face it.
+fn polygon_normal(positions: &[Vec3], face: &[u32]) -> Vec3 {
+    (0..face.len())
+        .map(|i| {
+            let (a, b) = (positions[face[i] as usize], positions[face[(i + 1) % face.len()] as usize]);
+            Vec3::cross(a, b)
+        })
+        .fold(Vec3::from(0.), |sum, n| sum + n)
+        * 0.5
+}
+
+impl SubdivisionSurface {
+    pub fn new(scheme: SubdivisionScheme, polygons: &PolygonMesh, levels: SubdivisionLevels) -> Self {
+        let faces = match scheme {
+            SubdivisionScheme::CatmullClark => polygons.faces.clone(),
+            SubdivisionScheme::Loop => polygons.triangulate().chunks_exact(3).map(|t| t.to_vec()).collect(),
+        };
+        SubdivisionSurface {
+            scheme,
+            positions: polygons.positions.clone(),
+            uv: polygons.uv.clone(),
+            faces,
+            creases: HashMap::new(),
+            corners: HashSet::new(),
+            levels,
+        }
+    }
+
+    pub fn add_crease(&mut self, a: u32, b: u32, sharpness: Float) {
+        self.creases.insert(edge_key(a, b), sharpness);
+    }
+
+    // Levels the cage is subdivided to
+    pub fn level(&self) -> u32 {
+        match self.levels {
+            SubdivisionLevels::Uniform(level) => level,
+            SubdivisionLevels::Adaptive { edge_length, max_level, metric } => {
+                let longest = self
+                    .faces
+                    .iter()
+                    .flat_map(|face| (0..face.len()).map(move |i| (face[i], face[(i + 1) % face.len()])))
+                    .map(|(a, b)| metric.pixels(self.positions[a as usize], self.positions[b as usize]))
+                    .fold(0., Float::max);
+                let level = (longest / edge_length.max(1e-3)).log2().ceil();
+                if level.is_finite() {
+                    (level.max(0.) as u32).min(max_level)
+                } else {
+                    0
+                }
+            }
+        }
+    }
+
+    // Triangles of the subdivided surface, its vertices moved to their limit
+    // positions with the normals of the limit surface
+    pub fn refine(&self) -> Mesh {
+        let mut cage = Cage {
+            positions: self.positions.clone(),
+            uv: self.uv.clone(),
+            faces: self.faces.clone(),
+            creases: self.creases.clone(),
+            corners: self.corners.clone(),
+        };
+        for _ in 0..self.level() {
+            cage = match self.scheme {
+                SubdivisionScheme::CatmullClark => cage.catmull_clark(),
+                SubdivisionScheme::Loop => cage.loop_subdivide(),
+            };
+        }
+
+        let (positions, normals) = cage.limit(self.scheme);
+        let polygons = PolygonMesh {
+            positions,
+            normals,
+            uv: cage.uv,
+            faces: cage.faces,
+        };
+        Mesh::from_polygons(&polygons)
+    }
+}
+
+// How a vertex moves, from the sharp edges around it
+enum VertexRule {
+    Smooth,
+    // Along the curve of its two sharp edges, to the other ends. The blend
+    // toward it is below 1 for creases about to fade.
+    Crease(u32, u32, Float),
+    Corner,
+}
+
+// Adjacency of a cage
+struct Topology {
+    edge_faces: HashMap<(u32, u32), Vec<usize>>,
+    // In the order the faces list them
+    edges: Vec<(u32, u32)>,
+    vertex_edges: Vec<Vec<(u32, u32)>>,
+    vertex_faces: Vec<Vec<usize>>,
+}
+
+// One level of a subdivision surface
+struct Cage {
+    positions: Vec<Vec3>,
+    uv: Vec<Vec2>,
+    faces: Vec<Vec<u32>>,
+    creases: HashMap<(u32, u32), Float>,
+    corners: HashSet<u32>,
+}
+
+impl Cage {
+    fn topology(&self) -> Topology {
+        let mut topology = Topology {
+            edge_faces: HashMap::new(),
+            edges: Vec::new(),
+            vertex_edges: vec![Vec::new(); self.positions.len()],
+            vertex_faces: vec![Vec::new(); self.positions.len()],
+        };
+        for (f, face) in self.faces.iter().enumerate() {
+            for i in 0..face.len() {
+                let key = edge_key(face[i], face[(i + 1) % face.len()]);
+                let faces = topology.edge_faces.entry(key).or_default();
+                if faces.is_empty() {
+                    topology.edges.push(key);
+                    topology.vertex_edges[key.0 as usize].push(key);
+                    topology.vertex_edges[key.1 as usize].push(key);
+                }
+                faces.push(f);
+                topology.vertex_faces[face[i] as usize].push(f);
+            }
+        }
+        topology
+    }
+
+    // Boundaries and edges of more than two faces are sharp
+    fn sharpness(&self, topology: &Topology, key: (u32, u32)) -> Float {
+        if topology.edge_faces[&key].len() != 2 {
+            return Float::INFINITY;
+        }
+        self.creases.get(&key).copied().unwrap_or(0.)
+    }
+
+    fn vertex_rule(&self, topology: &Topology, v: u32) -> VertexRule {
+        if self.corners.contains(&v) {
+            return VertexRule::Corner;
+        }
+        let sharp: Vec<((u32, u32), Float)> = topology.vertex_edges[v as usize]
+            .iter()
+            .map(|&key| (key, self.sharpness(topology, key)))
+            .filter(|(_, sharpness)| *sharpness > 0.)
+            .collect();
+        let other = |key: (u32, u32)| if key.0 == v { key.1 } else { key.0 };
+        match sharp.as_slice() {
+            _ if topology.vertex_edges[v as usize].is_empty() => VertexRule::Corner,
+            // A single sharp edge ends smoothly, like a dart
+            [] | [_] => VertexRule::Smooth,
+            [(a, sa), (b, sb)] => VertexRule::Crease(other(*a), other(*b), (0.5 * (sa + sb)).min(1.)),
+            _ => VertexRule::Corner,
+        }
+    }
+
+    // Creases one level down, on the halves of their edges
+    fn child_creases(&self, edge_points: &HashMap<(u32, u32), u32>) -> HashMap<(u32, u32), Float> {
+        let mut creases = HashMap::new();
+        for (&(a, b), &sharpness) in &self.creases {
+            let (Some(&middle), true) = (edge_points.get(&(a, b)), sharpness > 1.) else {
+                continue;
+            };
+            creases.insert(edge_key(a, middle), sharpness - 1.);
+            creases.insert(edge_key(middle, b), sharpness - 1.);
+        }
+        creases
+    }
+
+    // New points on the edges, placed by `smooth` unless the edge is sharp
+    fn edge_points(
+        &self,
+        topology: &Topology,
+        positions: &mut Vec<Vec3>,
+        uv: &mut Vec<Vec2>,
+        smooth: impl Fn((u32, u32), &[usize]) -> Vec3,
+    ) -> HashMap<(u32, u32), u32> {
+        let mut edge_points = HashMap::new();
+        for &key in &topology.edges {
+            let (a, b) = (self.positions[key.0 as usize], self.positions[key.1 as usize]);
+            let middle = (a + b) * 0.5;
+            let faces = &topology.edge_faces[&key];
+            let sharpness = self.sharpness(topology, key);
+            let point = if sharpness >= 1. {
+                middle
+            } else {
+                smooth(key, faces) * (1. - sharpness) + middle * sharpness
+            };
+            edge_points.insert(key, positions.len() as u32);
+            positions.push(point);
+            if !self.uv.is_empty() {
+                uv.push((self.uv[key.0 as usize] + self.uv[key.1 as usize]) * 0.5);
+            }
+        }
+        edge_points
+    }
+
+    // Old vertices moved by `smooth`, or along their creases
+    fn vertex_points(
+        &self,
+        topology: &Topology,
+        positions: &mut [Vec3],
+        crease: impl Fn(Vec3, Vec3, Vec3) -> Vec3,
+        smooth: impl Fn(u32) -> Vec3,
+    ) {
+        for v in 0..self.positions.len() as u32 {
+            let p = self.positions[v as usize];
+            positions[v as usize] = match self.vertex_rule(topology, v) {
+                VertexRule::Corner => p,
+                VertexRule::Smooth => smooth(v),
+                VertexRule::Crease(a, b, blend) => {
+                    let sharp = crease(p, self.positions[a as usize], self.positions[b as usize]);
+                    if blend >= 1. {
+                        sharp
+                    } else {
+                        smooth(v) * (1. - blend) + sharp * blend
+                    }
+                }
+            };
+        }
+    }
+
+    fn catmull_clark(&self) -> Cage {
+        let topology = self.topology();
+        let face_points: Vec<Vec3> = self
+            .faces
+            .iter()
+            .map(|face| average(face.iter().map(|&i| self.positions[i as usize])))
+            .collect();
+
+        // Old vertices first, then the edge points, then the face points
+        let mut positions = self.positions.clone();
+        let mut uv = self.uv.clone();
+        let edge_points = self.edge_points(&topology, &mut positions, &mut uv, |key, faces| {
+            let (a, b) = (self.positions[key.0 as usize], self.positions[key.1 as usize]);
+            (a + b + face_points[faces[0]] + face_points[faces[1]]) * 0.25
+        });
+        let first_face_point = positions.len() as u32;
+        positions.extend(&face_points);
+        if !self.uv.is_empty() {
+            uv.extend(self.faces.iter().map(|face| {
+                face.iter().fold(Vec2::from(0.), |sum, &i| sum + self.uv[i as usize]) * (1. / face.len() as Float)
+            }));
+        }
+
+        // (F + 2 R + (n - 3) P) / n, from the faces and the midpoints of the
+        // edges around
+        self.vertex_points(
+            &topology,
+            &mut positions,
+            |p, a, b| (p * 6. + a + b) * 0.125,
+            |v| {
+                let p = self.positions[v as usize];
+                let edges = &topology.vertex_edges[v as usize];
+                let n = edges.len() as Float;
+                let f = average(topology.vertex_faces[v as usize].iter().map(|&f| face_points[f]));
+                let r = average(edges.iter().map(|&(a, b)| (self.positions[a as usize] + self.positions[b as usize]) * 0.5));
+                (f + r * 2. + p * (n - 3.)) / n
+            },
+        );
+
+        let mut faces = Vec::new();
+        for (f, face) in self.faces.iter().enumerate() {
+            let m = face.len();
+            for i in 0..m {
+                let (previous, corner, next) = (face[(i + m - 1) % m], face[i], face[(i + 1) % m]);
+                faces.push(vec![
+                    corner,
+                    edge_points[&edge_key(corner, next)],
+                    first_face_point + f as u32,
+                    edge_points[&edge_key(previous, corner)],
+                ]);
+            }
+        }
+
+        Cage {
+            creases: self.child_creases(&edge_points),
+            corners: self.corners.clone(),
+            positions,
+            uv,
+            faces,
+        }
+    }
+
+    fn loop_subdivide(&self) -> Cage {
+        let topology = self.topology();
+        let opposite = |face: usize, key: (u32, u32)| {
+            let corner = self.faces[face].iter().find(|&&i| i != key.0 && i != key.1);
+            self.positions[*corner.unwrap_or(&key.0) as usize]
+        };
+
+        let mut positions = self.positions.clone();
+        let mut uv = self.uv.clone();
+        let edge_points = self.edge_points(&topology, &mut positions, &mut uv, |key, faces| {
+            let (a, b) = (self.positions[key.0 as usize], self.positions[key.1 as usize]);
+            (a + b) * 0.375 + (opposite(faces[0], key) + opposite(faces[1], key)) * 0.125
+        });
+
+        // Warren's weights
+        self.vertex_points(
+            &topology,
+            &mut positions,
+            |p, a, b| p * 0.75 + (a + b) * 0.125,
+            |v| {
+                let edges = &topology.vertex_edges[v as usize];
+                let n = edges.len();
+                let beta = if n == 3 { 3. / 16. } else { 3. / (8. * n as Float) };
+                let ring = edges.iter().map(|&(a, b)| self.positions[if a == v { b } else { a } as usize]);
+                self.positions[v as usize] * (1. - n as Float * beta) + ring.fold(Vec3::from(0.), |sum, p| sum + p) * beta
+            },
+        );
+
+        let mut faces = Vec::new();
+        for face in &self.faces {
+            let (a, b, c) = (face[0], face[1], face[2]);
+            let (ab, bc, ca) = (edge_points[&edge_key(a, b)], edge_points[&edge_key(b, c)], edge_points[&edge_key(c, a)]);
+            faces.extend([vec![a, ab, ca], vec![ab, b, bc], vec![ca, bc, c], vec![ab, bc, ca]]);
+        }
+
+        Cage {
+            creases: self.child_creases(&edge_points),
+            corners: self.corners.clone(),
+            positions,
+            uv,
+            faces,
+        }
+    }
+
+    // Neighbours of an inner vertex in order around it, with the corner
+    // across each face for quads. None on boundaries and odd topology.
+    fn one_ring(&self, topology: &Topology, v: u32) -> Option<(Vec<u32>, Vec<u32>)> {
+        // Corners before and after v, and across from it, in each face
+        let around: Vec<(u32, u32, u32)> = topology.vertex_faces[v as usize]
+            .iter()
+            .map(|&f| {
+                let face = &self.faces[f];
+                let m = face.len();
+                let i = face.iter().position(|&c| c == v).unwrap_or(0);
+                (face[(i + m - 1) % m], face[(i + 1) % m], face[(i + 2) % m])
+            })
+            .collect();
+        let (mut ring, mut diagonals) = (Vec::new(), Vec::new());
+        let mut current = *around.first()?;
+        for _ in 0..around.len() {
+            ring.push(current.0);
+            diagonals.push(current.2);
+            current = *around.iter().find(|(previous, _, _)| *previous == current.1)?;
+        }
+        (current == around[0]).then_some((ring, diagonals))
+    }
+
+    // Limit positions and normals of the vertices. Smooth ones get the
+    // tangents of the limit surface, the others the normals of the faces
+    // around.
+    fn limit(&self, scheme: SubdivisionScheme) -> (Vec<Vec3>, Vec<Vec3>) {
+        let topology = self.topology();
+        // Catmull-Clark's limit rules hold for quads
+        let quads = self.faces.iter().all(|face| face.len() == 4);
+        let mut face_normals = vec![Vec3::from(0.); self.positions.len()];
+        for face in &self.faces {
+            let normal = polygon_normal(&self.positions, face);
+            for &i in face {
+                face_normals[i as usize] += normal;
+            }
+        }
+
+        let mut positions = self.positions.clone();
+        let mut normals = Vec::with_capacity(self.positions.len());
+        for v in 0..self.positions.len() as u32 {
+            let p = self.positions[v as usize];
+            let face_normal = face_normals[v as usize].normalize();
+            let ring = match self.vertex_rule(&topology, v) {
+                VertexRule::Crease(a, b, _) if scheme == SubdivisionScheme::Loop || quads => {
+                    let (a, b) = (self.positions[a as usize], self.positions[b as usize]);
+                    positions[v as usize] = match scheme {
+                        SubdivisionScheme::CatmullClark => (p * 4. + a + b) / 6.,
+                        SubdivisionScheme::Loop => (p * 3. + a + b) / 5.,
+                    };
+                    None
+                }
+                VertexRule::Smooth if scheme == SubdivisionScheme::Loop || quads => self.one_ring(&topology, v),
+                _ => None,
+            };
+            let Some((ring, diagonals)) = ring else {
+                normals.push(face_normal);
+                continue;
+            };
+
+            let n = ring.len();
+            let at = |i: usize| self.positions[ring[i % n] as usize];
+            let across = |i: usize| self.positions[diagonals[i % n] as usize];
+            let angle = |i: usize| 2. * PI * i as Float / n as Float;
+            let (mut t1, mut t2) = (Vec3::from(0.), Vec3::from(0.));
+            match scheme {
+                SubdivisionScheme::Loop => {
+                    let beta = if n == 3 { 3. / 16. } else { 3. / (8. * n as Float) };
+                    let gamma = 1. / (n as Float + 3. / (8. * beta));
+                    let sum = (0..n).fold(Vec3::from(0.), |sum, i| sum + at(i));
+                    positions[v as usize] = p * (1. - n as Float * gamma) + sum * gamma;
+                    for i in 0..n {
+                        t1 += at(i) * angle(i).cos();
+                        t2 += at(i) * angle(i).sin();
+                    }
+                }
+                SubdivisionScheme::CatmullClark => {
+                    let nf = n as Float;
+                    let (edges, faces) = (0..n).fold((Vec3::from(0.), Vec3::from(0.)), |(e, f), i| (e + at(i), f + across(i)));
+                    positions[v as usize] = (p * nf * nf + edges * 4. + faces) / (nf * (nf + 5.));
+                    let a = 1. + (2. * PI / nf).cos() + (PI / nf).cos() * (2. * (9. + (2. * PI / nf).cos())).sqrt();
+                    for i in 0..n {
+                        let (c0, c1) = (angle(i).cos(), angle(i + 1).cos());
+                        let (s0, s1) = (angle(i).sin(), angle(i + 1).sin());
+                        t1 = t1 + at(i) * (a * c0) + across(i) * (c0 + c1);
+                        t2 = t2 + at(i) * (a * s0) + across(i) * (s0 + s1);
+                    }
+                }
+            }
+            let normal = Vec3::cross(t1, t2);
+            normals.push(if normal.length2() == 0. || !normal.x.is_finite() {
+                face_normal
+            } else if Vec3::dot(normal, face_normal) < 0. {
+                -normal.normalize()
+            } else {
+                normal.normalize()
+            });
+        }
+        (positions, normals)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Unit cube with its faces wound outward
+    fn cube() -> PolygonMesh {
+        let positions = (0..8)
+            .map(|i| Vec3::new((i & 1) as Float, ((i >> 1) & 1) as Float, ((i >> 2) & 1) as Float) - Vec3::from(0.5))
+            .collect();
+        PolygonMesh {
+            positions,
+            faces: vec![
+                vec![0, 2, 3, 1],
+                vec![4, 5, 7, 6],
+                vec![0, 1, 5, 4],
+                vec![2, 6, 7, 3],
+                vec![0, 4, 6, 2],
+                vec![1, 3, 7, 5],
+            ],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_subdivision() {
+        // Every level makes 4 quads of each one
+        let mut surface = SubdivisionSurface::new(SubdivisionScheme::CatmullClark, &cube(), SubdivisionLevels::Uniform(2));
+        let mesh = surface.refine();
+        assert_eq!(mesh.indices.len() / 3, 6 * 16 * 2);
+        // A cube rounds into a blob, the corners pulled in and the normals
+        // pointing out
+        let radius = |p: &Vec3| p.length();
+        assert!(mesh.positions.iter().all(|p| radius(p) < 0.87 && radius(p) > 0.3));
+        for (p, n) in mesh.positions.iter().zip(&mesh.normals) {
+            assert!(Vec3::dot(*p, *n) > 0.);
+            assert!((n.length() - 1.).abs() < 1e-4);
+        }
+
+        // Creasing all the edges and tagging the corners keeps the cube
+        for face in cube().faces {
+            for i in 0..4 {
+                surface.add_crease(face[i], face[(i + 1) % 4], Float::INFINITY);
+            }
+        }
+        surface.corners = (0..8).collect();
+        let sharp = surface.refine();
+        assert!(sharp.positions.iter().all(|p| (p.x.abs().max(p.y.abs()).max(p.z.abs()) - 0.5).abs() < 1e-5));
+
+        // Loop subdivision of a tetrahedron stays inside it and turns four
+        // faces into four per level
+        let tetrahedron = PolygonMesh {
+            positions: vec![Vec3::new(1., 1., 1.), Vec3::new(1., -1., -1.), Vec3::new(-1., 1., -1.), Vec3::new(-1., -1., 1.)],
+            faces: vec![vec![0, 1, 2], vec![0, 3, 1], vec![0, 2, 3], vec![1, 3, 2]],
+            ..Default::default()
+        };
+        let smooth = SubdivisionSurface::new(SubdivisionScheme::Loop, &tetrahedron, SubdivisionLevels::Uniform(3)).refine();
+        assert_eq!(smooth.indices.len() / 3, 4 * 64);
+        assert!(smooth.positions.iter().all(|p| p.length() < Float::sqrt(3.)));
+        for (p, n) in smooth.positions.iter().zip(&smooth.normals) {
+            assert!(Vec3::dot(*p, *n) > 0.);
+        }
+
+        // Far away cages need fewer levels
+        let far = SubdivisionLevels::Adaptive {
+            edge_length: 4.,
+            max_level: 5,
+            metric: EdgeMetric {
+                eye: Vec3::new(0., 0., -100.),
+                units_per_pixel: 0.,
+                radians_per_pixel: 0.001,
+            },
+        };
+        let adaptive = SubdivisionSurface::new(SubdivisionScheme::CatmullClark, &cube(), far);
+        assert_eq!(adaptive.level(), 2);
+        let near = SubdivisionLevels::Adaptive {
+            edge_length: 4.,
+            max_level: 5,
+            metric: EdgeMetric {
+                eye: Vec3::new(0., 0., -10.),
+                units_per_pixel: 0.,
+                radians_per_pixel: 0.001,
+            },
+        };
+        assert_eq!(SubdivisionSurface::new(SubdivisionScheme::CatmullClark, &cube(), near).level(), 5);
+    }
+}
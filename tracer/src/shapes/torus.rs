@@ -0,0 +1,268 @@
+use math::{Float, Vec2, Vec3};
+use std::f64::consts::PI;
+
+use crate::core::bounds::Bounds3f;
+use crate::core::interaction::SurfaceInteraction;
+use crate::core::ray::Ray;
+use crate::core::shape::ShapeSample;
+use crate::shapes::analytic::{phi_of, Placement};
+
+// Ring around the z axis, a tube of `minor_radius` whose center line is a
+// circle of `major_radius`, swept up to `phi_max`
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Torus {
+    pub major_radius: Float,
+    pub minor_radius: Float,
+    pub phi_max: Float,
+    pub placement: Placement,
+}
+
+impl Torus {
+    pub fn new(major_radius: Float, minor_radius: Float, phi_max_degrees: Float, placement: Placement) -> Self {
+        Self {
+            major_radius,
+            minor_radius,
+            phi_max: phi_max_degrees.clamp(0., 360.).to_radians(),
+            placement,
+        }
+    }
+
+    // Away from the closest point on the center circle
+    fn normal_at(&self, p: Vec3) -> Vec3 {
+        let center = self.major_radius * Vec3::new(p.x, p.y, 0.).normalize();
+        p - center
+    }
+
+    // Angle around the tube, 0 on the outer equator and turning up first
+    fn theta_of(&self, p: Vec3) -> Float {
+        let theta = p.z.atan2((p.x * p.x + p.y * p.y).sqrt() - self.major_radius);
+        if theta < 0. {
+            theta + 2. * std::f32::consts::PI
+        } else {
+            theta
+        }
+    }
+
+    pub fn intersect(&self, ray: &Ray, isect: &mut SurfaceInteraction) -> bool {
+        const T_MIN: Float = 1e-3;
+
+        // (x^2 + y^2 + z^2 + R^2 - r^2)^2 = 4 R^2 (x^2 + y^2) along the ray,
+        // in double precision and with a unit direction to keep the quartic
+        // well conditioned
+        let object_ray = self.placement.ray_to_object(ray);
+        let length = object_ray.direction.length() as f64;
+        if length == 0. {
+            return false;
+        }
+        let (ox, oy, oz) = (object_ray.origin.x as f64, object_ray.origin.y as f64, object_ray.origin.z as f64);
+        let (dx, dy, dz) = (
+            object_ray.direction.x as f64 / length,
+            object_ray.direction.y as f64 / length,
+            object_ray.direction.z as f64 / length,
+        );
+        let major_sq = (self.major_radius as f64).powi(2);
+        let minor_sq = (self.minor_radius as f64).powi(2);
+        let f = ox * dx + oy * dy + oz * dz;
+        let e = ox * ox + oy * oy + oz * oz + major_sq - minor_sq;
+        let coefficients = [
+            4. * f,
+            4. * f * f + 2. * e - 4. * major_sq * (dx * dx + dy * dy),
+            4. * f * e - 8. * major_sq * (ox * dx + oy * dy),
+            e * e - 4. * major_sq * (ox * ox + oy * oy),
+        ];
+
+        let mut roots = solve_quartic(coefficients);
+        roots.sort_by(|a, b| a.total_cmp(b));
+        for root in roots {
+            let t = (polish_root(coefficients, root) / length) as Float;
+            if t <= T_MIN {
+                continue;
+            }
+            let p = object_ray.point_at(t);
+            let phi = phi_of(p);
+            if phi > self.phi_max {
+                continue;
+            }
+            let theta = self.theta_of(p);
+            let uv = (phi / self.phi_max, theta / (2. * std::f32::consts::PI));
+            self.placement.record_hit(ray, t, self.normal_at(p), uv, isect);
+            return true;
+        }
+        false
+    }
+
+    pub fn world_bound(&self) -> Bounds3f {
+        let outer = self.major_radius + self.minor_radius;
+        self.placement.bounds_to_world(&Bounds3f {
+            p_min: Vec3::new(-outer, -outer, -self.minor_radius),
+            p_max: Vec3::new(outer, outer, self.minor_radius),
+        })
+    }
+
+    pub fn area(&self) -> Float {
+        let object_area = self.phi_max * self.major_radius * 2. * std::f32::consts::PI * self.minor_radius;
+        object_area * self.placement.area_scale()
+    }
+
+    // Uniform over the area. The outside of the ring is bigger than the
+    // inside, theta goes with R + r cos(theta) and is found by bisecting its
+    // distribution.
+    pub fn sample(&self, u: &Vec2) -> ShapeSample {
+        let (major, minor) = (self.major_radius as f64, self.minor_radius as f64);
+        let cdf = |theta: f64| (major * theta + minor * theta.sin()) / (2. * PI * major);
+        let (mut low, mut high) = (0., 2. * PI);
+        for _ in 0..32 {
+            let middle = 0.5 * (low + high);
+            if cdf(middle) < u.0 as f64 {
+                low = middle;
+            } else {
+                high = middle;
+            }
+        }
+        let theta = (0.5 * (low + high)) as Float;
+        let phi = u.1 * self.phi_max;
+        let ring = self.major_radius + self.minor_radius * theta.cos();
+        let p = Vec3::new(ring * phi.cos(), ring * phi.sin(), self.minor_radius * theta.sin());
+        ShapeSample {
+            point: self.placement.point_to_world(p),
+            normal: self.placement.normal_to_world(self.normal_at(p)),
+            pdf: 1. / self.area(),
+        }
+    }
+}
+
+const EPSILON: f64 = 1e-9;
+
+// Real roots of x^2 + p x + q
+fn solve_quadric(p: f64, q: f64) -> Vec<f64> {
+    let half_p = 0.5 * p;
+    let discriminant = half_p * half_p - q;
+    if discriminant.abs() < EPSILON {
+        vec![-half_p]
+    } else if discriminant < 0. {
+        Vec::new()
+    } else {
+        let root = discriminant.sqrt();
+        vec![-half_p - root, -half_p + root]
+    }
+}
+
+// Real roots of x^3 + a x^2 + b x + c, from Schwarze in Graphics Gems
+fn solve_cubic(a: f64, b: f64, c: f64) -> Vec<f64> {
+    // Substitute x = y - a / 3 to get y^3 + 3 p y + 2 q
+    let a_sq = a * a;
+    let p = (-a_sq / 3. + b) / 3.;
+    let q = (2. / 27. * a * a_sq - a * b / 3. + c) / 2.;
+    let p_cubed = p * p * p;
+    let discriminant = q * q + p_cubed;
+
+    let roots = if discriminant.abs() < EPSILON {
+        if q.abs() < EPSILON {
+            vec![0.]
+        } else {
+            let u = (-q).cbrt();
+            vec![2. * u, -u]
+        }
+    } else if discriminant < 0. {
+        // Three real roots
+        let phi = (-q / (-p_cubed).sqrt()).clamp(-1., 1.).acos() / 3.;
+        let t = 2. * (-p).sqrt();
+        vec![t * phi.cos(), -t * (phi + PI / 3.).cos(), -t * (phi - PI / 3.).cos()]
+    } else {
+        let root = discriminant.sqrt();
+        vec![(root - q).cbrt() - (root + q).cbrt()]
+    };
+    roots.into_iter().map(|y| y - a / 3.).collect()
+}
+
+// Real roots of x^4 + a x^3 + b x^2 + c x + d, with Ferrari's method
+fn solve_quartic([a, b, c, d]: [f64; 4]) -> Vec<f64> {
+    // Substitute x = y - a / 4 to get y^4 + p y^2 + q y + r
+    let a_sq = a * a;
+    let p = -3. / 8. * a_sq + b;
+    let q = a_sq * a / 8. - a * b / 2. + c;
+    let r = -3. / 256. * a_sq * a_sq + a_sq * b / 16. - a * c / 4. + d;
+
+    let roots = if r.abs() < EPSILON {
+        // y (y^3 + p y + q) = 0
+        let mut roots = solve_cubic(0., p, q);
+        roots.push(0.);
+        roots
+    } else {
+        // One root of the resolvent cubic splits it into two quadrics
+        let z = solve_cubic(-p / 2., -r, r * p / 2. - q * q / 8.)[0];
+        let root_of = |x: f64| {
+            if x.abs() < EPSILON {
+                Some(0.)
+            } else if x > 0. {
+                Some(x.sqrt())
+            } else {
+                None
+            }
+        };
+        let (Some(u), Some(v)) = (root_of(z * z - r), root_of(2. * z - p)) else {
+            return Vec::new();
+        };
+        let v = if q < 0. { -v } else { v };
+        let mut roots = solve_quadric(v, z - u);
+        roots.extend(solve_quadric(-v, z + u));
+        roots
+    };
+    roots.into_iter().map(|y| y - a / 4.).collect()
+}
+
+// A couple of Newton steps, the closed form loses digits when roots are close
+fn polish_root([a, b, c, d]: [f64; 4], mut x: f64) -> f64 {
+    for _ in 0..2 {
+        let value = (((x + a) * x + b) * x + c) * x + d;
+        let slope = ((4. * x + 3. * a) * x + 2. * b) * x + c;
+        if slope.abs() < EPSILON {
+            break;
+        }
+        x -= value / slope;
+    }
+    x
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_torus() {
+        // (x - 1)(x - 2)(x - 3)(x - 4)
+        let mut roots = solve_quartic([-10., 35., -50., 24.]);
+        roots.sort_by(|a, b| a.total_cmp(b));
+        assert_eq!(roots.len(), 4);
+        for (root, expected) in roots.iter().zip([1., 2., 3., 4.]) {
+            assert!((root - expected).abs() < 1e-6);
+        }
+
+        let torus = Torus::new(2., 0.5, 360., Placement::default());
+        let pi = std::f32::consts::PI;
+        assert!((torus.area() - 4. * pi * pi * 2. * 0.5).abs() < 1e-3);
+
+        // Along x through the hole: the outer side, then the inner side of
+        // the same tube
+        let mut isect = SurfaceInteraction::new();
+        assert!(torus.intersect(&Ray::new(Vec3::new(5., 0., 0.), Vec3::new(-1., 0., 0.)), &mut isect));
+        assert!((isect.t - 2.5).abs() < 1e-4);
+        assert!((isect.hit_normal - Vec3::new(1., 0., 0.)).length() < 1e-4);
+        let mut isect = SurfaceInteraction::new();
+        assert!(torus.intersect(&Ray::new(Vec3::new(0., 0., 0.), Vec3::new(1., 0., 0.)), &mut isect));
+        assert!((isect.t - 1.5).abs() < 1e-4);
+        assert!(isect.hit_front_face);
+        // Straight down the hole
+        assert!(!torus.intersect(&Ray::new(Vec3::new(0., 0., 5.), Vec3::new(0., 0., -1.)), &mut SurfaceInteraction::new()));
+
+        // On top of the tube
+        let mut isect = SurfaceInteraction::new();
+        assert!(torus.intersect(&Ray::new(Vec3::new(0., 2., 3.), Vec3::new(0., 0., -1.)), &mut isect));
+        assert!((isect.hit_point.z - 0.5).abs() < 1e-4);
+        assert!((isect.hit_uv.y() - 0.25).abs() < 1e-4);
+
+        let sample = torus.sample(&Vec2::new(&[0.5, 0.25]));
+        assert!((sample.point - Vec3::new(0., 1.5, 0.)).length() < 1e-4);
+        assert!((sample.normal - Vec3::new(0., -1., 0.)).length() < 1e-4);
+    }
+}
@@ -1,7 +1,9 @@
-use math::{Vec2, Vec3};
+use math::{Float, Vec2, Vec3};
 
 use crate::core::interaction::SurfaceInteraction;
 use crate::core::ray::Ray;
+use crate::core::shape::ShapeSample;
+
 #[derive(Copy, Clone, PartialEq, Debug)]
 pub struct Triangle {
     pub v0: Vec3,
@@ -17,7 +19,7 @@ impl Triangle {
     pub fn normal_at(&self, _point: &Vec3) -> Vec3 {
         let v1v0 = self.v1 - self.v0;
         let v2v0 = self.v2 - self.v0;
-        Vec3::cross(v2v0, v1v0)
+        Vec3::cross(v2v0, v1v0).normalize()
     }
 
     pub fn intersect(&self, ray: &Ray, isect: &mut SurfaceInteraction) -> bool {
@@ -34,14 +36,22 @@ impl Triangle {
         let v = d * Vec3::dot(q, v1v0);
         let t = d * Vec3::dot(-n, rov0);
 
-        if u < 0.0 || v < 0.0 || (u + v) > 1.0 {
+        const T_MIN: Float = 1e-3;
+        if u < 0.0 || v < 0.0 || (u + v) > 1.0 || t.is_nan() || t <= T_MIN {
             return false;
         }
 
         isect.t = t;
-        isect.hit_point = Vec3 { x: t, y: u, z: v };
-        isect.hit_normal = self.normal_at(&isect.hit_point);
+        isect.hit_point = ray.point_at(t);
+        // Triangles are two sided, the normal faces the ray
+        let normal = self.normal_at(&isect.hit_point);
+        isect.hit_normal = if Vec3::dot(ray.direction, normal) > 0. { -normal } else { normal };
+        // The side the vertices wind counterclockwise around is the front
+        isect.hit_front_face = Vec3::dot(ray.direction, n) < 0.;
         isect.hit_uv = Vec2 { 0: u, 1: v };
+        // The barycentrics are the uv, the edges from v0 their derivatives
+        isect.dpdu = v1v0;
+        isect.dpdv = v2v0;
         return true;
     }
 
@@ -50,4 +60,21 @@ impl Triangle {
         let p_max = Vec3::component_max(self.v0, Vec3::component_max(self.v1, self.v2));
         crate::core::bounds::Bounds3f { p_min, p_max }
     }
+
+    pub fn area(&self) -> Float {
+        0.5 * Vec3::cross(self.v1 - self.v0, self.v2 - self.v0).length()
+    }
+
+    // Uniform barycentrics, the square root keeps the corner at v0 from
+    // getting more than its share
+    pub fn sample(&self, u: &Vec2) -> ShapeSample {
+        let s = u.0.sqrt();
+        let (b1, b2) = (s * (1. - u.1), s * u.1);
+        let point = (1. - b1 - b2) * self.v0 + b1 * self.v1 + b2 * self.v2;
+        ShapeSample {
+            point,
+            normal: self.normal_at(&point),
+            pdf: 1. / self.area(),
+        }
+    }
 }
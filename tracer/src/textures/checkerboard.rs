@@ -0,0 +1,29 @@
+use crate::core::interaction::SurfaceInteraction;
+use crate::core::texture::{Texture, TextureMapping};
+
+// Alternating cubes of unit size in texture space. With the uv mapping the
+// point has z = 0, so it is a flat checkerboard of unit squares in uv.
+#[derive(Clone, PartialEq, Debug)]
+pub struct CheckerboardTexture<T> {
+    pub mapping: TextureMapping,
+    pub even: T,
+    pub odd: T,
+}
+
+impl<T: Copy> CheckerboardTexture<T> {
+    pub fn new(mapping: TextureMapping, even: T, odd: T) -> Self {
+        CheckerboardTexture { mapping, even, odd }
+    }
+}
+
+impl<T: Copy> Texture<T> for CheckerboardTexture<T> {
+    fn evaluate(&self, si: &SurfaceInteraction) -> T {
+        let p = self.mapping.point(si);
+        let cell = p.x.floor() as i64 + p.y.floor() as i64 + p.z.floor() as i64;
+        if cell.rem_euclid(2) == 0 {
+            self.even
+        } else {
+            self.odd
+        }
+    }
+}
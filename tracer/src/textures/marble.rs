@@ -0,0 +1,59 @@
+use math::{Float, Vec3};
+
+use crate::core::interaction::SurfaceInteraction;
+use crate::core::spectrum::Spectrum;
+use crate::core::texture::{Texture, TextureMapping};
+use crate::textures::noise::{fbm, NoiseBasis};
+use crate::textures::ramp::ColorRamp;
+
+// Veins along y, a sine wave bent by fbm like pbrt's marble
+#[derive(Clone, PartialEq, Debug)]
+pub struct MarbleTexture {
+    pub mapping: TextureMapping,
+    pub octaves: u32,
+    pub roughness: Float,
+    // Frequency of the pattern
+    pub scale: Float,
+    // How far the noise pushes the veins around
+    pub variation: Float,
+    pub ramp: ColorRamp,
+}
+
+impl MarbleTexture {
+    pub fn new(mapping: TextureMapping) -> Self {
+        // The colors of pbrt's marble, grey stone with dark blue veins
+        let colors = [
+            [0.58, 0.58, 0.6],
+            [0.58, 0.58, 0.6],
+            [0.58, 0.58, 0.6],
+            [0.5, 0.5, 0.5],
+            [0.6, 0.59, 0.58],
+            [0.58, 0.58, 0.6],
+            [0.58, 0.58, 0.6],
+            [0.2, 0.2, 0.33],
+            [0.58, 0.58, 0.6],
+        ];
+        MarbleTexture {
+            mapping,
+            octaves: 8,
+            roughness: 0.5,
+            scale: 1.,
+            variation: 0.2,
+            ramp: ColorRamp::evenly_spaced(&colors.map(|color| Vec3::from(&color))),
+        }
+    }
+}
+
+impl Texture<Float> for MarbleTexture {
+    fn evaluate(&self, si: &SurfaceInteraction) -> Float {
+        let p = self.mapping.point(si) * self.scale;
+        let marble = p.y + self.variation * fbm(p, NoiseBasis::Perlin, self.octaves, 1.99, self.roughness);
+        0.5 + 0.5 * marble.sin()
+    }
+}
+
+impl Texture<Spectrum> for MarbleTexture {
+    fn evaluate(&self, si: &SurfaceInteraction) -> Spectrum {
+        Spectrum::ColorRGB(self.ramp.evaluate(Texture::<Float>::evaluate(self, si)))
+    }
+}
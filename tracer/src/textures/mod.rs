@@ -1 +1,8 @@
+pub mod checkerboard;
 pub mod constant;
+pub mod marble;
+pub mod noise;
+pub mod nodes;
+pub mod ramp;
+pub mod voronoi;
+pub mod wood;
@@ -0,0 +1,310 @@
+use math::{Float, Vec2, Vec3};
+use strum_macros::{Display, EnumIter, EnumString};
+
+use crate::core::interaction::SurfaceInteraction;
+use crate::core::spectrum::Spectrum;
+use crate::core::texture::{FloatTexture, SpectrumTexture, Texture, TextureRef, TextureValue};
+use crate::textures::ramp::ColorRamp;
+
+// Nodes computing a texture from other ones, so scenes can build the
+// parameters of materials without code. Most of them work on floats and
+// colors alike.
+
+// Blend from `a` to `b` by `amount`
+#[derive(Clone)]
+pub struct MixTexture<T> {
+    pub a: TextureRef<T>,
+    pub b: TextureRef<T>,
+    pub amount: FloatTexture,
+}
+
+impl<T: TextureValue> Texture<T> for MixTexture<T> {
+    fn evaluate(&self, si: &SurfaceInteraction) -> T {
+        let t = self.amount.evaluate(si);
+        // Skip the side that doesn't show
+        if t <= 0. {
+            return self.a.evaluate(si);
+        }
+        if t >= 1. {
+            return self.b.evaluate(si);
+        }
+        self.a.evaluate(si) * (1. - t) + self.b.evaluate(si) * t
+    }
+}
+
+// A texture times a float one, like pbrt's scale texture
+#[derive(Clone)]
+pub struct ScaleTexture<T> {
+    pub texture: TextureRef<T>,
+    pub scale: FloatTexture,
+}
+
+impl<T: TextureValue> Texture<T> for ScaleTexture<T> {
+    fn evaluate(&self, si: &SurfaceInteraction) -> T {
+        self.texture.evaluate(si) * self.scale.evaluate(si)
+    }
+}
+
+#[derive(Debug, EnumIter, EnumString, PartialEq, Eq, Clone, Copy, Display, Hash)]
+#[strum(ascii_case_insensitive)]
+pub enum Operation {
+    Add,
+    Multiply,
+}
+
+#[derive(Clone)]
+pub struct CombineTexture<T> {
+    pub a: TextureRef<T>,
+    pub b: TextureRef<T>,
+    pub operation: Operation,
+}
+
+impl<T: TextureValue> Texture<T> for CombineTexture<T> {
+    fn evaluate(&self, si: &SurfaceInteraction) -> T {
+        let (a, b) = (self.a.evaluate(si), self.b.evaluate(si));
+        match self.operation {
+            Operation::Add => a + b,
+            Operation::Multiply => a * b,
+        }
+    }
+}
+
+// 1 - x on every channel
+#[derive(Clone)]
+pub struct InvertTexture<T> {
+    pub texture: TextureRef<T>,
+}
+
+impl<T: TextureValue> Texture<T> for InvertTexture<T> {
+    fn evaluate(&self, si: &SurfaceInteraction) -> T {
+        self.texture.evaluate(si).map(|x| 1. - x)
+    }
+}
+
+// Linear map of the range `from` to the range `to`, clamped to it or not
+#[derive(Clone)]
+pub struct RemapTexture<T> {
+    pub texture: TextureRef<T>,
+    pub from: (Float, Float),
+    pub to: (Float, Float),
+    pub clamp: bool,
+}
+
+impl<T: TextureValue> Texture<T> for RemapTexture<T> {
+    fn evaluate(&self, si: &SurfaceInteraction) -> T {
+        let ((a, b), (c, d)) = (self.from, self.to);
+        self.texture.evaluate(si).map(|x| {
+            let t = if b == a { 0. } else { (x - a) / (b - a) };
+            let t = if self.clamp { t.clamp(0., 1.) } else { t };
+            c + t * (d - c)
+        })
+    }
+}
+
+// Curve through points (x, y) applied to every channel, linear between
+// them and flat past the ends
+#[derive(Clone)]
+pub struct CurveTexture<T> {
+    pub texture: TextureRef<T>,
+    points: Vec<(Float, Float)>,
+}
+
+impl<T> CurveTexture<T> {
+    pub fn new(texture: TextureRef<T>, mut points: Vec<(Float, Float)>) -> Self {
+        assert!(!points.is_empty(), "a curve needs at least one point");
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+        CurveTexture { texture, points }
+    }
+
+    pub fn curve(&self, x: Float) -> Float {
+        let next = self.points.partition_point(|point| point.0 <= x);
+        if next == 0 {
+            return self.points[0].1;
+        }
+        if next == self.points.len() {
+            return self.points[next - 1].1;
+        }
+        let ((x0, y0), (x1, y1)) = (self.points[next - 1], self.points[next]);
+        y0 + (x - x0) / (x1 - x0) * (y1 - y0)
+    }
+}
+
+impl<T: TextureValue> Texture<T> for CurveTexture<T> {
+    fn evaluate(&self, si: &SurfaceInteraction) -> T {
+        self.texture.evaluate(si).map(|x| self.curve(x))
+    }
+}
+
+// Colors of a float texture
+#[derive(Clone)]
+pub struct RampTexture {
+    pub input: FloatTexture,
+    pub ramp: ColorRamp,
+}
+
+impl Texture<Spectrum> for RampTexture {
+    fn evaluate(&self, si: &SurfaceInteraction) -> Spectrum {
+        Spectrum::ColorRGB(self.ramp.evaluate(self.input.evaluate(si)))
+    }
+}
+
+#[derive(Debug, EnumIter, PartialEq, Eq, Clone, Copy, Display, Hash)]
+pub enum Channel {
+    R,
+    G,
+    B,
+    Zero,
+    One,
+}
+
+impl Channel {
+    // "r", "g", "b", "0" or "1"
+    pub fn from_char(c: char) -> Option<Channel> {
+        match c.to_ascii_lowercase() {
+            'r' => Some(Channel::R),
+            'g' => Some(Channel::G),
+            'b' => Some(Channel::B),
+            '0' => Some(Channel::Zero),
+            '1' => Some(Channel::One),
+            _ => None,
+        }
+    }
+
+    fn of(&self, rgb: Vec3) -> Float {
+        match self {
+            Channel::R => rgb.x,
+            Channel::G => rgb.y,
+            Channel::B => rgb.z,
+            Channel::Zero => 0.,
+            Channel::One => 1.,
+        }
+    }
+}
+
+// Channels of a color rearranged, "bgr" or "rrr" or "rg0". As a float
+// texture it is the first channel, like the metalness in the blue of a
+// glTF metallic roughness map.
+#[derive(Clone)]
+pub struct SwizzleTexture {
+    pub texture: SpectrumTexture,
+    pub channels: [Channel; 3],
+}
+
+impl SwizzleTexture {
+    pub fn new(texture: SpectrumTexture, channels: &str) -> Result<Self, String> {
+        let parsed: Option<Vec<Channel>> = channels.chars().map(Channel::from_char).collect();
+        let channels = match parsed.as_deref() {
+            Some([c]) => [*c; 3],
+            Some([r, g, b]) => [*r, *g, *b],
+            _ => return Err(format!("invalid channels '{}', expected 1 or 3 of r, g, b, 0 and 1", channels)),
+        };
+        Ok(SwizzleTexture { texture, channels })
+    }
+}
+
+// Sampled spectra have no channels to swap, textures give colors anyway
+impl Texture<Spectrum> for SwizzleTexture {
+    fn evaluate(&self, si: &SurfaceInteraction) -> Spectrum {
+        match self.texture.evaluate(si) {
+            Spectrum::ColorRGB(rgb) => Spectrum::ColorRGB(Vec3::new(
+                self.channels[0].of(rgb),
+                self.channels[1].of(rgb),
+                self.channels[2].of(rgb),
+            )),
+            sampled => sampled,
+        }
+    }
+}
+
+impl Texture<Float> for SwizzleTexture {
+    fn evaluate(&self, si: &SurfaceInteraction) -> Float {
+        self.channels[0].of(self.texture.evaluate(si).to_rgb())
+    }
+}
+
+// Texture looked up at transformed uv, in the order of glTF's
+// KHR_texture_transform: scaled, rotated by `rotation` radians, then offset
+#[derive(Clone)]
+pub struct UvTransformTexture<T> {
+    pub texture: TextureRef<T>,
+    pub offset: Vec2,
+    pub rotation: Float,
+    pub scale: Vec2,
+}
+
+impl<T> UvTransformTexture<T> {
+    pub fn transform(&self, uv: Vec2) -> Vec2 {
+        let (sin, cos) = self.rotation.sin_cos();
+        let (u, v) = (uv.0 * self.scale.0, uv.1 * self.scale.1);
+        Vec2::new(&[cos * u + sin * v + self.offset.0, -sin * u + cos * v + self.offset.1])
+    }
+}
+
+impl<T: TextureValue> Texture<T> for UvTransformTexture<T> {
+    fn evaluate(&self, si: &SurfaceInteraction) -> T {
+        self.texture.evaluate(&si.with_uv(self.transform(si.hit_uv)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::core::texture::TextureMapping;
+    use crate::textures::constant::ConstantTexture;
+    use crate::textures::ramp::{GradientKind, GradientTexture};
+
+    fn constant<T: TextureValue>(value: T) -> TextureRef<T> {
+        Arc::new(ConstantTexture::new(value))
+    }
+
+    #[test]
+    fn test_nodes() {
+        let si = SurfaceInteraction::new();
+        let rgb = |r, g, b| Spectrum::ColorRGB(Vec3::new(r, g, b));
+        let color = constant(rgb(0.2, 0.4, 0.8));
+
+        let mix = MixTexture {
+            a: color.clone(),
+            b: constant(rgb(1., 1., 1.)),
+            amount: constant(0.5),
+        };
+        assert_eq!(mix.evaluate(&si), rgb(0.6, 0.7, 0.9));
+
+        let scale = ScaleTexture { texture: color.clone(), scale: constant(2.) };
+        assert_eq!(scale.evaluate(&si), rgb(0.4, 0.8, 1.6));
+        let add = CombineTexture { a: constant(0.25), b: constant(0.5), operation: Operation::Add };
+        let multiply = CombineTexture { a: constant(0.25), b: constant(0.5), operation: Operation::Multiply };
+        assert_eq!((add.evaluate(&si), multiply.evaluate(&si)), (0.75, 0.125));
+        assert_eq!(InvertTexture { texture: constant(0.25) }.evaluate(&si), 0.75);
+
+        let remap = |clamp| RemapTexture { texture: constant(0.9), from: (0.5, 0.7), to: (0., 10.), clamp };
+        assert!((remap(false).evaluate(&si) - 20.).abs() < 1e-4);
+        assert_eq!(remap(true).evaluate(&si), 10.);
+        let curve = CurveTexture::new(color.clone(), vec![(1., 1.), (0., 0.), (0.5, 0.9)]);
+        assert!((curve.evaluate(&si).to_rgb() - Vec3::new(0.36, 0.72, 0.96)).length() < 1e-5);
+
+        let ramp = RampTexture { input: constant(0.5), ramp: ColorRamp::default() };
+        assert_eq!(ramp.evaluate(&si), rgb(0.5, 0.5, 0.5));
+
+        let swizzle = SwizzleTexture::new(color.clone(), "b1r").unwrap();
+        assert_eq!(Texture::<Spectrum>::evaluate(&swizzle, &si), rgb(0.8, 1., 0.2));
+        assert_eq!(Texture::<Float>::evaluate(&SwizzleTexture::new(color, "g").unwrap(), &si), 0.4);
+        assert!(SwizzleTexture::new(constant(rgb(0., 0., 0.)), "rgba").is_err());
+
+        // A quarter turn takes u to -v, then the offset moves it back to [0, 1]
+        let gradient: FloatTexture = Arc::new(GradientTexture::new(TextureMapping::default(), GradientKind::Linear));
+        let transform = UvTransformTexture {
+            texture: gradient,
+            offset: Vec2::new(&[0., 1.]),
+            rotation: std::f32::consts::FRAC_PI_2,
+            scale: Vec2::new(&[2., 1.]),
+        };
+        let uv = transform.transform(Vec2::new(&[0.25, 0.5]));
+        assert!((uv.0 - 0.5).abs() < 1e-6 && (uv.1 - 0.5).abs() < 1e-6);
+        let mut at = SurfaceInteraction::new();
+        at.hit_uv = Vec2::new(&[0.25, 0.3]);
+        assert!((transform.evaluate(&at) - 0.3).abs() < 1e-6);
+    }
+}
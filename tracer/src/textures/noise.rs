@@ -0,0 +1,229 @@
+use math::{Float, Vec3};
+use strum_macros::{Display, EnumIter, EnumString};
+
+use crate::core::interaction::SurfaceInteraction;
+use crate::core::spectrum::Spectrum;
+use crate::core::texture::{Texture, TextureMapping};
+use crate::textures::ramp::ColorRamp;
+
+// Hash of a lattice point. It stands in for the permutation table of
+// Perlin's noise and seeds the cells of Voronoi textures.
+pub fn hash3(x: i32, y: i32, z: i32) -> u32 {
+    let mut h = (x as u32).wrapping_mul(0x8da6_b343) ^ (y as u32).wrapping_mul(0xd816_3841) ^ (z as u32).wrapping_mul(0xcb1a_b31f);
+    // Murmur3 finalizer, so nearby points get unrelated hashes
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x85eb_ca6b);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0xc2b2_ae35);
+    h ^ (h >> 16)
+}
+
+// Uniform in [0, 1) from a hash
+pub fn hash_to_unit(h: u32) -> Float {
+    (h >> 8) as Float / (1u32 << 24) as Float
+}
+
+// Dot product of the offset with one of the 12 directions to the edges of
+// a cube, picked by the hash
+fn gradient(h: u32, x: Float, y: Float, z: Float) -> Float {
+    let h = h & 15;
+    let u = if h < 8 { x } else { y };
+    let v = if h < 4 {
+        y
+    } else if h == 12 || h == 14 {
+        x
+    } else {
+        z
+    };
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
+
+fn lerp(t: Float, a: Float, b: Float) -> Float {
+    a + t * (b - a)
+}
+
+// Improved Perlin noise, in about [-1, 1] and 0 at the lattice points
+pub fn perlin(p: Vec3) -> Float {
+    let (xi, yi, zi) = (p.x.floor() as i32, p.y.floor() as i32, p.z.floor() as i32);
+    let (x, y, z) = (p.x - p.x.floor(), p.y - p.y.floor(), p.z - p.z.floor());
+    let fade = |t: Float| t * t * t * (t * (t * 6. - 15.) + 10.);
+    let (u, v, w) = (fade(x), fade(y), fade(z));
+
+    let corner = |dx: i32, dy: i32, dz: i32| {
+        gradient(hash3(xi + dx, yi + dy, zi + dz), x - dx as Float, y - dy as Float, z - dz as Float)
+    };
+    lerp(
+        w,
+        lerp(v, lerp(u, corner(0, 0, 0), corner(1, 0, 0)), lerp(u, corner(0, 1, 0), corner(1, 1, 0))),
+        lerp(v, lerp(u, corner(0, 0, 1), corner(1, 0, 1)), lerp(u, corner(0, 1, 1), corner(1, 1, 1))),
+    )
+}
+
+// Simplex noise, in about [-1, 1]. It sums the 4 corners of the
+// tetrahedron around the point instead of the 8 of a cube, and has no
+// visible axis aligned artifacts.
+pub fn simplex(p: Vec3) -> Float {
+    const F3: Float = 1. / 3.;
+    const G3: Float = 1. / 6.;
+    // Skew to the lattice of tetrahedra
+    let s = (p.x + p.y + p.z) * F3;
+    let (i, j, k) = ((p.x + s).floor() as i32, (p.y + s).floor() as i32, (p.z + s).floor() as i32);
+    let t = (i + j + k) as Float * G3;
+    let x0 = Vec3::new(p.x - (i as Float - t), p.y - (j as Float - t), p.z - (k as Float - t));
+
+    // The second and third corners depend on which tetrahedron of the cube
+    // the point is in
+    let (second, third) = if x0.x >= x0.y {
+        if x0.y >= x0.z {
+            ((1, 0, 0), (1, 1, 0))
+        } else if x0.x >= x0.z {
+            ((1, 0, 0), (1, 0, 1))
+        } else {
+            ((0, 0, 1), (1, 0, 1))
+        }
+    } else if x0.y < x0.z {
+        ((0, 0, 1), (0, 1, 1))
+    } else if x0.x < x0.z {
+        ((0, 1, 0), (0, 1, 1))
+    } else {
+        ((0, 1, 0), (1, 1, 0))
+    };
+
+    let mut sum = 0.;
+    for (n, (di, dj, dk)) in [(0, 0, 0), second, third, (1, 1, 1)].into_iter().enumerate() {
+        let d = x0 - Vec3::new(di as Float, dj as Float, dk as Float) + Vec3::from(n as Float * G3);
+        let falloff = 0.6 - d.length2();
+        if falloff > 0. {
+            sum += falloff.powi(4) * gradient(hash3(i + di, j + dj, k + dk), d.x, d.y, d.z);
+        }
+    }
+    32. * sum
+}
+
+#[derive(Debug, EnumIter, EnumString, PartialEq, Eq, Clone, Copy, Display, Hash)]
+#[strum(ascii_case_insensitive)]
+pub enum NoiseBasis {
+    Perlin,
+    Simplex,
+}
+
+impl NoiseBasis {
+    pub fn noise(&self, p: Vec3) -> Float {
+        match self {
+            NoiseBasis::Perlin => perlin(p),
+            NoiseBasis::Simplex => simplex(p),
+        }
+    }
+}
+
+// Fractional Brownian motion: octaves of noise, each `lacunarity` times
+// finer and `gain` times fainter than the previous one
+pub fn fbm(p: Vec3, basis: NoiseBasis, octaves: u32, lacunarity: Float, gain: Float) -> Float {
+    let (mut sum, mut frequency, mut amplitude) = (0., 1., 1.);
+    for _ in 0..octaves {
+        sum += amplitude * basis.noise(p * frequency);
+        frequency *= lacunarity;
+        amplitude *= gain;
+    }
+    sum
+}
+
+// Like fbm over the absolute value of the noise, which creases it where it
+// crosses 0
+pub fn turbulence(p: Vec3, basis: NoiseBasis, octaves: u32, lacunarity: Float, gain: Float) -> Float {
+    let (mut sum, mut frequency, mut amplitude) = (0., 1., 1.);
+    for _ in 0..octaves {
+        sum += amplitude * basis.noise(p * frequency).abs();
+        frequency *= lacunarity;
+        amplitude *= gain;
+    }
+    sum
+}
+
+#[derive(Debug, EnumIter, EnumString, PartialEq, Eq, Clone, Copy, Display, Hash)]
+#[strum(ascii_case_insensitive)]
+pub enum NoiseKind {
+    Fbm,
+    Turbulence,
+}
+
+// Cloudy noise, in [0, 1]. Fbm is centered on 0.5, turbulence starts at 0.
+#[derive(Clone, PartialEq, Debug)]
+pub struct NoiseTexture {
+    pub mapping: TextureMapping,
+    pub basis: NoiseBasis,
+    pub kind: NoiseKind,
+    pub octaves: u32,
+    pub lacunarity: Float,
+    // Gain from one octave to the next
+    pub roughness: Float,
+    pub ramp: ColorRamp,
+}
+
+impl NoiseTexture {
+    pub fn new(mapping: TextureMapping, kind: NoiseKind) -> Self {
+        NoiseTexture {
+            mapping,
+            basis: NoiseBasis::Perlin,
+            kind,
+            octaves: 8,
+            lacunarity: 1.99,
+            roughness: 0.5,
+            ramp: ColorRamp::default(),
+        }
+    }
+}
+
+impl Texture<Float> for NoiseTexture {
+    fn evaluate(&self, si: &SurfaceInteraction) -> Float {
+        let p = self.mapping.point(si);
+        let value = match self.kind {
+            NoiseKind::Fbm => 0.5 + 0.5 * fbm(p, self.basis, self.octaves, self.lacunarity, self.roughness),
+            NoiseKind::Turbulence => turbulence(p, self.basis, self.octaves, self.lacunarity, self.roughness),
+        };
+        value.clamp(0., 1.)
+    }
+}
+
+impl Texture<Spectrum> for NoiseTexture {
+    fn evaluate(&self, si: &SurfaceInteraction) -> Spectrum {
+        Spectrum::ColorRGB(self.ramp.evaluate(Texture::<Float>::evaluate(self, si)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use strum::IntoEnumIterator;
+
+    use super::*;
+
+    #[test]
+    fn test_noise() {
+        // 0 on the lattice, bounded and continuous elsewhere
+        assert_eq!(perlin(Vec3::new(3., -2., 7.)), 0.);
+        for basis in NoiseBasis::iter() {
+            let mut previous = basis.noise(Vec3::zero());
+            let (mut min, mut max) = (Float::MAX, Float::MIN);
+            for i in 1..2000 {
+                let p = Vec3::new(0.013 * i as Float, 0.007 * i as Float, -0.011 * i as Float);
+                let value = basis.noise(p);
+                assert!(value.abs() <= 1.1, "{} {}", basis, value);
+                assert!((value - previous).abs() < 0.2, "{} jumps at {:?}", basis, p);
+                (min, max, previous) = (min.min(value), max.max(value), value);
+            }
+            // It does vary
+            assert!(max - min > 0.8, "{} {} {}", basis, min, max);
+        }
+
+        let p = Vec3::new(0.3, 1.7, -2.2);
+        assert_eq!(fbm(p, NoiseBasis::Perlin, 1, 2., 0.5), perlin(p));
+        assert!(turbulence(p, NoiseBasis::Simplex, 6, 2., 0.5) >= 0.);
+
+        let mut si = SurfaceInteraction::new();
+        si.hit_point = p;
+        let texture = NoiseTexture::new(TextureMapping::World(math::Mat4::identity()), NoiseKind::Fbm);
+        let value = Texture::<Float>::evaluate(&texture, &si);
+        assert!((0. ..=1.).contains(&value));
+        assert_eq!(Texture::<Spectrum>::evaluate(&texture, &si), Spectrum::ColorRGB(Vec3::from(value)));
+    }
+}
@@ -0,0 +1,116 @@
+use math::{Float, Vec3};
+use strum_macros::{Display, EnumIter, EnumString};
+
+use crate::core::interaction::SurfaceInteraction;
+use crate::core::spectrum::Spectrum;
+use crate::core::texture::{Texture, TextureMapping};
+
+// Colors at positions in [0, 1], linearly blended in between. Scalar
+// textures go through one to give colors.
+#[derive(Clone, PartialEq, Debug)]
+pub struct ColorRamp {
+    stops: Vec<(Float, Vec3)>,
+}
+
+impl Default for ColorRamp {
+    fn default() -> Self {
+        ColorRamp::new(vec![(0., Vec3::from(0.)), (1., Vec3::from(1.))])
+    }
+}
+
+impl ColorRamp {
+    pub fn new(mut stops: Vec<(Float, Vec3)>) -> Self {
+        assert!(!stops.is_empty(), "a color ramp needs at least one stop");
+        stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+        ColorRamp { stops }
+    }
+
+    // Stops spread evenly over [0, 1]
+    pub fn evenly_spaced(colors: &[Vec3]) -> Self {
+        let last = (colors.len().max(2) - 1) as Float;
+        ColorRamp::new(colors.iter().enumerate().map(|(i, color)| (i as Float / last, *color)).collect())
+    }
+
+    pub fn evaluate(&self, t: Float) -> Vec3 {
+        let next = self.stops.partition_point(|stop| stop.0 <= t);
+        if next == 0 {
+            return self.stops[0].1;
+        }
+        if next == self.stops.len() {
+            return self.stops[next - 1].1;
+        }
+        let ((t0, c0), (t1, c1)) = (self.stops[next - 1], self.stops[next]);
+        let s = (t - t0) / (t1 - t0);
+        (1. - s) * c0 + s * c1
+    }
+}
+
+#[derive(Debug, EnumIter, EnumString, PartialEq, Eq, Clone, Copy, Display, Hash)]
+#[strum(ascii_case_insensitive)]
+pub enum GradientKind {
+    // Along x, from 0 to 1
+    Linear,
+    // Around the z axis, a full turn from the -x axis
+    Radial,
+    // From 1 at the origin to 0 at the unit sphere
+    Spherical,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct GradientTexture {
+    pub mapping: TextureMapping,
+    pub kind: GradientKind,
+    pub ramp: ColorRamp,
+}
+
+impl GradientTexture {
+    pub fn new(mapping: TextureMapping, kind: GradientKind) -> Self {
+        GradientTexture {
+            mapping,
+            kind,
+            ramp: ColorRamp::default(),
+        }
+    }
+}
+
+impl Texture<Float> for GradientTexture {
+    fn evaluate(&self, si: &SurfaceInteraction) -> Float {
+        let p = self.mapping.point(si);
+        let t = match self.kind {
+            GradientKind::Linear => p.x,
+            GradientKind::Radial => 0.5 + p.y.atan2(p.x) / (2. * std::f32::consts::PI),
+            GradientKind::Spherical => 1. - p.length(),
+        };
+        t.clamp(0., 1.)
+    }
+}
+
+impl Texture<Spectrum> for GradientTexture {
+    fn evaluate(&self, si: &SurfaceInteraction) -> Spectrum {
+        Spectrum::ColorRGB(self.ramp.evaluate(Texture::<Float>::evaluate(self, si)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use math::Vec2;
+
+    use super::*;
+
+    #[test]
+    fn test_gradient() {
+        let ramp = ColorRamp::new(vec![(1., Vec3::new(0., 0., 1.)), (0., Vec3::new(1., 0., 0.)), (0.5, Vec3::from(1.))]);
+        assert_eq!(ramp.evaluate(-1.), Vec3::new(1., 0., 0.));
+        assert_eq!(ramp.evaluate(0.25), Vec3::new(1., 0.5, 0.5));
+        assert_eq!(ramp.evaluate(2.), Vec3::new(0., 0., 1.));
+        assert_eq!(ColorRamp::evenly_spaced(&[Vec3::from(0.), Vec3::from(1.)]), ColorRamp::default());
+
+        let mut si = SurfaceInteraction::new();
+        si.hit_uv = Vec2::new(&[0.25, 0.5]);
+        let linear = GradientTexture::new(TextureMapping::default(), GradientKind::Linear);
+        assert_eq!(Texture::<Float>::evaluate(&linear, &si), 0.25);
+        assert_eq!(Texture::<Spectrum>::evaluate(&linear, &si), Spectrum::ColorRGB(Vec3::from(0.25)));
+        let spherical = GradientTexture::new(TextureMapping::World(math::Mat4::identity()), GradientKind::Spherical);
+        assert_eq!(Texture::<Float>::evaluate(&spherical, &si), 1.);
+    }
+}
@@ -0,0 +1,133 @@
+use math::{Float, Vec3};
+use strum_macros::{Display, EnumIter, EnumString};
+
+use crate::core::interaction::SurfaceInteraction;
+use crate::core::spectrum::Spectrum;
+use crate::core::texture::{Texture, TextureMapping};
+use crate::textures::noise::{hash3, hash_to_unit};
+use crate::textures::ramp::ColorRamp;
+
+// Distances from a point to the nearest feature points of Worley noise,
+// one feature point in each unit cell
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Worley {
+    pub f1: Float,
+    pub f2: Float,
+    // Hash of the cell of the nearest feature point
+    pub cell: u32,
+}
+
+// `jitter` moves the feature points from the cell centers, 1 anywhere in
+// their cell
+pub fn worley(p: Vec3, jitter: Float) -> Worley {
+    let (xi, yi, zi) = (p.x.floor() as i32, p.y.floor() as i32, p.z.floor() as i32);
+    let mut nearest = Worley {
+        f1: Float::MAX,
+        f2: Float::MAX,
+        cell: 0,
+    };
+    for dz in -1..=1 {
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                let cell = hash3(xi + dx, yi + dy, zi + dz);
+                let offset = Vec3::new(
+                    hash_to_unit(cell),
+                    hash_to_unit(cell.rotate_left(11)),
+                    hash_to_unit(cell.rotate_left(22)),
+                );
+                let center = Vec3::new((xi + dx) as Float, (yi + dy) as Float, (zi + dz) as Float) + Vec3::from(0.5);
+                let feature = center + (offset - Vec3::from(0.5)) * jitter;
+                let distance = (feature - p).length();
+                if distance < nearest.f1 {
+                    nearest = Worley {
+                        f1: distance,
+                        f2: nearest.f1,
+                        cell,
+                    };
+                } else if distance < nearest.f2 {
+                    nearest.f2 = distance;
+                }
+            }
+        }
+    }
+    nearest
+}
+
+#[derive(Debug, EnumIter, EnumString, PartialEq, Eq, Clone, Copy, Display, Hash)]
+#[strum(ascii_case_insensitive)]
+pub enum VoronoiOutput {
+    // To the nearest feature point, dark at the centers of the cells
+    Distance,
+    // Difference of the two nearest, dark along the borders of the cells
+    Border,
+    // A random value for each cell
+    Cell,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct VoronoiTexture {
+    pub mapping: TextureMapping,
+    pub output: VoronoiOutput,
+    pub jitter: Float,
+    pub ramp: ColorRamp,
+}
+
+impl VoronoiTexture {
+    pub fn new(mapping: TextureMapping, output: VoronoiOutput) -> Self {
+        VoronoiTexture {
+            mapping,
+            output,
+            jitter: 1.,
+            ramp: ColorRamp::default(),
+        }
+    }
+}
+
+impl Texture<Float> for VoronoiTexture {
+    fn evaluate(&self, si: &SurfaceInteraction) -> Float {
+        let nearest = worley(self.mapping.point(si), self.jitter);
+        let value = match self.output {
+            VoronoiOutput::Distance => nearest.f1,
+            VoronoiOutput::Border => nearest.f2 - nearest.f1,
+            VoronoiOutput::Cell => hash_to_unit(nearest.cell),
+        };
+        value.clamp(0., 1.)
+    }
+}
+
+impl Texture<Spectrum> for VoronoiTexture {
+    fn evaluate(&self, si: &SurfaceInteraction) -> Spectrum {
+        Spectrum::ColorRGB(self.ramp.evaluate(Texture::<Float>::evaluate(self, si)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_worley() {
+        // Without jitter the feature points are the cell centers
+        let regular = worley(Vec3::new(0.5, 0.5, 0.5), 0.);
+        assert_eq!((regular.f1, regular.f2), (0., 1.));
+        let between = worley(Vec3::new(1., 0.5, 0.5), 0.);
+        assert!((between.f1 - 0.5).abs() < 1e-6 && (between.f2 - 0.5).abs() < 1e-6);
+
+        for i in 0..200 {
+            let p = Vec3::new(0.37 * i as Float, -0.21 * i as Float, 0.05 * i as Float);
+            let nearest = worley(p, 1.);
+            assert!(nearest.f1 <= nearest.f2);
+            // The feature point of the cell of p is at most a diagonal away
+            assert!(nearest.f1 <= Float::sqrt(3.));
+        }
+
+        // Points of one cell share its value
+        let mut si = SurfaceInteraction::new();
+        let mut cells = VoronoiTexture::new(TextureMapping::World(math::Mat4::identity()), VoronoiOutput::Cell);
+        cells.jitter = 0.;
+        si.hit_point = Vec3::new(10.5, 3.5, -4.5);
+        let center = Texture::<Float>::evaluate(&cells, &si);
+        si.hit_point = Vec3::new(10.45, 3.52, -4.5);
+        assert_eq!(Texture::<Float>::evaluate(&cells, &si), center);
+    }
+}
@@ -0,0 +1,53 @@
+use math::{Float, Vec3};
+
+use crate::core::interaction::SurfaceInteraction;
+use crate::core::spectrum::Spectrum;
+use crate::core::texture::{Texture, TextureMapping};
+use crate::textures::noise::{fbm, NoiseBasis};
+use crate::textures::ramp::ColorRamp;
+
+// Growth rings around the y axis, made irregular by fbm. The value goes
+// from 0 in the light early wood of a ring to 1 in its dark late wood.
+#[derive(Clone, PartialEq, Debug)]
+pub struct WoodTexture {
+    pub mapping: TextureMapping,
+    // Rings per unit of radius
+    pub rings: Float,
+    // How far the noise pushes the rings around, in rings
+    pub distortion: Float,
+    pub octaves: u32,
+    pub ramp: ColorRamp,
+}
+
+impl WoodTexture {
+    pub fn new(mapping: TextureMapping) -> Self {
+        WoodTexture {
+            mapping,
+            rings: 8.,
+            distortion: 0.6,
+            octaves: 4,
+            ramp: ColorRamp::new(vec![
+                (0., Vec3::new(0.75, 0.55, 0.33)),
+                (0.6, Vec3::new(0.64, 0.43, 0.23)),
+                (1., Vec3::new(0.36, 0.2, 0.09)),
+            ]),
+        }
+    }
+}
+
+impl Texture<Float> for WoodTexture {
+    fn evaluate(&self, si: &SurfaceInteraction) -> Float {
+        let p = self.mapping.point(si);
+        let radius = (p.x * p.x + p.z * p.z).sqrt();
+        // Stretched along the trunk, like the grain
+        let grain = Vec3::new(p.x * 2., p.y * 0.25, p.z * 2.);
+        let ring = radius * self.rings + self.distortion * fbm(grain, NoiseBasis::Perlin, self.octaves, 1.99, 0.5);
+        ring - ring.floor()
+    }
+}
+
+impl Texture<Spectrum> for WoodTexture {
+    fn evaluate(&self, si: &SurfaceInteraction) -> Spectrum {
+        Spectrum::ColorRGB(self.ramp.evaluate(Texture::<Float>::evaluate(self, si)))
+    }
+}